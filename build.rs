@@ -0,0 +1,71 @@
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    // Embedded migrations must trigger a rebuild when they change.
+    println!("cargo:rerun-if-changed=migrations");
+
+    // GIT_SHA can be supplied by CI when building outside a git checkout.
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|sha| sha.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+
+    // Honour SOURCE_DATE_EPOCH for reproducible builds.
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+
+    let mut features = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_string))
+        .map(|feature| feature.to_lowercase().replace('_', "-"))
+        .collect::<Vec<_>>();
+    features.sort();
+
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+
+    // The vendored protoc and well-known types unless PROTOC is supplied, so
+    // building needs no system protobuf compiler.
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        std::env::set_var(
+            "PROTOC_INCLUDE",
+            protoc_bin_vendored::include_path().unwrap(),
+        );
+    }
+
+    // Served by the reflection service.
+    let descriptor_path =
+        std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("todo_descriptor.bin");
+
+    tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
+        .compile_protos(&["proto/todo/v1/todo.proto"], &["proto"])
+        .unwrap();
+}
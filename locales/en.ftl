@@ -0,0 +1,49 @@
+# Messages of the response envelope, in English, the fallback for every
+# other language.
+
+internal-error = the server failed to handle the request, try again later
+database-error = the database failed to handle the request, try again later
+limit-out-of-range = limit must be between 1 and { $max }
+reversed-date-range = from must not be after to
+too-many-buckets = a time series has at most { $max } buckets: narrow the range or widen the interval
+date-out-of-range = a time series spans days from 0001-01-01 to 9999-12-31
+todo-not-found = todo with ID: { $id } not found
+if-match-required = If-Match header with the todo's ETag is required
+todo-modified = todo with ID: { $id } was modified, fetch it and retry
+todo-modified-or-deleted = todo with ID: { $id } was modified or deleted, fetch it and retry
+
+not-acceptable = none of the accepted media types is served, expected one of: { $types }
+unreadable-body = couldn't read the request body: { $error }
+invalid-method-override = X-HTTP-Method-Override can only make POST a PUT, PATCH or DELETE, not { $method }
+invalid-path-param = { $name } must be { $expected ->
+        [integer] an integer
+        [number] a number
+        [boolean] true or false
+       *[other] a { $expected }
+    }, not "{ $value }"
+invalid-query-param = the { $name } query parameter is invalid: { $error }
+invalid-json = invalid JSON body: { $error }
+invalid-fields = the request body is invalid
+field-blank = must not be blank
+field-too-short = must be at least { $min } characters long
+field-too-long = must be at most { $max } characters long
+field-count = must hold between { $min } and { $max } items
+unknown-relation = unknown relation { $relation }: todos have no relations to include
+nested-batch = a batch can't hold batches
+batch-method = invalid method: { $method }
+batch-path = invalid path: { $path }
+batch-header = invalid header: { $name }
+batch-request = invalid request: { $error }
+batch-stream = streaming route can't be batched: { $path }
+unknown-fields = unknown fields: { $fields }
+invalid-msgpack = invalid MessagePack body: { $error }
+
+jsonapi-include = todos have no relationships to include
+jsonapi-parameters = { $media_type } is only accepted without media type parameters
+jsonapi-data = expected a resource object in data
+jsonapi-type = expected a resource of type { $type }
+jsonapi-client-id = client-generated ids are not supported
+jsonapi-id = expected the resource with id { $id }
+
+route-not-found = no route serves { $path }
+method-not-allowed = { $method } is not allowed on { $path }, see Allow for the methods that are
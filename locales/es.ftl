@@ -0,0 +1,46 @@
+internal-error = el servidor no pudo atender la solicitud, inténtalo de nuevo más tarde
+database-error = la base de datos no pudo atender la solicitud, inténtalo de nuevo más tarde
+limit-out-of-range = limit debe estar entre 1 y { $max }
+reversed-date-range = from no puede ser posterior a to
+too-many-buckets = una serie temporal tiene como máximo { $max } intervalos: acorta el rango o amplía el intervalo
+date-out-of-range = una serie temporal abarca días del 0001-01-01 al 9999-12-31
+todo-not-found = tarea con ID: { $id } no encontrada
+if-match-required = se requiere el encabezado If-Match con la ETag de la tarea
+todo-modified = la tarea con ID: { $id } fue modificada, obtenla y vuelve a intentarlo
+todo-modified-or-deleted = la tarea con ID: { $id } fue modificada o eliminada, obtenla y vuelve a intentarlo
+
+not-acceptable = ninguno de los tipos de medio aceptados se sirve, se esperaba uno de: { $types }
+unreadable-body = no se pudo leer el cuerpo de la solicitud: { $error }
+invalid-method-override = X-HTTP-Method-Override solo convierte POST en PUT, PATCH o DELETE, no en { $method }
+invalid-path-param = { $name } debe ser { $expected ->
+        [integer] un número entero
+        [number] un número
+        [boolean] true o false
+       *[other] un { $expected }
+    }, no "{ $value }"
+invalid-query-param = el parámetro de consulta { $name } no es válido: { $error }
+invalid-json = cuerpo JSON inválido: { $error }
+invalid-fields = el cuerpo de la solicitud no es válido
+field-blank = no puede estar en blanco
+field-too-short = debe tener como mínimo { $min } caracteres
+field-too-long = debe tener como máximo { $max } caracteres
+field-count = debe contener entre { $min } y { $max } elementos
+unknown-relation = relación desconocida { $relation }: las tareas no tienen relaciones que incluir
+nested-batch = un lote no puede contener lotes
+batch-method = método inválido: { $method }
+batch-path = ruta inválida: { $path }
+batch-header = cabecera inválida: { $name }
+batch-request = solicitud inválida: { $error }
+batch-stream = una ruta de streaming no se puede incluir en un lote: { $path }
+unknown-fields = campos desconocidos: { $fields }
+invalid-msgpack = cuerpo MessagePack inválido: { $error }
+
+jsonapi-include = las tareas no tienen relaciones que incluir
+jsonapi-parameters = { $media_type } solo se acepta sin parámetros de tipo de medio
+jsonapi-data = se esperaba un objeto de recurso en data
+jsonapi-type = se esperaba un recurso del tipo { $type }
+jsonapi-client-id = no se admiten ids generados por el cliente
+jsonapi-id = se esperaba el recurso con id { $id }
+
+route-not-found = ninguna ruta atiende { $path }
+method-not-allowed = { $method } no está permitido en { $path }, consulta Allow para los métodos permitidos
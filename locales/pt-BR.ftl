@@ -0,0 +1,46 @@
+internal-error = o servidor falhou ao tratar a requisição, tente novamente mais tarde
+database-error = o banco de dados falhou ao tratar a requisição, tente novamente mais tarde
+limit-out-of-range = limit deve estar entre 1 e { $max }
+reversed-date-range = from não pode ser posterior a to
+too-many-buckets = uma série temporal tem no máximo { $max } intervalos: reduza o período ou aumente o intervalo
+date-out-of-range = uma série temporal abrange dias de 0001-01-01 a 9999-12-31
+todo-not-found = tarefa com ID: { $id } não encontrada
+if-match-required = o cabeçalho If-Match com a ETag da tarefa é obrigatório
+todo-modified = a tarefa com ID: { $id } foi modificada, busque-a e tente novamente
+todo-modified-or-deleted = a tarefa com ID: { $id } foi modificada ou excluída, busque-a e tente novamente
+
+not-acceptable = nenhum dos tipos de mídia aceitos é servido, esperado um de: { $types }
+unreadable-body = não foi possível ler o corpo da requisição: { $error }
+invalid-method-override = X-HTTP-Method-Override só transforma POST em PUT, PATCH ou DELETE, não em { $method }
+invalid-path-param = { $name } deve ser { $expected ->
+        [integer] um número inteiro
+        [number] um número
+        [boolean] true ou false
+       *[other] um { $expected }
+    }, não "{ $value }"
+invalid-query-param = o parâmetro de consulta { $name } é inválido: { $error }
+invalid-json = corpo JSON inválido: { $error }
+invalid-fields = o corpo da requisição é inválido
+field-blank = não pode estar em branco
+field-too-short = deve ter no mínimo { $min } caracteres
+field-too-long = deve ter no máximo { $max } caracteres
+field-count = deve conter entre { $min } e { $max } itens
+unknown-relation = relação desconhecida { $relation }: tarefas não têm relações a incluir
+nested-batch = um lote não pode conter lotes
+batch-method = método inválido: { $method }
+batch-path = caminho inválido: { $path }
+batch-header = cabeçalho inválido: { $name }
+batch-request = requisição inválida: { $error }
+batch-stream = uma rota de streaming não pode entrar em um lote: { $path }
+unknown-fields = campos desconhecidos: { $fields }
+invalid-msgpack = corpo MessagePack inválido: { $error }
+
+jsonapi-include = tarefas não têm relacionamentos a incluir
+jsonapi-parameters = { $media_type } só é aceito sem parâmetros de tipo de mídia
+jsonapi-data = esperado um objeto de recurso em data
+jsonapi-type = esperado um recurso do tipo { $type }
+jsonapi-client-id = ids gerados pelo cliente não são suportados
+jsonapi-id = esperado o recurso com id { $id }
+
+route-not-found = nenhuma rota atende { $path }
+method-not-allowed = { $method } não é permitido em { $path }, veja Allow para os métodos permitidos
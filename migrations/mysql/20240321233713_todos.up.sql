@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS todos (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT NOT NULL,
+    body TEXT NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    updated_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+);
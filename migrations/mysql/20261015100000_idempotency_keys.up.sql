@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    idempotency_key VARCHAR(400) PRIMARY KEY NOT NULL,
+    fingerprint VARCHAR(64) NOT NULL,
+    status INTEGER,
+    headers TEXT,
+    body MEDIUMTEXT,
+    created_at BIGINT NOT NULL,
+    INDEX idempotency_keys_created_at (created_at)
+);
@@ -0,0 +1,16 @@
+-- A single row, locked by every write until it commits, so sequence numbers
+-- are assigned in commit order.
+CREATE TABLE IF NOT EXISTS change_sequence (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    last_seq BIGINT NOT NULL
+);
+
+INSERT INTO change_sequence (id, last_seq) VALUES (1, 0);
+
+CREATE TABLE IF NOT EXISTS changes (
+    seq BIGINT PRIMARY KEY NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    todo_id BIGINT NOT NULL,
+    todo MEDIUMTEXT,
+    changed_at DATETIME(6) NOT NULL
+);
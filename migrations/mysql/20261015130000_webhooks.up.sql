@@ -0,0 +1,28 @@
+-- Times are unix seconds.
+CREATE TABLE IF NOT EXISTS webhooks (
+    id BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    url TEXT NOT NULL,
+    -- Comma separated event types; every type when empty.
+    events VARCHAR(255) NOT NULL,
+    secret VARCHAR(255) NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    webhook_id BIGINT NOT NULL,
+    event VARCHAR(16) NOT NULL,
+    payload MEDIUMTEXT NOT NULL,
+    -- pending, delivered or failed.
+    status VARCHAR(16) NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at BIGINT NOT NULL,
+    -- 0 until a response is received.
+    last_status_code BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    -- 0 until delivered.
+    delivered_at BIGINT NOT NULL DEFAULT 0,
+    INDEX webhook_deliveries_due (status, next_attempt_at),
+    INDEX webhook_deliveries_webhook_id (webhook_id)
+);
@@ -0,0 +1,21 @@
+-- Todo events waiting to be published to Kafka, written in the transaction
+-- of the write they describe. `seq` is the write's change sequence number, so
+-- events are published in commit order. Rows are deleted once published.
+CREATE TABLE IF NOT EXISTS outbox (
+    seq BIGINT PRIMARY KEY NOT NULL,
+    event VARCHAR(16) NOT NULL,
+    todo_id BIGINT NOT NULL,
+    payload MEDIUMTEXT NOT NULL,
+    -- Unix milliseconds.
+    created_at BIGINT NOT NULL
+);
+
+-- A single row naming the instance relaying the outbox until `expires_at`
+-- (unix seconds), so events are published by one instance at a time.
+CREATE TABLE IF NOT EXISTS outbox_relay (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    holder VARCHAR(64) NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+INSERT INTO outbox_relay (id, holder, expires_at) VALUES (1, '', 0);
@@ -0,0 +1,20 @@
+-- Times are unix seconds.
+CREATE TABLE IF NOT EXISTS email_queue (
+    id BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    -- Queuing an email with a key already queued does nothing, so instances
+    -- don't send the same one twice.
+    dedupe_key VARCHAR(255) NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    subject TEXT NOT NULL,
+    body MEDIUMTEXT NOT NULL,
+    -- pending, sent or failed.
+    status VARCHAR(16) NOT NULL,
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at BIGINT NOT NULL,
+    last_error TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    -- 0 until sent.
+    sent_at BIGINT NOT NULL DEFAULT 0,
+    UNIQUE INDEX email_queue_dedupe_key (dedupe_key),
+    INDEX email_queue_due (status, next_attempt_at)
+);
@@ -0,0 +1,17 @@
+-- Times are unix seconds.
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id BIGINT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+    -- The push service URL of the browser's PushSubscription.
+    endpoint VARCHAR(512) NOT NULL,
+    -- Its keys, base64url: the browser's P-256 public key and auth secret.
+    p256dh VARCHAR(255) NOT NULL,
+    auth VARCHAR(255) NOT NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE INDEX push_subscriptions_endpoint (endpoint)
+);
+
+-- A row per day a reminder was pushed, so instances push it once.
+CREATE TABLE IF NOT EXISTS push_reminders (
+    day VARCHAR(10) PRIMARY KEY NOT NULL,
+    sent_at BIGINT NOT NULL
+);
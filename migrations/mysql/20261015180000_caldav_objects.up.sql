@@ -0,0 +1,8 @@
+-- The todos CalDAV clients created, by the name they gave them in the
+-- calendar and their UID, which clients expect back as they gave them.
+CREATE TABLE IF NOT EXISTS caldav_objects (
+    name VARCHAR(255) PRIMARY KEY NOT NULL,
+    todo_id BIGINT NOT NULL,
+    uid VARCHAR(255) NOT NULL,
+    INDEX caldav_objects_todo_id (todo_id)
+);
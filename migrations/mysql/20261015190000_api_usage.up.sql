@@ -0,0 +1,12 @@
+-- Requests per hour, by principal and endpoint, summarized for operators.
+-- Hours are the unix seconds they start at.
+CREATE TABLE IF NOT EXISTS api_usage (
+    hour BIGINT NOT NULL,
+    principal VARCHAR(255) NOT NULL,
+    method VARCHAR(16) NOT NULL,
+    route VARCHAR(255) NOT NULL,
+    requests BIGINT NOT NULL,
+    client_errors BIGINT NOT NULL,
+    server_errors BIGINT NOT NULL,
+    PRIMARY KEY (hour, principal, method, route)
+);
@@ -0,0 +1 @@
+DROP INDEX todos_body_prefix ON todos;
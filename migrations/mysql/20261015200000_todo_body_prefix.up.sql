@@ -0,0 +1,3 @@
+-- Serves the prefix matches of suggestions, case-insensitive by the column's
+-- collation. TEXT columns can only be indexed by a prefix of them.
+CREATE INDEX todos_body_prefix ON todos (body(191));
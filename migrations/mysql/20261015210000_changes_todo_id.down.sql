@@ -0,0 +1 @@
+DROP INDEX changes_todo_id ON changes;
@@ -0,0 +1,9 @@
+-- The todos CalDAV clients created, by the name they gave them in the
+-- calendar and their UID, which clients expect back as they gave them.
+CREATE TABLE IF NOT EXISTS caldav_objects (
+    name TEXT PRIMARY KEY NOT NULL,
+    todo_id BIGINT NOT NULL,
+    uid TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS caldav_objects_todo_id ON caldav_objects (todo_id);
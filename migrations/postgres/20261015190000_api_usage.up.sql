@@ -0,0 +1,12 @@
+-- Requests per hour, by principal and endpoint, summarized for operators.
+-- Hours are the unix seconds they start at.
+CREATE TABLE IF NOT EXISTS api_usage (
+    hour BIGINT NOT NULL,
+    principal TEXT NOT NULL,
+    method TEXT NOT NULL,
+    route TEXT NOT NULL,
+    requests BIGINT NOT NULL,
+    client_errors BIGINT NOT NULL,
+    server_errors BIGINT NOT NULL,
+    PRIMARY KEY (hour, principal, method, route)
+);
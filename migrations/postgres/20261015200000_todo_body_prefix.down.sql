@@ -0,0 +1 @@
+DROP INDEX IF EXISTS todos_body_prefix;
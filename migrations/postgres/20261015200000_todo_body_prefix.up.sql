@@ -0,0 +1,3 @@
+-- Serves the case-insensitive prefix matches of suggestions, made on
+-- lower(body); text_pattern_ops makes LIKE usable whatever the collation.
+CREATE INDEX IF NOT EXISTS todos_body_prefix ON todos (lower(body) text_pattern_ops);
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS changes_todo_id;
@@ -0,0 +1,16 @@
+-- A single row, locked by every write until it commits, so sequence numbers
+-- are assigned in commit order.
+CREATE TABLE IF NOT EXISTS change_sequence (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    last_seq INTEGER NOT NULL
+);
+
+INSERT INTO change_sequence (id, last_seq) VALUES (1, 0);
+
+CREATE TABLE IF NOT EXISTS changes (
+    seq INTEGER PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    todo_id INTEGER NOT NULL,
+    todo TEXT,
+    changed_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,6 @@
+-- The CRDT document of a todo's body, once it was edited through sync, as a
+-- base64 encoded Yjs update.
+CREATE TABLE IF NOT EXISTS todo_documents (
+    todo_id INTEGER PRIMARY KEY NOT NULL,
+    state TEXT NOT NULL
+);
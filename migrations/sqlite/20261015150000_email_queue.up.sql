@@ -0,0 +1,21 @@
+-- Times are unix seconds.
+CREATE TABLE IF NOT EXISTS email_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- Queuing an email with a key already queued does nothing, so instances
+    -- don't send the same one twice.
+    dedupe_key TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    -- pending, sent or failed.
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    last_error TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL,
+    -- 0 until sent.
+    sent_at INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS email_queue_dedupe_key ON email_queue (dedupe_key);
+CREATE INDEX IF NOT EXISTS email_queue_due ON email_queue (status, next_attempt_at);
@@ -0,0 +1,18 @@
+-- Times are unix seconds.
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- The push service URL of the browser's PushSubscription.
+    endpoint TEXT NOT NULL,
+    -- Its keys, base64url: the browser's P-256 public key and auth secret.
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS push_subscriptions_endpoint ON push_subscriptions (endpoint);
+
+-- A row per day a reminder was pushed, so instances push it once.
+CREATE TABLE IF NOT EXISTS push_reminders (
+    day TEXT PRIMARY KEY NOT NULL,
+    sent_at INTEGER NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Whether a channel notifies of an event; enabled unless a row says
+-- otherwise. Times are unix seconds.
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    channel TEXT NOT NULL,
+    event TEXT NOT NULL,
+    enabled INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (channel, event)
+);
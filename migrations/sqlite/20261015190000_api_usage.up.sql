@@ -0,0 +1,12 @@
+-- Requests per hour, by principal and endpoint, summarized for operators.
+-- Hours are the unix seconds they start at.
+CREATE TABLE IF NOT EXISTS api_usage (
+    hour INTEGER NOT NULL,
+    principal TEXT NOT NULL,
+    method TEXT NOT NULL,
+    route TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    client_errors INTEGER NOT NULL,
+    server_errors INTEGER NOT NULL,
+    PRIMARY KEY (hour, principal, method, route)
+);
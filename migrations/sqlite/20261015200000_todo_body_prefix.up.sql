@@ -0,0 +1,3 @@
+-- Serves the prefix matches of suggestions. LIKE is case-insensitive here,
+-- so only a NOCASE index can serve it.
+CREATE INDEX IF NOT EXISTS todos_body_prefix ON todos (body COLLATE NOCASE);
@@ -0,0 +1,2 @@
+-- Serves the history of a todo, read by its revisions latest first.
+CREATE INDEX IF NOT EXISTS changes_todo_id ON changes (todo_id, seq);
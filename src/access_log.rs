@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+use axum::{
+    body::HttpBody as _,
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+
+// Identity of the caller, placed in the response extensions by whichever
+// authentication layer accepted the request.
+#[derive(Clone, Debug)]
+pub struct Principal(pub String);
+
+// Emits a single `access_log` event once the response is ready.
+pub async fn log(State(config): State<AccessLogConfig>, request: Request, next: Next) -> Response {
+    if !config.enabled {
+        return next.run(request).await;
+    }
+
+    let start = Instant::now();
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let version = request.version();
+    let request_bytes = body_size(request.headers(), request.body().size_hint().exact());
+
+    let response = next.run(request).await;
+
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+    let response_bytes = body_size(response.headers(), response.body().size_hint().exact());
+    let principal = response
+        .extensions()
+        .get::<Principal>()
+        .map(|principal| principal.0.as_str())
+        .unwrap_or("-");
+
+    // Failures are logged at a higher level so that sampling rules such as
+    // `access_log@info=0.01` thin out successful requests but keep every error.
+    macro_rules! access_event {
+        ($($args:tt)+) => {
+            if response.status().is_server_error() {
+                tracing::error!(target: "access_log", $($args)+)
+            } else if response.status().is_client_error() {
+                tracing::warn!(target: "access_log", $($args)+)
+            } else {
+                tracing::info!(target: "access_log", $($args)+)
+            }
+        };
+    }
+
+    match config.format {
+        AccessLogFormat::Fields => access_event!(
+            method = %method,
+            path = uri.path(),
+            status,
+            latency_ms,
+            request_bytes,
+            response_bytes,
+            principal,
+            "request completed"
+        ),
+        AccessLogFormat::Common => access_event!(
+            "{} \"{} {} {:?}\" {} {} {:.3}ms",
+            principal,
+            method,
+            uri,
+            version,
+            status,
+            response_bytes
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            latency_ms,
+        ),
+    }
+
+    response
+}
+
+// Prefers the declared Content-Length and falls back to the body's exact size
+// hint; streaming bodies have no known size.
+fn body_size(headers: &axum::http::HeaderMap, hint: Option<u64>) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or(hint)
+}
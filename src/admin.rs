@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::access_log::Principal;
+use crate::params::QueryParams;
+use crate::problem::Problem;
+
+// Bearer token required on every admin route, taken from ADMIN_TOKEN. Admin
+// routes are not mounted at all when it is unset.
+#[derive(Clone)]
+pub struct AdminToken(pub String);
+
+pub async fn require_admin(
+    State(AdminToken(token)): State<AdminToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|candidate| constant_time_eq(candidate.as_bytes(), token.as_bytes()));
+
+    if !authorized {
+        return (
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            Problem::new(StatusCode::UNAUTHORIZED).detail("admin token required"),
+        )
+            .into_response();
+    }
+
+    let mut response = next.run(request).await;
+    response
+        .extensions_mut()
+        .insert(Principal("admin".to_string()));
+    response
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+pub struct ProfileParams {
+    seconds: Option<u64>,
+    frequency: Option<i32>,
+}
+
+// Samples the whole process for the requested duration and returns a
+// uncompressed pprof protobuf, readable by `go tool pprof` or speedscope.
+pub async fn pprof_profile(
+    QueryParams(params): QueryParams<ProfileParams>,
+) -> Result<impl IntoResponse, Problem> {
+    let seconds = params.seconds.unwrap_or(30).clamp(1, 300);
+    let frequency = params.frequency.unwrap_or(100).clamp(1, 1000);
+
+    let profile = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        use pprof::protos::Message;
+
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(frequency)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        std::thread::sleep(Duration::from_secs(seconds));
+
+        let profile = guard
+            .report()
+            .build()
+            .and_then(|report| report.pprof())
+            .map_err(|e| e.to_string())?;
+
+        Ok(profile.encode_to_vec())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .map_err(|e| {
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR).detail(format!("Profiler error: {}", e))
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"profile.pb\"",
+            ),
+        ],
+        profile,
+    ))
+}
+
+// Panics on purpose, to check end to end that panics answer a 500 problem and
+// reach error reporting.
+pub async fn panic() {
+    panic!("panic requested on /debug/panic");
+}
@@ -1,23 +1,54 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
+    extract::{FromRef, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
 };
 use chrono::NaiveDateTime;
-use serde::Serialize;
-use serde_json::json;
-use sqlx::SqlitePool;
+use serde::{Deserialize, Serialize};
 
+use crate::body::JsonBody;
+use crate::config::NextTodoWeights;
+use crate::envelope::Success;
+use crate::error::{row_not_found, ApiError};
+use crate::params::{PathParams, QueryParams};
+use crate::repository::{DynTodoRepository, Resolution};
+use crate::state::AppState;
 use crate::todo::{CreateTodo, Todo, UpdateTodo};
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, utoipa::ToSchema)]
 pub struct TodoResponse {
     pub id: i64,
     pub body: String,
     pub completed: bool,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub version: i64,
+    #[serde(rename = "_links")]
+    pub links: TodoLinks,
+}
+
+// A HAL link (draft-kelly-json-hal).
+#[derive(Serialize, Clone, utoipa::ToSchema)]
+pub struct Link {
+    #[schema(example = "/v1/todos/1")]
+    pub href: String,
+}
+
+impl Link {
+    fn new(href: String) -> Self {
+        Link { href }
+    }
+}
+
+// Where a todo, and what relates to it, can be found.
+#[derive(Serialize, Clone, utoipa::ToSchema)]
+pub struct TodoLinks {
+    #[serde(rename = "self")]
+    pub self_: Link,
+    // Every todo.
+    pub collection: Link,
+    // The todo's collaborative document.
+    pub document: Link,
 }
 
 // Convert DB Model to Response
@@ -28,163 +59,561 @@ fn to_todo_response(todo: &Todo) -> TodoResponse {
         completed: todo.completed.to_owned(),
         created_at: todo.created_at.to_owned(),
         updated_at: todo.updated_at.to_owned(),
+        version: todo.version,
+        links: TodoLinks {
+            self_: Link::new(format!("/v1/todos/{}", todo.id)),
+            collection: Link::new("/v1/todos".to_string()),
+            document: Link::new(format!("/v1/todos/{}/document", todo.id)),
+        },
     }
 }
 
-pub async fn ping(
-    State(dbpool): State<SqlitePool>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    use sqlx::Connection;
-
-    let mut conn = dbpool.acquire().await.map_err(|e| {
-        let error_response = serde_json::json!({
-            "status": "error",
-            "message": format!("Pool acquire error: { }", e),
-        });
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?;
-
-    conn.ping().await.map_err(|e| {
-        let error_response = serde_json::json!({
-            "status": "error",
-            "message": format!("Database error: { }", e),
-        });
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?;
-
-    let json_response = serde_json::json!({
-        "status": "healthy",
-    });
+// Todos listed at most per page.
+const MAX_LIMIT: usize = 1000;
 
-    Ok(Json(json_response))
+// The size of the whole list on each of its pages.
+const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct ListParams {
+    // Todos skipped, in id order; 0 when absent.
+    offset: Option<usize>,
+    // Pages the list when set.
+    limit: Option<usize>,
+    // Related resources to embed, comma separated.
+    include: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ReadParams {
+    include: Option<String>,
 }
 
+// The related resources `include` can embed in a todo. There are none yet:
+// tags, comments and subtasks aren't stored, so naming any is refused rather
+// than silently answered without it.
+pub const RELATIONS: [&str; 0] = [];
+
+// The relations of `?include=a,b`, each one known.
+fn includes(include: Option<&str>) -> Result<Vec<&'static str>, ApiError> {
+    include
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|relation| !relation.is_empty())
+        .map(|relation| {
+            RELATIONS
+                .into_iter()
+                .find(|known| *known == relation)
+                .ok_or_else(|| ApiError::UnknownRelation(relation.to_string()))
+        })
+        .collect()
+}
+
+// The data of a todo's responses.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct TodoData {
+    pub todo: TodoResponse,
+}
+
+// The data of the list of todos: a page of it when paged.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct TodoListData {
+    pub count: usize,
+    pub todos: Vec<TodoResponse>,
+}
+
+// `first`, `next` and `prev` only when the list is paged with `limit`.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ListLinks {
+    #[serde(rename = "self")]
+    pub self_: Link,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<Link>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Link>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<Link>,
+}
+
+// The list's own link, and the neighbouring pages when it is paged.
+fn list_links(offset: usize, limit: Option<usize>, total: usize) -> ListLinks {
+    let page = |offset: usize, limit: usize| {
+        Link::new(format!("/v1/todos?offset={}&limit={}", offset, limit))
+    };
+
+    let Some(limit) = limit else {
+        return ListLinks {
+            self_: Link::new("/v1/todos".to_string()),
+            first: None,
+            next: None,
+            prev: None,
+        };
+    };
+    ListLinks {
+        self_: page(offset, limit),
+        first: Some(page(0, limit)),
+        next: (offset.saturating_add(limit) < total).then(|| page(offset + limit, limit)),
+        prev: (offset > 0).then(|| page(offset.saturating_sub(limit), limit)),
+    }
+}
+
+// Strong entity tag of a todo: its version.
+fn etag(todo: &Todo) -> String {
+    format!("\"{}\"", todo.version)
+}
+
+// Whether an If-Match header value, `*` or a list of entity tags, matches the
+// todo. Weak tags never match, as RFC 9110 requires for If-Match.
+fn if_match(value: &str, todo: &Todo) -> bool {
+    let etag = etag(todo);
+
+    value.trim() == "*" || value.split(',').any(|candidate| candidate.trim() == etag)
+}
+
+// The version of the first strong entity tag of an If-Match header value that
+// names one: the revision the client last saw.
+fn if_match_version(value: &str) -> Option<i64> {
+    value.split(',').find_map(|candidate| {
+        let candidate = candidate.trim().strip_prefix('"')?.strip_suffix('"')?;
+        candidate.parse().ok()
+    })
+}
+
+// Tells the client its write conflicted with another and how that was
+// resolved, by CONFLICT_POLICY.
+fn with_resolution(mut response: Response, resolution: Option<Resolution>) -> Response {
+    if let Some(resolution) = resolution {
+        response.headers_mut().insert(
+            HeaderName::from_static("conflict-resolution"),
+            HeaderValue::from_static(resolution.as_str()),
+        );
+    }
+    response
+}
+
+// Entity tag of a page of todos out of `total`, changing whenever one is
+// created, updated or deleted.
+fn list_etag(todos: &[Todo], total: usize) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    for todo in todos {
+        (todo.id, todo.version).hash(&mut hasher);
+    }
+
+    // The total is part of it: the links change with it, even when the page
+    // doesn't.
+    format!("\"{}-{:016x}\"", total, hasher.finish())
+}
+
+fn http_date(time: NaiveDateTime) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Whether a conditional GET can be answered 304 Not Modified: If-None-Match
+// lists the current entity tag (compared weakly), or, when it is absent,
+// If-Modified-Since is no earlier than `last_modified`.
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<NaiveDateTime>) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+    if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+        let value = value.to_str().unwrap_or_default();
+        return value.trim() == "*" || value.split(',').any(|tag| opaque(tag) == opaque(etag));
+    }
+
+    let if_modified_since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+
+    match (if_modified_since, last_modified) {
+        // HTTP dates have whole seconds.
+        (Some(since), Some(modified)) => modified.and_utc().timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+// Lists carry an ETag but no Last-Modified: deleting a todo changes the list
+// without making anything in it newer.
+#[utoipa::path(
+    get,
+    path = "/v1/todos",
+    tag = "todos",
+    params(
+        ListParams,
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a list already fetched"),
+    ),
+    responses(
+        (status = 200, description = "Every todo", body = TodoList,
+            headers(("ETag" = String), ("X-Total-Count" = u64, description = "Todos in the whole list, whatever the page"),
+                ("Age" = Option<u64>, description = "Seconds since the list was read from the database, when cached"))),
+        (status = 304, description = "The list is unchanged"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
 pub async fn todo_list(
-    State(dbpool): State<SqlitePool>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let query_list_todos = Todo::list(dbpool).await.map_err(|e| {
-        let error_response = serde_json::json!({
-            "status": "error",
-            "message": format!("Database error: { }", e),
-        });
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?;
+    State(todos): State<DynTodoRepository>,
+    QueryParams(params): QueryParams<ListParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if params
+        .limit
+        .is_some_and(|limit| !(1..=MAX_LIMIT).contains(&limit))
+    {
+        return Err(ApiError::LimitOutOfRange(MAX_LIMIT));
+    }
+    includes(params.include.as_deref())?;
+
+    let offset = params.offset.unwrap_or(0);
+    let (query_list_todos, age) = todos.page(offset, params.limit).await?;
+    let total = todos.count().await? as usize;
+
+    let etag = list_etag(&query_list_todos, total);
+
+    let mut validators = HeaderMap::new();
+    validators.insert(header::ETAG, etag.parse().unwrap());
+    // Served from the read cache: how old the list is, per RFC 9111.
+    if let Some(age) = age {
+        validators.insert(header::AGE, age.as_secs().into());
+    }
+
+    if not_modified(&headers, &etag, None) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+    validators.insert(X_TOTAL_COUNT, total.into());
 
     let todo_responses = query_list_todos
         .iter()
-        .map(|todo| to_todo_response(&todo))
+        .map(to_todo_response)
         .collect::<Vec<TodoResponse>>();
 
-    let json_response = serde_json::json!({
-        "status": "ok",
-        "count": todo_responses.len(),
-        "notes": todo_responses
-    });
+    let list = Success::new(TodoListData {
+        count: todo_responses.len(),
+        todos: todo_responses,
+    })
+    .links(list_links(offset, params.limit, total));
+
+    Ok((validators, list).into_response())
+}
+
+// The data of the count of todos.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct CountData {
+    pub count: i64,
+}
+
+// Todos have no filters yet, so it's the count of the whole list, taken
+// without reading it.
+#[utoipa::path(
+    get,
+    path = "/v1/todos/count",
+    tag = "todos",
+    responses(
+        (status = 200, description = "How many todos there are", body = TodoCount),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
+pub async fn todo_count(
+    State(todos): State<DynTodoRepository>,
+) -> Result<Success<CountData>, ApiError> {
+    let count = todos.count().await?;
+
+    Ok(Success::new(CountData { count }))
+}
+
+impl FromRef<AppState> for NextTodoWeights {
+    fn from_ref(state: &AppState) -> NextTodoWeights {
+        state.config.next_todo_weights
+    }
+}
+
+// The data of the todo to do next: none when every todo is completed.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct NextTodoData {
+    pub todo: Option<TodoResponse>,
+    // How it scored by NEXT_TODO_WEIGHTS.
+    pub score: Option<f64>,
+}
+
+// The open todo scoring highest by NEXT_TODO_WEIGHTS, the oldest first on
+// equal scores: the one thing to do now.
+#[utoipa::path(
+    get,
+    path = "/v1/todos/next",
+    tag = "todos",
+    responses(
+        (status = 200, description = "The todo to do next, null when none is open", body = NextTodo),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
+pub async fn todo_next(
+    State(todos): State<DynTodoRepository>,
+    State(weights): State<NextTodoWeights>,
+) -> Result<Success<NextTodoData>, ApiError> {
+    let next = todos.next(weights).await?;
 
-    Ok(Json(json_response))
+    Ok(Success::new(NextTodoData {
+        score: next.as_ref().map(|(_, score)| *score),
+        todo: next.map(|(todo, _)| to_todo_response(&todo)),
+    }))
 }
 
+// Suggestions returned at most, and by default.
+const MAX_SUGGESTIONS: usize = 50;
+const DEFAULT_SUGGESTIONS: usize = 10;
+
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct SuggestParams {
+    // What was typed so far: suggestions start with it, ignoring case.
+    q: String,
+    // Suggestions returned; 10 when absent.
+    limit: Option<usize>,
+}
+
+// A body todos start with, for typeahead.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SuggestionData {
+    pub body: String,
+    // How many todos have it.
+    pub count: i64,
+    // When the latest of them was last updated.
+    pub last_used_at: NaiveDateTime,
+}
+
+// The data of the suggestions for a prefix, best first.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SuggestionsData {
+    pub suggestions: Vec<SuggestionData>,
+}
+
+// The distinct bodies starting with `q`, the most frequent first, then the
+// most recently used, for typeahead in UIs. Todos have no tags yet, so bodies
+// are all there is to suggest.
+#[utoipa::path(
+    get,
+    path = "/v1/todos/suggest",
+    tag = "todos",
+    params(SuggestParams),
+    responses(
+        (status = 200, description = "The bodies starting with q, best first", body = TodoSuggestions),
+        (status = 400, description = "Missing q or limit out of range", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
+pub async fn todo_suggest(
+    State(todos): State<DynTodoRepository>,
+    QueryParams(params): QueryParams<SuggestParams>,
+) -> Result<Success<SuggestionsData>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_SUGGESTIONS);
+    if !(1..=MAX_SUGGESTIONS).contains(&limit) {
+        return Err(ApiError::LimitOutOfRange(MAX_SUGGESTIONS));
+    }
+
+    let suggestions = todos.suggest(&params.q, limit as i64).await?;
+
+    Ok(Success::new(SuggestionsData {
+        suggestions: suggestions
+            .into_iter()
+            .map(|suggestion| SuggestionData {
+                body: suggestion.body,
+                count: suggestion.count,
+                last_used_at: suggestion.last_used_at,
+            })
+            .collect(),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("include" = Option<String>, Query, description = "Related resources to embed, comma separated; todos have none yet"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of the todo already fetched"),
+        ("If-Modified-Since" = Option<String>, Header, description = "HTTP date of the todo already fetched"),
+    ),
+    responses(
+        (status = 200, description = "The todo", body = TodoEnvelope, headers(("ETag" = String), ("Last-Modified" = String))),
+        (status = 304, description = "The todo is unchanged"),
+        (status = 404, description = "No such todo", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
 pub async fn todo_read(
-    State(dbpool): State<SqlitePool>,
-    Path(id): Path<i64>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let query_todo = Todo::read(dbpool, id).await;
-
-    match query_todo {
-        Ok(todo) => {
-            let todo_response = serde_json::json!({
-                "status": "success",
-                "data": serde_json::json!({
-                    "todo": to_todo_response(&todo)
-                })
-            });
-
-            return Ok(Json(todo_response));
-        }
-        Err(sqlx::Error::RowNotFound) => {
-            let error_response = serde_json::json!({
-                "status": "fail",
-                "message": format!("todo with ID: {} not found", id)
-            });
-            return Err((StatusCode::NOT_FOUND, Json(error_response)));
-        }
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"status": "error","message": format!("{:?}", e)})),
-            ));
-        }
-    };
+    State(todos): State<DynTodoRepository>,
+    PathParams(id): PathParams<i64>,
+    QueryParams(params): QueryParams<ReadParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    includes(params.include.as_deref())?;
+    let todo = todos
+        .read(id)
+        .await
+        .map_err(row_not_found(ApiError::NotFound(id)))?;
+
+    let validators = [
+        (header::ETAG, etag(&todo)),
+        (header::LAST_MODIFIED, http_date(todo.updated_at)),
+    ];
+
+    if not_modified(&headers, &validators[0].1, Some(todo.updated_at)) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+
+    let todo_response = Success::new(TodoData {
+        todo: to_todo_response(&todo),
+    });
+
+    Ok((validators, todo_response).into_response())
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/todos",
+    tag = "todos",
+    request_body = CreateTodo,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Makes retries replay the first response instead of creating another todo")),
+    responses(
+        (status = 201, description = "The created todo", body = TodoEnvelope, headers(("ETag" = String), ("Location" = String, description = "Where the todo is served"))),
+        (status = 409, description = "A request with the same Idempotency-Key is in progress", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 422, description = "The body is invalid, or the Idempotency-Key was used with another body", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
 pub async fn todo_create(
-    State(dbpool): State<SqlitePool>,
-    Json(new_todo): Json<CreateTodo>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let create_todo = Todo::create(dbpool, new_todo).await;
-
-    match create_todo {
-        Ok(todo) => {
-            let todo_response = serde_json::json!({
-                "status": "success",
-                "data": serde_json::json!({
-                    "todo": to_todo_response(&todo)
-                })
-            });
-
-            return Ok(Json(todo_response));
-        }
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"status": "error","message": format!("{:?}", e)})),
-            ));
-        }
-    };
+    State(todos): State<DynTodoRepository>,
+    JsonBody(new_todo): JsonBody<CreateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let todo = todos.create(new_todo).await?;
+
+    let todo_response = Success::new(TodoData {
+        todo: to_todo_response(&todo),
+    });
+
+    let headers = [
+        (header::ETAG, etag(&todo)),
+        (header::LOCATION, format!("/v1/todos/{}", todo.id)),
+    ];
+    Ok((StatusCode::CREATED, headers, todo_response))
 }
 
+// Updates require If-Match with the todo's current ETag, so a client can't
+// overwrite changes it hasn't seen: 428 without it, 412 when it's stale.
+// Unless CONFLICT_POLICY resolves the conflict, in which case the response
+// says how in Conflict-Resolution.
+#[utoipa::path(
+    put,
+    path = "/v1/todos/{id}",
+    tag = "todos",
+    request_body = UpdateTodo,
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("If-Match" = String, Header, description = "Current ETag of the todo, or `*`"),
+    ),
+    responses(
+        (status = 200, description = "The updated todo", body = TodoEnvelope, headers(("ETag" = String), ("Conflict-Resolution" = Option<String>))),
+        (status = 404, description = "No such todo", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 412, description = "The todo changed since the ETag was read", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 422, description = "The body is invalid", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 428, description = "If-Match is missing", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
 pub async fn todo_update(
-    State(dbpool): State<SqlitePool>,
-    Path(id): Path<i64>,
-    Json(updated_todo): Json<UpdateTodo>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let update_todo = Todo::update(dbpool, id, updated_todo).await;
-
-    match update_todo {
-        Ok(todo) => {
-            let todo_response = serde_json::json!({
-                "status": "success",
-                "data": serde_json::json!({
-                    "todo": to_todo_response(&todo)
-                })
-            });
-
-            return Ok(Json(todo_response));
-        }
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"status": "error","message": format!("{:?}", e)})),
-            ));
+    State(todos): State<DynTodoRepository>,
+    PathParams(id): PathParams<i64>,
+    headers: HeaderMap,
+    JsonBody(updated_todo): JsonBody<UpdateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(if_match_value) = headers.get(header::IF_MATCH) else {
+        return Err(ApiError::PreconditionRequired);
+    };
+
+    let current = todos
+        .read(id)
+        .await
+        .map_err(row_not_found(ApiError::NotFound(id)))?;
+
+    let if_match_value = if_match_value.to_str().unwrap_or_default();
+    let version = if if_match(if_match_value, &current) {
+        current.version
+    } else {
+        // A stale revision, left to the repository to resolve.
+        match if_match_version(if_match_value) {
+            Some(version) => version,
+            None => return Err(ApiError::Modified(id)),
         }
     };
+
+    let (todo, resolution) = todos
+        .update_resolving(id, updated_todo, version)
+        .await
+        // Changed or deleted since it was read.
+        .map_err(row_not_found(ApiError::Modified(id)))?;
+
+    let todo_response = Success::new(TodoData {
+        todo: to_todo_response(&todo),
+    });
+
+    let response = ([(header::ETAG, etag(&todo))], todo_response).into_response();
+    Ok(with_resolution(response, resolution))
 }
 
+// With If-Match, only deletes the revision the client last saw: 412 when the
+// todo changed, unless CONFLICT_POLICY resolves it, or was deleted since.
+// Without it, deleting is unconditional. 404 when there is no such todo.
+#[utoipa::path(
+    delete,
+    path = "/v1/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("If-Match" = Option<String>, Header, description = "Only delete this revision of the todo"),
+    ),
+    responses(
+        (status = 204, description = "Deleted", headers(("Conflict-Resolution" = Option<String>))),
+        (status = 404, description = "No such todo", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 412, description = "The todo changed or was deleted since the ETag was read", body = ErrorBody, content_type = "application/problem+json"),
+        (status = 500, description = "Database error", body = ErrorBody, content_type = "application/problem+json"),
+    )
+)]
 pub async fn todo_delete(
-    State(dbpool): State<SqlitePool>,
-    Path(id): Path<i64>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let delete_todo = Todo::delete(dbpool, id).await;
-
-    match delete_todo {
-        Ok(_) => {
-            return Ok(StatusCode::NO_CONTENT);
-        }
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"status": "error","message": format!("{:?}", e)})),
-            ));
+    State(todos): State<DynTodoRepository>,
+    PathParams(id): PathParams<i64>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let version = match headers.get(header::IF_MATCH) {
+        Some(if_match_value) => {
+            let current = todos
+                .read(id)
+                .await
+                .map_err(row_not_found(ApiError::NotFound(id)))?;
+
+            let if_match_value = if_match_value.to_str().unwrap_or_default();
+            if if_match(if_match_value, &current) {
+                Some(current.version)
+            } else {
+                match if_match_version(if_match_value) {
+                    Some(version) => Some(version),
+                    None => return Err(ApiError::ModifiedOrDeleted(id)),
+                }
+            }
         }
+        None => None,
     };
+
+    let (deleted, resolution) = todos
+        .delete_resolving(id, version)
+        .await
+        .map_err(row_not_found(ApiError::ModifiedOrDeleted(id)))?;
+    if deleted == 0 {
+        return Err(ApiError::NotFound(id));
+    }
+
+    Ok(with_resolution(
+        StatusCode::NO_CONTENT.into_response(),
+        resolution,
+    ))
 }
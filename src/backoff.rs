@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+// Exponential backoff with jitter, so concurrent retries spread out instead of
+// colliding again: `base` doubled for every attempt after the first, up to
+// `max`, then a random delay between half and all of that. Attempts count
+// from 1.
+pub fn delay(base: Duration, max: Duration, attempt: u32) -> Duration {
+    let delay = base
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(max);
+
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_max_with_jitter() {
+        let (base, max) = (Duration::from_secs(10), Duration::from_secs(60));
+
+        for (attempt, full) in [(1, 10), (2, 20), (3, 40), (4, 60), (30, 60)] {
+            let full = Duration::from_secs(full);
+            for _ in 0..100 {
+                let delay = delay(base, max, attempt);
+                assert!(full / 2 <= delay && delay <= full, "{attempt}: {delay:?}");
+            }
+        }
+    }
+}
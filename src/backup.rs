@@ -0,0 +1,109 @@
+use std::{path::Path, str::FromStr};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use sqlx::ConnectOptions;
+
+use crate::{
+    config::Config,
+    db::{self, Backend, DbConnectOptions, DbPool},
+    problem::Problem,
+};
+
+// Writes a consistent copy of the SQLite database to `path` with VACUUM INTO,
+// which runs inside a read transaction: the server keeps serving reads and
+// writes while it copies. Fails if `path` already exists.
+pub async fn vacuum_into<'e, E>(executor: E, path: &Path) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Any>,
+{
+    sqlx::query("vacuum into $1")
+        .bind(path.to_string_lossy().into_owned())
+        .execute(executor)
+        .await
+        .map(|_| ())
+}
+
+// Admin route: backs the database up to a temporary file and streams it.
+pub async fn download(State(dbpool): State<Option<DbPool>>) -> Result<impl IntoResponse, Problem> {
+    let dbpool = dbpool
+        .filter(|dbpool| Backend::of(dbpool) == Backend::Sqlite)
+        .ok_or_else(|| {
+            Problem::new(StatusCode::CONFLICT)
+                .detail("backups are only supported for SQLite storage")
+        })?;
+
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let path = std::env::temp_dir().join(format!(
+        "api-service-backup-{}-{}.sqlite",
+        std::process::id(),
+        timestamp
+    ));
+
+    let file = async {
+        vacuum_into(&dbpool, &path).await?;
+        let file = tokio::fs::File::open(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok::<_, sqlx::Error>((file, size))
+    }
+    .await;
+
+    // The open handle keeps the contents readable until the download ends.
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let (file, size) = file.map_err(|e| {
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR).detail(format!("Backup error: {}", e))
+    })?;
+
+    let body = Body::from_stream(tokio_util::io::ReaderStream::new(file));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"todos-{}.sqlite\"", timestamp),
+            ),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ],
+        body,
+    ))
+}
+
+// `api-service backup <path>`: backs up the database at DATABASE_URL to a new
+// file. Returns false on failure, after printing the error.
+pub async fn run(path: &str) -> bool {
+    let result = async {
+        let config = Config::try_from_env().map_err(|errors| errors.join("; "))?;
+
+        if Backend::from_url(&config.database_url)? != Backend::Sqlite {
+            return Err("backups are only supported for SQLite databases".to_string());
+        }
+
+        let mut conn = DbConnectOptions::from_str(&db::driver_url(&config.database_url))
+            .map_err(|e| e.to_string())?
+            .connect()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        vacuum_into(&mut conn, Path::new(path))
+            .await
+            .map_err(|e| e.to_string())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            println!("backed up to {}", path);
+            true
+        }
+        Err(e) => {
+            eprintln!("backup failed: {}", e);
+            false
+        }
+    }
+}
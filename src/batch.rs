@@ -0,0 +1,276 @@
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{FromRef, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    Extension, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tower::ServiceExt;
+
+use crate::{
+    body::JsonBody,
+    config::Constraints,
+    envelope::Success,
+    error::ApiError,
+    i18n::{self, Message},
+    problem::{self, Problem},
+    state::AppState,
+    validation::{Validate, Violations},
+};
+
+// The most requests a batch holds.
+pub const MAX_REQUESTS: usize = 100;
+
+// The largest sub-response body read back into the batch's.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+// The router the sub-requests of batches are served by, the one `/batch`
+// is a route of, set once it's built.
+#[derive(Clone, Default)]
+pub struct Batch(Arc<OnceLock<Router>>);
+
+impl Batch {
+    pub fn serve(&self, routes: Router) {
+        let _ = self.0.set(routes);
+    }
+}
+
+impl FromRef<AppState> for Batch {
+    fn from_ref(state: &AppState) -> Batch {
+        state.batch.clone()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    #[serde(default)]
+    mode: Mode,
+    requests: Vec<SubRequest>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    // One after the other, in order, so later requests see earlier writes.
+    #[default]
+    Sequential,
+    // All at once, for independent requests.
+    Parallel,
+}
+
+#[derive(Deserialize)]
+struct SubRequest {
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: Map<String, Value>,
+    body: Option<Value>,
+}
+
+impl Validate for BatchRequest {
+    fn validate(&self, _constraints: &Constraints, violations: &mut Violations) {
+        violations.field(
+            "requests",
+            &self.requests,
+            &[&|requests: &Vec<SubRequest>| {
+                (requests.is_empty() || requests.len() > MAX_REQUESTS).then(|| {
+                    Message::new("field-count")
+                        .arg("min", 1)
+                        .arg("max", MAX_REQUESTS)
+                })
+            }],
+        );
+    }
+}
+
+// Marks the sub-requests of a batch, which can't be batches themselves.
+#[derive(Clone)]
+pub struct InBatch;
+
+#[derive(Serialize)]
+pub struct BatchData {
+    responses: Vec<Value>,
+}
+
+// POST /batch: serves each of the requests, as JSON, and answers 200 with
+// their statuses, headers and bodies in the same order, whatever they are.
+// The headers of the batch request, like Authorization or Accept-Language,
+// are those of every sub-request unless it sets its own.
+pub async fn run(
+    State(routes): State<Batch>,
+    in_batch: Option<Extension<InBatch>>,
+    shared: HeaderMap,
+    JsonBody(batch): JsonBody<BatchRequest>,
+) -> Result<Success<BatchData>, ApiError> {
+    if in_batch.is_some() {
+        return Err(ApiError::NestedBatch);
+    }
+    let routes = routes
+        .0
+        .get()
+        .cloned()
+        .expect("the router of batches is set");
+
+    // Sub-requests share the batch's Accept-Language, so the problems made
+    // up for them here are in its language too.
+    let language = i18n::language(&shared);
+
+    let mut responses = Vec::with_capacity(batch.requests.len());
+    match batch.mode {
+        Mode::Sequential => {
+            for sub_request in batch.requests {
+                let path = sub_request.path.clone();
+                let request = build(&shared, sub_request);
+                responses.push(read(routes.clone(), path, request, language).await);
+            }
+        }
+        Mode::Parallel => {
+            let tasks: Vec<_> = batch
+                .requests
+                .into_iter()
+                .map(|sub_request| {
+                    let path = sub_request.path.clone();
+                    let request = build(&shared, sub_request);
+                    let task = tokio::spawn(read(routes.clone(), path.clone(), request, language));
+                    (path, task)
+                })
+                .collect();
+            for (path, task) in tasks {
+                let response = task.await.unwrap_or_else(|_| {
+                    problem(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &path,
+                        language,
+                        Message::new("internal-error"),
+                    )
+                });
+                responses.push(response);
+            }
+        }
+    }
+
+    Ok(Success::new(BatchData { responses }))
+}
+
+// The sub-request, or why it can't be made.
+fn build(shared: &HeaderMap, sub_request: SubRequest) -> Result<Request, Message> {
+    let method = Method::from_bytes(sub_request.method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| Message::new("batch-method").arg("method", &sub_request.method))?;
+    if !sub_request.path.starts_with('/') {
+        return Err(Message::new("batch-path").arg("path", &sub_request.path));
+    }
+
+    // Those of the batch's own body and of a single request aren't shared.
+    let mut headers = shared.clone();
+    for name in [
+        header::CONTENT_TYPE,
+        header::CONTENT_LENGTH,
+        header::ACCEPT,
+        header::IF_MATCH,
+        header::IF_NONE_MATCH,
+        HeaderName::from_static("idempotency-key"),
+    ] {
+        headers.remove(name);
+    }
+    headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+    for (name, value) in &sub_request.headers {
+        let (Ok(name), Some(Ok(value))) = (
+            HeaderName::try_from(name.as_str()),
+            value.as_str().map(HeaderValue::try_from),
+        ) else {
+            return Err(Message::new("batch-header").arg("name", name));
+        };
+        headers.insert(name, value);
+    }
+
+    let body = match &sub_request.body {
+        Some(body) => {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(sub_request.path.as_str())
+        .body(body)
+        .map_err(|e| Message::new("batch-request").arg("error", e))?;
+    *request.headers_mut() = headers;
+    request.extensions_mut().insert(InBatch);
+    Ok(request)
+}
+
+async fn read(
+    routes: Router,
+    path: String,
+    request: Result<Request, Message>,
+    language: &'static str,
+) -> Value {
+    let request = match request {
+        Ok(request) => request,
+        Err(message) => return problem(StatusCode::BAD_REQUEST, &path, language, message),
+    };
+    let Ok(response) = routes.oneshot(request).await;
+    let (parts, body) = response.into_parts();
+    // Streams, like the todo events, never end, so never make it into the
+    // batch's response.
+    if parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"))
+    {
+        let message = Message::new("batch-stream").arg("path", &path);
+        return problem(StatusCode::BAD_REQUEST, &path, language, message);
+    }
+
+    let headers: Map<String, Value> = parts
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            [
+                header::ETAG,
+                header::LOCATION,
+                header::LAST_MODIFIED,
+                header::CONTENT_TYPE,
+            ]
+            .contains(name)
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+        .collect();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) if bytes.is_empty() => Value::Null,
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned().into()),
+        Err(_) => {
+            return problem(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &path,
+                language,
+                Message::new("internal-error"),
+            );
+        }
+    };
+
+    json!({"status": parts.status.as_u16(), "headers": headers, "body": body})
+}
+
+// A sub-request that couldn't be made, or whose response couldn't be read,
+// answered with the problem a request made on its own would get.
+fn problem(status: StatusCode, path: &str, language: &str, message: Message) -> Value {
+    let mut problem = Problem::new(status).detail(message.render(language));
+    // Its path, like `negotiate` sets it.
+    problem.instance = path.split('?').next().map(ToOwned::to_owned);
+
+    json!({
+        "status": status.as_u16(),
+        "headers": {"content-type": problem::MEDIA_TYPE},
+        "body": problem.to_value(),
+    })
+}
@@ -0,0 +1,105 @@
+use axum::{
+    async_trait,
+    extract::{rejection::JsonRejection, FromRef, FromRequest, Request},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    config::Constraints,
+    error::ApiError,
+    problem::Problem,
+    state::AppState,
+    validation::{self, Validate},
+};
+
+// Whether request bodies with fields their type doesn't have are refused,
+// per STRICT_JSON, instead of those fields being ignored.
+#[derive(Clone, Copy)]
+pub struct StrictJson(pub bool);
+
+impl FromRef<AppState> for StrictJson {
+    fn from_ref(state: &AppState) -> StrictJson {
+        StrictJson(state.config.strict_json)
+    }
+}
+
+// A JSON request body, like `Json`, refused with 400 listing the fields `T`
+// doesn't have when STRICT_JSON is set, so a typo like `complted` isn't
+// silently ignored, and with 422 when it breaks the rules of `T`.
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    StrictJson: FromRef<S>,
+    Constraints: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let body = if StrictJson::from_ref(state).0 {
+            strict(request, state).await?
+        } else {
+            let Json(body) = Json::<T>::from_request(request, state)
+                .await
+                .map_err(rejected)?;
+            body
+        };
+        validation::validate(&body, &Constraints::from_ref(state))
+            .map_err(|violations| ApiError::InvalidFields(violations).into_response())?;
+
+        Ok(JsonBody(body))
+    }
+}
+
+async fn strict<T, S>(request: Request, state: &S) -> Result<T, Response>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    let Json(value) = Json::<Value>::from_request(request, state)
+        .await
+        .map_err(rejected)?;
+    let mut unknown = Vec::new();
+    let body = serde_ignored::deserialize(value, |path| unknown.push(path.to_string()))
+        .map_err(|e| ApiError::InvalidBody(e.to_string()).into_response())?;
+    if !unknown.is_empty() {
+        return Err(ApiError::UnknownFields(unknown).into_response());
+    }
+
+    Ok(body)
+}
+
+// A JSON request body of a type without rules, like `JsonBody` without the
+// validation, refused with a problem where `Json` answers plain text.
+pub struct PlainJsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for PlainJsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(rejected)?;
+
+        Ok(PlainJsonBody(body))
+    }
+}
+
+// The rejections of `Json`, like a missing Content-Type or a syntax error, as
+// problems detailed with their reason.
+fn rejected(rejection: JsonRejection) -> Response {
+    Problem::new(rejection.status())
+        .detail(rejection.body_text())
+        .into_response()
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::envelope::Success;
+
+// Build metadata embedded at compile time by `build.rs`.
+#[derive(Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_timestamp: Option<DateTime<Utc>>,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("GIT_SHA"),
+            build_timestamp: env!("BUILD_TIMESTAMP")
+                .parse()
+                .ok()
+                .and_then(|epoch| DateTime::from_timestamp(epoch, 0)),
+            features: env!("BUILD_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
+
+pub async fn version() -> Success<BuildInfo> {
+    Success::new(BuildInfo::current())
+}
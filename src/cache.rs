@@ -0,0 +1,272 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ReadCacheConfig,
+    events::{TodoEvent, TodoEventHandler},
+    redis_store::RedisStore,
+    todo::Todo,
+};
+
+// Where `CachingTodoRepository` keeps todos it read. Every write bumps a
+// generation once committed, and a read only stores its result while the
+// generation is still the one it started with, so a row fetched before a
+// write can't be cached after it. Cache failures are logged under the
+// `cache` target and otherwise ignored: reads then go to the database.
+#[async_trait]
+pub trait TodoCache: Send + Sync {
+    // None when the cache can't tell, in which case nothing gets stored.
+    async fn generation(&self) -> Option<u64>;
+
+    async fn todo(&self, id: i64) -> Option<Todo>;
+
+    async fn list(&self) -> Option<CachedList>;
+
+    async fn put_todo(&self, generation: u64, todo: &Todo);
+
+    async fn put_list(&self, generation: u64, todos: &[Todo]);
+
+    // Bumps the generation and drops the list, and the todo when given.
+    async fn invalidate(&self, id: Option<i64>);
+}
+
+pub type DynTodoCache = Arc<dyn TodoCache>;
+
+// The todo list is kept past the TTL for the stale window, so it carries its
+// age.
+#[derive(Serialize, Deserialize)]
+pub struct CachedList {
+    // Unix time in milliseconds, comparable between instances.
+    pub stored_at: i64,
+    pub todos: Vec<Todo>,
+}
+
+impl CachedList {
+    fn new(todos: &[Todo]) -> Self {
+        CachedList {
+            stored_at: chrono::Utc::now().timestamp_millis(),
+            todos: todos.to_vec(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        let age = chrono::Utc::now().timestamp_millis() - self.stored_at;
+        Duration::from_millis(age.max(0) as u64)
+    }
+}
+
+// Subscribed to the event bus: drops what each write made stale.
+pub struct CacheInvalidation(pub DynTodoCache);
+
+#[async_trait]
+impl TodoEventHandler for CacheInvalidation {
+    async fn handle(&self, event: &TodoEvent) {
+        match event {
+            // A new todo can't be cached yet, only the list it joins.
+            TodoEvent::Created(_) => self.0.invalidate(None).await,
+            TodoEvent::Updated(_) | TodoEvent::Deleted(_) => {
+                self.0.invalidate(Some(event.id())).await
+            }
+        }
+    }
+}
+
+// Process-local cache, evicting the least used todos past `max_entries`.
+pub struct MemoryTodoCache {
+    todos: moka::future::Cache<i64, Todo>,
+    list: moka::future::Cache<(), Arc<CachedList>>,
+    generation: AtomicU64,
+}
+
+impl MemoryTodoCache {
+    pub fn new(config: ReadCacheConfig) -> Self {
+        MemoryTodoCache {
+            todos: moka::future::Cache::builder()
+                .max_capacity(config.max_entries)
+                .time_to_live(config.ttl)
+                .build(),
+            list: moka::future::Cache::builder()
+                .max_capacity(1)
+                .time_to_live(config.ttl + config.stale)
+                .build(),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl TodoCache for MemoryTodoCache {
+    async fn generation(&self) -> Option<u64> {
+        Some(self.generation.load(Ordering::SeqCst))
+    }
+
+    async fn todo(&self, id: i64) -> Option<Todo> {
+        self.todos.get(&id).await
+    }
+
+    async fn list(&self) -> Option<CachedList> {
+        self.list.get(&()).await.map(|list| CachedList {
+            stored_at: list.stored_at,
+            todos: list.todos.clone(),
+        })
+    }
+
+    async fn put_todo(&self, generation: u64, todo: &Todo) {
+        if self.generation.load(Ordering::SeqCst) == generation {
+            self.todos.insert(todo.id, todo.clone()).await;
+        }
+    }
+
+    async fn put_list(&self, generation: u64, todos: &[Todo]) {
+        if self.generation.load(Ordering::SeqCst) == generation {
+            self.list.insert((), Arc::new(CachedList::new(todos))).await;
+        }
+    }
+
+    async fn invalidate(&self, id: Option<i64>) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(id) = id {
+            self.todos.invalidate(&id).await;
+        }
+        self.list.invalidate(&()).await;
+    }
+}
+
+// Sets KEYS[2] to ARGV[2] for ARGV[3] milliseconds, only while the generation
+// at KEYS[1] is still ARGV[1].
+static PUT_IF_GENERATION: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if (redis.call('GET', KEYS[1]) or '0') == ARGV[1] then
+            return redis.call('SET', KEYS[2], ARGV[2], 'PX', ARGV[3])
+        end
+        return false
+        ",
+    )
+});
+
+// Cache shared by every replica through Redis, so a write on one is seen by
+// the others straight away. Entries are JSON; Redis' own memory limits and
+// eviction policy bound their number.
+pub struct RedisTodoCache {
+    store: RedisStore,
+    ttl: Duration,
+    stale: Duration,
+}
+
+impl RedisTodoCache {
+    pub fn new(store: RedisStore, config: ReadCacheConfig) -> Self {
+        RedisTodoCache {
+            store,
+            ttl: config.ttl,
+            stale: config.stale,
+        }
+    }
+
+    fn todo_key(&self, id: i64) -> String {
+        self.store.key(&format!("todos:{}", id))
+    }
+
+    fn list_key(&self) -> String {
+        self.store.key("todos:list")
+    }
+
+    fn generation_key(&self) -> String {
+        self.store.key("todos:generation")
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, key: String) -> Option<T> {
+        let value: Option<String> = redis::cmd("GET")
+            .arg(&key)
+            .query_async(&mut self.store.connection())
+            .await
+            .map_err(|e| tracing::warn!(target: "cache", key, error = %e, "redis read failed"))
+            .ok()?;
+
+        serde_json::from_str(&value?)
+            .map_err(|e| tracing::warn!(target: "cache", key, error = %e, "invalid cached value"))
+            .ok()
+    }
+
+    async fn put<T: Serialize>(&self, generation: u64, key: String, value: &T, ttl: Duration) {
+        let value = serde_json::to_string(value).unwrap();
+
+        let result = PUT_IF_GENERATION
+            .key(self.generation_key())
+            .key(&key)
+            .arg(generation)
+            .arg(value)
+            .arg(ttl.as_millis() as u64)
+            .invoke_async::<_, ()>(&mut self.store.connection())
+            .await;
+
+        if let Err(e) = result {
+            tracing::warn!(target: "cache", key, error = %e, "redis write failed");
+        }
+    }
+}
+
+#[async_trait]
+impl TodoCache for RedisTodoCache {
+    async fn generation(&self) -> Option<u64> {
+        let generation: Option<u64> = redis::cmd("GET")
+            .arg(self.generation_key())
+            .query_async(&mut self.store.connection())
+            .await
+            .map_err(|e| tracing::warn!(target: "cache", error = %e, "redis read failed"))
+            .ok()?;
+
+        Some(generation.unwrap_or(0))
+    }
+
+    async fn todo(&self, id: i64) -> Option<Todo> {
+        self.get(self.todo_key(id)).await
+    }
+
+    async fn list(&self) -> Option<CachedList> {
+        self.get(self.list_key()).await
+    }
+
+    async fn put_todo(&self, generation: u64, todo: &Todo) {
+        self.put(generation, self.todo_key(todo.id), todo, self.ttl)
+            .await
+    }
+
+    async fn put_list(&self, generation: u64, todos: &[Todo]) {
+        let list = CachedList::new(todos);
+        self.put(generation, self.list_key(), &list, self.ttl + self.stale)
+            .await
+    }
+
+    async fn invalidate(&self, id: Option<i64>) {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .incr(self.generation_key(), 1)
+            .ignore()
+            .del(self.list_key())
+            .ignore();
+        if let Some(id) = id {
+            pipe.del(self.todo_key(id)).ignore();
+        }
+
+        if let Err(e) = pipe
+            .query_async::<_, ()>(&mut self.store.connection())
+            .await
+        {
+            tracing::warn!(
+                target: "cache",
+                error = %e,
+                "redis invalidation failed, cached todos may be stale until they expire"
+            );
+        }
+    }
+}
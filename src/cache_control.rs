@@ -0,0 +1,102 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+
+// A single `selector=value` directive. The selector is a method, a route as
+// declared in the router (`/v1/todos/:id`), both separated by a space, or `*`
+// for any request: `GET /v1/todos=max-age=5`, `POST=no-store`.
+#[derive(Debug)]
+struct CacheControlRule {
+    method: Option<Method>,
+    path: Option<String>,
+    value: HeaderValue,
+}
+
+impl CacheControlRule {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        // HEAD is answered by the GET handler, so it gets the same policy.
+        let method = if method == Method::HEAD {
+            &Method::GET
+        } else {
+            method
+        };
+
+        self.method.as_ref().is_none_or(|m| m == method)
+            && self.path.as_deref().is_none_or(|p| p == path)
+    }
+}
+
+// Cache-Control policies, parsed from CACHE_CONTROL. The first matching rule
+// sets the header on the response, unless the handler already did; requests
+// that match no rule get none.
+#[derive(Clone, Debug)]
+pub struct CacheControlRules(Arc<Vec<CacheControlRule>>);
+
+impl FromStr for CacheControlRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+
+        for directive in s.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let (selector, value) = directive
+                .split_once('=')
+                .ok_or_else(|| format!("missing Cache-Control value in '{}'", directive))?;
+
+            let value = HeaderValue::from_str(value.trim())
+                .map_err(|_| format!("invalid Cache-Control value in '{}'", directive))?;
+
+            let (method, path) = match selector.trim() {
+                "*" => (None, None),
+                path if path.starts_with('/') => (None, Some(path)),
+                selector => match selector.split_once(' ') {
+                    Some((method, path)) => (Some(method), Some(path.trim())),
+                    None => (Some(selector), None),
+                },
+            };
+
+            let method = method
+                .map(|method| Method::from_str(&method.to_uppercase()))
+                .transpose()
+                .map_err(|_| format!("invalid method in '{}'", directive))?;
+
+            rules.push(CacheControlRule {
+                method,
+                path: path.map(str::to_string),
+                value,
+            });
+        }
+
+        Ok(CacheControlRules(Arc::new(rules)))
+    }
+}
+
+pub async fn apply(
+    State(rules): State<CacheControlRules>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    let mut response = next.run(request).await;
+
+    if !response.headers().contains_key(header::CACHE_CONTROL) {
+        if let Some(rule) = rules.0.iter().find(|rule| rule.matches(&method, &path)) {
+            response
+                .headers_mut()
+                .insert(header::CACHE_CONTROL, rule.value.clone());
+        }
+    }
+
+    response
+}
@@ -0,0 +1,983 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use chrono::NaiveDateTime;
+use quick_xml::{
+    events::{BytesStart, Event},
+    name::ResolveResult,
+    NsReader,
+};
+use sha2::{Digest, Sha256};
+use sqlx::{Error, Row};
+
+use crate::{
+    db::{Backend, DbPool},
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, Todo, UpdateTodo},
+};
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+// The principal, which is also the calendar home, and the one calendar in it.
+const HOME: &str = "/caldav/";
+const CALENDAR: &str = "/caldav/todos/";
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, REPORT";
+
+// A todo a CalDAV client created, by the name it gave it in the calendar.
+#[derive(Clone)]
+pub struct ClientObject {
+    pub name: String,
+    pub todo_id: i64,
+    pub uid: String,
+}
+
+// Where the names and UIDs of the todos CalDAV clients created are kept.
+#[async_trait]
+pub trait CalDavStore: Send + Sync {
+    async fn get(&self, name: &str) -> Result<Option<ClientObject>, Error>;
+
+    async fn list(&self) -> Result<Vec<ClientObject>, Error>;
+
+    // Replaces the object with its name, if any.
+    async fn put(&self, object: &ClientObject) -> Result<(), Error>;
+
+    async fn delete(&self, name: &str) -> Result<(), Error>;
+}
+
+pub type DynCalDavStore = Arc<dyn CalDavStore>;
+
+// Objects in the `caldav_objects` table.
+pub struct SqlCalDavStore {
+    dbpool: DbPool,
+}
+
+impl SqlCalDavStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlCalDavStore { dbpool }
+    }
+}
+
+#[async_trait]
+impl CalDavStore for SqlCalDavStore {
+    async fn get(&self, name: &str) -> Result<Option<ClientObject>, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql("select name, todo_id, uid from caldav_objects where name = $1"))
+            .bind(name)
+            .fetch_optional(&self.dbpool)
+            .await?
+            .map(|row| {
+                Ok(ClientObject {
+                    name: row.try_get("name")?,
+                    todo_id: row.try_get("todo_id")?,
+                    uid: row.try_get("uid")?,
+                })
+            })
+            .transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<ClientObject>, Error> {
+        sqlx::query("select name, todo_id, uid from caldav_objects")
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(ClientObject {
+                    name: row.try_get("name")?,
+                    todo_id: row.try_get("todo_id")?,
+                    uid: row.try_get("uid")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn put(&self, object: &ClientObject) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+        let mut tx = self.dbpool.begin().await?;
+
+        sqlx::query(&backend.sql("delete from caldav_objects where name = $1"))
+            .bind(&object.name)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            &backend.sql("insert into caldav_objects (name, todo_id, uid) values ($1, $2, $3)"),
+        )
+        .bind(&object.name)
+        .bind(object.todo_id)
+        .bind(&object.uid)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql("delete from caldav_objects where name = $1"))
+            .bind(name)
+            .execute(&self.dbpool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Keeps objects in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryCalDavStore {
+    objects: Mutex<BTreeMap<String, ClientObject>>,
+}
+
+#[async_trait]
+impl CalDavStore for InMemoryCalDavStore {
+    async fn get(&self, name: &str) -> Result<Option<ClientObject>, Error> {
+        Ok(self.objects.lock().unwrap().get(name).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<ClientObject>, Error> {
+        Ok(self.objects.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn put(&self, object: &ClientObject) -> Result<(), Error> {
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(object.name.clone(), object.clone());
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), Error> {
+        self.objects.lock().unwrap().remove(name);
+
+        Ok(())
+    }
+}
+
+// A todo as a calendar object resource.
+struct Object {
+    name: String,
+    uid: String,
+    todo: Todo,
+}
+
+impl Object {
+    // Todos created elsewhere are named and identified after their id.
+    fn of(todo: Todo, client: Option<&ClientObject>) -> Self {
+        match client {
+            Some(client) => Object {
+                name: client.name.clone(),
+                uid: client.uid.clone(),
+                todo,
+            },
+            None => Object {
+                name: format!("todo-{}.ics", todo.id),
+                uid: format!("todo-{}@api-service", todo.id),
+                todo,
+            },
+        }
+    }
+
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.todo.version)
+    }
+}
+
+// The todos as a calendar of VTODOs, for CalDAV clients such as Apple
+// Reminders, Thunderbird and Tasks.org (through DAVx5). Only a todo's body
+// and whether it is completed are kept: other properties clients set, like
+// due dates or priorities, are dropped.
+#[derive(Clone)]
+pub struct CalDav {
+    pub todos: DynTodoRepository,
+    pub store: DynCalDavStore,
+    // Refuses writes while the schema is outdated, when set.
+    pub refuse_writes: Option<Lifecycle>,
+}
+
+impl CalDav {
+    async fn objects(&self) -> Result<Vec<Object>, Error> {
+        let clients: HashMap<i64, ClientObject> = self
+            .store
+            .list()
+            .await?
+            .into_iter()
+            .map(|client| (client.todo_id, client))
+            .collect();
+
+        Ok(self
+            .todos
+            .list()
+            .await?
+            .into_iter()
+            .map(|todo| {
+                let client = clients.get(&todo.id);
+                Object::of(todo, client)
+            })
+            .collect())
+    }
+
+    async fn object(&self, name: &str) -> Result<Option<Object>, Error> {
+        let (todo_id, client) = match self.store.get(name).await? {
+            Some(client) => (client.todo_id, Some(client)),
+            None => match own_todo_id(name) {
+                Some(todo_id) => (todo_id, None),
+                None => return Ok(None),
+            },
+        };
+
+        match self.todos.read(todo_id).await {
+            Ok(todo) => Ok(Some(Object::of(todo, client.as_ref()))),
+            // Deleted outside CalDAV.
+            Err(Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Changes whenever a todo is created, updated or deleted, so clients
+    // know when to look for changes.
+    fn ctag(objects: &[Object]) -> String {
+        let mut hasher = Sha256::new();
+        for object in objects {
+            hasher.update(format!("{}:{};", object.name, object.todo.version));
+        }
+
+        hasher.finalize()[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn writes_refused(&self) -> bool {
+        self.refuse_writes
+            .as_ref()
+            .is_some_and(|lifecycle| lifecycle.is_schema_outdated())
+    }
+}
+
+// The id of a todo by the name it has when not created by a client.
+fn own_todo_id(name: &str) -> Option<i64> {
+    name.strip_prefix("todo-")?
+        .strip_suffix(".ics")?
+        .parse()
+        .ok()
+}
+
+// Names clients give objects, usually a UUID, that need no escaping in URLs
+// or XML.
+fn valid_name(name: &str) -> bool {
+    name.len() <= 255
+        && name.ends_with(".ics")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.~@+".contains(c))
+}
+
+pub async fn well_known() -> Redirect {
+    Redirect::permanent(HOME)
+}
+
+// The principal and calendar home.
+pub async fn home(
+    State(caldav): State<CalDav>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    match method.as_str() {
+        "OPTIONS" => options(),
+        "PROPFIND" => {
+            let request = match DavRequest::parse(&body) {
+                Ok(request) => request,
+                Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+            };
+            let mut responses = vec![request.response(HOME, &Resource::Home)];
+            if depth(&headers) > 0 {
+                let objects = match caldav.objects().await {
+                    Ok(objects) => objects,
+                    Err(e) => return database_error(e),
+                };
+                let ctag = CalDav::ctag(&objects);
+                responses.push(request.response(CALENDAR, &Resource::Calendar(&ctag)));
+            }
+            multistatus(responses)
+        }
+        _ => method_not_allowed(),
+    }
+}
+
+// The calendar of todos.
+pub async fn calendar(
+    State(caldav): State<CalDav>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let request = match method.as_str() {
+        "OPTIONS" => return options(),
+        "PROPFIND" | "REPORT" => match DavRequest::parse(&body) {
+            Ok(request) => request,
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        _ => return method_not_allowed(),
+    };
+    let objects = match caldav.objects().await {
+        Ok(objects) => objects,
+        Err(e) => return database_error(e),
+    };
+
+    let mut responses = Vec::new();
+    if method.as_str() == "PROPFIND" {
+        let ctag = CalDav::ctag(&objects);
+        responses.push(request.response(CALENDAR, &Resource::Calendar(&ctag)));
+        if depth(&headers) > 0 {
+            for object in &objects {
+                let href = format!("{}{}", CALENDAR, object.name);
+                responses.push(request.response(&href, &Resource::Object(object)));
+            }
+        }
+        return multistatus(responses);
+    }
+
+    match request.root.as_ref().map(|(ns, name)| (ns.as_str(), name.as_str())) {
+        // The objects asked for by href.
+        Some((CALDAV, "calendar-multiget")) => {
+            for href in &request.hrefs {
+                let name = href.rsplit('/').next().unwrap_or_default();
+                match objects.iter().find(|object| object.name == name) {
+                    Some(object) => responses.push(request.response(href, &Resource::Object(object))),
+                    None => responses.push(format!(
+                        "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+                        escape_xml(href)
+                    )),
+                }
+            }
+        }
+        // Every object, unless the filter asks for components other than
+        // VTODOs. Time ranges and property filters are ignored.
+        Some((CALDAV, "calendar-query")) => {
+            let components: Vec<_> = request
+                .components
+                .iter()
+                .filter(|component| *component != "VCALENDAR")
+                .collect();
+            if components.is_empty() || components.iter().any(|component| *component == "VTODO") {
+                for object in &objects {
+                    let href = format!("{}{}", CALENDAR, object.name);
+                    responses.push(request.response(&href, &Resource::Object(object)));
+                }
+            }
+        }
+        _ => {
+            return (
+                StatusCode::FORBIDDEN,
+                [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+                r#"<?xml version="1.0" encoding="utf-8"?><d:error xmlns:d="DAV:"><d:supported-report/></d:error>"#,
+            )
+                .into_response()
+        }
+    }
+
+    multistatus(responses)
+}
+
+// A todo in the calendar.
+pub async fn object(
+    State(caldav): State<CalDav>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    if method.as_str() == "OPTIONS" {
+        return options();
+    }
+    if !valid_name(&name) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let writes = matches!(method.as_str(), "PUT" | "DELETE");
+    if writes && caldav.writes_refused() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "database migrations are pending, writes are disabled",
+        )
+            .into_response();
+    }
+
+    let existing = match caldav.object(&name).await {
+        Ok(existing) => existing,
+        Err(e) => return database_error(e),
+    };
+    // The version the client last saw, when it only writes over that one.
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if let (Some(if_match), Some(object)) = (if_match, &existing) {
+        if if_match.trim() != "*" && if_match.trim() != object.etag() {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        }
+    }
+
+    match (method.as_str(), existing) {
+        ("GET" | "HEAD", Some(object)) => (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "text/calendar; charset=utf-8".to_string(),
+                ),
+                (header::ETAG, object.etag()),
+            ],
+            to_ical(&object),
+        )
+            .into_response(),
+        ("PROPFIND", Some(object)) => match DavRequest::parse(&body) {
+            Ok(request) => multistatus(vec![request.response(
+                &format!("{}{}", CALENDAR, object.name),
+                &Resource::Object(&object),
+            )]),
+            Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        ("PUT", existing) => put(&caldav, &name, &headers, &body, existing).await,
+        ("DELETE", Some(object)) => {
+            let version = if_match.map(|_| object.todo.version);
+            match caldav.todos.delete(object.todo.id, version).await {
+                Ok(_) => {}
+                Err(Error::RowNotFound) => return StatusCode::PRECONDITION_FAILED.into_response(),
+                Err(e) => return database_error(e),
+            }
+            if let Err(e) = caldav.store.delete(&name).await {
+                tracing::warn!(error = %e, name, "couldn't forget a deleted CalDAV object");
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        ("GET" | "HEAD" | "PROPFIND" | "DELETE", None) if if_match.is_some() => {
+            StatusCode::PRECONDITION_FAILED.into_response()
+        }
+        ("GET" | "HEAD" | "PROPFIND" | "DELETE", None) => StatusCode::NOT_FOUND.into_response(),
+        _ => method_not_allowed(),
+    }
+}
+
+// Creates the todo, or updates it when the name is taken, keeping the UID the
+// client gave it.
+async fn put(
+    caldav: &CalDav,
+    name: &str,
+    headers: &HeaderMap,
+    body: &str,
+    existing: Option<Object>,
+) -> Response {
+    let Some(vtodo) = parse_ical(body) else {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+            r#"<?xml version="1.0" encoding="utf-8"?><d:error xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><c:supported-calendar-component/></d:error>"#,
+        )
+            .into_response();
+    };
+
+    let (todo, status) = match existing {
+        Some(_) if headers.contains_key(header::IF_NONE_MATCH) => {
+            return StatusCode::PRECONDITION_FAILED.into_response()
+        }
+        Some(object) => {
+            let updated = caldav
+                .todos
+                .update(
+                    object.todo.id,
+                    UpdateTodo::new(vtodo.summary, vtodo.completed),
+                    object.todo.version,
+                )
+                .await;
+            match updated {
+                Ok(todo) => (todo, StatusCode::NO_CONTENT),
+                // Updated meanwhile.
+                Err(Error::RowNotFound) => return StatusCode::PRECONDITION_FAILED.into_response(),
+                Err(e) => return database_error(e),
+            }
+        }
+        None if headers.contains_key(header::IF_MATCH) => {
+            return StatusCode::PRECONDITION_FAILED.into_response()
+        }
+        // Taken by the todo, deleted since, it names.
+        None if own_todo_id(name).is_some() => return StatusCode::FORBIDDEN.into_response(),
+        None => {
+            let created = caldav.todos.create(CreateTodo::new(vtodo.summary)).await;
+            let created = match created {
+                Ok(todo) if vtodo.completed => {
+                    let completed = UpdateTodo::new(todo.body.clone(), true);
+                    caldav.todos.update(todo.id, completed, todo.version).await
+                }
+                created => created,
+            };
+            match created {
+                Ok(todo) => (todo, StatusCode::CREATED),
+                Err(e) => return database_error(e),
+            }
+        }
+    };
+
+    let client = ClientObject {
+        name: name.to_string(),
+        todo_id: todo.id,
+        uid: vtodo
+            .uid
+            .unwrap_or_else(|| name.trim_end_matches(".ics").to_string()),
+    };
+    let own = Object::of(todo.clone(), None);
+    // Todos created elsewhere keep their name, and their UID unless the
+    // client changed it.
+    if own.name != client.name || own.uid != client.uid {
+        if let Err(e) = caldav.store.put(&client).await {
+            return database_error(e);
+        }
+    }
+
+    (status, [(header::ETAG, format!("\"{}\"", todo.version))]).into_response()
+}
+
+fn depth(headers: &HeaderMap) -> u8 {
+    match headers.get("depth").and_then(|value| value.to_str().ok()) {
+        Some("0") => 0,
+        // Infinity, the default, is served as 1, as calendars hold no
+        // collections.
+        _ => 1,
+    }
+}
+
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::ALLOW, ALLOW),
+            (
+                header::HeaderName::from_static("dav"),
+                "1, 3, calendar-access",
+            ),
+        ],
+    )
+        .into_response()
+}
+
+fn method_not_allowed() -> Response {
+    (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()
+}
+
+fn multistatus(responses: Vec<String>) -> Response {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="{}" xmlns:c="{}" xmlns:cs="{}">{}</d:multistatus>"#,
+        DAV,
+        CALDAV,
+        CALENDARSERVER,
+        responses.concat()
+    );
+
+    (
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
+fn database_error(e: Error) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Database error: {}", e),
+    )
+        .into_response()
+}
+
+enum Resource<'a> {
+    Home,
+    // With its ctag.
+    Calendar(&'a str),
+    Object(&'a Object),
+}
+
+impl Resource<'_> {
+    // The properties returned when none are asked for.
+    fn all_props(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Resource::Home => &[
+                (DAV, "resourcetype"),
+                (DAV, "displayname"),
+                (DAV, "current-user-principal"),
+                (CALDAV, "calendar-home-set"),
+            ],
+            Resource::Calendar(_) => &[
+                (DAV, "resourcetype"),
+                (DAV, "displayname"),
+                (CALDAV, "supported-calendar-component-set"),
+                (CALENDARSERVER, "getctag"),
+            ],
+            Resource::Object(_) => &[
+                (DAV, "resourcetype"),
+                (DAV, "getetag"),
+                (DAV, "getcontenttype"),
+            ],
+        }
+    }
+
+    // The content of a property, None when the resource doesn't have it.
+    fn prop(&self, ns: &str, name: &str) -> Option<String> {
+        let href = |href: &str| format!("<d:href>{}</d:href>", href);
+        let collection = matches!(self, Resource::Home | Resource::Calendar(_));
+
+        match (ns, name, self) {
+            (DAV, "resourcetype", Resource::Home) => {
+                Some("<d:collection/><d:principal/>".to_string())
+            }
+            (DAV, "resourcetype", Resource::Calendar(_)) => {
+                Some("<d:collection/><c:calendar/>".to_string())
+            }
+            (DAV, "resourcetype", Resource::Object(_)) => Some(String::new()),
+            (DAV, "displayname", Resource::Home) => Some("api-service".to_string()),
+            (DAV, "displayname", Resource::Calendar(_)) => Some("Todos".to_string()),
+            (DAV, "current-user-principal" | "principal-URL" | "owner", _)
+            | (CALDAV, "calendar-home-set", _)
+                if collection =>
+            {
+                Some(href(HOME))
+            }
+            (DAV, "current-user-privilege-set", _) => Some(
+                [
+                    "read",
+                    "write",
+                    "write-properties",
+                    "write-content",
+                    "bind",
+                    "unbind",
+                ]
+                .iter()
+                .map(|privilege| format!("<d:privilege><d:{}/></d:privilege>", privilege))
+                .collect(),
+            ),
+            (CALDAV, "supported-calendar-component-set", Resource::Calendar(_)) => {
+                Some(r#"<c:comp name="VTODO"/>"#.to_string())
+            }
+            (DAV, "supported-report-set", Resource::Calendar(_)) => Some(
+                ["calendar-multiget", "calendar-query"]
+                    .iter()
+                    .map(|report| {
+                        format!(
+                            "<d:supported-report><d:report><c:{}/></d:report></d:supported-report>",
+                            report
+                        )
+                    })
+                    .collect(),
+            ),
+            (CALENDARSERVER, "getctag", Resource::Calendar(ctag)) => Some(ctag.to_string()),
+            (DAV, "getetag", Resource::Calendar(ctag)) => Some(format!("\"{}\"", ctag)),
+            (DAV, "getetag", Resource::Object(object)) => Some(escape_xml(&object.etag())),
+            (DAV, "getcontenttype", Resource::Object(_)) => {
+                Some("text/calendar; charset=utf-8; component=vtodo".to_string())
+            }
+            (CALDAV, "calendar-data", Resource::Object(object)) => {
+                Some(escape_xml(&to_ical(object)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// What a PROPFIND or REPORT body asks for.
+#[derive(Default)]
+struct DavRequest {
+    // Namespace and name of the root element.
+    root: Option<(String, String)>,
+    // None asks for every property.
+    props: Option<Vec<(String, String)>>,
+    // Of a calendar-multiget.
+    hrefs: Vec<String>,
+    // The components a calendar-query filters on.
+    components: Vec<String>,
+}
+
+impl DavRequest {
+    // An empty body asks for every property.
+    fn parse(body: &str) -> Result<DavRequest, String> {
+        let mut request = DavRequest::default();
+        if body.trim().is_empty() {
+            return Ok(request);
+        }
+
+        let mut reader = NsReader::from_str(body);
+        reader.config_mut().trim_text(true);
+        // The elements open, by namespace and name.
+        let mut open: Vec<(String, String)> = Vec::new();
+
+        loop {
+            let (ns, event) = reader.read_resolved_event().map_err(|e| e.to_string())?;
+            let ns = match ns {
+                ResolveResult::Bound(ns) => String::from_utf8_lossy(ns.as_ref()).into_owned(),
+                _ => String::new(),
+            };
+
+            match event {
+                Event::Start(element) => {
+                    let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                    request.element(&open, &ns, &name, &element);
+                    open.push((ns, name));
+                }
+                Event::Empty(element) => {
+                    let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                    request.element(&open, &ns, &name, &element);
+                }
+                Event::End(_) => {
+                    open.pop();
+                }
+                Event::Text(text)
+                    if open
+                        .last()
+                        .is_some_and(|(ns, name)| ns == DAV && name == "href") =>
+                {
+                    let href = text.unescape().map_err(|e| e.to_string())?;
+                    request.hrefs.push(href.into_owned());
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+
+    fn element(&mut self, open: &[(String, String)], ns: &str, name: &str, element: &BytesStart) {
+        if open.is_empty() {
+            self.root = Some((ns.to_string(), name.to_string()));
+        }
+        match open.last().map(|(ns, name)| (ns.as_str(), name.as_str())) {
+            Some((DAV, "prop")) => self
+                .props
+                .get_or_insert_with(Vec::new)
+                .push((ns.to_string(), name.to_string())),
+            _ if ns == CALDAV && name == "comp-filter" => {
+                if let Ok(Some(component)) = element.try_get_attribute("name") {
+                    if let Ok(component) = component.unescape_value() {
+                        self.components.push(component.to_ascii_uppercase());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A multistatus response for the resource: the properties asked for it
+    // has, then those it doesn't.
+    fn response(&self, href: &str, resource: &Resource) -> String {
+        let asked: Vec<(&str, &str)> = match &self.props {
+            Some(props) => props
+                .iter()
+                .map(|(ns, name)| (ns.as_str(), name.as_str()))
+                .collect(),
+            None => resource.all_props().to_vec(),
+        };
+
+        let mut found = String::new();
+        let mut missing = String::new();
+        for (ns, name) in asked {
+            match resource.prop(ns, name) {
+                Some(value) => found.push_str(&element(ns, name, &value)),
+                None => missing.push_str(&element(ns, name, "")),
+            }
+        }
+
+        let mut response = format!("<d:response><d:href>{}</d:href>", escape_xml(href));
+        if !found.is_empty() {
+            response.push_str(&format!(
+                "<d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>",
+                found
+            ));
+        }
+        if !missing.is_empty() {
+            response.push_str(&format!(
+                "<d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>",
+                missing
+            ));
+        }
+        response.push_str("</d:response>");
+
+        response
+    }
+}
+
+// A property element, prefixed as declared by `multistatus` for the known
+// namespaces.
+fn element(ns: &str, name: &str, content: &str) -> String {
+    let (tag, declaration) = match ns {
+        DAV => (format!("d:{}", name), String::new()),
+        CALDAV => (format!("c:{}", name), String::new()),
+        CALENDARSERVER => (format!("cs:{}", name), String::new()),
+        "" => (name.to_string(), String::new()),
+        ns => (
+            format!("x:{}", name),
+            format!(r#" xmlns:x="{}""#, escape_xml(ns)),
+        ),
+    };
+
+    if content.is_empty() {
+        format!("<{}{}/>", tag, declaration)
+    } else {
+        format!("<{}{}>{}</{}>", tag, declaration, content, tag)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// The todo as an iCalendar object (RFC 5545) holding a VTODO.
+fn to_ical(object: &Object) -> String {
+    let todo = &object.todo;
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//api-service//todos//EN".to_string(),
+        "BEGIN:VTODO".to_string(),
+        format!("UID:{}", escape_text(&object.uid)),
+        format!("DTSTAMP:{}", ical_time(todo.updated_at)),
+        format!("CREATED:{}", ical_time(todo.created_at)),
+        format!("LAST-MODIFIED:{}", ical_time(todo.updated_at)),
+        format!("SEQUENCE:{}", todo.version - 1),
+        format!("SUMMARY:{}", escape_text(&todo.body)),
+    ];
+    if todo.completed {
+        lines.push("STATUS:COMPLETED".to_string());
+        lines.push(format!("COMPLETED:{}", ical_time(todo.updated_at)));
+        lines.push("PERCENT-COMPLETE:100".to_string());
+    } else {
+        lines.push("STATUS:NEEDS-ACTION".to_string());
+    }
+    lines.push("END:VTODO".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+fn ical_time(time: NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\r', "")
+        .replace('\n', "\\n")
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n' | 'N') => unescaped.push('\n'),
+                Some(escaped) => unescaped.push(escaped),
+                None => {}
+            },
+            (c, false) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// Folds a content line into lines of at most 75 octets, continued by a space,
+// ending each with CRLF.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+// What is kept of a VTODO.
+struct VTodo {
+    uid: Option<String>,
+    summary: String,
+    completed: bool,
+}
+
+// The first VTODO of an iCalendar object, None without one.
+fn parse_ical(ical: &str) -> Option<VTodo> {
+    let unfolded = ical
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut vtodo: Option<VTodo> = None;
+    // Components nested in the VTODO, like VALARMs, whose properties aren't
+    // the todo's.
+    let mut nested = 0;
+    for line in unfolded.lines() {
+        let line = line.trim_end_matches('\r');
+        let Some((name, value)) = split_property(line) else {
+            continue;
+        };
+
+        match (name.as_str(), vtodo.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                vtodo = Some(VTodo {
+                    uid: None,
+                    summary: String::new(),
+                    completed: false,
+                })
+            }
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => break,
+            (_, Some(_)) if nested > 0 => {}
+            ("UID", Some(vtodo)) => vtodo.uid = Some(unescape_text(value)),
+            ("SUMMARY", Some(vtodo)) => vtodo.summary = unescape_text(value),
+            ("STATUS", Some(vtodo)) => vtodo.completed = value.eq_ignore_ascii_case("COMPLETED"),
+            ("COMPLETED", Some(vtodo)) => vtodo.completed = true,
+            _ => {}
+        }
+    }
+
+    vtodo
+}
+
+// The upper-cased name and the value of a content line, skipping parameters,
+// whose quoted values may hold colons.
+fn split_property(line: &str) -> Option<(String, &str)> {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                let name = line[..i].split(';').next().unwrap_or_default();
+                return Some((name.to_ascii_uppercase(), &line[i + 1..]));
+            }
+            _ => {}
+        }
+    }
+    None
+}
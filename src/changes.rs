@@ -0,0 +1,55 @@
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    envelope::Success, params::QueryParams, problem::Problem, repository::DynTodoRepository,
+    todo::Change,
+};
+
+// Changes returned at most per request.
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ChangesParams {
+    // The `next` of the previous response; from the first change when absent.
+    since: Option<i64>,
+    // Defaults to 100.
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ChangesData {
+    // The `since` of the next request.
+    next: i64,
+    changes: Vec<Change>,
+}
+
+// The change feed: every write made to a todo, in the order the writes were
+// committed, numbered by `seq`. Unlike the live updates, it is read from the
+// database, so it spans restarts and instances; a client catches up by asking
+// again from `next` until no changes are returned.
+pub async fn list(
+    State(todos): State<DynTodoRepository>,
+    QueryParams(params): QueryParams<ChangesParams>,
+) -> Result<Success<ChangesData>, Problem> {
+    let since = params.since.unwrap_or(0);
+    if since < 0 {
+        return Err(fail("since must not be negative".to_string()));
+    }
+
+    let limit = params.limit.unwrap_or(100);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(fail(format!("limit must be between 1 and {}", MAX_LIMIT)));
+    }
+
+    let changes = todos.changes(since, limit).await?;
+
+    Ok(Success::new(ChangesData {
+        next: changes.last().map_or(since, |change| change.seq),
+        changes,
+    }))
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
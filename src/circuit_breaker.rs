@@ -0,0 +1,156 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::config::BreakerConfig;
+use crate::problem::Problem;
+
+// Stops sending requests to a database that keeps failing. After
+// `failure_threshold` consecutive 500s the breaker opens and requests are
+// answered 503 straight away, sparing the database and the pool. Once
+// `open_duration` has passed, a single probe request is let through: its
+// success closes the breaker, its failure opens it again. Transitions are
+// logged under the `circuit_breaker` target.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // A probe request is in flight.
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            state: Arc::new(Mutex::new(BreakerState::Closed { failures: 0 })),
+        }
+    }
+
+    // Whether a request may go through and is the probe, or how long until
+    // the breaker lets one through.
+    fn admit(&self) -> Result<bool, Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            BreakerState::Closed { .. } => Ok(false),
+            BreakerState::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(until - now);
+                }
+
+                tracing::info!(target: "circuit_breaker", "half-open, probing the database");
+                *state = BreakerState::HalfOpen;
+                Ok(true)
+            }
+            BreakerState::HalfOpen => Err(Duration::from_secs(1)),
+        }
+    }
+
+    fn record(&self, probe: bool, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        match &mut *state {
+            BreakerState::Closed { failures } if failed => {
+                *failures += 1;
+                if *failures >= self.config.failure_threshold {
+                    tracing::warn!(
+                        target: "circuit_breaker",
+                        failures = *failures,
+                        open_seconds = self.config.open_duration.as_secs(),
+                        "database failing, breaker opened"
+                    );
+                    *state = self.open();
+                }
+            }
+            BreakerState::Closed { failures } => *failures = 0,
+            BreakerState::HalfOpen if probe && failed => {
+                tracing::warn!(target: "circuit_breaker", "probe failed, breaker reopened");
+                *state = self.open();
+            }
+            BreakerState::HalfOpen if probe => {
+                tracing::info!(target: "circuit_breaker", "probe succeeded, breaker closed");
+                *state = BreakerState::Closed { failures: 0 };
+            }
+            // Requests admitted before the breaker opened don't change it.
+            _ => {}
+        }
+    }
+
+    fn open(&self) -> BreakerState {
+        BreakerState::Open {
+            until: Instant::now() + self.config.open_duration,
+        }
+    }
+}
+
+// Reopens a half-open breaker for an immediate new probe when the probe
+// request is dropped before completing, e.g. because the client went away.
+struct Admission<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    finished: bool,
+}
+
+impl Admission<'_> {
+    fn finish(mut self, failed: bool) {
+        self.finished = true;
+        self.breaker.record(self.probe, failed);
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.finished {
+            *self.breaker.state.lock().unwrap() = BreakerState::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+}
+
+pub async fn guard(
+    State(breaker): State<CircuitBreaker>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let probe = match breaker.admit() {
+        Ok(probe) => probe,
+        Err(retry_after) => {
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+
+            return (
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Problem::new(StatusCode::SERVICE_UNAVAILABLE)
+                    .detail("database unavailable, try again later"),
+            )
+                .into_response();
+        }
+    };
+
+    let admission = Admission {
+        breaker: &breaker,
+        probe,
+        finished: false,
+    };
+
+    let response = next.run(request).await;
+
+    admission.finish(response.status() == StatusCode::INTERNAL_SERVER_ERROR);
+
+    response
+}
@@ -0,0 +1,58 @@
+use yrs::{
+    updates::decoder::Decode, Doc, GetString, OffsetKind, Options, ReadTxn, StateVector, Text,
+    Transact, Update,
+};
+
+// Todo bodies edited through sync are Yjs documents holding the body in a
+// text of this name, so concurrent edits merge character by character.
+const BODY: &str = "body";
+
+// Edits the server makes itself are made as this client. Documents the server
+// builds from the same body are then identical, so a client that started from
+// one merges with another without duplicating the text.
+const SERVER_CLIENT_ID: u64 = 0;
+
+pub fn decode(update: &[u8]) -> Result<Update, String> {
+    Update::decode_v1(update).map_err(|e| format!("invalid document update: {}", e))
+}
+
+// Applies `updates` to the document of a todo whose body is `body`, starting
+// from `state`, or from the body alone when the todo has no document yet.
+// Returns the new state and body.
+//
+// A body written since without the document, by the other APIs, replaces the
+// document's text first, as an edit of the server.
+pub fn apply(
+    state: Option<&[u8]>,
+    body: &str,
+    updates: Vec<Update>,
+) -> Result<(Vec<u8>, String), String> {
+    let doc = Doc::with_options(Options {
+        // Offsets in UTF-16 code units, like Yjs in browsers.
+        offset_kind: OffsetKind::Utf16,
+        ..Options::with_client_id(SERVER_CLIENT_ID)
+    });
+    let text = doc.get_or_insert_text(BODY);
+    let mut txn = doc.transact_mut();
+
+    if let Some(state) = state {
+        txn.apply_update(decode(state)?)
+            .map_err(|e| format!("invalid stored document: {}", e))?;
+    }
+
+    if text.get_string(&txn) != body {
+        let len = text.len(&txn);
+        text.remove_range(&mut txn, 0, len);
+        text.insert(&mut txn, 0, body);
+    }
+
+    for update in updates {
+        txn.apply_update(update)
+            .map_err(|e| format!("invalid document update: {}", e))?;
+    }
+
+    Ok((
+        txn.encode_state_as_update_v1(&StateVector::default()),
+        text.get_string(&txn),
+    ))
+}
@@ -0,0 +1,43 @@
+use serde_json::Value;
+
+use crate::todo::Todo;
+
+pub const MEDIA_TYPE: &str = "text/csv";
+
+const HEADER: &str = "id,body,completed,created_at,updated_at,version\r\n";
+
+// A todo or a list, the plain bodies of the todo routes, as CSV (RFC 4180)
+// with a header row, one row per todo. None for other bodies.
+pub fn encode(body: &Value) -> Option<Result<String, serde_json::Error>> {
+    let todos = match (body.pointer("/data/todo"), body.pointer("/data/todos")) {
+        (Some(todo), _) => serde_json::from_value::<Todo>(todo.clone()).map(|todo| vec![todo]),
+        (None, Some(todos)) => serde_json::from_value::<Vec<Todo>>(todos.clone()),
+        (None, None) => return None,
+    };
+
+    Some(todos.map(|todos| {
+        let mut csv = HEADER.to_string();
+        for todo in todos {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\r\n",
+                todo.id,
+                field(&todo.body),
+                todo.completed,
+                todo.created_at.format("%Y-%m-%dT%H:%M:%S"),
+                todo.updated_at.format("%Y-%m-%dT%H:%M:%S"),
+                todo.version
+            ));
+        }
+        csv
+    }))
+}
+
+// Quoted when it holds a separator, a quote or a line break, with quotes
+// doubled.
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
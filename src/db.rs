@@ -0,0 +1,202 @@
+// Database backend selection. The pool goes through sqlx's Any driver, so one
+// binary connects to SQLite, Postgres or MySQL/MariaDB depending on the scheme
+// of DATABASE_URL.
+//
+// Queries are written with `$N` placeholders, which SQLite and Postgres
+// accept; `Backend::sql` rewrites them to `?` for MySQL.
+use std::borrow::Cow;
+
+use sqlx::migrate::Migrator;
+
+use crate::config::{PoolConfig, SqlitePragmas};
+
+pub type DbPool = sqlx::AnyPool;
+pub type DbConnection = sqlx::AnyConnection;
+pub type DbConnectOptions = sqlx::any::AnyConnectOptions;
+pub type DbQueryResult = sqlx::any::AnyQueryResult;
+
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("./migrations/mysql");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    MySql,
+}
+
+impl Backend {
+    // Picks the backend from the URL scheme, rejecting unknown schemes with an
+    // actionable message instead of an obscure driver error.
+    pub fn from_url(database_url: &str) -> Result<Backend, String> {
+        let scheme = database_url.split(':').next().unwrap_or_default();
+
+        match scheme {
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" | "postgresql" => Ok(Backend::Postgres),
+            "mysql" | "mariadb" => Ok(Backend::MySql),
+            _ => Err(format!("unsupported database URL scheme: {}", scheme)),
+        }
+    }
+
+    // The backend a pool was connected to.
+    pub fn of(dbpool: &DbPool) -> Backend {
+        Backend::from_url(dbpool.connect_options().database_url.as_str())
+            .expect("pool connected with an unsupported scheme")
+    }
+
+    // Each backend has its own migration set, kept at the same versions.
+    pub fn migrator(self) -> &'static Migrator {
+        match self {
+            Backend::Sqlite => &SQLITE_MIGRATOR,
+            Backend::Postgres => &POSTGRES_MIGRATOR,
+            Backend::MySql => &MYSQL_MIGRATOR,
+        }
+    }
+
+    // Adapts a query written with `$N` placeholders to the backend.
+    // Placeholders must appear in order, each once.
+    pub fn sql(self, query: &str) -> Cow<'_, str> {
+        if self == Backend::MySql {
+            use regex::Regex;
+            use std::sync::LazyLock;
+
+            static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$\d+").unwrap());
+
+            PLACEHOLDER.replace_all(query, "?")
+        } else {
+            Cow::Borrowed(query)
+        }
+    }
+}
+
+// Registers the compiled-in drivers with the Any driver. Must run before the
+// first connection.
+pub fn install_drivers() {
+    sqlx::any::install_default_drivers();
+}
+
+// Pools for reads and writes. SQLite allows many readers but a single writer,
+// so a file database gets a one-connection write pool: writers queue for it
+// instead of failing with "database is locked" inside SQLite. Elsewhere both
+// are the same pool.
+#[derive(Clone)]
+pub struct DbPools {
+    pub read: DbPool,
+    pub write: DbPool,
+}
+
+// SQLite databases are created when missing.
+pub async fn connect(
+    database_url: &str,
+    pool: &PoolConfig,
+    pragmas: &SqlitePragmas,
+) -> Result<DbPools, sqlx::Error> {
+    let mut database_url = driver_url(database_url);
+
+    let backend =
+        Backend::from_url(&database_url).map_err(|e| sqlx::Error::Configuration(e.into()))?;
+
+    if backend == Backend::Sqlite && !database_url.contains("mode=") {
+        let separator = if database_url.contains('?') { '&' } else { '?' };
+        database_url = format!("{}{}mode=rwc", database_url, separator);
+    }
+
+    let pragmas = (backend == Backend::Sqlite).then(|| pragma_statements(pragmas));
+
+    // Every connection to an in-memory database opens a new, empty one.
+    let in_memory = database_url.contains(":memory:") || database_url.contains("mode=memory");
+
+    if backend != Backend::Sqlite || in_memory {
+        let dbpool = pool_options(pool, pragmas).connect(&database_url).await?;
+        return Ok(DbPools {
+            read: dbpool.clone(),
+            write: dbpool,
+        });
+    }
+
+    // Connected first so it creates a missing database.
+    let write = pool_options(pool, pragmas.clone())
+        .max_connections(1)
+        .min_connections(pool.min_connections.min(1))
+        .connect(&database_url)
+        .await?;
+
+    Ok(DbPools {
+        read: pool_options(pool, pragmas).connect(&database_url).await?,
+        write,
+    })
+}
+
+// Applied with statements on connect; the Any driver has no typed SQLite
+// connect options.
+fn pragma_statements(pragmas: &SqlitePragmas) -> String {
+    format!(
+        "PRAGMA journal_mode = {}; PRAGMA synchronous = {}; PRAGMA busy_timeout = {}; \
+         PRAGMA foreign_keys = {}; PRAGMA wal_autocheckpoint = {};",
+        pragmas.journal_mode.as_str(),
+        pragmas.synchronous.as_str(),
+        pragmas.busy_timeout.as_millis(),
+        if pragmas.foreign_keys { "ON" } else { "OFF" },
+        // SQLite's default, in pages.
+        if pragmas.wal_autocheckpoint { 1000 } else { 0 },
+    )
+}
+
+fn pool_options(pool: &PoolConfig, pragmas: Option<String>) -> sqlx::any::AnyPoolOptions {
+    use sqlx::Executor;
+
+    let options = sqlx::any::AnyPoolOptions::new()
+        .max_connections(pool.max_connections)
+        .min_connections(pool.min_connections)
+        .acquire_timeout(pool.acquire_timeout)
+        .idle_timeout(pool.idle_timeout)
+        .max_lifetime(pool.max_lifetime);
+
+    match pragmas {
+        Some(pragmas) => options.after_connect(move |conn, _| {
+            let pragmas = pragmas.clone();
+            Box::pin(async move { conn.execute(pragmas.as_str()).await.map(|_| ()) })
+        }),
+        None => options,
+    }
+}
+
+// Errors worth retrying the whole operation for: lock contention and
+// conflicts the database resolved by aborting the statement or transaction.
+pub fn is_transient(error: &sqlx::Error) -> bool {
+    use sqlx::{mysql::MySqlDatabaseError, postgres::PgDatabaseError, sqlite::SqliteError};
+
+    let sqlx::Error::Database(error) = error else {
+        return false;
+    };
+
+    if error.try_downcast_ref::<SqliteError>().is_some() {
+        // SQLITE_BUSY and SQLITE_LOCKED, including their extended codes.
+        return error
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6));
+    }
+
+    if let Some(error) = error.try_downcast_ref::<PgDatabaseError>() {
+        // serialization_failure and deadlock_detected.
+        return matches!(error.code(), "40001" | "40P01");
+    }
+
+    if let Some(error) = error.try_downcast_ref::<MySqlDatabaseError>() {
+        // ER_LOCK_WAIT_TIMEOUT and ER_LOCK_DEADLOCK.
+        return matches!(error.number(), 1205 | 1213);
+    }
+
+    false
+}
+
+// sqlx only knows the `mysql` scheme.
+pub fn driver_url(database_url: &str) -> String {
+    match database_url.strip_prefix("mariadb:") {
+        Some(rest) => format!("mysql:{}", rest),
+        None => database_url.to_string(),
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::envelope::Success;
+
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+// A deprecated route, or every route under a prefix like `/v1`, in a
+// `selector deprecated=<date>, sunset=<date>, successor=<url>` directive;
+// only the deprecation date is required.
+#[derive(Debug)]
+struct Deprecation {
+    selector: String,
+    deprecated: DateTime<Utc>,
+    sunset: Option<DateTime<Utc>>,
+    successor: Option<String>,
+}
+
+impl Deprecation {
+    // The selector is a route as declared or a prefix of its segments.
+    fn matches(&self, route: &str) -> bool {
+        route
+            .strip_prefix(self.selector.trim_end_matches('/'))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    // Deprecation (RFC 9745), Sunset (RFC 8594) and the successor's Link.
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = Vec::new();
+        let mut push = |name: HeaderName, value: String| {
+            if let Ok(value) = HeaderValue::try_from(value) {
+                headers.push((name, value));
+            }
+        };
+        push(
+            DEPRECATION.clone(),
+            format!("@{}", self.deprecated.timestamp()),
+        );
+        if let Some(sunset) = self.sunset {
+            push(
+                SUNSET.clone(),
+                sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            );
+        }
+        if let Some(successor) = &self.successor {
+            push(
+                header::LINK,
+                format!("<{}>; rel=\"successor-version\"", successor),
+            );
+        }
+        headers
+    }
+}
+
+// The deprecated routes, parsed from DEPRECATIONS: semicolon-separated
+// directives, the first matching a route applying to it.
+#[derive(Clone, Debug, Default)]
+pub struct DeprecationRules(Arc<Vec<Deprecation>>);
+
+impl FromStr for DeprecationRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+
+        for directive in s.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let (selector, attributes) = directive
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("missing deprecation date in '{}'", directive))?;
+            let selector = selector.trim();
+            if !selector.starts_with('/') {
+                return Err(format!("invalid route in '{}'", directive));
+            }
+
+            let (mut deprecated, mut sunset, mut successor) = (None, None, None);
+            for attribute in attributes.split(',').map(str::trim) {
+                let (name, value) = attribute.split_once('=').ok_or_else(|| {
+                    format!("invalid attribute '{}' in '{}'", attribute, directive)
+                })?;
+                let value = value.trim();
+                match name.trim() {
+                    "deprecated" => deprecated = Some(date(value, directive)?),
+                    "sunset" => sunset = Some(date(value, directive)?),
+                    "successor" => successor = Some(value.to_string()),
+                    name => return Err(format!("unknown attribute '{}' in '{}'", name, directive)),
+                }
+            }
+
+            rules.push(Deprecation {
+                selector: selector.to_string(),
+                deprecated: deprecated
+                    .ok_or_else(|| format!("missing deprecation date in '{}'", directive))?,
+                sunset,
+                successor,
+            });
+        }
+
+        Ok(DeprecationRules(Arc::new(rules)))
+    }
+}
+
+// A date like 2026-06-01, at midnight UTC, or an RFC 3339 timestamp.
+fn date(value: &str, directive: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        })
+        .map_err(|_| format!("invalid date '{}' in '{}'", value, directive))
+}
+
+// The deprecation rules, and how often each deprecated route was requested
+// since startup, by method, for finding the clients left to move.
+#[derive(Clone)]
+pub struct Deprecations {
+    rules: DeprecationRules,
+    usage: Arc<Mutex<BTreeMap<(String, String), u64>>>,
+}
+
+impl Deprecations {
+    pub fn new(rules: DeprecationRules) -> Self {
+        Deprecations {
+            rules,
+            usage: Arc::default(),
+        }
+    }
+}
+
+// Announces the deprecation of the request's route on its response, and
+// counts the request.
+pub async fn announce(
+    State(deprecations): State<Deprecations>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let Some(route) = route else {
+        return next.run(request).await;
+    };
+    let Some(deprecation) = deprecations
+        .rules
+        .0
+        .iter()
+        .find(|rule| rule.matches(&route))
+    else {
+        return next.run(request).await;
+    };
+
+    let method = request.method().to_string();
+    tracing::info!(target: "deprecation", %method, %route, "deprecated route requested");
+    if let Ok(mut usage) = deprecations.usage.lock() {
+        *usage.entry((method, route)).or_default() += 1;
+    }
+
+    let mut response = next.run(request).await;
+    for (name, value) in deprecation.headers() {
+        response.headers_mut().append(name, value);
+    }
+    response
+}
+
+#[derive(Serialize)]
+pub struct UsageData {
+    routes: Vec<RouteUsage>,
+}
+
+#[derive(Serialize)]
+pub struct RouteUsage {
+    method: String,
+    route: String,
+    requests: u64,
+}
+
+// Admin route: the requests to deprecated routes since startup, by route and
+// method.
+pub async fn usage(State(deprecations): State<Deprecations>) -> Success<UsageData> {
+    let usage = deprecations
+        .usage
+        .lock()
+        .map(|usage| usage.clone())
+        .unwrap_or_default();
+    let routes = usage
+        .into_iter()
+        .map(|((method, route), requests)| RouteUsage {
+            method,
+            route,
+            requests,
+        })
+        .collect();
+
+    Success::new(UsageData { routes })
+}
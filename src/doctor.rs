@@ -0,0 +1,443 @@
+use std::{str::FromStr, time::Duration};
+
+use sqlx::ConnectOptions;
+
+use crate::{
+    config::{
+        Config, EmailConfig, MqttConfig, NatsConfig, RedisConfig, StartupMigrations,
+        StorageBackend, TelegramConfig,
+    },
+    db::{self, Backend, DbConnectOptions, DbConnection},
+    mqtt, outbox,
+    redact::Redactor,
+    redis_store::RedisStore,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Outcome {
+    Pass(String),
+    Warn(String),
+    Fail(String),
+}
+
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, outcome: Outcome) {
+        let (label, detail) = match outcome {
+            Outcome::Pass(detail) => ("pass", detail),
+            Outcome::Warn(detail) => ("warn", detail),
+            Outcome::Fail(detail) => {
+                self.failed = true;
+                ("FAIL", detail)
+            }
+        };
+
+        if detail.is_empty() {
+            println!("[{}] {}", label, name);
+        } else {
+            println!("[{}] {}: {}", label, name, detail);
+        }
+    }
+}
+
+// `api-service doctor`: validates the configuration and the environment the
+// server is about to run in, printing one line per check. Returns false when
+// any check failed, so it can gate a deployment.
+pub async fn run() -> bool {
+    let mut report = Report::default();
+
+    let config = match Config::try_from_env() {
+        Ok(config) => {
+            report.check("configuration", Outcome::Pass(String::new()));
+            config
+        }
+        Err(errors) => {
+            report.check("configuration", Outcome::Fail(errors.join("; ")));
+            return false;
+        }
+    };
+
+    report.check(
+        "log redaction patterns",
+        match Redactor::new(&config.log_redact_fields, &config.log_redact_patterns) {
+            Ok(_) => Outcome::Pass(String::new()),
+            Err(e) => Outcome::Fail(e.to_string()),
+        },
+    );
+
+    report.check("bind address", check_bind_addr(&config.bind_addr).await);
+
+    match config.storage {
+        StorageBackend::Sql => {
+            check_database(&mut report, &config.database_url, config.startup_migrations).await
+        }
+        StorageBackend::Memory => report.check(
+            "database connection",
+            Outcome::Warn("in-memory storage, todos are lost on restart".into()),
+        ),
+    }
+
+    if let Some(dsn) = &config.sentry_dsn {
+        report.check("sentry", check_sentry(dsn).await);
+    }
+    if let Some(redis) = &config.redis {
+        report.check("redis", check_redis(redis).await);
+    }
+    if let Some(kafka) = &config.kafka {
+        report.check(
+            "kafka",
+            match outbox::check(kafka).await {
+                Ok(partitions) => Outcome::Pass(format!(
+                    "topic {} has {} partitions",
+                    kafka.topic, partitions
+                )),
+                Err(e) => Outcome::Fail(e),
+            },
+        );
+    }
+    if let Some(nats) = &config.nats {
+        report.check("nats", check_nats(nats).await);
+    }
+    if let Some(mqtt) = &config.mqtt {
+        report.check("mqtt", check_mqtt(mqtt).await);
+    }
+    if let Some(email) = &config.email {
+        report.check("smtp", check_smtp(email).await);
+    }
+    // Posting would notify the channel, so only their hosts are reached.
+    for (name, notifier) in [("slack", &config.slack), ("discord", &config.discord)] {
+        if let Some(notifier) = notifier {
+            report.check(name, check_url(&notifier.webhook_url).await);
+        }
+    }
+    if let Some(telegram) = &config.telegram {
+        report.check("telegram", check_telegram(telegram).await);
+    }
+
+    !report.failed
+}
+
+async fn check_bind_addr(bind_addr: &str) -> Outcome {
+    match tokio::net::TcpListener::bind(bind_addr).await {
+        Ok(_) => Outcome::Pass(bind_addr.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Outcome::Warn(format!(
+            "{} is in use, is the server already running?",
+            bind_addr
+        )),
+        Err(e) => Outcome::Fail(format!("{}: {}", bind_addr, e)),
+    }
+}
+
+async fn check_database(report: &mut Report, database_url: &str, mode: StartupMigrations) {
+    let backend = match Backend::from_url(database_url) {
+        Ok(backend) => backend,
+        Err(e) => {
+            report.check("database connection", Outcome::Fail(e));
+            return;
+        }
+    };
+
+    let options = match DbConnectOptions::from_str(&db::driver_url(database_url)) {
+        Ok(options) => options,
+        Err(e) => {
+            report.check("database connection", Outcome::Fail(e.to_string()));
+            return;
+        }
+    };
+
+    let mut conn = match tokio::time::timeout(TIMEOUT, options.connect()).await {
+        Ok(Ok(conn)) => {
+            report.check(
+                "database connection",
+                Outcome::Pass(database_url.to_string()),
+            );
+            conn
+        }
+        // SQLITE_CANTOPEN
+        Ok(Err(sqlx::Error::Database(e))) if e.code().as_deref() == Some("14") => {
+            report.check(
+                "database connection",
+                Outcome::Warn("database file does not exist, the server will create it".into()),
+            );
+            return;
+        }
+        Ok(Err(e)) => {
+            report.check("database connection", Outcome::Fail(e.to_string()));
+            return;
+        }
+        Err(_) => {
+            report.check("database connection", Outcome::Fail("timed out".into()));
+            return;
+        }
+    };
+
+    let write = async {
+        use sqlx::Connection;
+
+        // MySQL commits DDL implicitly, so the table is dropped instead.
+        if backend == Backend::MySql {
+            sqlx::query("create table _doctor_write_check (id integer)")
+                .execute(&mut conn)
+                .await?;
+            return sqlx::query("drop table _doctor_write_check")
+                .execute(&mut conn)
+                .await
+                .map(|_| ());
+        }
+
+        // Creating a table inside a rolled back transaction takes the write
+        // lock without leaving anything behind.
+        let mut tx = conn.begin().await?;
+        sqlx::query("create table _doctor_write_check (id integer)")
+            .execute(&mut *tx)
+            .await?;
+        tx.rollback().await
+    };
+
+    report.check(
+        "database write access",
+        match tokio::time::timeout(TIMEOUT, write).await {
+            Ok(Ok(())) => Outcome::Pass(String::new()),
+            Ok(Err(e)) => Outcome::Fail(e.to_string()),
+            Err(_) => Outcome::Fail("timed out waiting for the write lock".into()),
+        },
+    );
+
+    report.check(
+        "migrations",
+        check_migrations(&mut conn, backend, mode).await,
+    );
+
+    check_webhooks(report, &mut conn, backend).await;
+}
+
+// Each registered webhook's receiver accepts a TCP connection. Failed
+// deliveries are retried, so an unreachable one is only a warning.
+async fn check_webhooks(report: &mut Report, conn: &mut DbConnection, backend: Backend) {
+    use sqlx::Row;
+
+    let url = match backend {
+        Backend::Sqlite | Backend::Postgres => "url",
+        Backend::MySql => "cast(url as char)",
+    };
+    // Missing until the migrations are applied.
+    let Ok(webhooks) = sqlx::query(&format!("select id, {} from webhooks order by id", url))
+        .fetch_all(&mut *conn)
+        .await
+    else {
+        return;
+    };
+
+    for webhook in webhooks {
+        let id: i64 = webhook.get(0);
+        let url: String = webhook.get(1);
+        let outcome = match check_url(&url).await {
+            Outcome::Fail(detail) => Outcome::Warn(detail),
+            outcome => outcome,
+        };
+        report.check(&format!("webhook {}", id), outcome);
+    }
+}
+
+async fn check_migrations(
+    conn: &mut DbConnection,
+    backend: Backend,
+    mode: StartupMigrations,
+) -> Outcome {
+    use sqlx::migrate::Migrate;
+
+    let applied = conn.list_applied_migrations().await.unwrap_or_default();
+
+    let mut pending = Vec::new();
+
+    for migration in backend
+        .migrator()
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+    {
+        match applied
+            .iter()
+            .find(|applied| applied.version == migration.version)
+        {
+            Some(applied) if applied.checksum != migration.checksum => {
+                return Outcome::Fail(format!(
+                    "migration {} was modified after it was applied",
+                    migration.version
+                ))
+            }
+            Some(_) => {}
+            None => pending.push(migration.version),
+        }
+    }
+
+    if pending.is_empty() {
+        return Outcome::Pass(format!("{} applied", applied.len()));
+    }
+
+    match mode {
+        StartupMigrations::Run => Outcome::Warn(format!(
+            "pending, the server will apply them: {:?}",
+            pending
+        )),
+        StartupMigrations::Fail => Outcome::Fail(format!(
+            "pending, the server won't start until they are applied: {:?}",
+            pending
+        )),
+        StartupMigrations::Warn => Outcome::Warn(format!(
+            "pending, the server will refuse writes until they are applied: {:?}",
+            pending
+        )),
+    }
+}
+
+// Sentry is reachable when its ingestion host accepts a TCP connection.
+async fn check_sentry(dsn: &str) -> Outcome {
+    let dsn = match sentry::types::Dsn::from_str(dsn) {
+        Ok(dsn) => dsn,
+        Err(e) => return Outcome::Fail(format!("invalid SENTRY_DSN: {}", e)),
+    };
+
+    check_tcp(dsn.host(), dsn.port()).await
+}
+
+// Redis answers a PING.
+async fn check_redis(config: &RedisConfig) -> Outcome {
+    let ping = async {
+        let store = RedisStore::connect(config).await?;
+        store.ping().await
+    };
+    match tokio::time::timeout(TIMEOUT, ping).await {
+        Ok(Ok(latency)) => Outcome::Pass(format!("PING answered in {:?}", latency)),
+        Ok(Err(e)) => Outcome::Fail(e.to_string()),
+        Err(_) => Outcome::Fail("timed out".into()),
+    }
+}
+
+// The NATS server accepts the connection and answers a round trip, and the
+// JetStream stream exists, or can be created by the server.
+async fn check_nats(config: &NatsConfig) -> Outcome {
+    let check = async {
+        let client = async_nats::ConnectOptions::new()
+            .name("api-service doctor")
+            .connection_timeout(config.timeout)
+            .connect(config.url.as_str())
+            .await?;
+        client.flush().await?;
+
+        let Some(stream) = &config.stream else {
+            return Ok::<_, async_nats::Error>(Outcome::Pass(config.url.clone()));
+        };
+        let mut context = async_nats::jetstream::new(client);
+        context.set_timeout(config.timeout);
+        Ok(match context.get_stream(stream).await {
+            Ok(_) => Outcome::Pass(format!("{}, stream {}", config.url, stream)),
+            Err(_) => Outcome::Warn(format!(
+                "stream {} doesn't exist, the server will create it",
+                stream
+            )),
+        })
+    };
+    match tokio::time::timeout(TIMEOUT, check).await {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Outcome::Fail(e.to_string()),
+        Err(_) => Outcome::Fail("timed out".into()),
+    }
+}
+
+// The MQTT broker acknowledges a connection with the configured client id
+// and credentials.
+async fn check_mqtt(config: &MqttConfig) -> Outcome {
+    let options = match mqtt::options(config) {
+        Ok(options) => options,
+        Err(e) => return Outcome::Fail(e),
+    };
+    let (client, mut eventloop) = rumqttc::AsyncClient::new(options, 1);
+    let connect = async {
+        loop {
+            match eventloop.poll().await? {
+                rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
+                    return Ok::<_, rumqttc::ConnectionError>(())
+                }
+                _ => continue,
+            }
+        }
+    };
+    let outcome = match tokio::time::timeout(TIMEOUT, connect).await {
+        Ok(Ok(())) => Outcome::Pass(String::new()),
+        Ok(Err(e)) => Outcome::Fail(e.to_string()),
+        Err(_) => Outcome::Fail("timed out".into()),
+    };
+    let _ = client.try_disconnect();
+    outcome
+}
+
+// The SMTP relay greets, answers EHLO, authenticates when credentials are
+// given, and answers NOOP.
+async fn check_smtp(config: &EmailConfig) -> Outcome {
+    use lettre::{AsyncSmtpTransport, Tokio1Executor};
+
+    let transport: AsyncSmtpTransport<Tokio1Executor> =
+        match AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url) {
+            Ok(transport) => transport.timeout(Some(TIMEOUT)).build(),
+            Err(e) => return Outcome::Fail(format!("invalid SMTP_URL: {}", e)),
+        };
+    match transport.test_connection().await {
+        Ok(true) => Outcome::Pass(String::new()),
+        Ok(false) => Outcome::Fail("the relay didn't answer NOOP".into()),
+        Err(e) => Outcome::Fail(e.to_string()),
+    }
+}
+
+// The bot's token is accepted by the Bot API.
+async fn check_telegram(config: &TelegramConfig) -> Outcome {
+    let client = match reqwest::Client::builder().timeout(TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => return Outcome::Fail(e.to_string()),
+    };
+    let response = client
+        .post(format!("{}/bot{}/getMe", config.api_url, config.token))
+        .send()
+        .await;
+    // Errors are reported without the URL, which holds the token.
+    let me: serde_json::Value = match response {
+        Ok(response) => match response.json().await {
+            Ok(me) => me,
+            Err(e) => return Outcome::Fail(e.without_url().to_string()),
+        },
+        Err(e) => return Outcome::Fail(e.without_url().to_string()),
+    };
+    match me["result"]["username"].as_str() {
+        Some(username) if me["ok"] == true => Outcome::Pass(format!("@{}", username)),
+        _ => Outcome::Fail(
+            me["description"]
+                .as_str()
+                .unwrap_or("getMe failed")
+                .to_string(),
+        ),
+    }
+}
+
+// The host of an http(s) URL accepts a TCP connection.
+async fn check_url(url: &str) -> Outcome {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(e) => return Outcome::Fail(format!("invalid URL: {}", e)),
+    };
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => check_tcp(host.trim_matches(['[', ']']), port).await,
+        _ => Outcome::Fail(format!("{} has no host", url)),
+    }
+}
+
+async fn check_tcp(host: &str, port: u16) -> Outcome {
+    match tokio::time::timeout(TIMEOUT, tokio::net::TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Outcome::Pass(format!("{}:{} reachable", host, port)),
+        Ok(Err(e)) => Outcome::Fail(format!("{}:{}: {}", host, port, e)),
+        Err(_) => Outcome::Fail(format!("{}:{}: timed out", host, port)),
+    }
+}
@@ -0,0 +1,517 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use sqlx::{any::AnyRow, Error, Row};
+use tokio::sync::Notify;
+
+use crate::{
+    backoff,
+    config::EmailConfig,
+    db::{Backend, DbPool},
+    preferences::Preferences,
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    telegram,
+    todo::Todo,
+};
+
+// Emails the dispatcher sends at once.
+const BATCH_SIZE: i64 = 16;
+
+// How often the dispatcher looks for retries that came due when nothing
+// wakes it up.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Open todos listed at most in a reminder.
+const MAX_LISTED: usize = 100;
+
+// An email to send, and how sending it went so far. Times are unix seconds.
+#[derive(Clone)]
+pub struct Email {
+    pub id: i64,
+    pub dedupe_key: String,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    // `pending`, `sent` or `failed`, once out of attempts or rejected for
+    // good.
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub last_error: String,
+    pub created_at: i64,
+    // 0 until sent.
+    pub sent_at: i64,
+}
+
+// Where emails wait to be sent.
+#[async_trait]
+pub trait EmailQueue: Send + Sync {
+    // Queues an email due `now`, unless one with `dedupe_key` was queued
+    // already. Returns whether it was queued.
+    async fn enqueue(
+        &self,
+        dedupe_key: &str,
+        recipient: &str,
+        subject: &str,
+        body: &str,
+        now: i64,
+    ) -> Result<bool, Error>;
+
+    // Claims up to `limit` pending emails due by `now`, postponing their next
+    // attempt to `lease_until` so that other instances leave them alone while
+    // they are sent.
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Email>, Error>;
+
+    // Stores the outcome of an attempt at a claimed email.
+    async fn record(&self, email: &Email) -> Result<(), Error>;
+}
+
+pub type DynEmailQueue = Arc<dyn EmailQueue>;
+
+// Emails in the `email_queue` table, so they survive restarts and any
+// instance can send them.
+pub struct SqlEmailQueue {
+    dbpool: DbPool,
+}
+
+impl SqlEmailQueue {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlEmailQueue { dbpool }
+    }
+}
+
+// MySQL reports TEXT columns as blobs.
+fn email_columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => {
+            "id, dedupe_key, recipient, subject, body, status, attempts, next_attempt_at, \
+             last_error, created_at, sent_at"
+        }
+        Backend::MySql => {
+            "id, dedupe_key, recipient, cast(subject as char) as subject, \
+             cast(body as char) as body, status, attempts, next_attempt_at, \
+             cast(last_error as char) as last_error, created_at, sent_at"
+        }
+    }
+}
+
+fn email_from_row(row: &AnyRow) -> Result<Email, Error> {
+    Ok(Email {
+        id: row.try_get("id")?,
+        dedupe_key: row.try_get("dedupe_key")?,
+        recipient: row.try_get("recipient")?,
+        subject: row.try_get("subject")?,
+        body: row.try_get("body")?,
+        status: row.try_get("status")?,
+        attempts: row.try_get("attempts")?,
+        next_attempt_at: row.try_get("next_attempt_at")?,
+        last_error: row.try_get("last_error")?,
+        created_at: row.try_get("created_at")?,
+        sent_at: row.try_get("sent_at")?,
+    })
+}
+
+#[async_trait]
+impl EmailQueue for SqlEmailQueue {
+    async fn enqueue(
+        &self,
+        dedupe_key: &str,
+        recipient: &str,
+        subject: &str,
+        body: &str,
+        now: i64,
+    ) -> Result<bool, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let result = sqlx::query(&backend.sql(
+            "insert into email_queue (dedupe_key, recipient, subject, body, status, attempts, \
+             next_attempt_at, last_error, created_at, sent_at) \
+             values ($1, $2, $3, $4, 'pending', 0, $5, '', $6, 0)",
+        ))
+        .bind(dedupe_key)
+        .bind(recipient)
+        .bind(subject)
+        .bind(body)
+        .bind(now)
+        .bind(now)
+        .execute(&self.dbpool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(Error::Database(e)) if e.is_unique_violation() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Email>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from email_queue where status = 'pending' and next_attempt_at <= $1 \
+             order by next_attempt_at limit $2",
+            email_columns(backend)
+        );
+        let due = sqlx::query(&backend.sql(&sql))
+            .bind(now)
+            .bind(limit)
+            .fetch_all(&self.dbpool)
+            .await?;
+
+        // Only the instance whose update still finds the attempt it read
+        // gets the email.
+        let sql = backend.sql(
+            "update email_queue set next_attempt_at = $1 \
+             where id = $2 and status = 'pending' and next_attempt_at = $3",
+        );
+        let mut claimed = Vec::new();
+        for row in &due {
+            let mut email = email_from_row(row)?;
+            let result = sqlx::query(&sql)
+                .bind(lease_until)
+                .bind(email.id)
+                .bind(email.next_attempt_at)
+                .execute(&self.dbpool)
+                .await?;
+
+            if result.rows_affected() == 1 {
+                email.next_attempt_at = lease_until;
+                claimed.push(email);
+            }
+        }
+
+        Ok(claimed)
+    }
+
+    async fn record(&self, email: &Email) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql(
+            "update email_queue set status = $1, attempts = $2, next_attempt_at = $3, \
+             last_error = $4, sent_at = $5 where id = $6",
+        ))
+        .bind(&email.status)
+        .bind(email.attempts)
+        .bind(email.next_attempt_at)
+        .bind(&email.last_error)
+        .bind(email.sent_at)
+        .bind(email.id)
+        .execute(&self.dbpool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Keeps emails in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryEmailQueue {
+    state: Mutex<InMemoryState>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    emails: BTreeMap<i64, Email>,
+    last_id: i64,
+}
+
+#[async_trait]
+impl EmailQueue for InMemoryEmailQueue {
+    async fn enqueue(
+        &self,
+        dedupe_key: &str,
+        recipient: &str,
+        subject: &str,
+        body: &str,
+        now: i64,
+    ) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state
+            .emails
+            .values()
+            .any(|email| email.dedupe_key == dedupe_key)
+        {
+            return Ok(false);
+        }
+
+        state.last_id += 1;
+        let email = Email {
+            id: state.last_id,
+            dedupe_key: dedupe_key.to_string(),
+            recipient: recipient.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+            status: "pending".to_string(),
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: String::new(),
+            created_at: now,
+            sent_at: 0,
+        };
+        state.emails.insert(email.id, email);
+
+        Ok(true)
+    }
+
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Email>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        Ok(state
+            .emails
+            .values_mut()
+            .filter(|email| email.status == "pending" && email.next_attempt_at <= now)
+            .take(limit as usize)
+            .map(|email| {
+                email.next_attempt_at = lease_until;
+                email.clone()
+            })
+            .collect())
+    }
+
+    async fn record(&self, email: &Email) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(stored) = state.emails.get_mut(&email.id) {
+            *stored = email.clone();
+        }
+
+        Ok(())
+    }
+}
+
+// Sends the queued emails through SMTP_URL, and queues the daily reminder.
+#[derive(Clone)]
+pub struct Mailer {
+    queue: DynEmailQueue,
+    config: EmailConfig,
+    public_url: Option<String>,
+    preferences: Preferences,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    // Wakes the dispatcher up when emails are queued.
+    queued: Arc<Notify>,
+}
+
+impl Mailer {
+    pub fn new(
+        queue: DynEmailQueue,
+        config: EmailConfig,
+        public_url: Option<String>,
+        preferences: Preferences,
+    ) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url)
+            .map_err(|e| format!("invalid SMTP_URL: {}", e))?
+            .timeout(Some(config.timeout))
+            .build();
+
+        Ok(Mailer {
+            queue,
+            config,
+            public_url,
+            preferences,
+            transport,
+            queued: Arc::new(Notify::new()),
+        })
+    }
+
+    // Sends queued emails in the background as they come due, unless the
+    // schema is outdated, as the queue table may be missing then, and queues
+    // the reminder daily at EMAIL_REMINDER_AT. Attempts are logged under the
+    // `email` target.
+    pub fn spawn(&self, todos: DynTodoRepository, lifecycle: Lifecycle) {
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if !lifecycle.is_schema_outdated() {
+                    match mailer.dispatch().await {
+                        // More may be due already.
+                        Ok(attempted) if attempted > 0 => continue,
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::warn!(target: "email", error = %e, "couldn't dispatch emails")
+                        }
+                    }
+                }
+
+                tokio::select! {
+                    _ = mailer.queued.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+
+        if let Some(reminder_at) = self.config.reminder_at {
+            tokio::spawn(self.clone().remind(reminder_at, todos));
+        }
+    }
+
+    // Queues an email, keyed so that it is only ever sent once.
+    async fn enqueue(&self, dedupe_key: &str, to: &Mailbox, (subject, body): (String, String)) {
+        let now = chrono::Utc::now().timestamp();
+
+        match self
+            .queue
+            .enqueue(dedupe_key, &to.to_string(), &subject, &body, now)
+            .await
+        {
+            Ok(true) => self.queued.notify_one(),
+            Ok(false) => {}
+            Err(e) => {
+                tracing::warn!(target: "email", error = %e, dedupe_key, "couldn't queue email")
+            }
+        }
+    }
+
+    // Sends the emails due now, returning how many.
+    async fn dispatch(&self) -> Result<usize, Error> {
+        let now = chrono::Utc::now().timestamp();
+        // Past the timeout of an attempt, an email claimed by an instance that
+        // went away is due again.
+        let lease_until = now + self.config.timeout.as_secs() as i64 + 1;
+
+        let due = self.queue.claim(now, lease_until, BATCH_SIZE).await?;
+        let attempted = due.len();
+
+        for email in due {
+            let email = self.attempt(email).await;
+            self.queue.record(&email).await?;
+        }
+
+        Ok(attempted)
+    }
+
+    // Sends the email and returns it updated with the outcome. Only
+    // transient failures, like a relay that is down or greylisting, are
+    // retried.
+    async fn attempt(&self, mut email: Email) -> Email {
+        let result = match self.message(&email) {
+            Ok(message) => self
+                .transport
+                .send(message)
+                .await
+                .map_err(|e| (e.is_permanent(), e.to_string())),
+            Err(e) => Err((true, e)),
+        };
+
+        email.attempts += 1;
+        let now = chrono::Utc::now().timestamp();
+
+        let (permanent, error) = match result {
+            Ok(_) => {
+                tracing::info!(
+                    target: "email",
+                    email_id = email.id,
+                    queued_seconds = now - email.created_at,
+                    "email sent"
+                );
+                email.status = "sent".to_string();
+                email.last_error = String::new();
+                email.sent_at = now;
+                return email;
+            }
+            Err(failure) => failure,
+        };
+
+        if permanent || email.attempts >= self.config.max_attempts as i64 {
+            email.status = "failed".to_string();
+            tracing::warn!(
+                target: "email",
+                email_id = email.id,
+                attempts = email.attempts,
+                error = %error,
+                "email failed, giving up"
+            );
+        } else {
+            // Like webhook deliveries, rounded up to a whole second.
+            email.next_attempt_at = now
+                + backoff::delay(
+                    self.config.retry_base,
+                    self.config.retry_max,
+                    email.attempts as u32,
+                )
+                .as_secs_f64()
+                .ceil() as i64;
+            tracing::info!(
+                target: "email",
+                email_id = email.id,
+                attempts = email.attempts,
+                error = %error,
+                "email failed, will retry"
+            );
+        }
+        email.last_error = error;
+
+        email
+    }
+
+    fn message(&self, email: &Email) -> Result<Message, String> {
+        let to: Mailbox = email
+            .recipient
+            .parse()
+            .map_err(|e| format!("invalid recipient: {}", e))?;
+
+        Message::builder()
+            .from(self.config.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())
+            .map_err(|e| e.to_string())
+    }
+
+    // Unless its preference is disabled. Every instance queues the reminder,
+    // and the dedupe key, by day and recipient, lets only the first one
+    // through.
+    async fn remind(self, at: chrono::NaiveTime, todos: DynTodoRepository) {
+        loop {
+            tokio::time::sleep(telegram::until(at)).await;
+
+            let open: Vec<Todo> = match todos.list().await {
+                Ok(todos) => todos.into_iter().filter(|todo| !todo.completed).collect(),
+                Err(e) => {
+                    tracing::warn!(target: "email", error = %e, "couldn't list todos for the reminder");
+                    continue;
+                }
+            };
+            if open.is_empty() || !self.preferences.allows("email", "reminder").await {
+                continue;
+            }
+
+            let day = chrono::Utc::now().date_naive();
+            for to in &self.config.reminder_to {
+                let dedupe_key = format!("reminder:{}:{}", day, to.email);
+                self.enqueue(&dedupe_key, to, self.reminder(&open)).await;
+            }
+        }
+    }
+
+    // The subject and plain text body of the reminder, linking each todo
+    // under PUBLIC_URL if set.
+    fn reminder(&self, open: &[Todo]) -> (String, String) {
+        let subject = match open.len() {
+            1 => "1 open todo".to_string(),
+            n => format!("{} open todos", n),
+        };
+
+        let mut body = String::from("Open todos:\n\n");
+        for todo in open.iter().take(MAX_LISTED) {
+            body.push_str(&format!("#{} {}\n", todo.id, todo.body));
+            if let Some(public_url) = &self.public_url {
+                body.push_str(&format!("    {}/v1/todos/{}\n", public_url, todo.id));
+            }
+        }
+        if open.len() > MAX_LISTED {
+            body.push_str(&format!("and {} more\n", open.len() - MAX_LISTED));
+        }
+
+        (subject, body)
+    }
+}
@@ -0,0 +1,50 @@
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    api::{CountData, ListLinks, NextTodoData, SuggestionsData, TodoData, TodoListData},
+    negotiate::Representation,
+};
+
+// The body of every successful response, `{"status": "success", "data":
+// ...}`, with the data typed by the handler and, for lists of todos, their
+// HAL `_links` beside it. Failures are problems.
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    TodoEnvelope = Success<TodoData>,
+    TodoList = Success<TodoListData>,
+    TodoCount = Success<CountData>,
+    NextTodo = Success<NextTodoData>,
+    TodoSuggestions = Success<SuggestionsData>
+)]
+pub struct Success<T> {
+    #[schema(example = "success")]
+    status: &'static str,
+    pub data: T,
+    #[serde(rename = "_links", skip_serializing_if = "Option::is_none")]
+    pub links: Option<ListLinks>,
+}
+
+impl<T> Success<T> {
+    pub fn new(data: T) -> Self {
+        Success {
+            status: "success",
+            data,
+            links: None,
+        }
+    }
+
+    pub fn links(mut self, links: ListLinks) -> Self {
+        self.links = Some(links);
+        self
+    }
+}
+
+// Served through `negotiate`, which encodes it in the format the client
+// asked for.
+impl<T: Serialize> IntoResponse for Success<T> {
+    fn into_response(self) -> Response {
+        Representation(serde_json::to_value(self).unwrap_or_default()).into_response()
+    }
+}
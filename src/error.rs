@@ -1,25 +1,197 @@
-use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use std::sync::Arc;
 
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use crate::{
+    api::RELATIONS,
+    i18n::Message,
+    problem::Problem,
+    validation::{InvalidParams, Violation},
+};
+
+// The failures of the todo routes, and of requests no route serves, answered
+// as problems with their message rendered in the client's language by
+// `negotiate`.
 #[derive(Debug)]
-pub enum Error {
-    Sqlx(StatusCode, String),
-    NotFound,
+pub enum ApiError {
+    // No route has the path.
+    RouteNotFound(String),
+    // The route doesn't serve the method.
+    MethodNotAllowed {
+        method: String,
+        path: String,
+    },
+    // X-HTTP-Method-Override names a method POST can't tunnel.
+    InvalidMethodOverride(String),
+    // A parameter of the request path doesn't parse as the type expected.
+    InvalidPathParam {
+        name: String,
+        value: String,
+        expected: String,
+    },
+    InvalidQueryParam {
+        name: String,
+        error: String,
+    },
+    // `include` names a relation todos don't have.
+    UnknownRelation(String),
+    // A batch among the requests of a batch.
+    NestedBatch,
+    // The request body doesn't fit its type.
+    InvalidBody(String),
+    // Fields of the request body its type doesn't have, refused with
+    // STRICT_JSON.
+    UnknownFields(Vec<String>),
+    // Fields of the request body breaking its rules.
+    InvalidFields(Vec<Violation>),
+    LimitOutOfRange(usize),
+    // A time series whose `from` is after its `to`.
+    ReversedDateRange,
+    // A time series of more buckets than it can have.
+    TooManyBuckets(usize),
+    // A time series reaching past the days the databases store.
+    DateOutOfRange,
+    NotFound(i64),
+    PreconditionRequired,
+    // The todo changed since the client read it.
+    Modified(i64),
+    ModifiedOrDeleted(i64),
+    // What the database said is for the logs only.
+    Database(sqlx::Error),
+    // A handler panicked; what it panicked with is for the logs only.
+    Panic,
+}
+
+// Every database error is a server error: handlers say what a missing row
+// means with `row_not_found`.
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> ApiError {
+        ApiError::Database(err)
+    }
 }
 
-impl From<sqlx::Error> for Error {
-    fn from(err: sqlx::Error) -> Error {
-        match err {
-            sqlx::Error::RowNotFound => Error::NotFound,
-            _ => Error::Sqlx(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+// Maps RowNotFound to the error, like the todo being missing or modified.
+pub fn row_not_found(error: ApiError) -> impl FnOnce(sqlx::Error) -> ApiError {
+    move |err| match err {
+        sqlx::Error::RowNotFound => error,
+        err => err.into(),
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::RouteNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::InvalidBody(_) | ApiError::InvalidFields(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::InvalidMethodOverride(_)
+            | ApiError::NestedBatch
+            | ApiError::UnknownRelation(_)
+            | ApiError::InvalidPathParam { .. }
+            | ApiError::InvalidQueryParam { .. }
+            | ApiError::UnknownFields(_)
+            | ApiError::LimitOutOfRange(_)
+            | ApiError::ReversedDateRange
+            | ApiError::TooManyBuckets(_)
+            | ApiError::DateOutOfRange => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Modified(_) | ApiError::ModifiedOrDeleted(_) => {
+                StatusCode::PRECONDITION_FAILED
+            }
+            ApiError::Database(_) | ApiError::Panic => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> Message {
+        match self {
+            ApiError::RouteNotFound(path) => Message::new("route-not-found").arg("path", path),
+            ApiError::MethodNotAllowed { method, path } => Message::new("method-not-allowed")
+                .arg("method", method)
+                .arg("path", path),
+            ApiError::InvalidMethodOverride(method) => {
+                Message::new("invalid-method-override").arg("method", method)
+            }
+            ApiError::InvalidPathParam {
+                name,
+                value,
+                expected,
+            } => Message::new("invalid-path-param")
+                .arg("name", name)
+                .arg("value", value)
+                .arg("expected", expected),
+            ApiError::InvalidQueryParam { name, error } => Message::new("invalid-query-param")
+                .arg("name", name)
+                .arg("error", error),
+            ApiError::UnknownRelation(relation) => {
+                Message::new("unknown-relation").arg("relation", relation)
+            }
+            ApiError::NestedBatch => Message::new("nested-batch"),
+            ApiError::InvalidBody(error) => Message::new("invalid-json").arg("error", error),
+            ApiError::UnknownFields(fields) => {
+                Message::new("unknown-fields").arg("fields", fields.join(", "))
+            }
+            ApiError::InvalidFields(_) => Message::new("invalid-fields"),
+            ApiError::LimitOutOfRange(max) => Message::new("limit-out-of-range").arg("max", max),
+            ApiError::ReversedDateRange => Message::new("reversed-date-range"),
+            ApiError::TooManyBuckets(max) => Message::new("too-many-buckets").arg("max", max),
+            ApiError::DateOutOfRange => Message::new("date-out-of-range"),
+            ApiError::NotFound(id) => Message::new("todo-not-found").arg("id", id),
+            ApiError::PreconditionRequired => Message::new("if-match-required"),
+            ApiError::Modified(id) => Message::new("todo-modified").arg("id", id),
+            ApiError::ModifiedOrDeleted(id) => {
+                Message::new("todo-modified-or-deleted").arg("id", id)
+            }
+            ApiError::Database(_) => Message::new("database-error"),
+            ApiError::Panic => Message::new("internal-error"),
         }
     }
 }
 
-impl IntoResponse for Error {
+// Logged within the request's span, so with its id, which clients quote from
+// the problem.
+fn log_database_error(err: &sqlx::Error) {
+    tracing::error!(error = %err, "database error");
+}
+
+// Database errors of the routes failing with problems of their own, detailed
+// like those of the todo routes.
+impl From<sqlx::Error> for Problem {
+    fn from(err: sqlx::Error) -> Problem {
+        log_database_error(&err);
+        let error = ApiError::Database(err);
+        Problem::new(error.status()).detail(error.message().to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        let message = self.message();
+        let mut problem = Problem::new(self.status()).detail(message.to_string());
         match self {
-            Error::Sqlx(code, body) => (code, body).into_response(),
-            Error::NotFound => StatusCode::NOT_FOUND.into_response(),
+            ApiError::InvalidFields(violations) => {
+                let invalid = InvalidParams(Arc::new(violations));
+                problem
+                    .extensions
+                    .insert("invalid-params".to_string(), invalid.to_value("en"));
+                (message, Extension(invalid), problem).into_response()
+            }
+            // With the relations that can be included instead.
+            ApiError::UnknownRelation(_) => {
+                let problem = problem.extension("relations", RELATIONS.to_vec());
+                (message, problem).into_response()
+            }
+            ApiError::Database(err) => {
+                log_database_error(&err);
+                (message, problem).into_response()
+            }
+            _ => (message, problem).into_response(),
         }
     }
 }
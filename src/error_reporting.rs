@@ -0,0 +1,109 @@
+use std::any::Any;
+
+use axum::{
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sentry::integrations::tracing::EventFilter;
+
+use crate::{access_log::Principal, config::Config, error::ApiError, redact::Redactor, request_id};
+
+// Starts the Sentry client when SENTRY_DSN is set. The returned guard flushes
+// pending events on drop, so it must live until the server shuts down.
+pub fn init(config: &Config, redactor: Redactor) -> Option<sentry::ClientInitGuard> {
+    let dsn = config.sentry_dsn.as_deref()?;
+
+    let breadcrumb_redactor = redactor.clone();
+
+    Some(sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            environment: config.sentry_environment.clone().map(Into::into),
+            attach_stacktrace: true,
+            before_breadcrumb: Some(std::sync::Arc::new(move |mut breadcrumb| {
+                breadcrumb.message = breadcrumb
+                    .message
+                    .map(|message| breadcrumb_redactor.redact(&message).into_owned());
+                Some(breadcrumb)
+            })),
+            before_send: Some(std::sync::Arc::new(move |mut event| {
+                event.message = event
+                    .message
+                    .map(|message| redactor.redact(&message).into_owned());
+                Some(event)
+            })),
+            ..Default::default()
+        },
+    )))
+}
+
+// Forwards tracing events to the request's Sentry hub: errors become events,
+// everything down to info is kept as breadcrumbs. Access log errors are only
+// breadcrumbs since `report_server_errors` already captures 5xx responses,
+// and so are panics, which Sentry's panic hook captures with their backtrace.
+pub fn tracing_layer<S>() -> sentry::integrations::tracing::SentryLayer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    sentry::integrations::tracing::layer().event_filter(|metadata| match *metadata.level() {
+        tracing::Level::ERROR if !matches!(metadata.target(), "access_log" | "panic") => {
+            EventFilter::Event
+        }
+        tracing::Level::ERROR | tracing::Level::WARN | tracing::Level::INFO => {
+            EventFilter::Breadcrumb
+        }
+        _ => EventFilter::Ignore,
+    })
+}
+
+// Captures 5xx responses to Sentry, tagged with the request id and the
+// authenticated principal when there is one.
+pub async fn report_server_errors(request: Request, next: Next) -> Response {
+    let request_id = request_id::request_id(&request).map(ToOwned::to_owned);
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+
+    let response = next.run(request).await;
+
+    if response.status().is_server_error() {
+        let principal = response.extensions().get::<Principal>().cloned();
+
+        sentry::with_scope(
+            |scope| {
+                if let Some(request_id) = &request_id {
+                    scope.set_tag("request_id", request_id);
+                }
+                if let Some(principal) = principal {
+                    scope.set_user(Some(sentry::User {
+                        id: Some(principal.0),
+                        ..Default::default()
+                    }));
+                }
+            },
+            || {
+                sentry::capture_message(
+                    &format!("{} {} responded {}", method, path, response.status()),
+                    sentry::Level::Error,
+                )
+            },
+        );
+    }
+
+    response
+}
+
+// The response to a request whose handler panicked, instead of dropping the
+// connection: a 500 like any other, which gets the request id and is reported
+// like any other.
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    tracing::error!(target: "panic", panic = %message, "handler panicked");
+
+    ApiError::Panic.into_response()
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
+use crate::todo::Todo;
+
+// A change to the todos, published once it is committed.
+#[derive(Clone)]
+pub enum TodoEvent {
+    Created(Todo),
+    Updated(Todo),
+    Deleted(i64),
+}
+
+impl TodoEvent {
+    pub fn id(&self) -> i64 {
+        match self {
+            TodoEvent::Created(todo) | TodoEvent::Updated(todo) => todo.id,
+            TodoEvent::Deleted(id) => *id,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TodoEvent::Created(_) => "created",
+            TodoEvent::Updated(_) => "updated",
+            TodoEvent::Deleted(_) => "deleted",
+        }
+    }
+
+    // `{"type":"updated","id":1,"todo":{...}}`, without the todo for
+    // deletions.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            TodoEvent::Created(todo) | TodoEvent::Updated(todo) => {
+                serde_json::json!({"type": self.kind(), "id": todo.id, "todo": todo})
+            }
+            TodoEvent::Deleted(id) => serde_json::json!({"type": self.kind(), "id": id}),
+        }
+    }
+}
+
+#[async_trait]
+pub trait TodoEventHandler: Send + Sync {
+    async fn handle(&self, event: &TodoEvent);
+}
+
+// Delivers todo events to the handlers subscribed at startup, in order. The
+// write that published an event only completes once every handler has run,
+// so a client reading back its own write sees caches already invalidated.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    handlers: Vec<Arc<dyn TodoEventHandler>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, handler: Arc<dyn TodoEventHandler>) {
+        self.handlers.push(handler);
+    }
+
+    pub async fn publish(&self, event: TodoEvent) {
+        for handler in &self.handlers {
+            handler.handle(&event).await;
+        }
+    }
+}
+
+// Forwards events to live subscribers, such as GraphQL subscriptions. Each
+// subscriber gets its own queue of `capacity` events; one that falls further
+// behind misses events instead of holding up writes.
+//
+// Events are numbered in the order they are published, and the last
+// `capacity` are kept, so a subscriber that reconnects can catch up on what it
+// missed. Numbers restart with the process, which `run` tells apart.
+#[derive(Clone)]
+pub struct EventStream {
+    run: i64,
+    capacity: usize,
+    sender: broadcast::Sender<NumberedEvent>,
+    history: Arc<Mutex<VecDeque<NumberedEvent>>>,
+}
+
+#[derive(Clone)]
+pub struct NumberedEvent {
+    pub seq: u64,
+    pub event: TodoEvent,
+}
+
+// Where a resuming subscriber stands.
+pub enum Resume {
+    // The events published since the one it last saw.
+    Missed(Vec<NumberedEvent>),
+    // The event it last saw is too old or from an earlier run: it has to
+    // refetch, then carry on from `seq`, the latest event.
+    Lost { seq: u64 },
+}
+
+impl EventStream {
+    pub fn new(capacity: usize) -> Self {
+        EventStream {
+            run: chrono::Utc::now().timestamp_millis(),
+            capacity,
+            sender: broadcast::channel(capacity).0,
+            history: Arc::default(),
+        }
+    }
+
+    // The number of the latest event, 0 before the first.
+    pub fn latest(&self) -> u64 {
+        self.history
+            .lock()
+            .unwrap()
+            .back()
+            .map_or(0, |last| last.seq)
+    }
+
+    // `<run>-<seq>`, handed to clients to resume from, so numbers from before
+    // a restart aren't taken for current ones.
+    pub fn cursor(&self, seq: u64) -> String {
+        format!("{}-{}", self.run, seq)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NumberedEvent> {
+        self.sender.subscribe()
+    }
+
+    // Subscribes along with the events published after `seq` of `run`,
+    // neither missing nor repeating any in between.
+    pub fn resume(&self, run: i64, seq: u64) -> (Resume, broadcast::Receiver<NumberedEvent>) {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let next = history.back().map_or(1, |last| last.seq + 1);
+        let oldest = history.front().map_or(next, |first| first.seq);
+
+        let resume = if run != self.run || seq >= next || seq + 1 < oldest {
+            Resume::Lost { seq: next - 1 }
+        } else {
+            Resume::Missed(
+                history
+                    .iter()
+                    .filter(|numbered| numbered.seq > seq)
+                    .cloned()
+                    .collect(),
+            )
+        };
+
+        (resume, receiver)
+    }
+}
+
+// The run and event number of a cursor.
+pub fn parse_cursor(cursor: &str) -> Option<(i64, u64)> {
+    let (run, seq) = cursor.split_once('-')?;
+    Some((run.parse().ok()?, seq.parse().ok()?))
+}
+
+#[async_trait]
+impl TodoEventHandler for EventStream {
+    async fn handle(&self, event: &TodoEvent) {
+        let mut history = self.history.lock().unwrap();
+
+        let numbered = NumberedEvent {
+            seq: history.back().map_or(1, |last| last.seq + 1),
+            event: event.clone(),
+        };
+
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(numbered.clone());
+
+        // Fails only when nobody is subscribed.
+        let _ = self.sender.send(numbered);
+    }
+}
@@ -0,0 +1,28 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+
+// Requests for paths no route has.
+pub async fn not_found(uri: Uri) -> ApiError {
+    ApiError::RouteNotFound(uri.path().to_string())
+}
+
+// Gives a body to the empty 405 answered for methods a route doesn't serve.
+// The router lists those it does in Allow once every layer has run.
+pub async fn method_not_allowed(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    let empty = !response.headers().contains_key(header::CONTENT_TYPE);
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED || !empty {
+        return response;
+    }
+
+    ApiError::MethodNotAllowed { method, path }.into_response()
+}
@@ -0,0 +1,287 @@
+use async_graphql::{
+    connection::{self, Connection, Edge},
+    futures_util::Stream,
+    http::GraphiQLSource,
+    Context, Enum, ErrorExtensions, InputObject, Object, Result, Schema, SimpleObject,
+    Subscription,
+};
+use axum::response::Html;
+use chrono::NaiveDateTime;
+
+use crate::{
+    events::{EventStream, TodoEvent},
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, Todo, UpdateTodo},
+};
+
+pub type TodoSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+// Present when writes are refused while migrations are pending, like the REST
+// routes do.
+struct RefuseWrites(Lifecycle);
+
+// The todos over GraphQL, backed by the same repository as the REST routes,
+// so caching, retries and change events apply alike.
+pub fn schema(
+    todos: DynTodoRepository,
+    events: EventStream,
+    refuse_writes: Option<Lifecycle>,
+) -> TodoSchema {
+    let mut schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(todos)
+        .data(events);
+    if let Some(lifecycle) = refuse_writes {
+        schema = schema.data(RefuseWrites(lifecycle));
+    }
+
+    schema.finish()
+}
+
+pub async fn graphiql() -> Html<String> {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/graphql/ws")
+            .finish(),
+    )
+}
+
+struct TodoNode(Todo);
+
+#[Object(name = "Todo")]
+impl TodoNode {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn body(&self) -> &str {
+        &self.0.body
+    }
+
+    async fn completed(&self) -> bool {
+        self.0.completed
+    }
+
+    async fn created_at(&self) -> NaiveDateTime {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> NaiveDateTime {
+        self.0.updated_at
+    }
+
+    // Incremented by every update; pass it back to update or delete only
+    // the revision that was read.
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+}
+
+// Only todos with this completion state, or whose body contains `search`.
+#[derive(InputObject, Default)]
+struct TodoFilter {
+    completed: Option<bool>,
+    search: Option<String>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn todo(&self, ctx: &Context<'_>, id: i64) -> Result<Option<TodoNode>> {
+        match ctx.data_unchecked::<DynTodoRepository>().read(id).await {
+            Ok(todo) => Ok(Some(TodoNode(todo))),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(database_error(e)),
+        }
+    }
+
+    // Todos in id order, paginated with cursors.
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: TodoFilter,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<i64, TodoNode>> {
+        let mut todos = ctx
+            .data_unchecked::<DynTodoRepository>()
+            .list()
+            .await
+            .map_err(database_error)?;
+
+        todos.retain(|todo| {
+            filter
+                .completed
+                .is_none_or(|completed| todo.completed == completed)
+                && filter
+                    .search
+                    .as_deref()
+                    .is_none_or(|search| todo.body.contains(search))
+        });
+        todos.sort_by_key(|todo| todo.id);
+
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after: Option<i64>, before: Option<i64>, first, last| async move {
+                let start = after.map_or(0, |after| todos.partition_point(|todo| todo.id <= after));
+                let end = before.map_or(todos.len(), |before| {
+                    todos.partition_point(|todo| todo.id < before)
+                });
+                let (mut start, mut end) = (start, end.max(start));
+
+                if let Some(first) = first {
+                    end = end.min(start + first);
+                }
+                if let Some(last) = last {
+                    start = start.max(end.saturating_sub(last));
+                }
+
+                let mut connection = Connection::new(start > 0, end < todos.len());
+                connection.edges.extend(
+                    todos
+                        .drain(start..end)
+                        .map(|todo| Edge::new(todo.id, TodoNode(todo))),
+                );
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_todo(&self, ctx: &Context<'_>, body: String) -> Result<TodoNode> {
+        check_writable(ctx)?;
+
+        ctx.data_unchecked::<DynTodoRepository>()
+            .create(CreateTodo::new(body))
+            .await
+            .map(TodoNode)
+            .map_err(database_error)
+    }
+
+    // Applies only while the todo is still at `version`, so concurrent edits
+    // can't overwrite each other.
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        version: i64,
+        body: String,
+        completed: bool,
+    ) -> Result<TodoNode> {
+        check_writable(ctx)?;
+
+        ctx.data_unchecked::<DynTodoRepository>()
+            .update(id, UpdateTodo::new(body, completed), version)
+            .await
+            .map(TodoNode)
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => modified_error(id),
+                e => database_error(e),
+            })
+    }
+
+    // With `version`, only deletes that revision of the todo. False when it
+    // was already gone.
+    async fn delete_todo(&self, ctx: &Context<'_>, id: i64, version: Option<i64>) -> Result<bool> {
+        check_writable(ctx)?;
+
+        match ctx
+            .data_unchecked::<DynTodoRepository>()
+            .delete(id, version)
+            .await
+        {
+            Ok(deleted) => Ok(deleted > 0),
+            Err(sqlx::Error::RowNotFound) => Err(modified_error(id)),
+            Err(e) => Err(database_error(e)),
+        }
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(SimpleObject)]
+struct TodoChange {
+    kind: ChangeKind,
+    id: i64,
+    // The todo as written; null when deleted.
+    todo: Option<TodoNode>,
+}
+
+impl From<TodoEvent> for TodoChange {
+    fn from(event: TodoEvent) -> Self {
+        let id = event.id();
+        let (kind, todo) = match event {
+            TodoEvent::Created(todo) => (ChangeKind::Created, Some(TodoNode(todo))),
+            TodoEvent::Updated(todo) => (ChangeKind::Updated, Some(TodoNode(todo))),
+            TodoEvent::Deleted(_) => (ChangeKind::Deleted, None),
+        };
+
+        TodoChange { kind, id, todo }
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    // Todos created, updated and deleted from now on, through any API of this
+    // instance. A subscriber too slow to keep up skips the events it missed.
+    async fn todo_changes(&self, ctx: &Context<'_>) -> impl Stream<Item = TodoChange> {
+        use tokio::sync::broadcast::error::RecvError;
+
+        let mut events = ctx.data_unchecked::<EventStream>().subscribe();
+
+        async_graphql::async_stream::stream! {
+            loop {
+                match events.recv().await {
+                    Ok(numbered) => yield TodoChange::from(numbered.event),
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "graphql subscriber fell behind, events skipped");
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
+
+fn check_writable(ctx: &Context<'_>) -> Result<()> {
+    match ctx.data_opt::<RefuseWrites>() {
+        Some(RefuseWrites(lifecycle)) if lifecycle.is_schema_outdated() => Err(
+            async_graphql::Error::new("database migrations are pending, writes are disabled")
+                .extend_with(|_, e| e.set("code", "UNAVAILABLE")),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn modified_error(id: i64) -> async_graphql::Error {
+    async_graphql::Error::new(format!(
+        "todo with ID: {} was modified or deleted, fetch it and retry",
+        id
+    ))
+    .extend_with(|_, e| e.set("code", "CONFLICT"))
+}
+
+fn database_error(e: sqlx::Error) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Database error: {}", e))
+        .extend_with(|_, e| e.set("code", "INTERNAL"))
+}
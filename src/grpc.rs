@@ -0,0 +1,191 @@
+// tonic::Status is large, and the generated service trait returns it anyway.
+#![allow(clippy::result_large_err)]
+
+use chrono::NaiveDateTime;
+use tonic::{Request, Response, Status};
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tower::Layer;
+
+use crate::{
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, Todo, UpdateTodo},
+};
+
+pub mod pb {
+    tonic::include_proto!("todo.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("todo_descriptor");
+}
+
+use pb::todo_service_server::{TodoService, TodoServiceServer};
+
+// The `todo.v1.TodoService` of `proto/todo/v1/todo.proto`, backed by the
+// same repository as the REST routes, so caching, retries and change events
+// apply alike.
+pub struct TodoGrpc {
+    todos: DynTodoRepository,
+    // Present when writes are refused while migrations are pending, like the
+    // REST routes do.
+    refuse_writes: Option<Lifecycle>,
+}
+
+// Served on the main listener: requests with a gRPC content type are routed
+// here by path, `/todo.v1.TodoService/<method>`, along with the health
+// service and server reflection, which lets grpcurl and the like discover
+// both without the proto files. Browsers can call them too, over gRPC-Web
+// with HTTP/1.1; CORS is handled by the router like for the other routes.
+pub fn routes(
+    todos: DynTodoRepository,
+    refuse_writes: Option<Lifecycle>,
+    health: HealthServer<impl Health>,
+) -> axum::Router {
+    // Built from descriptors embedded at compile time, so it can't fail.
+    let reflection = || {
+        tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+    };
+
+    // Passes plain gRPC requests through.
+    let web = tonic_web::GrpcWebLayer::new();
+
+    tonic::service::Routes::new(web.layer(TodoServiceServer::new(TodoGrpc {
+        todos,
+        refuse_writes,
+    })))
+    .add_service(web.layer(health))
+    .add_service(web.layer(reflection().build_v1().unwrap()))
+    .add_service(web.layer(reflection().build_v1alpha().unwrap()))
+    .into_axum_router()
+}
+
+impl TodoGrpc {
+    fn check_writable(&self) -> Result<(), Status> {
+        match &self.refuse_writes {
+            Some(lifecycle) if lifecycle.is_schema_outdated() => Err(Status::unavailable(
+                "database migrations are pending, writes are disabled",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+type TodoStream = tokio_stream::Iter<std::vec::IntoIter<Result<pb::Todo, Status>>>;
+
+#[tonic::async_trait]
+impl TodoService for TodoGrpc {
+    type ListTodosStream = TodoStream;
+
+    async fn list_todos(
+        &self,
+        _request: Request<pb::ListTodosRequest>,
+    ) -> Result<Response<Self::ListTodosStream>, Status> {
+        let mut todos = self.todos.list().await.map_err(database_error)?;
+        todos.sort_by_key(|todo| todo.id);
+
+        let todos = todos
+            .into_iter()
+            .map(|todo| Ok(pb::Todo::from(todo)))
+            .collect::<Vec<_>>();
+
+        Ok(Response::new(tokio_stream::iter(todos)))
+    }
+
+    async fn get_todo(
+        &self,
+        request: Request<pb::GetTodoRequest>,
+    ) -> Result<Response<pb::Todo>, Status> {
+        let id = request.into_inner().id;
+
+        match self.todos.read(id).await {
+            Ok(todo) => Ok(Response::new(todo.into())),
+            Err(sqlx::Error::RowNotFound) => {
+                Err(Status::not_found(format!("todo with ID: {} not found", id)))
+            }
+            Err(e) => Err(database_error(e)),
+        }
+    }
+
+    async fn create_todo(
+        &self,
+        request: Request<pb::CreateTodoRequest>,
+    ) -> Result<Response<pb::Todo>, Status> {
+        self.check_writable()?;
+
+        self.todos
+            .create(CreateTodo::new(request.into_inner().body))
+            .await
+            .map(|todo| Response::new(todo.into()))
+            .map_err(database_error)
+    }
+
+    async fn update_todo(
+        &self,
+        request: Request<pb::UpdateTodoRequest>,
+    ) -> Result<Response<pb::Todo>, Status> {
+        self.check_writable()?;
+
+        let request = request.into_inner();
+        self.todos
+            .update(
+                request.id,
+                UpdateTodo::new(request.body, request.completed),
+                request.version,
+            )
+            .await
+            .map(|todo| Response::new(todo.into()))
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => modified_error(request.id),
+                e => database_error(e),
+            })
+    }
+
+    async fn delete_todo(
+        &self,
+        request: Request<pb::DeleteTodoRequest>,
+    ) -> Result<Response<pb::DeleteTodoResponse>, Status> {
+        self.check_writable()?;
+
+        let request = request.into_inner();
+        match self.todos.delete(request.id, request.version).await {
+            Ok(_) => Ok(Response::new(pb::DeleteTodoResponse {})),
+            Err(sqlx::Error::RowNotFound) => Err(modified_error(request.id)),
+            Err(e) => Err(database_error(e)),
+        }
+    }
+}
+
+impl From<Todo> for pb::Todo {
+    fn from(todo: Todo) -> Self {
+        pb::Todo {
+            id: todo.id,
+            body: todo.body,
+            completed: todo.completed,
+            created_at: Some(timestamp(todo.created_at)),
+            updated_at: Some(timestamp(todo.updated_at)),
+            version: todo.version,
+        }
+    }
+}
+
+// Todo timestamps are UTC.
+fn timestamp(time: NaiveDateTime) -> prost_types::Timestamp {
+    let time = time.and_utc();
+
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn modified_error(id: i64) -> Status {
+    Status::aborted(format!(
+        "todo with ID: {} was modified or deleted, fetch it and retry",
+        id
+    ))
+}
+
+fn database_error(e: sqlx::Error) -> Status {
+    Status::internal(format!("Database error: {}", e))
+}
@@ -0,0 +1,502 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tonic_health::pb::health_server::{Health, HealthServer};
+
+use crate::{
+    build_info::BuildInfo,
+    db::DbPool,
+    problem::Problem,
+    state::{AppState, Lifecycle},
+};
+
+// Liveness: the process is up and serving requests. Deliberately checks no
+// dependencies, so a database outage doesn't get the pod restarted. Like the
+// other probes it answers a bare body outside the envelope: orchestrators
+// only read the status, and a failing probe isn't a problem of the request.
+pub async fn live() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "alive",
+    }))
+}
+
+// Readiness: the database is reachable, every embedded migration has been
+// applied and the server isn't draining for shutdown, as last seen by the
+// database monitor, so probes never wait on the database. The in-memory
+// storage backend has no database, so those checks are skipped.
+struct Readiness {
+    database: Option<Result<(), String>>,
+    migrations: Option<Result<(), String>>,
+    draining: bool,
+}
+
+impl Readiness {
+    fn check(dbpool: Option<&DbPool>, db_health: &DatabaseHealth, lifecycle: &Lifecycle) -> Self {
+        let (database, migrations) = match dbpool {
+            Some(_) => (
+                Some(db_health.latest().map(|_| ())),
+                Some(db_health.migrations()),
+            ),
+            None => (None, None),
+        };
+
+        Readiness {
+            database,
+            migrations,
+            draining: lifecycle.is_draining(),
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.database
+            .iter()
+            .chain(&self.migrations)
+            .all(Result::is_ok)
+            && !self.draining
+    }
+}
+
+// Its 503 is a state, with the checks behind it, not a problem, so the body
+// stays the same shape either way.
+pub async fn ready(
+    State(dbpool): State<Option<DbPool>>,
+    State(db_health): State<DatabaseHealth>,
+    State(lifecycle): State<Lifecycle>,
+) -> impl IntoResponse {
+    let readiness = Readiness::check(dbpool.as_ref(), &db_health, &lifecycle);
+    let is_ready = readiness.is_ready();
+
+    let json_response = serde_json::json!({
+        "status": if is_ready { "ready" } else { "not ready" },
+        "checks": {
+            "database": check_status(readiness.database.as_ref()),
+            "migrations": check_status(readiness.migrations.as_ref()),
+            "draining": readiness.draining,
+        },
+    });
+
+    let status = if is_ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(json_response))
+}
+
+// The gRPC health checking protocol, `grpc.health.v1.Health`, for load
+// balancers probing over gRPC. The server as a whole ("") and the todo
+// service report SERVING exactly when `/healthz/ready` would answer 200,
+// re-checked every second and straight away once draining starts.
+pub fn grpc_health(state: &AppState) -> HealthServer<impl Health> {
+    use crate::grpc::pb::todo_service_server::SERVICE_NAME;
+    use tonic_health::ServingStatus;
+
+    let (mut reporter, service) = tonic_health::server::health_reporter();
+
+    let dbpool = state.dbpool.clone();
+    let db_health = state.db_health.clone();
+    let lifecycle = state.lifecycle.clone();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let draining = tokio::select! {
+                _ = interval.tick() => false,
+                _ = lifecycle.draining() => true,
+            };
+
+            let status = if !draining
+                && Readiness::check(dbpool.as_ref(), &db_health, &lifecycle).is_ready()
+            {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+
+            for service_name in ["", SERVICE_NAME] {
+                reporter.set_service_status(service_name, status).await;
+            }
+
+            // Draining never ends, so neither does NOT_SERVING.
+            if draining {
+                break;
+            }
+        }
+    });
+
+    service
+}
+
+fn check_status(check: Option<&Result<(), String>>) -> serde_json::Value {
+    match check {
+        Some(Ok(())) => "ok".into(),
+        Some(Err(message)) => message.as_str().into(),
+        None => "disabled".into(),
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Pass,
+    Disabled,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+pub struct ComponentHealth {
+    status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ComponentHealth {
+    fn new(status: HealthStatus) -> Self {
+        ComponentHealth {
+            status,
+            message: None,
+            details: serde_json::Map::new(),
+        }
+    }
+
+    fn failed(message: String) -> Self {
+        ComponentHealth {
+            message: Some(message),
+            ..ComponentHealth::new(HealthStatus::Fail)
+        }
+    }
+
+    fn detail(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+// Detailed health: the status of each dependency, plus build and uptime
+// information. The overall status is the worst component status; `fail`
+// responds 503, `warn` still responds 200. Monitors read `status` and
+// `components` at the top level whatever the code, as in the health check
+// response format, so there is no envelope.
+pub async fn details(State(state): State<AppState>) -> impl IntoResponse {
+    let mut components = BTreeMap::new();
+
+    match &state.dbpool {
+        Some(_) => {
+            let database = state.db_health.latest();
+            let migrations = state.db_health.migrations();
+
+            components.insert(
+                "database",
+                match database {
+                    // A ping as slow as a slow query means the database is struggling.
+                    Ok(latency) if latency >= state.config.slow_query_threshold => {
+                        ComponentHealth {
+                            message: Some("high latency".to_string()),
+                            ..ComponentHealth::new(HealthStatus::Warn)
+                        }
+                        .detail("latency_ms", latency.as_secs_f64() * 1000.0)
+                    }
+                    Ok(latency) => ComponentHealth::new(HealthStatus::Pass)
+                        .detail("latency_ms", latency.as_secs_f64() * 1000.0),
+                    Err(message) => ComponentHealth::failed(message),
+                },
+            );
+
+            components.insert(
+                "migrations",
+                match migrations {
+                    Ok(()) => ComponentHealth::new(HealthStatus::Pass),
+                    Err(message) => ComponentHealth::failed(message),
+                },
+            );
+
+            components.insert("storage", check_storage(&state.config.database_url));
+        }
+        None => {
+            for name in ["database", "migrations", "storage"] {
+                components.insert(name, ComponentHealth::new(HealthStatus::Disabled));
+            }
+        }
+    }
+
+    components.insert(
+        "cache",
+        match (&state.dbpool, state.config.read_cache, &state.redis) {
+            // Reads fall back to the database while Redis is unreachable.
+            (Some(_), Some(read_cache), Some(redis)) => match redis.ping().await {
+                Ok(latency) => ComponentHealth::new(HealthStatus::Pass)
+                    .detail("backend", "redis")
+                    .detail("latency_ms", latency.as_secs_f64() * 1000.0),
+                Err(e) => ComponentHealth {
+                    message: Some(format!("redis unreachable: {}", e)),
+                    ..ComponentHealth::new(HealthStatus::Warn)
+                }
+                .detail("backend", "redis"),
+            }
+            .detail("ttl_seconds", read_cache.ttl.as_secs()),
+            (Some(_), Some(read_cache), None) => ComponentHealth::new(HealthStatus::Pass)
+                .detail("backend", "memory")
+                .detail("ttl_seconds", read_cache.ttl.as_secs()),
+            _ => ComponentHealth::new(HealthStatus::Disabled),
+        },
+    );
+
+    let status = components
+        .values()
+        .map(|component| component.status)
+        .filter(|status| *status != HealthStatus::Disabled)
+        .max()
+        .unwrap_or(HealthStatus::Pass);
+
+    let build = BuildInfo::current();
+
+    let json_response = serde_json::json!({
+        "status": status,
+        "version": build.version,
+        "git_sha": build.git_sha,
+        "uptime_seconds": state.lifecycle.uptime().as_secs(),
+        "draining": state.lifecycle.is_draining(),
+        "components": components,
+    });
+
+    let code = if status == HealthStatus::Fail {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    (code, Json(json_response))
+}
+
+// The latest database ping and migrations check by the background monitor,
+// shared with the health endpoints so probes report them instead of waiting
+// on the database.
+#[derive(Clone)]
+pub struct DatabaseHealth {
+    interval: Duration,
+    latest: Arc<RwLock<Option<DatabaseCheck>>>,
+}
+
+struct DatabaseCheck {
+    checked_at: Instant,
+    latency: Result<Duration, String>,
+    migrations: Result<(), String>,
+}
+
+impl DatabaseHealth {
+    pub fn new(interval: Duration) -> Self {
+        DatabaseHealth {
+            interval,
+            latest: Arc::default(),
+        }
+    }
+
+    fn latest(&self) -> Result<Duration, String> {
+        self.fresh(|check| check.latency.clone())
+    }
+
+    fn migrations(&self) -> Result<(), String> {
+        self.fresh(|check| check.migrations.clone())
+    }
+
+    // A result older than a few intervals means the monitor is stuck and the
+    // database can't be vouched for.
+    fn fresh<T>(&self, result: impl Fn(&DatabaseCheck) -> Result<T, String>) -> Result<T, String> {
+        match &*self.latest.read().unwrap() {
+            None => Err("not checked yet".to_string()),
+            Some(check) if check.checked_at.elapsed() > self.interval * 3 => Err(format!(
+                "last checked {}s ago",
+                check.checked_at.elapsed().as_secs()
+            )),
+            Some(check) => result(check),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Up,
+    Slow,
+    Down,
+}
+
+// Pings the database every interval, and checks for pending migrations,
+// recording the outcome in `health` and whether the schema is outdated in the
+// lifecycle. Logs a warning when the database becomes unreachable or its
+// latency reaches `slow_threshold`, and again when it recovers.
+pub fn spawn_monitor(
+    dbpool: DbPool,
+    health: DatabaseHealth,
+    lifecycle: Lifecycle,
+    slow_threshold: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(health.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut previous = Condition::Up;
+
+        loop {
+            interval.tick().await;
+
+            let result = tokio::time::timeout(health.interval, check_database(&dbpool))
+                .await
+                .unwrap_or_else(|_| Err("ping timed out".to_string()));
+
+            let condition = match &result {
+                Ok(latency) if *latency >= slow_threshold => Condition::Slow,
+                Ok(_) => Condition::Up,
+                Err(_) => Condition::Down,
+            };
+
+            match (&result, previous, condition) {
+                (_, previous, current) if previous == current => {}
+                (Err(e), _, _) => tracing::warn!(error = %e, "database unreachable"),
+                (Ok(latency), _, Condition::Slow) => tracing::warn!(
+                    latency_ms = latency.as_secs_f64() * 1000.0,
+                    "database latency degraded"
+                ),
+                (Ok(latency), _, _) => tracing::info!(
+                    latency_ms = latency.as_secs_f64() * 1000.0,
+                    "database healthy again"
+                ),
+            }
+            previous = condition;
+
+            let migrations = match result {
+                Ok(_) => {
+                    tokio::time::timeout(health.interval, check_migrations(&dbpool, &lifecycle))
+                        .await
+                        .unwrap_or_else(|_| Err("migrations check timed out".to_string()))
+                }
+                Err(_) => Err("database unavailable".to_string()),
+            };
+
+            *health.latest.write().unwrap() = Some(DatabaseCheck {
+                checked_at: Instant::now(),
+                latency: result,
+                migrations,
+            });
+        }
+    });
+}
+
+// Round-trip time of a ping on a pooled connection.
+async fn check_database(dbpool: &DbPool) -> Result<Duration, String> {
+    use sqlx::Connection;
+
+    let start = Instant::now();
+
+    let mut conn = dbpool
+        .acquire()
+        .await
+        .map_err(|e| format!("Pool acquire error: {}", e))?;
+
+    conn.ping()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    Ok(start.elapsed())
+}
+
+// Also refreshes the lifecycle's schema flag used to gate writes.
+async fn check_migrations(dbpool: &DbPool, lifecycle: &Lifecycle) -> Result<(), String> {
+    let result = match pending_migrations(dbpool).await {
+        Ok(pending) if pending.is_empty() => Ok(()),
+        Ok(pending) => Err(format!("pending migrations: {:?}", pending)),
+        Err(e) => Err(format!("Database error: {}", e)),
+    };
+
+    lifecycle.set_schema_outdated(result.is_err());
+
+    result
+}
+
+// The SQLite database file must exist and be writable; in-memory databases
+// have no storage to check, and server backends manage their own.
+fn check_storage(database_url: &str) -> ComponentHealth {
+    use crate::db::Backend;
+    use sqlx::sqlite::SqliteConnectOptions;
+    use std::str::FromStr;
+
+    if Backend::from_url(database_url) != Ok(Backend::Sqlite) {
+        return ComponentHealth::new(HealthStatus::Disabled);
+    }
+
+    let path = match SqliteConnectOptions::from_str(database_url) {
+        Ok(options) => options.get_filename().into_owned(),
+        Err(e) => return ComponentHealth::failed(e.to_string()),
+    };
+
+    if path.as_os_str() == ":memory:" {
+        return ComponentHealth::new(HealthStatus::Disabled);
+    }
+
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.permissions().readonly() => ComponentHealth {
+            message: Some("database file is read-only".to_string()),
+            ..ComponentHealth::new(HealthStatus::Warn)
+        }
+        .detail("path", path.display().to_string()),
+        Ok(metadata) => ComponentHealth::new(HealthStatus::Pass)
+            .detail("path", path.display().to_string())
+            .detail("size_bytes", metadata.len()),
+        Err(e) => ComponentHealth::failed(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// Versions of the embedded migrations that haven't been applied successfully.
+pub async fn pending_migrations(dbpool: &DbPool) -> Result<Vec<i64>, sqlx::Error> {
+    use sqlx::migrate::Migrate;
+
+    let mut conn = dbpool.acquire().await?;
+    let applied = conn.list_applied_migrations().await?;
+
+    Ok(crate::db::Backend::of(dbpool)
+        .migrator()
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .filter(|migration| {
+            !applied
+                .iter()
+                .any(|applied| applied.version == migration.version)
+        })
+        .map(|migration| migration.version)
+        .collect())
+}
+
+// Rejects unsafe methods while the schema is behind the embedded migrations,
+// so a binary rolled out ahead of its migration job can't write against an
+// old schema. Reads keep working.
+pub async fn refuse_writes_if_schema_outdated(
+    State(lifecycle): State<Lifecycle>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method().is_safe() || !lifecycle.is_schema_outdated() {
+        return next.run(request).await;
+    }
+
+    (
+        [(header::RETRY_AFTER, "30")],
+        Problem::new(StatusCode::SERVICE_UNAVAILABLE)
+            .detail("database migrations are pending, writes are disabled"),
+    )
+        .into_response()
+}
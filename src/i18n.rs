@@ -0,0 +1,173 @@
+use std::{convert::Infallible, fmt, sync::LazyLock};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponseParts, Response, ResponseParts},
+};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+use crate::{negotiate::Representation, validation::InvalidParams};
+
+// The languages messages are translated to, English first: it's the
+// fallback, for clients without Accept-Language or accepting none of them,
+// and for messages missing in a translation.
+const LANGUAGES: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("pt-BR", include_str!("../locales/pt-BR.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+];
+
+static BUNDLES: LazyLock<Vec<(&'static str, FluentBundle<FluentResource>)>> = LazyLock::new(|| {
+    LANGUAGES
+        .iter()
+        .map(|&(language, source)| {
+            let id: LanguageIdentifier = language.parse().expect("a language identifier");
+            let mut bundle = FluentBundle::new_concurrent(vec![id]);
+            // Arguments are ids and media types, not text to isolate.
+            bundle.set_use_isolating(false);
+            let resource = FluentResource::try_new(source.to_string())
+                .unwrap_or_else(|(_, errors)| panic!("locales/{}.ftl: {:?}", language, errors));
+            bundle
+                .add_resource(resource)
+                .unwrap_or_else(|errors| panic!("locales/{}.ftl: {:?}", language, errors));
+            (language, bundle)
+        })
+        .collect()
+});
+
+// A message of the response envelope, by its id in `locales/*.ftl`. Responses
+// carrying one have it rendered in English, and in the client's language by
+// `negotiate`.
+#[derive(Clone, Debug)]
+pub struct Message {
+    id: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(id: &'static str) -> Self {
+        Message {
+            id,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    // In the language, or English when it has no translation.
+    pub fn render(&self, language: &str) -> String {
+        let mut args = FluentArgs::new();
+        for (name, value) in &self.args {
+            args.set(*name, value.as_str());
+        }
+
+        [language, "en"]
+            .into_iter()
+            .filter_map(|language| BUNDLES.iter().find(|(candidate, _)| *candidate == language))
+            .find_map(|(_, bundle)| {
+                let pattern = bundle.get_message(self.id)?.value()?;
+                let mut errors = Vec::new();
+                let message = bundle.format_pattern(pattern, Some(&args), &mut errors);
+                errors.is_empty().then(|| message.into_owned())
+            })
+            .unwrap_or_else(|| self.id.to_string())
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render("en"))
+    }
+}
+
+impl IntoResponseParts for Message {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Infallible> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+// The language the client prefers among those translated, by Accept-Language
+// (RFC 9110, section 12.5.4): a range matches the languages it is a prefix
+// of, like `pt` for `pt-BR`, and those that are a prefix of it, like `es` for
+// `es-MX`. English when nothing else is acceptable.
+pub fn language(headers: &HeaderMap) -> &'static str {
+    let mut ranges = Vec::new();
+    for value in headers.get_all(header::ACCEPT_LANGUAGE) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for range in value.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let tag = params.next().unwrap_or_default().to_ascii_lowercase();
+            let quality: f32 = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.trim().parse().unwrap_or(0.0));
+            if !tag.is_empty() && quality > 0.0 {
+                ranges.push((tag, quality));
+            }
+        }
+    }
+    // Stable, so equals keep the client's order.
+    ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    for (tag, _) in &ranges {
+        if tag == "*" {
+            break;
+        }
+        let matched = LANGUAGES
+            .iter()
+            .map(|&(language, _)| language)
+            .find(|language| {
+                let language = language.to_ascii_lowercase();
+                language == *tag
+                    || language.starts_with(&format!("{}-", tag))
+                    || tag.starts_with(&format!("{}-", language))
+            });
+        if let Some(language) = matched {
+            return language;
+        }
+    }
+
+    "en"
+}
+
+// Renders the message of a response carrying one in the language, saying so
+// in Content-Language.
+pub fn localize(response: Response, language: &'static str) -> Response {
+    let Some(message) = response.extensions().get::<Message>().cloned() else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(language));
+    // Caches keep the languages apart.
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-language"));
+
+    let invalid = parts.extensions.get::<InvalidParams>().cloned();
+    let represented = parts.extensions.get_mut::<Representation>();
+    let Some(Representation(represented)) = represented.filter(|_| language != "en") else {
+        return Response::from_parts(parts, body);
+    };
+    if let Some(body) = represented.as_object_mut() {
+        body.insert("detail".to_string(), message.render(language).into());
+        if let Some(invalid) = invalid {
+            body.insert("invalid-params".to_string(), invalid.to_value(language));
+        }
+    }
+    let body = Body::from(represented.to_string());
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{Error, Row};
+
+use crate::{
+    db::{Backend, DbPool},
+    negotiate::Representation,
+    problem::Problem,
+};
+
+// Longest Idempotency-Key accepted.
+const MAX_KEY_LENGTH: usize = 255;
+
+// Request and response bodies buffered at most, like axum's default limit.
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// Response headers replayed along with the status and body.
+const REPLAYED_HEADERS: [HeaderName; 3] = [header::CONTENT_TYPE, header::ETAG, header::LOCATION];
+
+// A response recorded for an idempotency key.
+#[derive(Clone)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+pub enum Begin {
+    // First use of the key, now reserved for this request.
+    New,
+    // The first request with the key hasn't completed.
+    InProgress,
+    // The key was used for a request with another body.
+    Mismatch,
+    Completed(StoredResponse),
+}
+
+// Where idempotency keys and the responses they produced are kept until they
+// expire.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    // Reserves `key` for a request with this fingerprint unless a live entry
+    // exists, which is returned instead. Expired entries are purged.
+    async fn begin(&self, key: &str, fingerprint: &str, ttl: Duration) -> Result<Begin, Error>;
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), Error>;
+
+    // Releases the key of a request that failed, so it can be retried.
+    async fn abandon(&self, key: &str) -> Result<(), Error>;
+}
+
+pub type DynIdempotencyStore = Arc<dyn IdempotencyStore>;
+
+// Keys in the `idempotency_keys` table, so they survive restarts and are
+// shared by every instance using the database.
+pub struct SqlIdempotencyStore {
+    dbpool: DbPool,
+}
+
+impl SqlIdempotencyStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlIdempotencyStore { dbpool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for SqlIdempotencyStore {
+    async fn begin(&self, key: &str, fingerprint: &str, ttl: Duration) -> Result<Begin, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let now = chrono::Utc::now().timestamp();
+
+        sqlx::query(&backend.sql("delete from idempotency_keys where created_at < $1"))
+            .bind(now - ttl.as_secs() as i64)
+            .execute(&self.dbpool)
+            .await?;
+
+        let inserted = sqlx::query(&backend.sql(
+            "insert into idempotency_keys (idempotency_key, fingerprint, created_at) \
+             values ($1, $2, $3)",
+        ))
+        .bind(key)
+        .bind(fingerprint)
+        .bind(now)
+        .execute(&self.dbpool)
+        .await;
+
+        match inserted {
+            Ok(_) => return Ok(Begin::New),
+            Err(Error::Database(e)) if e.is_unique_violation() => {}
+            Err(e) => return Err(e),
+        }
+
+        // MySQL reports TEXT columns as blobs.
+        let sql = match backend {
+            Backend::Sqlite | Backend::Postgres => {
+                "select fingerprint, status, headers, body from idempotency_keys \
+                 where idempotency_key = $1"
+            }
+            Backend::MySql => {
+                "select cast(fingerprint as char) as fingerprint, status, \
+                 cast(headers as char) as headers, cast(body as char) as body \
+                 from idempotency_keys where idempotency_key = $1"
+            }
+        };
+        let row = sqlx::query(&backend.sql(sql))
+            .bind(key)
+            .fetch_optional(&self.dbpool)
+            .await?;
+
+        // Gone since the insert failed: the first request was just abandoned.
+        let Some(row) = row else {
+            return Ok(Begin::InProgress);
+        };
+
+        if row.get::<String, _>("fingerprint") != fingerprint {
+            return Ok(Begin::Mismatch);
+        }
+
+        Ok(match row.get::<Option<i32>, _>("status") {
+            Some(status) => Begin::Completed(StoredResponse {
+                status: status as u16,
+                headers: serde_json::from_str(&row.get::<String, _>("headers")).unwrap_or_default(),
+                body: row.get("body"),
+            }),
+            None => Begin::InProgress,
+        })
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql(
+            "update idempotency_keys set status = $1, headers = $2, body = $3 \
+             where idempotency_key = $4",
+        ))
+        .bind(response.status as i32)
+        .bind(serde_json::to_string(&response.headers).unwrap())
+        .bind(response.body)
+        .bind(key)
+        .execute(&self.dbpool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn abandon(&self, key: &str) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql("delete from idempotency_keys where idempotency_key = $1"))
+            .bind(key)
+            .execute(&self.dbpool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Keeps keys in a process-local map, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryIdempotencyStore {
+    entries: Mutex<HashMap<String, InMemoryEntry>>,
+}
+
+struct InMemoryEntry {
+    fingerprint: String,
+    created_at: Instant,
+    response: Option<StoredResponse>,
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn begin(&self, key: &str, fingerprint: &str, ttl: Duration) -> Result<Begin, Error> {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.retain(|_, entry| entry.created_at.elapsed() < ttl);
+
+        Ok(match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Begin::Mismatch,
+            Some(InMemoryEntry {
+                response: Some(response),
+                ..
+            }) => Begin::Completed(response.clone()),
+            Some(_) => Begin::InProgress,
+            None => {
+                entries.insert(
+                    key.to_string(),
+                    InMemoryEntry {
+                        fingerprint: fingerprint.to_string(),
+                        created_at: Instant::now(),
+                        response: None,
+                    },
+                );
+                Begin::New
+            }
+        })
+    }
+
+    async fn complete(&self, key: &str, response: StoredResponse) -> Result<(), Error> {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+        }
+
+        Ok(())
+    }
+
+    async fn abandon(&self, key: &str) -> Result<(), Error> {
+        self.entries.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Idempotency {
+    pub store: DynIdempotencyStore,
+    pub ttl: Duration,
+}
+
+// Releases the key unless the response was recorded: after a server error,
+// or when the request is dropped before completing because the client went
+// away, so its retry isn't refused as in progress.
+struct Reservation {
+    store: DynIdempotencyStore,
+    key: String,
+    finished: bool,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.finished {
+            let (store, key) = (self.store.clone(), std::mem::take(&mut self.key));
+            tokio::spawn(async move {
+                if let Err(e) = store.abandon(&key).await {
+                    tracing::warn!(error = %e, "couldn't release idempotency key");
+                }
+            });
+        }
+    }
+}
+
+// Makes POST requests carrying an `Idempotency-Key` header safe to retry: the
+// first response for a key, unless a server error, is recorded with the
+// request body's hash and replayed with `Idempotent-Replayed: true` to
+// retries until the key expires. Reusing a key with another body is refused
+// 422, and a retry arriving while the first request runs is refused 409.
+pub async fn guard(
+    State(idempotency): State<Idempotency>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+
+    let key = match request.headers().get("idempotency-key") {
+        None => return next.run(request).await,
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+            _ => {
+                return fail(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Idempotency-Key must be 1 to {} visible ASCII characters",
+                        MAX_KEY_LENGTH
+                    ),
+                )
+            }
+        },
+    };
+
+    // Keys are scoped to the route they were used on.
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let key = format!("POST {} {}", path, key);
+
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => {
+            return fail(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large".to_string(),
+            )
+        }
+    };
+    let fingerprint = {
+        use sha2::{Digest, Sha256};
+
+        Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+
+    match idempotency
+        .store
+        .begin(&key, &fingerprint, idempotency.ttl)
+        .await
+    {
+        Ok(Begin::New) => {}
+        Ok(Begin::Completed(stored)) => return replay(stored),
+        Ok(Begin::InProgress) => {
+            return fail(
+                StatusCode::CONFLICT,
+                "a request with this Idempotency-Key is in progress".to_string(),
+            )
+        }
+        Ok(Begin::Mismatch) => {
+            return fail(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request body".to_string(),
+            )
+        }
+        Err(e) => return Problem::from(e).into_response(),
+    }
+
+    let mut reservation = Reservation {
+        store: idempotency.store.clone(),
+        key,
+        finished: false,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        // Dropping the reservation releases the key.
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    // Server errors are worth retrying, so they aren't recorded.
+    if !parts.status.is_server_error() {
+        if let Ok(text) = std::str::from_utf8(&body) {
+            let stored = StoredResponse {
+                status: parts.status.as_u16(),
+                headers: REPLAYED_HEADERS
+                    .iter()
+                    .filter_map(|name| {
+                        let value = parts.headers.get(name)?.to_str().ok()?;
+                        Some((name.to_string(), value.to_string()))
+                    })
+                    .collect(),
+                body: text.to_string(),
+            };
+
+            match idempotency.store.complete(&reservation.key, stored).await {
+                Ok(()) => reservation.finished = true,
+                Err(e) => tracing::warn!(error = %e, "couldn't record idempotent response"),
+            }
+        }
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let represented = serde_json::from_str(&stored.body).ok().map(Representation);
+    let mut response = (
+        StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK),
+        stored.body,
+    )
+        .into_response();
+    // Encoded by `negotiate` like the response it replays.
+    if let Some(represented) = represented {
+        response.extensions_mut().insert(represented);
+    }
+
+    let headers = response.headers_mut();
+    for (name, value) in stored.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.insert(name, value);
+        }
+    }
+    headers.insert("idempotent-replayed", HeaderValue::from_static("true"));
+
+    response
+}
+
+fn fail(status: StatusCode, message: String) -> Response {
+    Problem::new(status).detail(message).into_response()
+}
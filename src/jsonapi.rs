@@ -0,0 +1,195 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+};
+use serde_json::{json, Map, Value};
+
+use crate::i18n::Message;
+
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+// The only resource type.
+const TYPE: &str = "todos";
+
+// JSON:API (https://jsonapi.org/format/1.1/) for the todo routes, as
+// negotiated by `negotiate`: request documents are unwrapped into the plain
+// bodies the handlers take, and responses wrapped into resource objects and
+// error objects.
+
+// Whether the request body is a JSON:API document.
+pub fn is_document(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(MEDIA_TYPE))
+}
+
+// Todos have no relationships yet, so there is nothing to include.
+pub fn check_query(request: &Request) -> Result<(), (StatusCode, Message)> {
+    let query = request.uri().query().unwrap_or_default();
+    if query
+        .split('&')
+        .any(|pair| pair == "include" || pair.starts_with("include="))
+    {
+        return Err((StatusCode::BAD_REQUEST, Message::new("jsonapi-include")));
+    }
+
+    Ok(())
+}
+
+// Replaces a `{"data": {"type": "todos", "attributes": {...}}}` document by
+// its attributes, as application/json. The spec reserves media type
+// parameters for extensions and profiles, none of which are supported.
+pub async fn read_document(request: Request) -> Result<Request, (StatusCode, Message)> {
+    let bare = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| !value.contains(';'));
+    if !bare {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Message::new("jsonapi-parameters").arg("media_type", MEDIA_TYPE),
+        ));
+    }
+    let id = request
+        .uri()
+        .path()
+        .rsplit('/')
+        .next()
+        .and_then(|segment| segment.parse::<i64>().ok());
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+        let message = Message::new("unreadable-body").arg("error", e);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+
+    let document: Value = serde_json::from_slice(&bytes).map_err(|e| {
+        let message = Message::new("invalid-json").arg("error", e);
+        (StatusCode::BAD_REQUEST, message)
+    })?;
+    let data = document
+        .get("data")
+        .filter(|data| data.is_object())
+        .ok_or((StatusCode::BAD_REQUEST, Message::new("jsonapi-data")))?;
+
+    if data.get("type").and_then(Value::as_str) != Some(TYPE) {
+        return Err((
+            StatusCode::CONFLICT,
+            Message::new("jsonapi-type").arg("type", TYPE),
+        ));
+    }
+    match (data.get("id"), id) {
+        (None, _) => {}
+        // Ids are assigned by the server.
+        (Some(_), None) => return Err((StatusCode::FORBIDDEN, Message::new("jsonapi-client-id"))),
+        (Some(given), Some(id)) if given.as_str() != Some(id.to_string().as_str()) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Message::new("jsonapi-id").arg("id", id),
+            ))
+        }
+        (Some(_), Some(_)) => {}
+    }
+
+    let attributes = data.get("attributes").cloned().unwrap_or_else(|| json!({}));
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Ok(Request::from_parts(
+        parts,
+        Body::from(attributes.to_string()),
+    ))
+}
+
+// The JSON:API document for a plain body: a todo, a list or an error. None
+// for other bodies.
+pub fn document(status: StatusCode, body: &Value) -> Option<Value> {
+    if status.is_client_error() || status.is_server_error() {
+        return Some(error_document(status, body));
+    }
+
+    if let Some(todo) = body.pointer("/data/todo") {
+        return Some(json!({
+            "jsonapi": {"version": "1.1"},
+            "data": resource(todo),
+            "links": {"self": self_link(todo)},
+        }));
+    }
+
+    let todos = body.pointer("/data/todos").and_then(Value::as_array)?;
+    Some(json!({
+        "jsonapi": {"version": "1.1"},
+        "data": todos.iter().map(resource).collect::<Vec<_>>(),
+        "meta": {"count": todos.len()},
+        "links": links(body),
+    }))
+}
+
+// A todo as a resource object: its id and type apart from its attributes.
+fn resource(todo: &Value) -> Value {
+    let mut attributes = todo.as_object().cloned().unwrap_or_else(Map::new);
+    let id = attributes.remove("id").unwrap_or(Value::Null);
+    attributes.remove("_links");
+
+    json!({
+        "type": TYPE,
+        "id": id.to_string(),
+        "attributes": attributes,
+        "links": {"self": self_link(todo)},
+    })
+}
+
+// The HAL links of a plain body, as JSON:API links.
+fn links(body: &Value) -> Value {
+    let links = body["_links"].as_object().cloned().unwrap_or_default();
+
+    links
+        .into_iter()
+        .map(|(rel, link)| (rel, link["href"].clone()))
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+fn self_link(todo: &Value) -> String {
+    format!("/v1/todos/{}", todo["id"])
+}
+
+// The request id, a custom member, goes in `meta`.
+fn error_document(status: StatusCode, body: &Value) -> Value {
+    let mut error = json!({
+        "status": status.as_u16().to_string(),
+        "title": status.canonical_reason().unwrap_or_default(),
+    });
+    if let Some(detail) = body.get("detail") {
+        error["detail"] = detail.clone();
+    }
+
+    // An error for each invalid field, pointing at its attribute.
+    let errors = match body.get("invalid-params").and_then(Value::as_array) {
+        Some(params) => params
+            .iter()
+            .map(|param| {
+                let mut error = error.clone();
+                error["detail"] = param["reason"].clone();
+                error["source"] = json!({
+                    "pointer": format!("/data/attributes/{}", param["name"].as_str().unwrap_or_default()),
+                });
+                error
+            })
+            .collect(),
+        None => vec![error],
+    };
+
+    let mut document = json!({"jsonapi": {"version": "1.1"}, "errors": errors});
+    if let Some(request_id) = body.get("request_id") {
+        document["meta"] = json!({ "request_id": request_id });
+    }
+    document
+}
@@ -0,0 +1,277 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, UpdateTodo},
+};
+
+// Calls answered at most per batch.
+const MAX_BATCH: usize = 100;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+// Server errors of this API, in the range JSON-RPC reserves for them.
+const NOT_FOUND: i64 = -32001;
+const CONFLICT: i64 = -32002;
+const UNAVAILABLE: i64 = -32003;
+
+#[derive(Clone)]
+pub struct JsonRpc {
+    pub todos: DynTodoRepository,
+    // Present when writes are refused while migrations are pending, like the
+    // REST routes do.
+    pub refuse_writes: Option<Lifecycle>,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+// JSON-RPC 2.0 over `POST /rpc`, with methods mirroring the REST routes:
+// `todos.list`, `todos.get`, `todos.create`, `todos.update` and
+// `todos.delete`, taking their params by name. A batch is answered with the
+// responses of its calls in order; notifications, calls without an id, get
+// none, and a request made only of notifications is answered 204.
+pub async fn handle(State(rpc): State<JsonRpc>, body: Bytes) -> Response {
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+            .into_response()
+        }
+    };
+
+    let response = match request {
+        Value::Array(calls) if calls.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(calls) if calls.len() > MAX_BATCH => Some(error_response(
+            Value::Null,
+            RpcError::new(
+                INVALID_REQUEST,
+                format!("batches are limited to {} calls", MAX_BATCH),
+            ),
+        )),
+        Value::Array(calls) => {
+            let mut responses = Vec::new();
+            for call in calls {
+                responses.extend(rpc.call(call).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        call => rpc.call(call).await,
+    };
+
+    match response {
+        Some(response) => Json(response).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Call {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    // Absent for notifications; `null` is a valid id.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+}
+
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct IdParams {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct CreateParams {
+    body: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateParams {
+    id: i64,
+    version: i64,
+    body: String,
+    completed: bool,
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    id: i64,
+    version: Option<i64>,
+}
+
+impl JsonRpc {
+    // The response to one call, or None for a notification.
+    async fn call(&self, call: Value) -> Option<Value> {
+        // Invalid requests are answered even without an id.
+        let raw_id = call.get("id").cloned().unwrap_or(Value::Null);
+
+        let call = match serde_json::from_value::<Call>(call) {
+            Ok(call) if call.jsonrpc == "2.0" => call,
+            Ok(_) => {
+                return Some(error_response(
+                    raw_id,
+                    RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+                ))
+            }
+            Err(e) => {
+                return Some(error_response(
+                    raw_id,
+                    RpcError::new(INVALID_REQUEST, e.to_string()),
+                ))
+            }
+        };
+
+        let result = self.dispatch(&call.method, call.params).await;
+
+        let id = call.id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+            Err(e) => error_response(id, e),
+        })
+    }
+
+    async fn dispatch(&self, method: &str, params: Option<Value>) -> Result<Value, RpcError> {
+        match method {
+            "todos.list" => {
+                let mut todos = self.todos.list().await.map_err(database_error)?;
+                todos.sort_by_key(|todo| todo.id);
+                Ok(json!(todos))
+            }
+            "todos.get" => {
+                let IdParams { id } = parse_params(params)?;
+                match self.todos.read(id).await {
+                    Ok(todo) => Ok(json!(todo)),
+                    Err(sqlx::Error::RowNotFound) => Err(RpcError::new(
+                        NOT_FOUND,
+                        format!("todo with ID: {} not found", id),
+                    )),
+                    Err(e) => Err(database_error(e)),
+                }
+            }
+            "todos.create" => {
+                let CreateParams { body } = parse_params(params)?;
+                self.check_writable()?;
+
+                let todo = self
+                    .todos
+                    .create(CreateTodo::new(body))
+                    .await
+                    .map_err(database_error)?;
+                Ok(json!(todo))
+            }
+            // Applies only while the todo is still at `version`.
+            "todos.update" => {
+                let UpdateParams {
+                    id,
+                    version,
+                    body,
+                    completed,
+                } = parse_params(params)?;
+                self.check_writable()?;
+
+                match self
+                    .todos
+                    .update(id, UpdateTodo::new(body, completed), version)
+                    .await
+                {
+                    Ok(todo) => Ok(json!(todo)),
+                    Err(sqlx::Error::RowNotFound) => Err(modified_error(id)),
+                    Err(e) => Err(database_error(e)),
+                }
+            }
+            // With `version`, only deletes that revision of the todo.
+            "todos.delete" => {
+                let DeleteParams { id, version } = parse_params(params)?;
+                self.check_writable()?;
+
+                match self.todos.delete(id, version).await {
+                    Ok(_) => Ok(Value::Null),
+                    Err(sqlx::Error::RowNotFound) => Err(modified_error(id)),
+                    Err(e) => Err(database_error(e)),
+                }
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no such method: {}", method),
+            )),
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), RpcError> {
+        match &self.refuse_writes {
+            Some(lifecycle) if lifecycle.is_schema_outdated() => Err(RpcError::new(
+                UNAVAILABLE,
+                "database migrations are pending, writes are disabled",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    match params {
+        Some(params @ Value::Object(_)) => {
+            serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+        }
+        _ => Err(RpcError::new(
+            INVALID_PARAMS,
+            "params must be an object of named parameters",
+        )),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": {"code": error.code, "message": error.message},
+        "id": id,
+    })
+}
+
+fn modified_error(id: i64) -> RpcError {
+    RpcError::new(
+        CONFLICT,
+        format!(
+            "todo with ID: {} was modified or deleted, fetch it and retry",
+            id
+        ),
+    )
+}
+
+fn database_error(e: sqlx::Error) -> RpcError {
+    RpcError::new(INTERNAL_ERROR, format!("Database error: {}", e))
+}
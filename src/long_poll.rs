@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    envelope::Success,
+    events::{parse_cursor, EventStream, Resume},
+    params::QueryParams,
+    problem::Problem,
+    state::Lifecycle,
+};
+
+// Longest a request is held.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+pub struct ChangesParams {
+    // The cursor of the previous response.
+    since: Option<String>,
+    // Seconds, optionally suffixed with `s`: `30`, `30s`. Defaults to 30.
+    wait: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChangesData {
+    // The `since` of the next request.
+    cursor: String,
+    // The changes since the cursor aren't known anymore.
+    reset: bool,
+    changes: Vec<Value>,
+}
+
+// Long polling, for clients that can neither keep a WebSocket nor an event
+// stream open: answers with the changes since the cursor as soon as there are
+// any, or with none once `wait` elapses, along with the cursor to ask from
+// next. Without a cursor, answers straight away with the current one. When
+// the changes since a cursor aren't known anymore, because it is too old or
+// from before a restart, `reset` is true: the client has to refetch what it
+// shows.
+pub async fn todo_changes(
+    State(events): State<EventStream>,
+    State(lifecycle): State<Lifecycle>,
+    QueryParams(params): QueryParams<ChangesParams>,
+) -> Result<Success<ChangesData>, Problem> {
+    let wait = match params.wait.as_deref().map(parse_wait) {
+        None => Duration::from_secs(30),
+        Some(Some(wait)) => wait.min(MAX_WAIT),
+        Some(None) => {
+            return Err(fail(
+                "wait must be a number of seconds, like 30 or 30s".to_string(),
+            ))
+        }
+    };
+
+    let (run, seq) = match params.since.as_deref() {
+        None => {
+            return Ok(changes(
+                &events,
+                events.latest(),
+                Resume::Missed(Vec::new()),
+            ))
+        }
+        Some(since) => match parse_cursor(since) {
+            Some(cursor) => cursor,
+            None => return Err(fail(format!("invalid cursor: {}", since))),
+        },
+    };
+
+    let (resume, mut receiver) = events.resume(run, seq);
+
+    // Nothing yet: wait for the next change, then answer with every change
+    // since the cursor, which may be more than one by then.
+    let resume = match resume {
+        Resume::Missed(missed) if missed.is_empty() => {
+            tokio::select! {
+                _ = receiver.recv() => {}
+                _ = tokio::time::sleep(wait) => {}
+                _ = lifecycle.draining() => {}
+            }
+            events.resume(run, seq).0
+        }
+        resume => resume,
+    };
+
+    Ok(changes(&events, seq, resume))
+}
+
+fn changes(events: &EventStream, seq: u64, resume: Resume) -> Success<ChangesData> {
+    let (reset, seq, changes) = match resume {
+        Resume::Missed(missed) => (
+            false,
+            missed.last().map_or(seq, |last| last.seq),
+            missed
+                .iter()
+                .map(|numbered| numbered.event.to_json())
+                .collect(),
+        ),
+        Resume::Lost { seq } => (true, seq, Vec::new()),
+    };
+
+    Success::new(ChangesData {
+        cursor: events.cursor(seq),
+        reset,
+        changes,
+    })
+}
+
+fn parse_wait(wait: &str) -> Option<Duration> {
+    let seconds = wait.strip_suffix('s').unwrap_or(wait);
+    seconds.parse().ok().map(Duration::from_secs)
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
@@ -1,60 +1,490 @@
-mod router;
+mod access_log;
+mod admin;
 mod api;
-mod todo;
+mod backoff;
+mod backup;
+mod batch;
+mod body;
+mod build_info;
+mod cache;
+mod cache_control;
+mod caldav;
+mod changes;
+mod circuit_breaker;
+mod config;
+mod crdt;
+mod csv;
+mod db;
+mod deprecation;
+mod doctor;
+mod email;
+mod envelope;
 mod error;
+mod error_reporting;
+mod events;
+mod fallback;
+mod graphql;
+mod grpc;
+mod health;
+mod i18n;
+mod idempotency;
+mod jsonapi;
+mod jsonrpc;
+mod long_poll;
+mod maintenance;
+mod method_override;
+mod migrate;
+mod mqtt;
+mod msgpack;
+mod nats;
+mod negotiate;
+mod notifications;
+mod openapi;
+mod options;
+mod outbox;
+mod params;
+mod preferences;
+mod problem;
+mod protobuf;
+mod push;
+mod redact;
+mod redis_store;
+mod replication;
+mod repository;
+mod request_id;
+mod router;
+mod sampling;
+mod sse;
+mod state;
+mod stats;
+mod sync;
+mod telegram;
+mod todo;
+mod trailing_slash;
+mod usage;
+mod validation;
+mod webhooks;
+mod websocket;
+
+use std::sync::Arc;
+
+use config::{Config, ConflictPolicy, LogFormat, StartupMigrations, StorageBackend};
+use redact::Redactor;
+use state::{AppState, Lifecycle};
 
 #[tokio::main]
 async fn main() {
-    init_tracing();
+    db::install_drivers();
+
+    match std::env::args().nth(1).as_deref() {
+        None | Some("serve") => serve().await,
+        Some("doctor") => std::process::exit(if doctor::run().await { 0 } else { 1 }),
+        Some("restore") => match std::env::args().nth(2) {
+            Some(path) => {
+                let timestamp = std::env::args().nth(3);
+                let restored = replication::restore(&path, timestamp.as_deref()).await;
+                std::process::exit(if restored { 0 } else { 1 })
+            }
+            None => {
+                eprintln!("usage: api-service restore <path> [timestamp]");
+                std::process::exit(2);
+            }
+        },
+        Some("backup") => match std::env::args().nth(2) {
+            Some(path) => std::process::exit(if backup::run(&path).await { 0 } else { 1 }),
+            None => {
+                eprintln!("usage: api-service backup <path>");
+                std::process::exit(2);
+            }
+        },
+        Some("migrate") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            match migrate::Command::parse(&args) {
+                Some(command) => {
+                    std::process::exit(if migrate::run(command).await { 0 } else { 1 })
+                }
+                None => {
+                    eprintln!("usage: api-service migrate <status|to <version>|revert>");
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(command) => {
+            eprintln!("unknown command: {}", command);
+            eprintln!(
+                "usage: api-service [serve|doctor|migrate <command>|backup <path>|restore <path> [timestamp]]"
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn serve() {
+    let config = Config::from_env();
+
+    let redactor = Redactor::new(&config.log_redact_fields, &config.log_redact_patterns)
+        .expect("invalid LOG_REDACT_PATTERNS");
+
+    let _sentry = error_reporting::init(&config, redactor.clone());
 
-    let dbpool = init_dbpool().await.expect("couldn't initialize DB pool");
+    init_tracing(&config, redactor);
 
-    let router = router::create_router(dbpool).await;
+    todo::set_slow_query_threshold(config.slow_query_threshold);
 
-    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
+    let lifecycle = Lifecycle::default();
+
+    let mut replication = None;
+
+    let db_health = health::DatabaseHealth::new(config.db_health_interval);
+
+    let redis = match &config.redis {
+        Some(redis) => Some(
+            redis_store::RedisStore::connect(redis)
+                .await
+                .expect("couldn't connect to Redis"),
+        ),
+        None => None,
+    };
+
+    let mut events = events::EventBus::default();
+    let event_stream = events::EventStream::new(1024);
+    events.subscribe(Arc::new(event_stream.clone()));
+    if let Some(nats) = &config.nats {
+        let publisher = nats::NatsPublisher::connect(nats)
+            .await
+            .expect("couldn't connect to NATS");
+        events.subscribe(Arc::new(publisher));
+    }
+    if let Some(mqtt) = &config.mqtt {
+        let publisher = mqtt::MqttPublisher::start(mqtt).expect("couldn't set up MQTT");
+        events.subscribe(Arc::new(publisher));
+    }
+
+    let (
+        dbpool,
+        todos,
+        idempotency_store,
+        webhook_store,
+        email_queue,
+        push_store,
+        preference_store,
+        caldav_store,
+        usage_store,
+    ): (
+        _,
+        repository::DynTodoRepository,
+        idempotency::DynIdempotencyStore,
+        webhooks::DynWebhookStore,
+        email::DynEmailQueue,
+        push::DynPushStore,
+        preferences::DynPreferenceStore,
+        caldav::DynCalDavStore,
+        usage::DynUsageStore,
+    ) = match config.storage {
+        StorageBackend::Sql => {
+            let pools = init_dbpools(&config)
+                .await
+                .expect("couldn't initialize DB pools");
+
+            let pending = health::pending_migrations(&pools.read)
+                .await
+                .expect("couldn't check migrations");
+            if !pending.is_empty() {
+                if config.startup_migrations == StartupMigrations::Fail {
+                    tracing::error!(?pending, "database migrations are pending, exiting");
+                    std::process::exit(1);
+                }
+                tracing::warn!(?pending, "database migrations are pending");
+            }
+            lifecycle.set_schema_outdated(!pending.is_empty());
+
+            if let Some(replica) = &config.replica {
+                replication = Some(
+                    replication::Replication::start(replica, &config.database_url, pools.clone())
+                        .await
+                        .expect("couldn't start replication"),
+                );
+            }
+
+            if let Some(window) = config.maintenance_window {
+                if db::Backend::of(&pools.write) == db::Backend::Sqlite {
+                    maintenance::spawn(window, pools.clone());
+                } else {
+                    tracing::warn!("MAINTENANCE_WINDOW only applies to SQLite, ignoring it");
+                }
+            }
+
+            // Health checks use the read pool, so they don't queue behind writes.
+            let dbpool = pools.read.clone();
+            health::spawn_monitor(
+                dbpool.clone(),
+                db_health.clone(),
+                lifecycle.clone(),
+                config.slow_query_threshold,
+            );
+            let idempotency_store =
+                Arc::new(idempotency::SqlIdempotencyStore::new(pools.write.clone()));
+            let webhook_store = Arc::new(webhooks::SqlWebhookStore::new(pools.write.clone()));
+            let email_queue = Arc::new(email::SqlEmailQueue::new(pools.write.clone()));
+            let push_store = Arc::new(push::SqlPushStore::new(pools.write.clone()));
+            let preference_store =
+                Arc::new(preferences::SqlPreferenceStore::new(pools.write.clone()));
+            let caldav_store = Arc::new(caldav::SqlCalDavStore::new(pools.write.clone()));
+            let usage_store = Arc::new(usage::SqlUsageStore::new(pools.write.clone()));
+            let sql_todos = todo::SqlTodoRepository::new(pools.clone());
+            let todos: repository::DynTodoRepository = match &config.kafka {
+                Some(kafka) => {
+                    outbox::spawn(kafka.clone(), pools.write.clone(), lifecycle.clone());
+                    Arc::new(sql_todos.with_outbox())
+                }
+                None => Arc::new(sql_todos),
+            };
+            let todos = if config.db_retry.max_attempts > 1 {
+                Arc::new(repository::RetryingTodoRepository::new(
+                    todos,
+                    config.db_retry,
+                ))
+            } else {
+                todos
+            };
+            let read_cache: Option<cache::DynTodoCache> = match (config.read_cache, &redis) {
+                (Some(read_cache), Some(redis)) => Some(Arc::new(cache::RedisTodoCache::new(
+                    redis.clone(),
+                    read_cache,
+                ))),
+                (Some(read_cache), None) => Some(Arc::new(cache::MemoryTodoCache::new(read_cache))),
+                (None, _) => None,
+            };
+            let todos = match (read_cache, config.read_cache) {
+                (Some(read_cache), Some(config)) => {
+                    events.subscribe(Arc::new(cache::CacheInvalidation(read_cache.clone())));
+                    Arc::new(repository::CachingTodoRepository::new(
+                        todos, read_cache, config,
+                    ))
+                }
+                _ => todos,
+            };
+            (
+                Some(dbpool),
+                todos,
+                idempotency_store,
+                webhook_store,
+                email_queue,
+                push_store,
+                preference_store,
+                caldav_store,
+                usage_store,
+            )
+        }
+        StorageBackend::Memory => {
+            tracing::warn!("using in-memory storage, todos are lost on restart");
+            if config.kafka.is_some() {
+                tracing::warn!("KAFKA_BROKERS only applies to SQL storage, ignoring it");
+            }
+            (
+                None,
+                Arc::new(repository::InMemoryTodoRepository::default()),
+                Arc::new(idempotency::InMemoryIdempotencyStore::default()),
+                Arc::new(webhooks::InMemoryWebhookStore::default()),
+                Arc::new(email::InMemoryEmailQueue::default()),
+                Arc::new(push::InMemoryPushStore::default()),
+                Arc::new(preferences::InMemoryPreferenceStore::default()),
+                Arc::new(caldav::InMemoryCalDavStore::default()),
+                Arc::new(usage::InMemoryUsageStore::default()),
+            )
+        }
+    };
+    let preferences = preferences::Preferences::new(preference_store);
+    let usage = usage::Usage::new(usage_store);
+    usage.spawn_flusher(config.usage_flush_interval, lifecycle.clone());
+    let webhooks = webhooks::Webhooks::new(webhook_store, config.webhooks.private_networks);
+    events.subscribe(Arc::new(webhooks.clone()));
+    webhooks.spawn_dispatcher(config.webhooks, lifecycle.clone());
+    let notifiers = [
+        (notifications::Service::Slack, &config.slack),
+        (notifications::Service::Discord, &config.discord),
+    ];
+    for (service, notifier) in notifiers {
+        if let Some(notifier) = notifier {
+            events.subscribe(Arc::new(notifications::Notifier::new(
+                service,
+                notifier.clone(),
+                config.public_url.clone(),
+                todos.clone(),
+                preferences.clone(),
+            )));
+        }
+    }
+    let todos: repository::DynTodoRepository =
+        Arc::new(repository::PublishingTodoRepository::new(todos, events));
+    // Outermost, so `update_resolving` and `delete_resolving` reach it.
+    let todos = match config.conflict_policy {
+        ConflictPolicy::Reject => todos,
+        policy => Arc::new(repository::ResolvingTodoRepository::new(todos, policy)),
+    };
+
+    if let Some(telegram) = &config.telegram {
+        telegram::spawn(
+            telegram.clone(),
+            todos.clone(),
+            lifecycle.clone(),
+            preferences.clone(),
+        );
+    }
+    if let Some(email) = &config.email {
+        email::Mailer::new(
+            email_queue,
+            email.clone(),
+            config.public_url.clone(),
+            preferences.clone(),
+        )
+        .expect("couldn't set up email")
+        .spawn(todos.clone(), lifecycle.clone());
+    }
+    let push = config.push.as_ref().map(|push| {
+        let push = push::WebPush::new(
+            push_store,
+            push.clone(),
+            config.public_url.clone(),
+            preferences.clone(),
+        )
+        .expect("couldn't set up Web Push");
+        push.spawn(todos.clone(), lifecycle.clone());
+        push
+    });
+
+    let state = AppState {
+        config: Arc::new(config.clone()),
+        dbpool,
+        todos,
+        lifecycle: lifecycle.clone(),
+        db_health,
+        redis,
+        idempotency: idempotency::Idempotency {
+            store: idempotency_store,
+            ttl: config.idempotency_ttl,
+        },
+        events: event_stream,
+        webhooks,
+        push,
+        preferences,
+        caldav: caldav_store,
+        batch: batch::Batch::default(),
+        usage,
+    };
+
+    let router = router::create_router(&config, state).await;
 
     // run our app with hyper
-    let listener = tokio::net::TcpListener::bind(bind_addr)
+    let listener = tokio::net::TcpListener::bind(&config.bind_addr)
         .await
         .expect("unable to listen tcp addr");
 
-    axum::serve(listener, router.into_make_service())
-        .await
-        .expect("unable to start server");
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(lifecycle, config.shutdown_drain_delay))
+    .await
+    .expect("unable to start server");
+
+    if let Some(replication) = replication {
+        replication.stop().await;
+    }
 }
 
-fn init_tracing() {
+fn init_tracing(config: &Config, redactor: Redactor) {
+    use redact::RedactingMakeWriter;
     use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
 
-    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV)
-        .unwrap_or_else(|_| "sqlx=info,tower_http=debug,info".to_string());
+    let writer = RedactingMakeWriter::new(redactor, std::io::stdout);
+
+    // LOG_FORMAT=json emits one JSON object per event, including the fields of
+    // the current span and its parents, for log shippers like Loki or ELK.
+    let fmt_layer = match config.log_format {
+        LogFormat::Json => fmt::layer()
+            .with_writer(writer)
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        LogFormat::Text => fmt::layer().with_writer(writer).boxed(),
+    };
 
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(fmt_layer.with_filter(config.log_sampling.clone()))
+        .with(
+            config
+                .sentry_dsn
+                .is_some()
+                .then(error_reporting::tracing_layer),
+        )
         .with(
             EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
-                .parse_lossy(rust_log),
+                .parse_lossy(&config.rust_log),
         )
         .init();
 }
 
-async fn init_dbpool() -> Result<sqlx::Pool<sqlx::Sqlite>, sqlx::Error> {
-    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-    use std::str::FromStr;
-
-    let db_connection_str =
-        std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:db.sqlite".to_string());
+async fn init_dbpools(config: &Config) -> Result<db::DbPools, sqlx::Error> {
+    db::Backend::from_url(&config.database_url).expect("invalid DATABASE_URL");
 
-    let dbpool = SqlitePoolOptions::new()
-        .connect_with(SqliteConnectOptions::from_str(&db_connection_str)?.create_if_missing(true))
+    let pools = db::connect(&config.database_url, &config.db_pool, &config.sqlite)
         .await
         .expect("can't connect to database");
 
-    sqlx::migrate!()
-        .run(&dbpool)
-        .await
-        .expect("database migration failed");
+    if config.startup_migrations == StartupMigrations::Run {
+        db::Backend::of(&pools.write)
+            .migrator()
+            .run(&pools.write)
+            .await
+            .expect("database migration failed");
+    } else {
+        use sqlx::migrate::Migrate;
+
+        // Lets pending migrations be listed before they were ever run.
+        pools
+            .write
+            .acquire()
+            .await?
+            .ensure_migrations_table()
+            .await
+            .expect("couldn't create the migrations table");
+    }
+
+    Ok(pools)
+}
+
+// Waits for SIGINT/SIGTERM, then reports not-ready for the drain delay so load
+// balancers stop routing new requests before in-flight ones are finished.
+async fn shutdown_signal(lifecycle: Lifecycle, drain_delay: std::time::Duration) {
+    use tokio::signal;
+
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!(?drain_delay, "shutdown requested, draining");
 
-    Ok(dbpool)
+    lifecycle.start_draining();
+    tokio::time::sleep(drain_delay).await;
 }
@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::MaintenanceWindow,
+    db::{Backend, DbPool, DbPools},
+    envelope::Success,
+    params::QueryParams,
+    problem::Problem,
+};
+
+// Problems reported at most by one integrity check.
+const MAX_PROBLEMS: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct IntegrityParams {
+    mode: Option<String>,
+    timeout_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct IntegrityData {
+    mode: &'static str,
+    duration_ms: f64,
+}
+
+// Admin route: runs `PRAGMA quick_check` (default) or, with `mode=full`,
+// `PRAGMA integrity_check`, which also verifies indexes against their tables.
+// Responds 500 listing the problems found, or 504 when the check outlives
+// `timeout_seconds`.
+pub async fn integrity_check(
+    State(dbpool): State<Option<DbPool>>,
+    QueryParams(params): QueryParams<IntegrityParams>,
+) -> Result<impl IntoResponse, Problem> {
+    let dbpool = dbpool
+        .filter(|dbpool| Backend::of(dbpool) == Backend::Sqlite)
+        .ok_or_else(|| {
+            Problem::new(StatusCode::CONFLICT).detail("only supported for SQLite storage")
+        })?;
+
+    let (mode, pragma) = match params.mode.as_deref().unwrap_or("quick") {
+        "quick" => ("quick", "quick_check"),
+        "full" => ("full", "integrity_check"),
+        mode => {
+            return Err(Problem::new(StatusCode::BAD_REQUEST)
+                .detail(format!("unknown mode: {}, expected quick or full", mode)));
+        }
+    };
+    let timeout = Duration::from_secs(params.timeout_seconds.unwrap_or(30).clamp(1, 600));
+
+    let mut conn = dbpool.acquire().await?;
+
+    let start = Instant::now();
+    let sql = format!("PRAGMA {}({})", pragma, MAX_PROBLEMS);
+    let result = tokio::time::timeout(
+        timeout,
+        sqlx::query_scalar::<_, String>(&sql).fetch_all(&mut *conn),
+    )
+    .await;
+    let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let rows = match result {
+        Ok(rows) => rows?,
+        Err(_) => {
+            // The check may still be running on the connection; keep it out
+            // of the pool.
+            drop(conn.detach());
+
+            return Err(Problem::new(StatusCode::GATEWAY_TIMEOUT)
+                .detail(format!("integrity check timed out after {:?}", timeout)));
+        }
+    };
+
+    tracing::info!(mode, duration_ms, ok = rows == ["ok"], "integrity check");
+
+    if rows == ["ok"] {
+        return Ok(Success::new(IntegrityData { mode, duration_ms }));
+    }
+
+    tracing::error!(mode, problems = ?rows, "database integrity check failed");
+
+    Err(Problem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        .detail("database integrity check failed")
+        .extension("mode", mode)
+        .extension("duration_ms", duration_ms)
+        .extension("problems", rows))
+}
+
+// Pages freed per incremental vacuum step. Each step holds the write
+// connection only briefly, so requests keep flowing during maintenance.
+const VACUUM_STEP_PAGES: i64 = 1000;
+
+// Runs SQLite maintenance daily when the window opens: `PRAGMA optimize` to
+// refresh query planner statistics, then incremental vacuum steps returning
+// free pages to the filesystem until none are left or the window closes.
+// Each run is logged under the `maintenance` target with its duration and
+// the space reclaimed.
+pub fn spawn(window: MaintenanceWindow, pools: DbPools) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(until_next_start(&window)).await;
+
+            if let Err(e) = run(&window, &pools).await {
+                tracing::warn!(target: "maintenance", error = %e, "database maintenance failed");
+            }
+        }
+    })
+}
+
+fn until_next_start(window: &MaintenanceWindow) -> Duration {
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut start = now.date().and_time(window.start);
+    if start <= now {
+        start += chrono::TimeDelta::try_days(1).unwrap();
+    }
+
+    (start - now).to_std().unwrap_or_default()
+}
+
+async fn run(window: &MaintenanceWindow, pools: &DbPools) -> Result<(), sqlx::Error> {
+    let start = Instant::now();
+    let size_before = database_size(&pools.write).await?;
+
+    sqlx::query("PRAGMA optimize").execute(&pools.write).await?;
+
+    // Incremental vacuum needs auto_vacuum=INCREMENTAL, which only a full
+    // VACUUM can switch an existing database to. That happens once, on the
+    // first run.
+    let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum")
+        .fetch_one(&pools.write)
+        .await?;
+    if auto_vacuum != 2 {
+        tracing::info!(target: "maintenance", "enabling incremental vacuum, running a full VACUUM");
+
+        let mut conn = pools.write.acquire().await?;
+        sqlx::query("PRAGMA auto_vacuum = INCREMENTAL")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("VACUUM").execute(&mut *conn).await?;
+    }
+
+    loop {
+        let free_pages: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+            .fetch_one(&pools.write)
+            .await?;
+
+        if free_pages == 0 || !window.contains(chrono::Utc::now().time()) {
+            break;
+        }
+
+        sqlx::query(&format!("PRAGMA incremental_vacuum({})", VACUUM_STEP_PAGES))
+            .execute(&pools.write)
+            .await?;
+    }
+
+    let size_after = database_size(&pools.write).await?;
+
+    tracing::info!(
+        target: "maintenance",
+        duration_ms = start.elapsed().as_secs_f64() * 1000.0,
+        size_before,
+        size_after,
+        reclaimed_bytes = (size_before - size_after).max(0),
+        "database maintenance completed"
+    );
+
+    Ok(())
+}
+
+// Bytes in use by the database, excluding the WAL.
+async fn database_size(dbpool: &DbPool) -> Result<i64, sqlx::Error> {
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+        .fetch_one(dbpool)
+        .await?;
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+        .fetch_one(dbpool)
+        .await?;
+
+    Ok(page_count * page_size)
+}
@@ -0,0 +1,38 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+
+static HEADER: HeaderName = HeaderName::from_static("x-http-method-override");
+
+// The methods POST can tunnel.
+const METHODS: [Method; 3] = [Method::PUT, Method::PATCH, Method::DELETE];
+
+// Serves a POST with X-HTTP-Method-Override as the method it names, for
+// clients behind proxies that only let GET and POST through. Runs before
+// routing, with METHOD_OVERRIDE set.
+pub async fn apply(mut request: Request, next: Next) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(value) = request.headers_mut().remove(&HEADER) else {
+        return next.run(request).await;
+    };
+
+    let method = value
+        .to_str()
+        .ok()
+        .and_then(|value| Method::from_bytes(value.trim().to_ascii_uppercase().as_bytes()).ok())
+        .filter(|method| METHODS.contains(method));
+    let Some(method) = method else {
+        return ApiError::InvalidMethodOverride(String::from_utf8_lossy(value.as_bytes()).into())
+            .into_response();
+    };
+
+    *request.method_mut() = method;
+    next.run(request).await
+}
@@ -0,0 +1,330 @@
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::migrate::{AppliedMigration, Migrate, Migration, Migrator};
+
+use crate::{
+    config::Config,
+    db::{self, Backend, DbConnection, DbPool},
+    envelope::Success,
+    problem::Problem,
+};
+
+pub enum Command {
+    Status,
+    To(i64),
+    Revert,
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Option<Command> {
+        match args
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            ["status"] => Some(Command::Status),
+            ["to", version] => version.parse().ok().map(Command::To),
+            ["revert"] => Some(Command::Revert),
+            _ => None,
+        }
+    }
+}
+
+// `api-service migrate <status|to <version>|revert>`: inspects and moves the
+// schema of the database at DATABASE_URL with the migrations embedded in the
+// binary, the same set the server applies at startup. Returns false on
+// failure, after printing the error.
+pub async fn run(command: Command) -> bool {
+    let result = async {
+        let config = Config::try_from_env().map_err(|errors| errors.join("; "))?;
+
+        let pools = db::connect(&config.database_url, &config.db_pool, &config.sqlite)
+            .await
+            .map_err(|e| e.to_string())?;
+        let migrator = Backend::of(&pools.write).migrator();
+
+        let mut conn = pools.write.acquire().await.map_err(|e| e.to_string())?;
+        conn.ensure_migrations_table()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match command {
+            Command::Status => status(&mut conn, migrator).await,
+            Command::To(version) => migrate_to(&mut conn, migrator, version).await,
+            Command::Revert => revert_latest(&mut conn, migrator).await,
+        }
+    }
+    .await;
+
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("migrate failed: {}", e);
+            false
+        }
+    }
+}
+
+// Prints one line per migration: applied, pending, or modified when the
+// embedded SQL no longer matches what was applied. Applied migrations this
+// binary doesn't know about are listed as unknown.
+async fn status(conn: &mut DbConnection, migrator: &Migrator) -> Result<(), String> {
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for migration in up_migrations(migrator) {
+        let state = match applied.iter().find(|a| a.version == migration.version) {
+            Some(a) if a.checksum != migration.checksum => "modified",
+            Some(_) => "applied",
+            None => "pending",
+        };
+        println!(
+            "{:<8} {} {}",
+            state, migration.version, migration.description
+        );
+    }
+
+    for a in &applied {
+        if !migrator.version_exists(a.version) {
+            println!("{:<8} {}", "unknown", a.version);
+        }
+    }
+
+    if let Some(version) = conn.dirty_version().await.map_err(|e| e.to_string())? {
+        println!(
+            "migration {} failed partway and must be fixed by hand",
+            version
+        );
+    }
+
+    Ok(())
+}
+
+// Applies pending migrations up to and including `target`, and reverts applied
+// ones above it, latest first. `0` reverts every migration.
+async fn migrate_to(
+    conn: &mut DbConnection,
+    migrator: &Migrator,
+    target: i64,
+) -> Result<(), String> {
+    if target != 0 && !migrator.version_exists(target) {
+        return Err(format!("unknown migration version: {}", target));
+    }
+
+    conn.lock().await.map_err(|e| e.to_string())?;
+    let result = migrate_locked(conn, migrator, target).await;
+    conn.unlock().await.map_err(|e| e.to_string())?;
+
+    result
+}
+
+async fn migrate_locked(
+    conn: &mut DbConnection,
+    migrator: &Migrator,
+    target: i64,
+) -> Result<(), String> {
+    if let Some(version) = conn.dirty_version().await.map_err(|e| e.to_string())? {
+        return Err(format!(
+            "migration {} failed partway and must be fixed by hand",
+            version
+        ));
+    }
+
+    let applied = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?;
+    check_applied(migrator, &applied, target)?;
+
+    let mut changed = false;
+
+    for migration in migrator.iter().rev().filter(|migration| {
+        migration.migration_type.is_down_migration()
+            && migration.version > target
+            && applied.iter().any(|a| a.version == migration.version)
+    }) {
+        let elapsed = conn.revert(migration).await.map_err(|e| e.to_string())?;
+        println!(
+            "reverted {} {} in {:?}",
+            migration.version, migration.description, elapsed
+        );
+        changed = true;
+    }
+
+    for migration in up_migrations(migrator).filter(|migration| {
+        migration.version <= target && !applied.iter().any(|a| a.version == migration.version)
+    }) {
+        let elapsed = conn.apply(migration).await.map_err(|e| e.to_string())?;
+        println!(
+            "applied {} {} in {:?}",
+            migration.version, migration.description, elapsed
+        );
+        changed = true;
+    }
+
+    if !changed {
+        println!("already at {}", target);
+    }
+
+    Ok(())
+}
+
+// Refuses to move a schema this binary can't vouch for: applied migrations
+// that were modified or are unknown, and ones above `target` that have no
+// down migration.
+fn check_applied(
+    migrator: &Migrator,
+    applied: &[AppliedMigration],
+    target: i64,
+) -> Result<(), String> {
+    for a in applied {
+        let up = up_migrations(migrator).find(|migration| migration.version == a.version);
+
+        match up {
+            None => return Err(format!("migration {} is unknown to this binary", a.version)),
+            Some(up) if up.checksum != a.checksum => {
+                return Err(format!(
+                    "migration {} was modified after it was applied",
+                    a.version
+                ))
+            }
+            Some(_) => {}
+        }
+
+        let reversible = migrator.iter().any(|migration| {
+            migration.version == a.version && migration.migration_type.is_down_migration()
+        });
+        if a.version > target && !reversible {
+            return Err(format!("migration {} can't be reverted", a.version));
+        }
+    }
+
+    Ok(())
+}
+
+// Reverts the latest applied migration.
+async fn revert_latest(conn: &mut DbConnection, migrator: &Migrator) -> Result<(), String> {
+    let mut applied: Vec<i64> = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|a| a.version)
+        .collect();
+    applied.sort_unstable();
+
+    match applied.pop() {
+        Some(_) => migrate_to(conn, migrator, applied.pop().unwrap_or(0)).await,
+        None => {
+            println!("no migrations applied");
+            Ok(())
+        }
+    }
+}
+
+fn up_migrations(migrator: &Migrator) -> impl Iterator<Item = &Migration> {
+    migrator
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+}
+
+#[derive(Serialize)]
+pub struct MigrationsData {
+    applied: Vec<AppliedData>,
+    pending: Vec<PendingData>,
+}
+
+#[derive(Serialize)]
+pub struct AppliedData {
+    version: i64,
+    description: String,
+    installed_on: String,
+    success: bool,
+    checksum: String,
+    execution_time_ms: f64,
+    state: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct PendingData {
+    version: i64,
+    description: String,
+}
+
+// Admin route: the migrations recorded in the database, with the checksum and
+// time each was applied, and the embedded ones still pending. `state` tells
+// whether an applied migration matches the binary: `applied`, `modified` or
+// `unknown`.
+pub async fn list(
+    State(dbpool): State<Option<DbPool>>,
+) -> Result<Success<MigrationsData>, Problem> {
+    use sqlx::Row;
+
+    let dbpool = dbpool.ok_or_else(|| {
+        Problem::new(StatusCode::CONFLICT).detail("only supported for SQL storage")
+    })?;
+
+    let backend = Backend::of(&dbpool);
+    let migrator = backend.migrator();
+
+    // The Any driver decodes neither booleans nor timestamps, reads SQLite
+    // integers as 32 bits, and MySQL reports TEXT columns as blobs.
+    let sql = match backend {
+        Backend::Sqlite | Backend::Postgres => {
+            "select cast(version as text) as version, description, \
+             cast(installed_on as text) as installed_on, cast(success as integer) as success, \
+             checksum, cast(execution_time as text) as execution_time \
+             from _sqlx_migrations order by version"
+        }
+        Backend::MySql => {
+            "select cast(version as char) as version, cast(description as char) as description, \
+             cast(installed_on as char) as installed_on, cast(success as signed) as success, \
+             checksum, cast(execution_time as char) as execution_time \
+             from _sqlx_migrations order by version"
+        }
+    };
+
+    let rows = sqlx::query(sql).fetch_all(&dbpool).await?;
+
+    let mut applied = Vec::new();
+    let mut applied_versions = Vec::new();
+
+    for row in rows {
+        let version: i64 = row.get::<String, _>("version").parse().unwrap_or_default();
+        let execution_time: i64 = row
+            .get::<String, _>("execution_time")
+            .parse()
+            .unwrap_or_default();
+        let checksum: Vec<u8> = row.get("checksum");
+
+        let state = match up_migrations(migrator).find(|migration| migration.version == version) {
+            Some(migration) if *migration.checksum != *checksum => "modified",
+            Some(_) => "applied",
+            None => "unknown",
+        };
+
+        applied.push(AppliedData {
+            version,
+            description: row.get("description"),
+            installed_on: row.get("installed_on"),
+            success: row.get::<i64, _>("success") != 0,
+            checksum: checksum.iter().map(|b| format!("{:02x}", b)).collect(),
+            execution_time_ms: execution_time as f64 / 1_000_000.0,
+            state,
+        });
+        applied_versions.push(version);
+    }
+
+    let pending = up_migrations(migrator)
+        .filter(|migration| !applied_versions.contains(&migration.version))
+        .map(|migration| PendingData {
+            version: migration.version,
+            description: migration.description.to_string(),
+        })
+        .collect();
+
+    Ok(Success::new(MigrationsData { applied, pending }))
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
+
+use crate::{
+    config::MqttConfig,
+    events::{TodoEvent, TodoEventHandler},
+};
+
+// Publishes queued at most while the broker is unreachable; past that,
+// publishes are dropped rather than holding up writes.
+const QUEUE_CAPACITY: usize = 1024;
+
+// Subscribed to the event bus: publishes the state of every todo written to
+// `<prefix>/<todo id>`, as the todo's JSON, or an empty message once it is
+// deleted. Retained by default, so a dashboard subscribing to `todos/+` gets
+// the current state of every todo straight away, and deleting a todo clears
+// its retained message.
+pub struct MqttPublisher {
+    client: AsyncClient,
+    prefix: String,
+    qos: QoS,
+    retain: bool,
+}
+
+impl MqttPublisher {
+    // Connects in the background, and reconnects whenever the connection is
+    // lost; failures are logged under the `mqtt` target.
+    pub fn start(config: &MqttConfig) -> Result<Self, String> {
+        let (client, eventloop) = AsyncClient::new(options(config)?, QUEUE_CAPACITY);
+        tokio::spawn(run(eventloop));
+
+        Ok(MqttPublisher {
+            client,
+            prefix: config.topic_prefix.clone(),
+            qos: rumqttc::qos(config.qos).map_err(|e| format!("MQTT_QOS: {}", e))?,
+            retain: config.retain,
+        })
+    }
+}
+
+// How to connect to the broker at MQTT_URL.
+pub fn options(config: &MqttConfig) -> Result<MqttOptions, String> {
+    let url = reqwest::Url::parse(&config.url).map_err(|e| format!("MQTT_URL: {}", e))?;
+    if url.scheme() != "mqtt" {
+        return Err("MQTT_URL: must be an mqtt:// URL".to_string());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| "MQTT_URL: missing host".to_string())?;
+
+    let mut options = MqttOptions::new(&config.client_id, host, url.port().unwrap_or(1883));
+    options.set_keep_alive(Duration::from_secs(30));
+    if !url.username().is_empty() {
+        options.set_credentials(url.username(), url.password().unwrap_or_default());
+    }
+
+    Ok(options)
+}
+
+// Drives the connection: sends what is published and handles the broker's
+// acknowledgements.
+async fn run(mut eventloop: EventLoop) {
+    let mut connected = false;
+
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                tracing::info!(target: "mqtt", "connected to the MQTT broker");
+                connected = true;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // Logged once per outage rather than at every retry.
+                if connected {
+                    tracing::warn!(target: "mqtt", error = %e, "lost the MQTT broker, reconnecting");
+                    connected = false;
+                } else {
+                    tracing::debug!(target: "mqtt", error = %e, "couldn't connect to the MQTT broker");
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TodoEventHandler for MqttPublisher {
+    async fn handle(&self, event: &TodoEvent) {
+        let topic = format!("{}/{}", self.prefix, event.id());
+        let payload = match event {
+            TodoEvent::Created(todo) | TodoEvent::Updated(todo) => {
+                serde_json::to_vec(todo).unwrap()
+            }
+            TodoEvent::Deleted(_) => Vec::new(),
+        };
+
+        if let Err(e) = self
+            .client
+            .try_publish(topic, self.qos, self.retain, payload)
+        {
+            tracing::warn!(target: "mqtt", error = %e, "couldn't publish todo state");
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+};
+use serde_json::Value;
+
+use crate::i18n::Message;
+
+pub const MEDIA_TYPE: &str = "application/msgpack";
+
+// Also the name used before it was registered.
+pub const MEDIA_TYPES: [&str; 2] = [MEDIA_TYPE, "application/x-msgpack"];
+
+// MessagePack (https://msgpack.org) for every endpoint that speaks JSON, as
+// negotiated by `negotiate`: request bodies are read as the JSON they encode,
+// and JSON responses encoded as maps keeping their field names.
+
+// Whether the request body is MessagePack.
+pub fn is_body(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| {
+            MEDIA_TYPES
+                .iter()
+                .any(|candidate| media_type.trim().eq_ignore_ascii_case(candidate))
+        })
+}
+
+// The request with its MessagePack body as JSON.
+pub async fn read_body(request: Request) -> Result<Request, Message> {
+    let (mut parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| Message::new("unreadable-body").arg("error", e))?;
+
+    let value: Value = rmp_serde::from_slice(&bytes)
+        .map_err(|e| Message::new("invalid-msgpack").arg("error", e))?;
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Ok(Request::from_parts(parts, Body::from(value.to_string())))
+}
+
+pub fn encode(body: &Value) -> Result<Vec<u8>, String> {
+    rmp_serde::to_vec(body).map_err(|e| e.to_string())
+}
@@ -0,0 +1,93 @@
+use async_nats::jetstream::{self, context::Publish};
+use async_trait::async_trait;
+
+use crate::{
+    config::NatsConfig,
+    events::{TodoEvent, TodoEventHandler},
+};
+
+// Subscribed to the event bus: publishes every todo event to NATS, at
+// `<prefix>.<type>.<todo id>`, e.g. `todos.updated.42`, so subscribers can
+// pick events by type (`todos.updated.*`), by todo (`todos.*.42`) or take
+// all of them (`todos.>`). The payload is the event as live updates send it.
+//
+// Core NATS delivers at most once, to the subscribers connected at the time.
+// With a JetStream stream, events are stored in it and deduplicated by their
+// `Nats-Msg-Id`; a publish that isn't acknowledged is logged under the `nats`
+// target.
+pub struct NatsPublisher {
+    client: async_nats::Client,
+    jetstream: Option<jetstream::Context>,
+    prefix: String,
+}
+
+impl NatsPublisher {
+    // Also creates the JetStream stream, capturing every subject under the
+    // prefix, unless it exists already.
+    pub async fn connect(config: &NatsConfig) -> Result<Self, async_nats::Error> {
+        let client = async_nats::ConnectOptions::new()
+            .name("api-service")
+            .connection_timeout(config.timeout)
+            .connect(config.url.as_str())
+            .await?;
+
+        let jetstream = match &config.stream {
+            Some(stream) => {
+                let mut context = jetstream::new(client.clone());
+                context.set_timeout(config.timeout);
+                context
+                    .get_or_create_stream(jetstream::stream::Config {
+                        name: stream.clone(),
+                        subjects: vec![format!("{}.>", config.subject_prefix)],
+                        ..Default::default()
+                    })
+                    .await?;
+                Some(context)
+            }
+            None => None,
+        };
+
+        Ok(NatsPublisher {
+            client,
+            jetstream,
+            prefix: config.subject_prefix.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl TodoEventHandler for NatsPublisher {
+    async fn handle(&self, event: &TodoEvent) {
+        let subject = format!("{}.{}.{}", self.prefix, event.kind(), event.id());
+        let payload = event.to_json().to_string().into();
+
+        let Some(jetstream) = &self.jetstream else {
+            // Buffered and flushed by the client in the background.
+            if let Err(e) = self.client.publish(subject, payload).await {
+                tracing::warn!(target: "nats", error = %e, "couldn't publish todo event");
+            }
+            return;
+        };
+
+        // The same for a todo's revision however often it is published.
+        let message_id = match event {
+            TodoEvent::Created(todo) | TodoEvent::Updated(todo) => {
+                format!("{}-{}", todo.id, todo.version)
+            }
+            TodoEvent::Deleted(id) => format!("{}-deleted", id),
+        };
+        let publish = Publish::build().payload(payload).message_id(message_id);
+
+        match jetstream.send_publish(subject, publish).await {
+            // Awaited in the background, so writes don't wait on the stream.
+            Ok(ack) => {
+                tokio::spawn(async move {
+                    if let Err(e) = ack.await {
+                        tracing::warn!(target: "nats", error = %e, "todo event not acknowledged by JetStream");
+                    }
+                });
+            }
+            Err(e) => tracing::warn!(target: "nats", error = %e, "couldn't publish todo event"),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+
+use crate::{
+    csv,
+    i18n::{self, Message},
+    jsonapi, msgpack,
+    problem::{self, Problem},
+    protobuf,
+    request_id::request_id,
+};
+
+// A response body, encoded in the format negotiated for the request. Without
+// the negotiation layer, or when JSON is negotiated, it is plain JSON.
+#[derive(Clone)]
+pub struct Representation(pub Value);
+
+impl IntoResponse for Representation {
+    fn into_response(self) -> Response {
+        let mut response = Json(&self.0).into_response();
+        // Spares `negotiate` parsing the body back.
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Json,
+    JsonApi,
+    MsgPack,
+    Protobuf,
+    Csv,
+}
+
+impl Format {
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::JsonApi => &[jsonapi::MEDIA_TYPE],
+            Format::MsgPack => &msgpack::MEDIA_TYPES,
+            Format::Protobuf => &protobuf::MEDIA_TYPES,
+            Format::Csv => &[csv::MEDIA_TYPE],
+        }
+    }
+}
+
+// Every format the todo routes are served in, preferred in this order when the
+// client has no preference.
+const TODO_FORMATS: &[Format] = &[
+    Format::Json,
+    Format::JsonApi,
+    Format::MsgPack,
+    Format::Protobuf,
+    Format::Csv,
+];
+
+// Every other route speaking JSON is also served as MessagePack.
+const FORMATS: &[Format] = &[Format::Json, Format::MsgPack];
+
+// The formats a route is served in, and whether they are all it serves: other
+// routes may respond with HTML, event streams or XML, so requests for
+// something else than their formats are left to them instead of refused.
+fn formats(route: &str) -> (&'static [Format], bool) {
+    match unversioned(route) {
+        Some("/todos" | "/todos/:id") => (TODO_FORMATS, true),
+        _ => (FORMATS, false),
+    }
+}
+
+// The route within its version of the REST API, like `/todos` for
+// `/v1/todos`, None outside them.
+fn unversioned(route: &str) -> Option<&str> {
+    let rest = route.strip_prefix("/v")?;
+    let (version, route) = rest.split_at(rest.find('/')?);
+    (!version.is_empty() && version.bytes().all(|b| b.is_ascii_digit())).then_some(route)
+}
+
+// How the response to a request is encoded.
+struct Encoding {
+    format: Format,
+    // Of messages, picked from Accept-Language.
+    language: &'static str,
+    // The instance of problems: the request's path.
+    path: String,
+    request_id: Option<String>,
+}
+
+// Picks the format of each response from the request's Accept header, by
+// quality, then specificity, then the order the client listed the media
+// types in, and answers 406 listing the supported media types when none is
+// acceptable. Request bodies are read the same way, by Content-Type, so
+// handlers only ever take and return JSON. Messages are rendered in the
+// language picked from Accept-Language, and failures described as problems.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let (formats, exhaustive) = formats(&route);
+    let mut encoding = Encoding {
+        format: Format::Json,
+        language: i18n::language(request.headers()),
+        path: request.uri().path().to_string(),
+        request_id: request_id(&request).map(ToOwned::to_owned),
+    };
+
+    encoding.format = match select(request.headers(), formats) {
+        Some(format) => format,
+        None if exhaustive => {
+            let supported: Vec<_> = formats
+                .iter()
+                .flat_map(|format| format.media_types())
+                .copied()
+                .collect();
+            let message = Message::new("not-acceptable").arg("types", supported.join(", "));
+            return fail(&encoding, StatusCode::NOT_ACCEPTABLE, message).await;
+        }
+        None => Format::Json,
+    };
+    let supports_jsonapi = formats.contains(&Format::JsonApi);
+    let sends_jsonapi = supports_jsonapi && jsonapi::is_document(request.headers());
+    // JSON:API clients that only send documents are answered with documents.
+    if encoding.format == Format::Json
+        && sends_jsonapi
+        && !request.headers().contains_key(header::ACCEPT)
+    {
+        encoding.format = Format::JsonApi;
+    }
+
+    let read = if msgpack::is_body(request.headers()) {
+        msgpack::read_body(request)
+            .await
+            .map_err(|message| (StatusCode::BAD_REQUEST, message))
+    } else if sends_jsonapi {
+        jsonapi::read_document(request).await
+    } else {
+        Ok(request)
+    };
+    let checked = read.and_then(|request| match encoding.format {
+        Format::JsonApi => jsonapi::check_query(&request).map(|()| request),
+        _ => Ok(request),
+    });
+    let request = match checked {
+        Ok(request) => request,
+        Err((status, message)) => return fail(&encoding, status, message).await,
+    };
+
+    let mut response = next.run(request).await;
+    // Caches keep the formats apart.
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+
+    let response = i18n::localize(response, encoding.language);
+    encode(&encoding, response).await
+}
+
+// The format of the best match in Accept, None when nothing supported is
+// acceptable. JSON without Accept.
+fn select(headers: &HeaderMap, formats: &[Format]) -> Option<Format> {
+    let mut ranges = Vec::new();
+    for value in headers.get_all(header::ACCEPT) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        ranges.extend(value.split(',').filter(|range| !range.trim().is_empty()));
+    }
+    if ranges.is_empty() {
+        return Some(Format::Json);
+    }
+
+    // Each format takes the quality of the most specific range matching it,
+    // the first listed among equals. The best is picked by quality,
+    // specificity, then the position in Accept and in `formats`, lower being
+    // better for positions.
+    let mut best: Option<((f32, u8, isize, isize), Format)> = None;
+    for (position, &format) in formats.iter().enumerate() {
+        let mut matched: Option<(u8, isize, f32)> = None;
+        for (index, range) in ranges.iter().enumerate() {
+            let mut params = range.split(';').map(str::trim);
+            let media_range = params.next().unwrap_or_default().to_ascii_lowercase();
+            let mut quality = 1.0;
+            let mut extended = false;
+            for param in params {
+                match param.split_once('=') {
+                    Some(("q", q)) => quality = q.trim().parse().unwrap_or(0.0),
+                    _ => extended = true,
+                }
+            }
+            // The spec reserves JSON:API media type parameters for extensions
+            // and profiles, none of which are supported.
+            if extended && format == Format::JsonApi {
+                continue;
+            }
+
+            let specificity = match media_range.as_str() {
+                "*/*" => 0,
+                range if range.ends_with("/*") => 1,
+                _ => 2,
+            };
+            let matches = format
+                .media_types()
+                .iter()
+                .any(|media_type| match specificity {
+                    0 => true,
+                    1 => media_type.starts_with(media_range.trim_end_matches('*')),
+                    _ => *media_type == media_range,
+                });
+            if matches && matched.is_none_or(|(best, _, _)| specificity > best) {
+                matched = Some((specificity, -(index as isize), quality));
+            }
+        }
+
+        let Some((specificity, index, quality)) = matched else {
+            continue;
+        };
+        let score = (quality, specificity, index, -(position as isize));
+        if quality > 0.0 && best.is_none_or(|(best, _)| score > best) {
+            best = Some((score, format));
+        }
+    }
+
+    best.map(|(_, format)| format)
+}
+
+// Encodes a JSON response, or a problem with the request's path as its
+// instance, in the format. Failures are encoded from their representation,
+// those without one are left as they are. A format with no encoding for a
+// body, like protobuf for problems, leaves it JSON.
+async fn encode(encoding: &Encoding, response: Response) -> Response {
+    let format = encoding.format;
+    let failed = response.status().is_client_error() || response.status().is_server_error();
+    if format == Format::Json && !failed {
+        return response;
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|value| value.as_bytes().to_vec())
+        .unwrap_or_default();
+    let is_problem = content_type.starts_with(problem::MEDIA_TYPE.as_bytes());
+    if !(is_problem || content_type.starts_with(b"application/json")) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let represented = parts
+        .extensions
+        .remove::<Representation>()
+        .map(|Representation(body)| body);
+    let mut body = match represented {
+        Some(body) => body,
+        None if failed => return Response::from_parts(parts, body),
+        None => {
+            let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(_) => return Response::from_parts(parts, Body::empty()),
+            };
+            match serde_json::from_slice(&bytes) {
+                Ok(body) => body,
+                Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+            }
+        }
+    };
+    let json_type = if is_problem {
+        if let Some(problem) = body.as_object_mut() {
+            problem
+                .entry("instance")
+                .or_insert_with(|| encoding.path.clone().into());
+        }
+        problem::MEDIA_TYPE
+    } else {
+        "application/json"
+    };
+
+    let encoded = match format {
+        Format::Json => None,
+        Format::JsonApi => jsonapi::document(parts.status, &body)
+            .map(|document| Ok((jsonapi::MEDIA_TYPE.to_string(), document.to_string().into()))),
+        Format::MsgPack => {
+            Some(msgpack::encode(&body).map(|encoded| (msgpack::MEDIA_TYPE.to_string(), encoded)))
+        }
+        Format::Protobuf if !failed => protobuf::encode(&body).map(|encoded| {
+            encoded
+                .map(|(message_type, encoded)| {
+                    let content_type =
+                        format!("{}; messageType=\"{}\"", protobuf::MEDIA_TYPE, message_type);
+                    (content_type, encoded)
+                })
+                .map_err(|e| e.to_string())
+        }),
+        Format::Csv if !failed => csv::encode(&body).map(|encoded| {
+            encoded
+                .map(|encoded| {
+                    let content_type =
+                        format!("{}; charset=utf-8; header=present", csv::MEDIA_TYPE);
+                    (content_type, encoded.into_bytes())
+                })
+                .map_err(|e| e.to_string())
+        }),
+        Format::Protobuf | Format::Csv => None,
+    };
+
+    let (content_type, encoded) = match encoded {
+        Some(Ok(encoded)) => encoded,
+        Some(Err(e)) => {
+            tracing::warn!(error = %e, ?format, "couldn't encode a response");
+            (json_type.to_string(), body.to_string().into_bytes())
+        }
+        None => (json_type.to_string(), body.to_string().into_bytes()),
+    };
+    if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+// A failure, in the format, with the request id the handlers' failures get.
+async fn fail(encoding: &Encoding, status: StatusCode, message: Message) -> Response {
+    let mut problem = Problem::new(status).detail(message.to_string());
+    if let Some(request_id) = &encoding.request_id {
+        problem
+            .extensions
+            .insert("request_id".to_string(), request_id.clone().into());
+    }
+
+    let response = (message, problem).into_response();
+    encode(encoding, i18n::localize(response, encoding.language)).await
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{
+    config::{NotifierConfig, NotifyEvent},
+    events::{TodoEvent, TodoEventHandler},
+    preferences::Preferences,
+    repository::DynTodoRepository,
+    todo::Todo,
+};
+
+// How long the chat service has to accept a message.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Where notifications are posted.
+#[derive(Clone, Copy, Debug)]
+pub enum Service {
+    // A Slack incoming webhook.
+    Slack,
+    // A Discord channel webhook.
+    Discord,
+}
+
+impl Service {
+    fn name(self) -> &'static str {
+        match self {
+            Service::Slack => "slack",
+            Service::Discord => "discord",
+        }
+    }
+
+    // The message body the service's webhook takes. Discord's carries an
+    // embed with the todo's details, titled with a link to it under
+    // PUBLIC_URL unless it was deleted.
+    fn message(
+        self,
+        text: &str,
+        event: NotifyEvent,
+        id: i64,
+        todo: Option<&Todo>,
+        url: Option<&str>,
+    ) -> serde_json::Value {
+        match self {
+            Service::Slack => json!({"text": text}),
+            Service::Discord => {
+                let mut embed = json!({
+                    "title": format!("Todo #{}", id),
+                    "color": match event {
+                        NotifyEvent::Created => 0x5865f2,
+                        NotifyEvent::Completed => 0x57f287,
+                        NotifyEvent::Reopened => 0xfee75c,
+                        NotifyEvent::Deleted => 0xed4245,
+                    },
+                });
+                if let Some(todo) = todo {
+                    if let Some(url) = url {
+                        embed["url"] = json!(url);
+                    }
+                    embed["description"] = json!(self.escape(&todo.body));
+                    embed["fields"] = json!([
+                        {"name": "Status", "value": if todo.completed { "completed" } else { "open" }, "inline": true},
+                        {"name": "Version", "value": todo.version.to_string(), "inline": true},
+                    ]);
+                    embed["timestamp"] = json!(todo.updated_at.and_utc().to_rfc3339());
+                }
+
+                json!({"content": text, "embeds": [embed]})
+            }
+        }
+    }
+
+    // Escapes what the todo's body would otherwise be formatted as.
+    fn escape(self, body: &str) -> String {
+        match self {
+            Service::Slack => body
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            Service::Discord => body.chars().fold(String::new(), |mut escaped, c| {
+                if "\\*_~`|>[]".contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+                escaped
+            }),
+        }
+    }
+}
+
+// Subscribed to the event bus: posts a message to a chat webhook for the
+// configured events. A todo is `completed` or `reopened` by an update that
+// changes whether it is completed, told from the revision before it.
+//
+// Events whose preference is disabled for the service are skipped.
+//
+// Messages are posted in the background, so writes don't wait on the chat
+// service, and once: failures are logged under the `notifications` target.
+#[derive(Clone)]
+pub struct Notifier {
+    service: Service,
+    config: NotifierConfig,
+    public_url: Option<String>,
+    todos: DynTodoRepository,
+    preferences: Preferences,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn new(
+        service: Service,
+        config: NotifierConfig,
+        public_url: Option<String>,
+        todos: DynTodoRepository,
+        preferences: Preferences,
+    ) -> Self {
+        Notifier {
+            service,
+            config,
+            public_url,
+            todos,
+            preferences,
+            client: reqwest::Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .expect("couldn't build the notifications HTTP client"),
+        }
+    }
+
+    fn url(&self, id: i64) -> Option<String> {
+        self.public_url
+            .as_ref()
+            .map(|public_url| format!("{}/v1/todos/{}", public_url, id))
+    }
+
+    fn render(&self, event: NotifyEvent, id: i64, todo: Option<&Todo>) -> String {
+        let body = todo.map_or_else(String::new, |todo| self.service.escape(&todo.body));
+
+        self.config
+            .templates
+            .get(event)
+            .replace("{id}", &id.to_string())
+            .replace("{url}", &self.url(id).unwrap_or_default())
+            .replace("{body}", &body)
+    }
+}
+
+#[async_trait]
+impl TodoEventHandler for Notifier {
+    async fn handle(&self, event: &TodoEvent) {
+        let event = event.clone();
+        let notifier = self.clone();
+
+        tokio::spawn(async move {
+            let (notify_event, todo) = match &event {
+                TodoEvent::Created(todo) => (NotifyEvent::Created, Some(todo)),
+                TodoEvent::Deleted(_) => (NotifyEvent::Deleted, None),
+                TodoEvent::Updated(_)
+                    if !notifier.config.events.iter().any(|wanted| {
+                        matches!(wanted, NotifyEvent::Completed | NotifyEvent::Reopened)
+                    }) =>
+                {
+                    return
+                }
+                TodoEvent::Updated(todo) => {
+                    match notifier.todos.revision(todo.id, todo.version - 1).await {
+                        Ok(previous) if previous.completed == todo.completed => return,
+                        Ok(_) if todo.completed => (NotifyEvent::Completed, Some(todo)),
+                        Ok(_) => (NotifyEvent::Reopened, Some(todo)),
+                        Err(e) => {
+                            tracing::warn!(target: "notifications", error = %e, id = todo.id, "couldn't read the previous revision of a todo");
+                            return;
+                        }
+                    }
+                }
+            };
+            if !notifier.config.events.contains(&notify_event)
+                || !notifier
+                    .preferences
+                    .allows(notifier.service.name(), notify_event.as_str())
+                    .await
+            {
+                return;
+            }
+
+            let text = notifier.render(notify_event, event.id(), todo);
+            let result = notifier
+                .client
+                .post(&notifier.config.webhook_url)
+                .json(&notifier.service.message(
+                    &text,
+                    notify_event,
+                    event.id(),
+                    todo,
+                    notifier.url(event.id()).as_deref(),
+                ))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(e) = result {
+                tracing::warn!(
+                    target: "notifications",
+                    service = notifier.service.name(),
+                    event = notify_event.as_str(),
+                    error = %e,
+                    "couldn't send notification"
+                );
+            }
+        });
+    }
+}
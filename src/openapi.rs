@@ -0,0 +1,108 @@
+use axum::{response::Html, Json};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    api::{
+        self, CountData, Link, ListLinks, NextTodoData, SuggestionData, SuggestionsData, TodoData,
+        TodoLinks, TodoListData, TodoResponse,
+    },
+    envelope::{NextTodo, TodoCount, TodoEnvelope, TodoList, TodoSuggestions},
+    todo::{CreateTodo, UpdateTodo},
+};
+
+// The REST API, described from the handler annotations in `api.rs`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "api-service", description = "Todo API"),
+    paths(
+        api::todo_list,
+        api::todo_count,
+        api::todo_next,
+        api::todo_suggest,
+        api::todo_read,
+        api::todo_create,
+        api::todo_update,
+        api::todo_delete
+    ),
+    components(schemas(
+        TodoResponse,
+        TodoLinks,
+        Link,
+        ListLinks,
+        CreateTodo,
+        UpdateTodo,
+        TodoEnvelope,
+        TodoData,
+        TodoList,
+        TodoListData,
+        TodoCount,
+        CountData,
+        NextTodo,
+        NextTodoData,
+        TodoSuggestions,
+        SuggestionsData,
+        SuggestionData,
+        ErrorBody,
+        InvalidParam
+    ))
+)]
+struct ApiDoc;
+
+// Problem details (RFC 7807), served as application/problem+json.
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct ErrorBody {
+    #[schema(rename = "type", example = "about:blank")]
+    type_: String,
+    #[schema(example = "Not Found")]
+    title: String,
+    #[schema(example = 404)]
+    status: u16,
+    #[schema(example = "todo with ID: 9 not found")]
+    detail: Option<String>,
+    #[schema(example = "/v1/todos/9")]
+    instance: Option<String>,
+    request_id: Option<String>,
+    // The fields of a `422` body breaking its rules.
+    #[schema(rename = "invalid-params")]
+    invalid_params: Option<Vec<InvalidParam>>,
+}
+
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct InvalidParam {
+    #[schema(example = "body")]
+    name: String,
+    #[schema(example = "must not be blank")]
+    reason: String,
+}
+
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    let mut doc = ApiDoc::openapi();
+    // Taken from Cargo.toml, which declares no license.
+    doc.info.license = None;
+
+    Json(doc)
+}
+
+// Swagger UI, loaded from a CDN and pointed at `/openapi.json`.
+pub async fn docs() -> Html<&'static str> {
+    Html(
+        r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>api-service</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##,
+    )
+}
@@ -0,0 +1,37 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower::ServiceExt;
+
+// Answers OPTIONS with the methods the path's route serves in Allow, as the
+// router lists them when refusing others. CORS preflights, OPTIONS with
+// Access-Control-Request-Method, are left to the CORS layer, which takes
+// every OPTIONS: the others are routed through `routes`, the router without
+// it.
+pub async fn allow(State(routes): State<Router>, request: Request, next: Next) -> Response {
+    let preflight = request
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if request.method() != Method::OPTIONS || preflight {
+        return next.run(request).await;
+    }
+
+    let Ok(response) = routes.oneshot(request).await;
+    let allow = response
+        .headers()
+        .get(header::ALLOW)
+        .and_then(|allow| allow.to_str().ok());
+    let Some(allow) = allow.filter(|_| response.status() == StatusCode::METHOD_NOT_ALLOWED) else {
+        return response;
+    };
+
+    let allow = format!("{},OPTIONS", allow);
+    match HeaderValue::try_from(allow) {
+        Ok(allow) => (StatusCode::NO_CONTENT, [(header::ALLOW, allow)]).into_response(),
+        Err(_) => response,
+    }
+}
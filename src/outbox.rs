@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use rskafka::{
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        ClientBuilder,
+    },
+    record::Record,
+};
+use sqlx::Row;
+
+use crate::{
+    config::KafkaConfig,
+    db::{Backend, DbPool},
+    state::Lifecycle,
+};
+
+// Events published at once.
+const BATCH_SIZE: i64 = 500;
+
+// How often the outbox is checked for events when it was drained.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// How long the relaying instance keeps the lease without renewing it, after
+// which another instance takes over.
+const LEASE: Duration = Duration::from_secs(30);
+
+// An event read from the outbox.
+struct OutboxEvent {
+    seq: i64,
+    event: String,
+    todo_id: i64,
+    payload: String,
+    created_at: i64,
+}
+
+// Publishes the events in the outbox to Kafka, in the order of their writes,
+// then deletes them. Writes record their event in the outbox in their own
+// transaction, so an event is published for every write that committed and
+// for no other. Events are published at least once: a relay that fails
+// between publishing and deleting publishes them again, so consumers should
+// skip the `event-id`s they have seen.
+//
+// One instance relays at a time, the one holding the lease in `outbox_relay`.
+// Failures are logged under the `outbox` target and retried.
+pub fn spawn(
+    config: KafkaConfig,
+    dbpool: DbPool,
+    lifecycle: Lifecycle,
+) -> tokio::task::JoinHandle<()> {
+    let holder: String = rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    tokio::spawn(async move {
+        let mut publisher = None;
+
+        loop {
+            // The outbox tables may be missing then.
+            if !lifecycle.is_schema_outdated() {
+                match relay(&config, &dbpool, &holder, &mut publisher).await {
+                    // More may be waiting already.
+                    Ok(published) if published as i64 == BATCH_SIZE => continue,
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!(target: "outbox", error = %e, "couldn't publish outbox events");
+                        // Connects again, in case the topic or brokers changed.
+                        publisher = None;
+                    }
+                }
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    })
+}
+
+// Publishes a batch of events if this instance holds the lease, returning
+// how many.
+async fn relay(
+    config: &KafkaConfig,
+    dbpool: &DbPool,
+    holder: &str,
+    publisher: &mut Option<Publisher>,
+) -> Result<usize, String> {
+    if !acquire_lease(dbpool, holder)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(0);
+    }
+
+    let events = pending(dbpool).await.map_err(|e| e.to_string())?;
+    let Some(last) = events.last().map(|event| event.seq) else {
+        return Ok(0);
+    };
+
+    let publisher = match publisher {
+        Some(publisher) => publisher,
+        None => publisher.insert(Publisher::connect(config).await?),
+    };
+    publisher.publish(&events, config.timeout).await?;
+
+    let backend = Backend::of(dbpool);
+    sqlx::query(&backend.sql("delete from outbox where seq <= $1"))
+        .bind(last)
+        .execute(dbpool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tracing::debug!(target: "outbox", published = events.len(), last_seq = last, "published outbox events");
+
+    Ok(events.len())
+}
+
+// Takes the lease, or renews it, unless another instance holds it.
+async fn acquire_lease(dbpool: &DbPool, holder: &str) -> Result<bool, sqlx::Error> {
+    let backend = Backend::of(dbpool);
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query(&backend.sql(
+        "update outbox_relay set holder = $1, expires_at = $2 \
+         where id = 1 and (holder = $3 or expires_at < $4)",
+    ))
+    .bind(holder)
+    .bind(now + LEASE.as_secs() as i64)
+    .bind(holder)
+    .bind(now)
+    .execute(dbpool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+async fn pending(dbpool: &DbPool) -> Result<Vec<OutboxEvent>, sqlx::Error> {
+    let backend = Backend::of(dbpool);
+    // MySQL reports TEXT columns as blobs.
+    let payload = match backend {
+        Backend::Sqlite | Backend::Postgres => "payload",
+        Backend::MySql => "cast(payload as char) as payload",
+    };
+    let sql = format!(
+        "select seq, event, todo_id, {}, created_at from outbox order by seq limit $1",
+        payload
+    );
+
+    sqlx::query(&backend.sql(&sql))
+        .bind(BATCH_SIZE)
+        .fetch_all(dbpool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(OutboxEvent {
+                seq: row.try_get("seq")?,
+                event: row.try_get("event")?,
+                todo_id: row.try_get("todo_id")?,
+                payload: row.try_get("payload")?,
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .collect()
+}
+
+// Connects to Kafka as the relay does, for `doctor`, returning the number of
+// partitions of the topic.
+pub async fn check(config: &KafkaConfig) -> Result<usize, String> {
+    Publisher::connect(config)
+        .await
+        .map(|publisher| publisher.partitions.len())
+}
+
+struct Publisher {
+    // One per partition of the topic, by partition id.
+    partitions: Vec<PartitionClient>,
+}
+
+impl Publisher {
+    async fn connect(config: &KafkaConfig) -> Result<Self, String> {
+        let connect = async {
+            let client = ClientBuilder::new(config.brokers.clone())
+                .client_id("api-service")
+                .build()
+                .await
+                .map_err(|e| format!("couldn't connect to Kafka: {}", e))?;
+
+            let topic = client
+                .list_topics()
+                .await
+                .map_err(|e| format!("couldn't list Kafka topics: {}", e))?
+                .into_iter()
+                .find(|topic| topic.name == config.topic)
+                .ok_or_else(|| format!("Kafka topic {} doesn't exist", config.topic))?;
+
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for partition in topic.partitions {
+                partitions.push(
+                    client
+                        .partition_client(
+                            config.topic.clone(),
+                            partition,
+                            UnknownTopicHandling::Error,
+                        )
+                        .await
+                        .map_err(|e| {
+                            format!("couldn't connect to partition {}: {}", partition, e)
+                        })?,
+                );
+            }
+            if partitions.is_empty() {
+                return Err(format!("Kafka topic {} has no partitions", config.topic));
+            }
+
+            Ok(Publisher { partitions })
+        };
+
+        tokio::time::timeout(config.timeout, connect)
+            .await
+            .map_err(|_| "timed out connecting to Kafka".to_string())?
+    }
+
+    // Keyed by todo id, and partitioned by it, so the events of a todo keep
+    // their order.
+    async fn publish(&self, events: &[OutboxEvent], timeout: Duration) -> Result<(), String> {
+        let mut batches: BTreeMap<usize, Vec<Record>> = BTreeMap::new();
+        for event in events {
+            let partition = event.todo_id.rem_euclid(self.partitions.len() as i64) as usize;
+            batches.entry(partition).or_default().push(Record {
+                key: Some(event.todo_id.to_string().into_bytes()),
+                value: Some(event.payload.clone().into_bytes()),
+                headers: BTreeMap::from([
+                    ("event-id".to_string(), event.seq.to_string().into_bytes()),
+                    ("event-type".to_string(), event.event.clone().into_bytes()),
+                ]),
+                timestamp: chrono::DateTime::from_timestamp_millis(event.created_at)
+                    .unwrap_or_default(),
+            });
+        }
+
+        for (partition, records) in batches {
+            let client = &self.partitions[partition];
+            tokio::time::timeout(timeout, client.produce(records, Compression::NoCompression))
+                .await
+                .map_err(|_| format!("timed out publishing to partition {}", client.partition()))?
+                .map_err(|e| {
+                    format!(
+                        "couldn't publish to partition {}: {}",
+                        client.partition(),
+                        e
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use axum::{
+    async_trait,
+    extract::{path::ErrorKind, rejection::PathRejection, FromRequestParts, Path, RawPathParams},
+    http::request::Parts,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::ApiError;
+use crate::problem::Problem;
+
+// The parameters of the request path, like `Path`, refused with 400 saying
+// which parameter has a value of the wrong type and what was expected, like
+// an integer for the id of `/v1/todos/abc`.
+pub struct PathParams<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for PathParams<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let rejection = match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(params)) => return Ok(PathParams(params)),
+            Err(PathRejection::FailedToDeserializePathParams(rejection)) => rejection,
+            Err(rejection) => return Err(rejected(rejection.status(), rejection.body_text())),
+        };
+
+        let (key, value, expected_type) = match rejection.kind() {
+            ErrorKind::ParseErrorAtKey {
+                key,
+                value,
+                expected_type,
+            } => (Some(key.clone()), value.clone(), *expected_type),
+            ErrorKind::ParseErrorAtIndex {
+                value,
+                expected_type,
+                ..
+            }
+            | ErrorKind::ParseError {
+                value,
+                expected_type,
+            } => (None, value.clone(), *expected_type),
+            _ => return Err(rejected(rejection.status(), rejection.body_text())),
+        };
+        // Single parameters are parsed without their name.
+        let name = match key {
+            Some(key) => key,
+            None => RawPathParams::from_request_parts(parts, state)
+                .await
+                .ok()
+                .and_then(|raw| {
+                    raw.iter()
+                        .find(|(_, raw)| *raw == value)
+                        .map(|(key, _)| key.to_string())
+                })
+                .unwrap_or_default(),
+        };
+
+        Err(ApiError::InvalidPathParam {
+            name,
+            value,
+            expected: expected(expected_type),
+        }
+        .into_response())
+    }
+}
+
+// The parameters of the query string, like `Query`, refused with 400 naming
+// the parameter that doesn't parse.
+pub struct QueryParams<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for QueryParams<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+
+        serde_path_to_error::deserialize(deserializer)
+            .map(QueryParams)
+            .map_err(|err| {
+                ApiError::InvalidQueryParam {
+                    name: err.path().to_string(),
+                    error: err.into_inner().to_string(),
+                }
+                .into_response()
+            })
+    }
+}
+
+// The other rejections of `Path`, as problems detailed with their reason.
+fn rejected(status: StatusCode, reason: String) -> Response {
+    Problem::new(status).detail(reason).into_response()
+}
+
+// The kind of value a Rust type parses, for the client: `integer`, `number`
+// or `boolean`, the type's name otherwise.
+fn expected(type_name: &str) -> String {
+    match type_name {
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+        | "usize" => "integer",
+        "f32" | "f64" => "number",
+        "bool" => "boolean",
+        other => other,
+    }
+    .to_string()
+}
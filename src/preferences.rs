@@ -0,0 +1,247 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::{Error, Row};
+
+use crate::{
+    body::PlainJsonBody,
+    db::{Backend, DbPool},
+    envelope::Success,
+    problem::Problem,
+};
+
+// The events each channel can notify of.
+const CHANNELS: [(&str, &[&str]); 5] = [
+    ("slack", &["created", "completed", "reopened", "deleted"]),
+    ("discord", &["created", "completed", "reopened", "deleted"]),
+    ("email", &["reminder"]),
+    ("push", &["reminder"]),
+    ("telegram", &["reminder"]),
+];
+
+// Where preferences are kept, as whether a channel notifies of an event.
+#[async_trait]
+pub trait PreferenceStore: Send + Sync {
+    // None unless it was set.
+    async fn get(&self, channel: &str, event: &str) -> Result<Option<bool>, Error>;
+
+    // Every preference set, as channel, event and whether it is enabled.
+    async fn list(&self) -> Result<Vec<(String, String, bool)>, Error>;
+
+    async fn set(&self, preferences: &[(String, String, bool)], now: i64) -> Result<(), Error>;
+}
+
+pub type DynPreferenceStore = Arc<dyn PreferenceStore>;
+
+// Preferences in the `notification_preferences` table, shared by every
+// instance.
+pub struct SqlPreferenceStore {
+    dbpool: DbPool,
+}
+
+impl SqlPreferenceStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlPreferenceStore { dbpool }
+    }
+}
+
+#[async_trait]
+impl PreferenceStore for SqlPreferenceStore {
+    async fn get(&self, channel: &str, event: &str) -> Result<Option<bool>, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let row =
+            sqlx::query(&backend.sql(
+                "select enabled from notification_preferences where channel = $1 and event = $2",
+            ))
+            .bind(channel)
+            .bind(event)
+            .fetch_optional(&self.dbpool)
+            .await?;
+
+        row.map(|row| Ok(row.try_get::<i64, _>("enabled")? != 0))
+            .transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<(String, String, bool)>, Error> {
+        sqlx::query("select channel, event, enabled from notification_preferences")
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok((
+                    row.try_get("channel")?,
+                    row.try_get("event")?,
+                    row.try_get::<i64, _>("enabled")? != 0,
+                ))
+            })
+            .collect()
+    }
+
+    async fn set(&self, preferences: &[(String, String, bool)], now: i64) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+        let delete =
+            backend.sql("delete from notification_preferences where channel = $1 and event = $2");
+        let insert = backend.sql(
+            "insert into notification_preferences (channel, event, enabled, updated_at) \
+             values ($1, $2, $3, $4)",
+        );
+
+        let mut tx = self.dbpool.begin().await?;
+        for (channel, event, enabled) in preferences {
+            sqlx::query(&delete)
+                .bind(channel)
+                .bind(event)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&insert)
+                .bind(channel)
+                .bind(event)
+                .bind(*enabled as i64)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await
+    }
+}
+
+// Keeps preferences in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryPreferenceStore {
+    preferences: Mutex<BTreeMap<(String, String), bool>>,
+}
+
+#[async_trait]
+impl PreferenceStore for InMemoryPreferenceStore {
+    async fn get(&self, channel: &str, event: &str) -> Result<Option<bool>, Error> {
+        let preferences = self.preferences.lock().unwrap();
+
+        Ok(preferences
+            .get(&(channel.to_string(), event.to_string()))
+            .copied())
+    }
+
+    async fn list(&self) -> Result<Vec<(String, String, bool)>, Error> {
+        let preferences = self.preferences.lock().unwrap();
+
+        Ok(preferences
+            .iter()
+            .map(|((channel, event), enabled)| (channel.clone(), event.clone(), *enabled))
+            .collect())
+    }
+
+    async fn set(&self, preferences: &[(String, String, bool)], _now: i64) -> Result<(), Error> {
+        let mut stored = self.preferences.lock().unwrap();
+
+        for (channel, event, enabled) in preferences {
+            stored.insert((channel.clone(), event.clone()), *enabled);
+        }
+
+        Ok(())
+    }
+}
+
+// Which events each channel notifies of, on top of the channel's own
+// configuration: a channel notifies of an event it is configured for unless
+// its preference is disabled.
+#[derive(Clone)]
+pub struct Preferences {
+    store: DynPreferenceStore,
+}
+
+impl Preferences {
+    pub fn new(store: DynPreferenceStore) -> Self {
+        Preferences { store }
+    }
+
+    // Allows the notification when the preference can't be read, as it is
+    // more likely wanted than not.
+    pub async fn allows(&self, channel: &str, event: &str) -> bool {
+        match self.store.get(channel, event).await {
+            Ok(enabled) => enabled.unwrap_or(true),
+            Err(e) => {
+                tracing::warn!(target: "notifications", error = %e, channel, event, "couldn't read notification preference");
+                true
+            }
+        }
+    }
+
+    // Every channel and event, set or not.
+    async fn all(&self) -> Result<PreferencesData, Error> {
+        let set = self.store.list().await?;
+
+        let mut preferences = BTreeMap::new();
+        for (channel, events) in CHANNELS {
+            let channel_preferences: &mut BTreeMap<_, _> = preferences.entry(channel).or_default();
+            for &event in events {
+                let disabled = set
+                    .iter()
+                    .any(|(c, e, enabled)| c == channel && e == event && !enabled);
+                channel_preferences.insert(event, !disabled);
+            }
+        }
+
+        Ok(PreferencesData { preferences })
+    }
+}
+
+// Whether each channel notifies of each event.
+#[derive(Serialize)]
+pub struct PreferencesData {
+    preferences: BTreeMap<&'static str, BTreeMap<&'static str, bool>>,
+}
+
+pub async fn get(
+    State(preferences): State<Preferences>,
+) -> Result<Success<PreferencesData>, Problem> {
+    let all = preferences.all().await?;
+
+    Ok(Success::new(all))
+}
+
+// Sets the preferences given, by channel then event, leaving the others as
+// they are.
+pub async fn update(
+    State(preferences): State<Preferences>,
+    PlainJsonBody(request): PlainJsonBody<BTreeMap<String, BTreeMap<String, bool>>>,
+) -> Result<Success<PreferencesData>, Problem> {
+    let mut changes = Vec::new();
+    for (channel, events) in request {
+        let Some((_, supported)) = CHANNELS.iter().find(|(name, _)| *name == channel) else {
+            return Err(fail(format!(
+                "unknown channel: {}, expected slack, discord, email, push or telegram",
+                channel
+            )));
+        };
+        for (event, enabled) in events {
+            if !supported.contains(&event.as_str()) {
+                return Err(fail(format!(
+                    "{} doesn't notify of {}, only of {}",
+                    channel,
+                    event,
+                    supported.join(", ")
+                )));
+            }
+            changes.push((channel.clone(), event, enabled));
+        }
+    }
+
+    preferences
+        .store
+        .set(&changes, chrono::Utc::now().timestamp())
+        .await?;
+    let all = preferences.all().await?;
+
+    Ok(Success::new(all))
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
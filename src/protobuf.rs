@@ -0,0 +1,31 @@
+use prost::Message;
+use serde_json::Value;
+
+use crate::{grpc::pb, todo::Todo};
+
+pub const MEDIA_TYPE: &str = "application/x-protobuf";
+
+// Also the name in the IETF draft.
+pub const MEDIA_TYPES: [&str; 2] = [MEDIA_TYPE, "application/protobuf"];
+
+// A todo or a list, the plain bodies of the todo routes, as the `todo.v1.Todo`
+// or `todo.v1.TodoList` message of `proto/todo/v1/todo.proto` also used by
+// the gRPC service, along with the message's name. None for other bodies.
+pub fn encode(body: &Value) -> Option<Result<(&'static str, Vec<u8>), serde_json::Error>> {
+    if let Some(todo) = body.pointer("/data/todo") {
+        return Some(
+            serde_json::from_value::<Todo>(todo.clone())
+                .map(|todo| ("todo.v1.Todo", pb::Todo::from(todo).encode_to_vec())),
+        );
+    }
+
+    let todos = body.pointer("/data/todos")?;
+    Some(
+        serde_json::from_value::<Vec<Todo>>(todos.clone()).map(|todos| {
+            let list = pb::TodoList {
+                todos: todos.into_iter().map(pb::Todo::from).collect(),
+            };
+            ("todo.v1.TodoList", list.encode_to_vec())
+        }),
+    )
+}
@@ -0,0 +1,622 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit, Nonce};
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+};
+use base64::prelude::*;
+use hkdf::Hkdf;
+use p256::{
+    ecdh::EphemeralSecret,
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey, SecretKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{any::AnyRow, Error, Row};
+
+use crate::{
+    body::PlainJsonBody,
+    config::PushConfig,
+    db::{Backend, DbPool},
+    envelope::Success,
+    params::PathParams,
+    preferences::Preferences,
+    problem::Problem,
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    telegram,
+    todo::Todo,
+};
+
+// Open todos listed at most in a reminder, which has to fit in the 4KB a
+// push can carry.
+const MAX_LISTED: usize = 10;
+
+// How long a VAPID signature is valid; push services accept at most 24 hours.
+const VAPID_VALIDITY: i64 = 12 * 3600;
+
+// A browser subscribed to pushes. Times are unix seconds.
+#[derive(Clone, Serialize)]
+pub struct Subscription {
+    pub id: i64,
+    pub endpoint: String,
+    #[serde(skip)]
+    pub p256dh: String,
+    #[serde(skip)]
+    pub auth: String,
+    pub created_at: i64,
+}
+
+// Where push subscriptions are kept. A missing subscription is reported as
+// `sqlx::Error::RowNotFound`.
+#[async_trait]
+pub trait PushStore: Send + Sync {
+    // Keeps a subscription, or updates the keys of the one to `endpoint`, as
+    // browsers subscribing again may have new ones.
+    async fn subscribe(
+        &self,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+        now: i64,
+    ) -> Result<Subscription, Error>;
+
+    async fn unsubscribe(&self, id: i64) -> Result<(), Error>;
+
+    async fn list(&self) -> Result<Vec<Subscription>, Error>;
+
+    // Whether the reminder of `day` is this instance's to push, being the
+    // first to claim it.
+    async fn claim_reminder(&self, day: &str, now: i64) -> Result<bool, Error>;
+}
+
+pub type DynPushStore = Arc<dyn PushStore>;
+
+// Subscriptions in the `push_subscriptions` table, shared by every instance.
+pub struct SqlPushStore {
+    dbpool: DbPool,
+}
+
+impl SqlPushStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlPushStore { dbpool }
+    }
+}
+
+// MySQL reports TEXT columns as blobs.
+fn subscription_columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => "id, endpoint, p256dh, auth, created_at",
+        Backend::MySql => "id, cast(endpoint as char) as endpoint, p256dh, auth, created_at",
+    }
+}
+
+fn subscription_from_row(row: &AnyRow) -> Result<Subscription, Error> {
+    Ok(Subscription {
+        id: row.try_get("id")?,
+        endpoint: row.try_get("endpoint")?,
+        p256dh: row.try_get("p256dh")?,
+        auth: row.try_get("auth")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[async_trait]
+impl PushStore for SqlPushStore {
+    async fn subscribe(
+        &self,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+        now: i64,
+    ) -> Result<Subscription, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let result = sqlx::query(&backend.sql(
+            "insert into push_subscriptions (endpoint, p256dh, auth, created_at) \
+             values ($1, $2, $3, $4)",
+        ))
+        .bind(endpoint)
+        .bind(p256dh)
+        .bind(auth)
+        .bind(now)
+        .execute(&self.dbpool)
+        .await;
+
+        match result {
+            Ok(_) => {}
+            Err(Error::Database(e)) if e.is_unique_violation() => {
+                sqlx::query(&backend.sql(
+                    "update push_subscriptions set p256dh = $1, auth = $2 where endpoint = $3",
+                ))
+                .bind(p256dh)
+                .bind(auth)
+                .bind(endpoint)
+                .execute(&self.dbpool)
+                .await?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let sql = format!(
+            "select {} from push_subscriptions where endpoint = $1",
+            subscription_columns(backend)
+        );
+        let row = sqlx::query(&backend.sql(&sql))
+            .bind(endpoint)
+            .fetch_one(&self.dbpool)
+            .await?;
+        subscription_from_row(&row)
+    }
+
+    async fn unsubscribe(&self, id: i64) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let result = sqlx::query(&backend.sql("delete from push_subscriptions where id = $1"))
+            .bind(id)
+            .execute(&self.dbpool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<Subscription>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from push_subscriptions order by id",
+            subscription_columns(backend)
+        );
+
+        sqlx::query(&sql)
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(subscription_from_row)
+            .collect()
+    }
+
+    async fn claim_reminder(&self, day: &str, now: i64) -> Result<bool, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let result =
+            sqlx::query(&backend.sql("insert into push_reminders (day, sent_at) values ($1, $2)"))
+                .bind(day)
+                .bind(now)
+                .execute(&self.dbpool)
+                .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(Error::Database(e)) if e.is_unique_violation() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Keeps subscriptions in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryPushStore {
+    state: Mutex<InMemoryState>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    subscriptions: BTreeMap<i64, Subscription>,
+    last_id: i64,
+    last_reminder: String,
+}
+
+#[async_trait]
+impl PushStore for InMemoryPushStore {
+    async fn subscribe(
+        &self,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+        now: i64,
+    ) -> Result<Subscription, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(subscription) = state
+            .subscriptions
+            .values_mut()
+            .find(|subscription| subscription.endpoint == endpoint)
+        {
+            subscription.p256dh = p256dh.to_string();
+            subscription.auth = auth.to_string();
+            return Ok(subscription.clone());
+        }
+
+        state.last_id += 1;
+        let subscription = Subscription {
+            id: state.last_id,
+            endpoint: endpoint.to_string(),
+            p256dh: p256dh.to_string(),
+            auth: auth.to_string(),
+            created_at: now,
+        };
+        state
+            .subscriptions
+            .insert(subscription.id, subscription.clone());
+
+        Ok(subscription)
+    }
+
+    async fn unsubscribe(&self, id: i64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state
+            .subscriptions
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(Error::RowNotFound)
+    }
+
+    async fn list(&self) -> Result<Vec<Subscription>, Error> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .subscriptions
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn claim_reminder(&self, day: &str, _now: i64) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.last_reminder == day {
+            return Ok(false);
+        }
+        state.last_reminder = day.to_string();
+
+        Ok(true)
+    }
+}
+
+// Pushes to subscribed browsers through their push service, encrypted for
+// each browser (RFC 8291) and signed with the VAPID key (RFC 8292).
+#[derive(Clone)]
+pub struct WebPush {
+    pub store: DynPushStore,
+    config: PushConfig,
+    public_url: Option<String>,
+    preferences: Preferences,
+    signing_key: SigningKey,
+    // Base64url, uncompressed: what browsers subscribe with as the
+    // `applicationServerKey`.
+    public_key: String,
+    client: reqwest::Client,
+}
+
+impl WebPush {
+    pub fn new(
+        store: DynPushStore,
+        config: PushConfig,
+        public_url: Option<String>,
+        preferences: Preferences,
+    ) -> Result<Self, String> {
+        let secret = decode(&config.vapid_private_key)
+            .ok()
+            .and_then(|key| SecretKey::from_slice(&key).ok())
+            .ok_or("invalid VAPID_PRIVATE_KEY, expected a base64url P-256 private key")?;
+        let public_key = BASE64_URL_SAFE_NO_PAD.encode(secret.public_key().to_encoded_point(false));
+
+        Ok(WebPush {
+            store,
+            client: reqwest::Client::builder()
+                .timeout(config.timeout)
+                .build()
+                .map_err(|e| e.to_string())?,
+            config,
+            public_url,
+            preferences,
+            signing_key: SigningKey::from(&secret),
+            public_key,
+        })
+    }
+
+    // Pushes the reminder daily at PUSH_REMINDER_AT, if set, unless its
+    // preference is disabled or the schema is outdated, as the subscription
+    // tables may be missing then. Pushes are logged under the `push` target.
+    pub fn spawn(&self, todos: DynTodoRepository, lifecycle: Lifecycle) {
+        if let Some(reminder_at) = self.config.reminder_at {
+            tokio::spawn(self.clone().remind(reminder_at, todos, lifecycle));
+        }
+    }
+
+    async fn remind(self, at: chrono::NaiveTime, todos: DynTodoRepository, lifecycle: Lifecycle) {
+        loop {
+            tokio::time::sleep(telegram::until(at)).await;
+            if lifecycle.is_schema_outdated() {
+                continue;
+            }
+
+            let open: Vec<Todo> = match todos.list().await {
+                Ok(todos) => todos.into_iter().filter(|todo| !todo.completed).collect(),
+                Err(e) => {
+                    tracing::warn!(target: "push", error = %e, "couldn't list todos for the reminder");
+                    continue;
+                }
+            };
+            if open.is_empty() || !self.preferences.allows("push", "reminder").await {
+                continue;
+            }
+
+            let now = chrono::Utc::now();
+            let day = now.date_naive().to_string();
+            match self.store.claim_reminder(&day, now.timestamp()).await {
+                Ok(true) => {}
+                // Another instance pushes it.
+                Ok(false) => continue,
+                Err(e) => {
+                    tracing::warn!(target: "push", error = %e, "couldn't claim the reminder");
+                    continue;
+                }
+            }
+
+            let subscriptions = match self.store.list().await {
+                Ok(subscriptions) => subscriptions,
+                Err(e) => {
+                    tracing::warn!(target: "push", error = %e, "couldn't list push subscriptions");
+                    continue;
+                }
+            };
+            let payload = self.reminder(&open).to_string();
+
+            let mut pushes = tokio::task::JoinSet::new();
+            for subscription in subscriptions {
+                let push = self.clone();
+                let payload = payload.clone();
+                pushes.spawn(async move { push.push(&subscription, &payload).await });
+            }
+            while pushes.join_next().await.is_some() {}
+        }
+    }
+
+    // For the service worker to show: a title and body, and where to go when
+    // the notification is clicked, under PUBLIC_URL if set.
+    fn reminder(&self, open: &[Todo]) -> Value {
+        let title = match open.len() {
+            1 => "1 open todo".to_string(),
+            n => format!("{} open todos", n),
+        };
+        let mut lines: Vec<_> = open
+            .iter()
+            .take(MAX_LISTED)
+            .map(|todo| format!("#{} {}", todo.id, todo.body))
+            .collect();
+        if open.len() > MAX_LISTED {
+            lines.push(format!("and {} more", open.len() - MAX_LISTED));
+        }
+
+        let mut reminder = json!({"type": "reminder", "title": title, "body": lines.join("\n")});
+        if let Some(public_url) = &self.public_url {
+            reminder["url"] = json!(format!("{}/v1/todos", public_url));
+        }
+
+        reminder
+    }
+
+    // Pushes once; the push service holds it for up to PUSH_TTL_SECONDS for
+    // a browser that is offline. A subscription the push service no longer
+    // knows is removed.
+    async fn push(&self, subscription: &Subscription, payload: &str) {
+        let result = match (
+            encrypt(subscription, payload.as_bytes()),
+            self.vapid(&subscription.endpoint),
+        ) {
+            (Ok(body), Ok(authorization)) => self
+                .client
+                .post(&subscription.endpoint)
+                .header(header::AUTHORIZATION, authorization)
+                .header(header::CONTENT_ENCODING, "aes128gcm")
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header("ttl", self.config.ttl.as_secs())
+                .body(body)
+                .send()
+                .await
+                .map_err(|e| e.without_url().to_string()),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+
+        match result {
+            Ok(response) if response.status().is_success() => {
+                tracing::debug!(target: "push", subscription_id = subscription.id, "pushed");
+            }
+            Ok(response)
+                if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) =>
+            {
+                tracing::info!(target: "push", subscription_id = subscription.id, "push subscription expired, removing it");
+                if let Err(e) = self.store.unsubscribe(subscription.id).await {
+                    tracing::warn!(target: "push", error = %e, subscription_id = subscription.id, "couldn't remove push subscription");
+                }
+            }
+            Ok(response) => {
+                tracing::warn!(target: "push", subscription_id = subscription.id, status = response.status().as_u16(), "push refused");
+            }
+            Err(e) => {
+                tracing::warn!(target: "push", subscription_id = subscription.id, error = %e, "couldn't push");
+            }
+        }
+    }
+
+    // The Authorization header: a JWT for the push service's origin, signed
+    // with the VAPID key, along with its public key.
+    fn vapid(&self, endpoint: &str) -> Result<String, String> {
+        let audience = reqwest::Url::parse(endpoint)
+            .map_err(|e| format!("invalid endpoint: {}", e))?
+            .origin()
+            .ascii_serialization();
+
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = BASE64_URL_SAFE_NO_PAD.encode(
+            json!({
+                "aud": audience,
+                "exp": chrono::Utc::now().timestamp() + VAPID_VALIDITY,
+                "sub": self.config.vapid_subject,
+            })
+            .to_string(),
+        );
+        let signing_input = format!("{}.{}", header, claims);
+        let signature: Signature = self.signing_key.sign(signing_input.as_bytes());
+
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key
+        ))
+    }
+}
+
+// Base64url with or without padding, as browsers and key generators differ.
+fn decode(value: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    BASE64_URL_SAFE_NO_PAD.decode(value.trim_end_matches('='))
+}
+
+// Encrypts the payload for the subscribed browser as a single aes128gcm
+// record (RFC 8188), keyed from an ephemeral ECDH exchange with its key and
+// its auth secret.
+fn encrypt(subscription: &Subscription, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid keys for subscription {}", subscription.id);
+    let browser_public = decode(&subscription.p256dh).map_err(|_| invalid())?;
+    let browser_key = PublicKey::from_sec1_bytes(&browser_public).map_err(|_| invalid())?;
+    let auth = decode(&subscription.auth).map_err(|_| invalid())?;
+
+    let secret = EphemeralSecret::random(&mut rand::rngs::OsRng);
+    let public = secret.public_key().to_encoded_point(false);
+    let shared = secret.diffie_hellman(&browser_key);
+
+    let mut info = b"WebPush: info\0".to_vec();
+    info.extend_from_slice(&browser_public);
+    info.extend_from_slice(public.as_bytes());
+    let mut ikm = [0; 32];
+    Hkdf::<Sha256>::new(Some(&auth), shared.raw_secret_bytes())
+        .expand(&info, &mut ikm)
+        .map_err(|e| e.to_string())?;
+
+    let salt: [u8; 16] = rand::random();
+    let prk = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+    let mut key = [0; 16];
+    let mut nonce = [0; 12];
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut key)
+        .and_then(|_| prk.expand(b"Content-Encoding: nonce\0", &mut nonce))
+        .map_err(|e| e.to_string())?;
+
+    // The delimiter of the last record, with no padding.
+    let mut plaintext = payload.to_vec();
+    plaintext.push(2);
+    let ciphertext = Aes128Gcm::new_from_slice(&key)
+        .map_err(|e| e.to_string())?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|e| e.to_string())?;
+
+    let mut body = salt.to_vec();
+    body.extend_from_slice(&4096u32.to_be_bytes());
+    body.push(public.as_bytes().len() as u8);
+    body.extend_from_slice(public.as_bytes());
+    body.extend_from_slice(&ciphertext);
+
+    Ok(body)
+}
+
+#[derive(Serialize)]
+pub struct PublicKeyData {
+    public_key: String,
+}
+
+// The key browsers subscribe with, as `applicationServerKey`.
+pub async fn public_key(State(push): State<WebPush>) -> Success<PublicKeyData> {
+    Success::new(PublicKeyData {
+        public_key: push.public_key,
+    })
+}
+
+// A browser's PushSubscription, as serialized by `toJSON()`.
+#[derive(Deserialize)]
+pub struct Subscribe {
+    endpoint: String,
+    keys: SubscriptionKeys,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionData {
+    subscription: Subscription,
+}
+
+// Subscribes a browser, or updates its keys when it already is.
+pub async fn subscribe(
+    State(push): State<WebPush>,
+    PlainJsonBody(request): PlainJsonBody<Subscribe>,
+) -> Result<Success<SubscriptionData>, Problem> {
+    match reqwest::Url::parse(&request.endpoint) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => {
+            return Err(fail(
+                "endpoint must be an absolute http or https URL".to_string(),
+            ))
+        }
+    }
+    if decode(&request.keys.p256dh)
+        .ok()
+        .and_then(|key| PublicKey::from_sec1_bytes(&key).ok())
+        .is_none()
+    {
+        return Err(fail(
+            "keys.p256dh must be a base64url P-256 public key".to_string(),
+        ));
+    }
+    if decode(&request.keys.auth).map_or(true, |auth| auth.len() != 16) {
+        return Err(fail(
+            "keys.auth must be a base64url 16 byte secret".to_string(),
+        ));
+    }
+
+    let subscription = push
+        .store
+        .subscribe(
+            &request.endpoint,
+            &request.keys.p256dh,
+            &request.keys.auth,
+            chrono::Utc::now().timestamp(),
+        )
+        .await?;
+
+    Ok(Success::new(SubscriptionData { subscription }))
+}
+
+pub async fn unsubscribe(
+    State(push): State<WebPush>,
+    PathParams(id): PathParams<i64>,
+) -> Result<StatusCode, Problem> {
+    match push.store.unsubscribe(id).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(Error::RowNotFound) => Err(Problem::new(StatusCode::NOT_FOUND)
+            .detail(format!("push subscription with ID: {} not found", id))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
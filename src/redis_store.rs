@@ -0,0 +1,56 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use redis::{aio::ConnectionManager, RedisResult};
+
+use crate::config::RedisConfig;
+
+// A Redis connection shared by the replicas of the service, for state they
+// must agree on. Keys are namespaced with REDIS_KEY_PREFIX so several
+// deployments can share a server. The connection reconnects by itself, and
+// commands time out instead of holding up requests while Redis is away.
+#[derive(Clone)]
+pub struct RedisStore {
+    conn: ConnectionManager,
+    prefix: Arc<str>,
+}
+
+impl RedisStore {
+    pub async fn connect(config: &RedisConfig) -> RedisResult<Self> {
+        let client = redis::Client::open(config.url.as_str())?;
+        let conn = ConnectionManager::new_with_backoff_and_timeouts(
+            client,
+            2,
+            100,
+            3,
+            config.timeout,
+            config.timeout,
+        )
+        .await?;
+
+        Ok(RedisStore {
+            conn,
+            prefix: config.key_prefix.as_str().into(),
+        })
+    }
+
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    // ConnectionManager is a cheap handle on a multiplexed connection.
+    pub fn connection(&self) -> ConnectionManager {
+        self.conn.clone()
+    }
+
+    pub async fn ping(&self) -> RedisResult<Duration> {
+        let start = Instant::now();
+        redis::cmd("PING")
+            .query_async::<_, ()>(&mut self.connection())
+            .await?;
+
+        Ok(start.elapsed())
+    }
+}
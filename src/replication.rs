@@ -0,0 +1,688 @@
+// Continuous replication of the SQLite database to object storage, in the
+// style of Litestream.
+//
+// At startup the database is checkpointed and copied as the snapshot of a new
+// generation. From then on, committed WAL frames are shipped as numbered
+// segments every sync interval. The replicator runs all checkpoints itself
+// (automatic checkpoints are disabled), holding the single write connection
+// while it ships the tail of the WAL and truncates it, so no frame is ever
+// checkpointed away before it has been shipped.
+//
+// Layout under the replica URL:
+//
+//   generations/<generation>/snapshot.sqlite
+//   generations/<generation>/wal/<sequence>-<unix millis>.wal
+//
+// Generations are named after their start time in unix millis, zero padded,
+// so listing order is chronological. Restoring downloads a snapshot and
+// replays segments onto it, optionally stopping at a point in time.
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+use crate::{
+    config::{Config, ReplicaConfig},
+    db::{Backend, DbPools},
+};
+
+const WAL_HEADER_SIZE: u64 = 32;
+const FRAME_HEADER_SIZE: u64 = 24;
+
+// The WAL is shipped and truncated once it grows past this.
+const CHECKPOINT_BYTES: u64 = 4 * 1024 * 1024;
+
+// Where replicas are stored.
+enum Store {
+    S3(Box<S3Store>),
+    Directory(PathBuf),
+}
+
+struct S3Store {
+    client: reqwest::Client,
+    bucket: rusty_s3::Bucket,
+    credentials: Option<rusty_s3::Credentials>,
+    prefix: String,
+}
+
+impl Store {
+    fn open(config: &ReplicaConfig) -> Result<Store, String> {
+        if let Some(path) = config.url.strip_prefix("file://") {
+            return Ok(Store::Directory(PathBuf::from(path)));
+        }
+
+        let location = config
+            .url
+            .strip_prefix("s3://")
+            .ok_or_else(|| format!("unsupported REPLICA_URL: {}", config.url))?;
+
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+
+        // Custom endpoints (MinIO, R2, ...) generally need path-style URLs.
+        let (endpoint, url_style) = match &config.endpoint {
+            Some(endpoint) => (endpoint.clone(), rusty_s3::UrlStyle::Path),
+            None => (
+                format!("https://s3.{}.amazonaws.com", config.region),
+                rusty_s3::UrlStyle::VirtualHost,
+            ),
+        };
+
+        let endpoint = reqwest::Url::parse(&endpoint)
+            .map_err(|e| format!("invalid REPLICA_ENDPOINT: {}", e))?;
+
+        let bucket = rusty_s3::Bucket::new(
+            endpoint,
+            url_style,
+            bucket.to_string(),
+            config.region.clone(),
+        )
+        .map_err(|e| format!("invalid replica bucket: {}", e))?;
+
+        let credentials = match (&config.access_key_id, &config.secret_access_key) {
+            (Some(key), Some(secret)) => Some(rusty_s3::Credentials::new(key, secret)),
+            _ => None,
+        };
+
+        Ok(Store::S3(Box::new(S3Store {
+            client: reqwest::Client::new(),
+            bucket,
+            credentials,
+            prefix: prefix.trim_end_matches('/').to_string(),
+        })))
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), String> {
+        use rusty_s3::S3Action;
+
+        match self {
+            Store::S3(s3) => {
+                let S3Store {
+                    client,
+                    bucket,
+                    credentials,
+                    prefix,
+                } = s3.as_ref();
+
+                let key = object_key(prefix, key);
+                let url = bucket
+                    .put_object(credentials.as_ref(), &key)
+                    .sign(Duration::from_secs(60));
+
+                client
+                    .put(url)
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            Store::Directory(root) => {
+                let path = root.join(key);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                tokio::fs::write(path, body)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        use rusty_s3::S3Action;
+
+        match self {
+            Store::S3(s3) => {
+                let S3Store {
+                    client,
+                    bucket,
+                    credentials,
+                    prefix,
+                } = s3.as_ref();
+
+                let key = object_key(prefix, key);
+                let url = bucket
+                    .get_object(credentials.as_ref(), &key)
+                    .sign(Duration::from_secs(60));
+
+                let response = client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| e.to_string())?;
+
+                response
+                    .bytes()
+                    .await
+                    .map(|bytes| bytes.to_vec())
+                    .map_err(|e| e.to_string())
+            }
+            Store::Directory(root) => tokio::fs::read(root.join(key))
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    // Keys under `dir`, relative to it, sorted.
+    async fn list(&self, dir: &str) -> Result<Vec<String>, String> {
+        use rusty_s3::{actions::ListObjectsV2, S3Action};
+
+        let mut keys = Vec::new();
+
+        match self {
+            Store::S3(s3) => {
+                let S3Store {
+                    client,
+                    bucket,
+                    credentials,
+                    prefix,
+                } = s3.as_ref();
+
+                let full_prefix = object_key(prefix, &format!("{}/", dir));
+                let mut continuation_token: Option<String> = None;
+
+                loop {
+                    let mut action = bucket.list_objects_v2(credentials.as_ref());
+                    action.with_prefix(full_prefix.as_str());
+                    if let Some(token) = &continuation_token {
+                        action.with_continuation_token(token.clone());
+                    }
+                    let url = action.sign(Duration::from_secs(60));
+
+                    let body = client
+                        .get(url)
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status())
+                        .map_err(|e| e.to_string())?
+                        .text()
+                        .await
+                        .map_err(|e| e.to_string())?;
+
+                    let listing =
+                        ListObjectsV2::parse_response(&body).map_err(|e| e.to_string())?;
+
+                    keys.extend(listing.contents.into_iter().filter_map(|object| {
+                        object
+                            .key
+                            .strip_prefix(full_prefix.as_str())
+                            .map(ToOwned::to_owned)
+                    }));
+
+                    match listing.next_continuation_token {
+                        Some(token) => continuation_token = Some(token),
+                        None => break,
+                    }
+                }
+            }
+            Store::Directory(root) => {
+                let root = root.join(dir);
+                let mut pending = vec![PathBuf::new()];
+
+                while let Some(relative) = pending.pop() {
+                    let mut entries = match tokio::fs::read_dir(root.join(&relative)).await {
+                        Ok(entries) => entries,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e.to_string()),
+                    };
+
+                    while let Some(entry) = entries.next_entry().await.map_err(|e| e.to_string())? {
+                        let path = relative.join(entry.file_name());
+                        if entry.file_type().await.map_err(|e| e.to_string())?.is_dir() {
+                            pending.push(path);
+                        } else {
+                            keys.push(path.to_string_lossy().into_owned());
+                        }
+                    }
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+fn object_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}/{}", prefix, key)
+    }
+}
+
+fn unix_millis(time: DateTime<Utc>) -> String {
+    format!("{:013}", time.timestamp_millis())
+}
+
+// How far into the current WAL has been shipped. The salt identifies the WAL
+// incarnation; it changes whenever SQLite starts the WAL over.
+struct WalPosition {
+    salt: [u8; 8],
+    offset: u64,
+    checksum: (u32, u32),
+}
+
+struct Replicator {
+    store: Store,
+    pools: DbPools,
+    db_path: PathBuf,
+    wal_path: PathBuf,
+    generation: String,
+    sequence: u64,
+    // None right after a truncating checkpoint, until the next WAL header.
+    position: Option<WalPosition>,
+}
+
+impl Replicator {
+    // Starts a new generation from a copy of the fully checkpointed database.
+    async fn snapshot(&mut self) -> Result<(), String> {
+        let started_at = Utc::now();
+
+        let snapshot = {
+            let mut conn = self
+                .pools
+                .write
+                .acquire()
+                .await
+                .map_err(|e| e.to_string())?;
+            truncate_wal(&mut conn).await?;
+
+            // Holding the only write connection keeps the file unchanged.
+            tokio::fs::read(&self.db_path)
+                .await
+                .map_err(|e| e.to_string())?
+        };
+
+        self.generation = unix_millis(started_at);
+        self.sequence = 0;
+        self.position = None;
+
+        self.store
+            .put(
+                &format!("generations/{}/snapshot.sqlite", self.generation),
+                snapshot,
+            )
+            .await?;
+
+        tracing::info!(generation = %self.generation, "replication snapshot uploaded");
+
+        Ok(())
+    }
+
+    // Ships the committed frames appended since the last sync. Returns false
+    // when SQLite restarted the WAL behind the replicator's back: frames may
+    // have been lost, so the generation must start over from a new snapshot.
+    async fn ship(&mut self) -> Result<bool, String> {
+        let mut file = match tokio::fs::File::open(&self.wal_path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut header = [0; WAL_HEADER_SIZE as usize];
+        if file.read_exact(&mut header).await.is_err() {
+            // Empty or being created.
+            return Ok(true);
+        }
+
+        let big_endian = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            0x377f0682 => false,
+            0x377f0683 => true,
+            _ => return Err("invalid WAL header".to_string()),
+        };
+        let page_size = u32::from_be_bytes(header[8..12].try_into().unwrap()) as u64;
+        let salt: [u8; 8] = header[16..24].try_into().unwrap();
+
+        let (offset, mut checksum) = match &self.position {
+            Some(position) if position.salt == salt => (position.offset, position.checksum),
+            Some(_) => return Ok(false),
+            None => (
+                WAL_HEADER_SIZE,
+                (
+                    u32::from_be_bytes(header[24..28].try_into().unwrap()),
+                    u32::from_be_bytes(header[28..32].try_into().unwrap()),
+                ),
+            ),
+        };
+
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut frames = Vec::new();
+        file.read_to_end(&mut frames)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Only whole, valid frames up to the last commit are shipped; the
+        // rest is a transaction still being written.
+        let frame_size = (FRAME_HEADER_SIZE + page_size) as usize;
+        let mut committed = 0;
+        let mut committed_checksum = checksum;
+
+        for (index, frame) in frames.chunks_exact(frame_size).enumerate() {
+            if frame[8..16] != salt {
+                break;
+            }
+
+            checksum = wal_checksum(checksum, &frame[0..8], big_endian);
+            checksum = wal_checksum(checksum, &frame[FRAME_HEADER_SIZE as usize..], big_endian);
+
+            let stored = (
+                u32::from_be_bytes(frame[16..20].try_into().unwrap()),
+                u32::from_be_bytes(frame[20..24].try_into().unwrap()),
+            );
+            if checksum != stored {
+                break;
+            }
+
+            if frame[4..8] != [0; 4] {
+                committed = (index + 1) * frame_size;
+                committed_checksum = checksum;
+            }
+        }
+
+        if committed > 0 {
+            frames.truncate(committed);
+
+            let key = format!(
+                "generations/{}/wal/{:010}-{}.wal",
+                self.generation,
+                self.sequence,
+                unix_millis(Utc::now())
+            );
+            self.store.put(&key, frames).await?;
+            self.sequence += 1;
+        }
+
+        self.position = Some(WalPosition {
+            salt,
+            offset: offset + committed as u64,
+            checksum: committed_checksum,
+        });
+
+        Ok(true)
+    }
+
+    // Ships the rest of the WAL and truncates it, holding the write
+    // connection so nothing is committed in between.
+    async fn checkpoint(&mut self) -> Result<(), String> {
+        let pools = self.pools.clone();
+        let mut conn = pools.write.acquire().await.map_err(|e| e.to_string())?;
+
+        if !self.ship().await? {
+            drop(conn);
+            return self.snapshot().await;
+        }
+
+        truncate_wal(&mut conn).await?;
+        self.position = None;
+
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<(), String> {
+        if !self.ship().await? {
+            return self.snapshot().await;
+        }
+
+        if self
+            .position
+            .as_ref()
+            .is_some_and(|position| position.offset > CHECKPOINT_BYTES)
+        {
+            self.checkpoint().await?;
+        }
+
+        Ok(())
+    }
+}
+
+// Copies every WAL frame into the database and truncates the WAL.
+async fn truncate_wal(conn: &mut sqlx::AnyConnection) -> Result<(), String> {
+    use sqlx::Row;
+
+    let row = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let busy: i64 = row.try_get(0).map_err(|e| e.to_string())?;
+    if busy != 0 {
+        return Err("checkpoint blocked by readers".to_string());
+    }
+
+    Ok(())
+}
+
+// SQLite's WAL checksum, chained over a frame header and page.
+fn wal_checksum(mut checksum: (u32, u32), data: &[u8], big_endian: bool) -> (u32, u32) {
+    for words in data.chunks_exact(8) {
+        let (x0, x1) = if big_endian {
+            (
+                u32::from_be_bytes(words[0..4].try_into().unwrap()),
+                u32::from_be_bytes(words[4..8].try_into().unwrap()),
+            )
+        } else {
+            (
+                u32::from_le_bytes(words[0..4].try_into().unwrap()),
+                u32::from_le_bytes(words[4..8].try_into().unwrap()),
+            )
+        };
+
+        checksum.0 = checksum.0.wrapping_add(x0).wrapping_add(checksum.1);
+        checksum.1 = checksum.1.wrapping_add(x1).wrapping_add(checksum.0);
+    }
+
+    checksum
+}
+
+fn database_path(database_url: &str) -> Result<PathBuf, String> {
+    use sqlx::sqlite::SqliteConnectOptions;
+
+    if Backend::from_url(database_url)? != Backend::Sqlite
+        || database_url.contains(":memory:")
+        || database_url.contains("mode=memory")
+    {
+        return Err("replication requires a SQLite database file".to_string());
+    }
+
+    SqliteConnectOptions::from_str(database_url)
+        .map(|options| options.get_filename().into_owned())
+        .map_err(|e| e.to_string())
+}
+
+// A running replicator.
+pub struct Replication {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Replication {
+    // Snapshots the database, then syncs in the background.
+    pub async fn start(
+        config: &ReplicaConfig,
+        database_url: &str,
+        pools: DbPools,
+    ) -> Result<Replication, String> {
+        let db_path = database_path(database_url)?;
+
+        let mut wal_path = db_path.clone().into_os_string();
+        wal_path.push("-wal");
+
+        let mut replicator = Replicator {
+            store: Store::open(config)?,
+            pools,
+            db_path,
+            wal_path: wal_path.into(),
+            generation: String::new(),
+            sequence: 0,
+            position: None,
+        };
+
+        replicator.snapshot().await?;
+
+        let (stop, mut stopped) = oneshot::channel();
+        let sync_interval = config.sync_interval;
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(sync_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = &mut stopped => break,
+                }
+
+                if let Err(e) = replicator.sync().await {
+                    tracing::warn!(error = %e, "replication sync failed");
+                }
+            }
+
+            if let Err(e) = replicator.checkpoint().await {
+                tracing::warn!(error = %e, "final replication sync failed");
+            }
+        });
+
+        Ok(Replication { stop, task })
+    }
+
+    // Ships whatever is left. Call after the server stopped writing.
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+// `api-service restore <path> [timestamp]`: rebuilds the database at `path`
+// from the replica, as of `timestamp` (RFC 3339) when given, else as of the
+// last shipped segment. Returns false on failure, after printing the error.
+pub async fn restore(path: &str, timestamp: Option<&str>) -> bool {
+    let result = async {
+        let config = Config::try_from_env().map_err(|errors| errors.join("; "))?;
+        let replica = config
+            .replica
+            .as_ref()
+            .ok_or("REPLICA_URL is not set".to_string())?;
+
+        let target = timestamp
+            .map(|timestamp| {
+                DateTime::parse_from_rfc3339(timestamp)
+                    .map(|time| unix_millis(time.with_timezone(&Utc)))
+                    .map_err(|e| format!("invalid timestamp: {}", e))
+            })
+            .transpose()?;
+
+        restore_into(&Store::open(replica)?, Path::new(path), target.as_deref()).await
+    }
+    .await;
+
+    match result {
+        Ok(applied) => {
+            println!("restored to {} ({} WAL segments applied)", path, applied);
+            true
+        }
+        Err(e) => {
+            eprintln!("restore failed: {}", e);
+            false
+        }
+    }
+}
+
+async fn restore_into(store: &Store, path: &Path, target: Option<&str>) -> Result<usize, String> {
+    if tokio::fs::try_exists(path).await.unwrap_or(true) {
+        return Err(format!("{} already exists", path.display()));
+    }
+
+    // The latest generation started before the target time.
+    let generation = store
+        .list("generations")
+        .await?
+        .iter()
+        .filter_map(|key| key.strip_suffix("/snapshot.sqlite"))
+        .filter(|generation| target.is_none_or(|target| *generation <= target))
+        .max()
+        .map(ToOwned::to_owned)
+        .ok_or("no snapshot in the replica before that time".to_string())?;
+
+    let snapshot = store
+        .get(&format!("generations/{}/snapshot.sqlite", generation))
+        .await?;
+
+    let page_size = match u16::from_be_bytes(
+        snapshot
+            .get(16..18)
+            .ok_or("invalid snapshot")?
+            .try_into()
+            .unwrap(),
+    ) {
+        1 => 65536,
+        size => size as u64,
+    };
+
+    let mut segments = store
+        .list(&format!("generations/{}/wal", generation))
+        .await?;
+    segments.retain(|segment| {
+        let shipped_at = segment
+            .trim_end_matches(".wal")
+            .split_once('-')
+            .map(|(_, millis)| millis);
+        target.is_none_or(|target| shipped_at.is_some_and(|at| at <= target))
+    });
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    file.write_all(&snapshot).await.map_err(|e| e.to_string())?;
+
+    let frame_size = (FRAME_HEADER_SIZE + page_size) as usize;
+
+    for segment in &segments {
+        let frames = store
+            .get(&format!("generations/{}/wal/{}", generation, segment))
+            .await?;
+
+        for frame in frames.chunks_exact(frame_size) {
+            let page_number = u32::from_be_bytes(frame[0..4].try_into().unwrap()) as u64;
+            let commit_size = u32::from_be_bytes(frame[4..8].try_into().unwrap()) as u64;
+
+            file.seek(SeekFrom::Start((page_number - 1) * page_size))
+                .await
+                .map_err(|e| e.to_string())?;
+            file.write_all(&frame[FRAME_HEADER_SIZE as usize..])
+                .await
+                .map_err(|e| e.to_string())?;
+
+            // A commit frame records the database size after the commit.
+            if commit_size != 0 {
+                file.set_len(commit_size * page_size)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    file.sync_all().await.map_err(|e| e.to_string())?;
+
+    Ok(segments.len())
+}
@@ -0,0 +1,951 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::Error;
+
+use crate::{
+    backoff,
+    cache::DynTodoCache,
+    config::{ConflictPolicy, NextTodoWeights, ReadCacheConfig, RetryPolicy},
+    db,
+    events::{EventBus, TodoEvent},
+    todo::{
+        self, Change, ChangeKind, CreateTodo, Interval, Metric, Suggestion, Todo, TodoStats,
+        UpdateTodo,
+    },
+};
+
+// Storage for todos. Handlers reach it through the app state, so the backend
+// can be swapped without touching them. A missing todo is reported as
+// `sqlx::Error::RowNotFound` by every implementation.
+//
+// `update`, and `delete` when given a version, only apply when the todo is
+// still at that version, and report `RowNotFound` otherwise, so concurrent
+// edits can't overwrite each other.
+#[async_trait]
+pub trait TodoRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<Todo>, Error>;
+
+    // Up to `limit` todos in id order after skipping `offset`, read with a
+    // query where the backend can, and how long ago they were read from the
+    // database when they come from a cache.
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        let todos = self.list().await?;
+        Ok((paged(todos, offset, limit), None))
+    }
+
+    // How many todos there are, without reading them where the backend can.
+    async fn count(&self) -> Result<i64, Error> {
+        Ok(self.list().await?.len() as i64)
+    }
+
+    // Aggregates over every todo, with queries where the backend can.
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        let todos = self.list().await?;
+        let changes = self.changes(0, i64::MAX).await?;
+        Ok(TodoStats::of(&todos, &changes))
+    }
+
+    // The non-empty buckets of the metric between the days, both included, in
+    // order, counted with queries where the backend can.
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        let todos = self.list().await?;
+        let changes = self.changes(0, i64::MAX).await?;
+        Ok(todo::time_series(
+            &todos, &changes, metric, interval, from, to,
+        ))
+    }
+
+    // Up to `limit` distinct bodies starting with the prefix, ignoring case,
+    // the most frequent first, then the most recently used.
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        let todos = self.list().await?;
+        Ok(todo::suggestions(&todos, prefix, limit as usize))
+    }
+
+    // The open todo scoring highest by the weights per day since it was
+    // created and since it was last updated, the oldest of equals, with its
+    // score; scored by a query where the backend can.
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        let now = chrono::Utc::now().naive_utc();
+        let days = |since: NaiveDateTime| (now - since).num_milliseconds() as f64 / 86_400_000.0;
+
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|todo| !todo.completed)
+            .map(|todo| {
+                let score =
+                    weights.age * days(todo.created_at) + weights.idle * days(todo.updated_at);
+                (todo, score)
+            })
+            // The list is in id order, so the first of equals is the oldest.
+            .reduce(|best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            }))
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error>;
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error>;
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error>;
+
+    // The number of todos deleted: 0 when it was already gone, which is only
+    // an error with `version`.
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error>;
+
+    // Up to `limit` changes numbered after `since`, in order. Every write is
+    // recorded along with the data it writes, numbered from 1.
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error>;
+
+    // The todo as it was at `version`, from the changes.
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error>;
+
+    // The CRDT document of the todo's body, once it was edited through one.
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error>;
+
+    // Like `update`, storing `document` as the body's document along with it.
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error>;
+
+    // Like `update` and `delete`, also telling how a conflict with a
+    // concurrent write was resolved, when there was one.
+    async fn update_resolving(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+    ) -> Result<(Todo, Option<Resolution>), Error> {
+        Ok((self.update(id, updated_todo, version).await?, None))
+    }
+
+    async fn delete_resolving(
+        &self,
+        id: i64,
+        version: Option<i64>,
+    ) -> Result<(u64, Option<Resolution>), Error> {
+        Ok((self.delete(id, version).await?, None))
+    }
+}
+
+// How a write conflicting with a concurrent one was applied anyway.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    // Over the other write.
+    Overwritten,
+    // Along with the fields the other write changed.
+    Merged,
+}
+
+impl Resolution {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Resolution::Overwritten => "last-write-wins",
+            Resolution::Merged => "merge",
+        }
+    }
+}
+
+pub type DynTodoRepository = Arc<dyn TodoRepository>;
+
+// The page of todos, already in id order, that `page` asks for.
+fn paged(todos: Vec<Todo>, offset: usize, limit: Option<usize>) -> Vec<Todo> {
+    todos
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+// Keeps todos in a process-local map, for demos and tests that need no
+// persistence. Ids are assigned sequentially from 1, like the SQL backends.
+#[derive(Default)]
+pub struct InMemoryTodoRepository {
+    state: Mutex<InMemoryState>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    todos: HashMap<i64, Todo>,
+    last_id: i64,
+    changes: Vec<Change>,
+    documents: HashMap<i64, Vec<u8>>,
+}
+
+impl InMemoryState {
+    fn update(&mut self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        let todo = self
+            .todos
+            .get_mut(&id)
+            .filter(|todo| todo.version == version)
+            .ok_or(Error::RowNotFound)?;
+        todo.body = updated_todo.body().to_string();
+        todo.completed = updated_todo.completed();
+        todo.updated_at = chrono::Utc::now().naive_utc();
+        todo.version += 1;
+
+        let todo = todo.clone();
+        self.record_change(ChangeKind::Updated, id, Some(todo.clone()));
+
+        Ok(todo)
+    }
+
+    fn record_change(&mut self, kind: ChangeKind, todo_id: i64, todo: Option<Todo>) {
+        self.changes.push(Change {
+            seq: self.changes.len() as i64 + 1,
+            kind,
+            todo_id,
+            todo,
+            changed_at: chrono::Utc::now().naive_utc(),
+        });
+    }
+}
+
+#[async_trait]
+impl TodoRepository for InMemoryTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        let state = self.state.lock().unwrap();
+
+        let mut todos: Vec<Todo> = state.todos.values().cloned().collect();
+        todos.sort_by_key(|todo| todo.id);
+
+        Ok(todos)
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        Ok(self.state.lock().unwrap().todos.len() as i64)
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        let state = self.state.lock().unwrap();
+
+        state.todos.get(&id).cloned().ok_or(Error::RowNotFound)
+    }
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.last_id += 1;
+
+        let now = chrono::Utc::now().naive_utc();
+        let todo = Todo {
+            id: state.last_id,
+            body: new_todo.body().to_string(),
+            completed: false,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+        };
+
+        state.todos.insert(todo.id, todo.clone());
+        state.record_change(ChangeKind::Created, todo.id, Some(todo.clone()));
+
+        Ok(todo)
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.update(id, updated_todo, version)
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(version) = version {
+            if state.todos.get(&id).map(|todo| todo.version) != Some(version) {
+                return Err(Error::RowNotFound);
+            }
+        }
+
+        if state.todos.remove(&id).is_none() {
+            return Ok(0);
+        }
+        state.documents.remove(&id);
+        state.record_change(ChangeKind::Deleted, id, None);
+
+        Ok(1)
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .changes
+            .iter()
+            .skip(since.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .changes
+            .iter()
+            .filter_map(|change| change.todo.as_ref())
+            .find(|todo| todo.id == id && todo.version == version)
+            .cloned()
+            .ok_or(Error::RowNotFound)
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state.documents.get(&id).cloned())
+    }
+
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let todo = state.update(id, updated_todo, version)?;
+        state.documents.insert(id, document);
+
+        Ok(todo)
+    }
+}
+
+// Wraps a repository, retrying operations that fail with a transient database
+// error such as SQLite reporting the database busy. An operation is retried as
+// a whole, so a transaction that was rolled back runs again from the start.
+// Retries are logged under the `db_retry` target.
+pub struct RetryingTodoRepository {
+    inner: DynTodoRepository,
+    policy: RetryPolicy,
+}
+
+impl RetryingTodoRepository {
+    pub fn new(inner: DynTodoRepository, policy: RetryPolicy) -> Self {
+        RetryingTodoRepository { inner, policy }
+    }
+
+    async fn retry<T, F, Fut>(&self, operation: &str, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match f().await {
+                Err(e) if db::is_transient(&e) && attempt < self.policy.max_attempts => {
+                    let delay =
+                        backoff::delay(self.policy.base_delay, self.policy.max_delay, attempt);
+                    tracing::warn!(
+                        target: "db_retry",
+                        operation,
+                        attempt,
+                        delay_ms = delay.as_secs_f64() * 1000.0,
+                        error = %e,
+                        "transient database error, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) if db::is_transient(&e) && attempt > 1 => {
+                    tracing::warn!(
+                        target: "db_retry",
+                        operation,
+                        attempt,
+                        error = %e,
+                        "transient database error, giving up"
+                    );
+                    return Err(e);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TodoRepository for RetryingTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        self.retry("list", || self.inner.list()).await
+    }
+
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        self.retry("page", || self.inner.page(offset, limit)).await
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        self.retry("count", || self.inner.count()).await
+    }
+
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        self.retry("stats", || self.inner.stats()).await
+    }
+
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        self.retry("time_series", || {
+            self.inner.time_series(metric, interval, from, to)
+        })
+        .await
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        self.retry("suggest", || self.inner.suggest(prefix, limit))
+            .await
+    }
+
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        self.retry("next", || self.inner.next(weights)).await
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        self.retry("read", || self.inner.read(id)).await
+    }
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        self.retry("create", || self.inner.create(new_todo.clone()))
+            .await
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        self.retry("update", || {
+            self.inner.update(id, updated_todo.clone(), version)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        self.retry("delete", || self.inner.delete(id, version))
+            .await
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        self.retry("changes", || self.inner.changes(since, limit))
+            .await
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        self.retry("revision", || self.inner.revision(id, version))
+            .await
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        self.retry("document", || self.inner.document(id)).await
+    }
+
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error> {
+        self.retry("update_with_document", || {
+            self.inner
+                .update_with_document(id, updated_todo.clone(), version, document.clone())
+        })
+        .await
+    }
+}
+
+// Wraps a repository, keeping todos and the todo list it returned in a cache
+// for up to the configured TTL, so repeated reads don't reach the database.
+// Writes pass through; the cache learns about them from the event bus.
+//
+// Past the TTL, the list is still served for the stale window while a single
+// background read refreshes it, so clients of the busiest query don't wait on
+// the database when it expires.
+pub struct CachingTodoRepository {
+    inner: DynTodoRepository,
+    cache: DynTodoCache,
+    config: ReadCacheConfig,
+    refreshing: Arc<AtomicBool>,
+}
+
+impl CachingTodoRepository {
+    pub fn new(inner: DynTodoRepository, cache: DynTodoCache, config: ReadCacheConfig) -> Self {
+        CachingTodoRepository {
+            inner,
+            cache,
+            config,
+            refreshing: Arc::default(),
+        }
+    }
+
+    async fn fetch_list(
+        inner: &DynTodoRepository,
+        cache: &DynTodoCache,
+    ) -> Result<Vec<Todo>, Error> {
+        let generation = cache.generation().await;
+        let todos = inner.list().await?;
+        if let Some(generation) = generation {
+            cache.put_list(generation, &todos).await;
+        }
+
+        Ok(todos)
+    }
+
+    fn refresh_list(&self) {
+        if self.refreshing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let (inner, cache, refreshing) = (
+            self.inner.clone(),
+            self.cache.clone(),
+            self.refreshing.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = Self::fetch_list(&inner, &cache).await {
+                tracing::warn!(target: "cache", error = %e, "refreshing the todo list failed");
+            }
+            refreshing.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+#[async_trait]
+impl TodoRepository for CachingTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        Ok(self.page(0, None).await?.0)
+    }
+
+    // The whole list is cached, so pages are cut from it; a miss reads the
+    // whole list to cache it.
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        if let Some(list) = self.cache.list().await {
+            let age = list.age();
+            if age < self.config.ttl + self.config.stale {
+                if age >= self.config.ttl {
+                    self.refresh_list();
+                }
+                return Ok((paged(list.todos, offset, limit), Some(age)));
+            }
+        }
+
+        let todos = Self::fetch_list(&self.inner, &self.cache).await?;
+        Ok((paged(todos, offset, limit), None))
+    }
+
+    // Not cached: counting is cheap next to listing.
+    async fn count(&self) -> Result<i64, Error> {
+        self.inner.count().await
+    }
+
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        self.inner.stats().await
+    }
+
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        self.inner.time_series(metric, interval, from, to).await
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        self.inner.suggest(prefix, limit).await
+    }
+
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        self.inner.next(weights).await
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        if let Some(todo) = self.cache.todo(id).await {
+            return Ok(todo);
+        }
+
+        let generation = self.cache.generation().await;
+        let todo = self.inner.read(id).await?;
+        if let Some(generation) = generation {
+            self.cache.put_todo(generation, &todo).await;
+        }
+
+        Ok(todo)
+    }
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        self.inner.create(new_todo).await
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        self.inner.update(id, updated_todo, version).await
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        self.inner.delete(id, version).await
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        self.inner.changes(since, limit).await
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        self.inner.revision(id, version).await
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.document(id).await
+    }
+
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error> {
+        self.inner
+            .update_with_document(id, updated_todo, version, document)
+            .await
+    }
+}
+
+// Wraps a repository, publishing an event on the bus for every successful
+// write, whichever API made it.
+pub struct PublishingTodoRepository {
+    inner: DynTodoRepository,
+    events: EventBus,
+}
+
+impl PublishingTodoRepository {
+    pub fn new(inner: DynTodoRepository, events: EventBus) -> Self {
+        PublishingTodoRepository { inner, events }
+    }
+}
+
+#[async_trait]
+impl TodoRepository for PublishingTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        self.inner.list().await
+    }
+
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        self.inner.page(offset, limit).await
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        self.inner.count().await
+    }
+
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        self.inner.stats().await
+    }
+
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        self.inner.time_series(metric, interval, from, to).await
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        self.inner.suggest(prefix, limit).await
+    }
+
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        self.inner.next(weights).await
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        self.inner.read(id).await
+    }
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        let todo = self.inner.create(new_todo).await?;
+        self.events.publish(TodoEvent::Created(todo.clone())).await;
+
+        Ok(todo)
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        let todo = self.inner.update(id, updated_todo, version).await?;
+        self.events.publish(TodoEvent::Updated(todo.clone())).await;
+
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        let deleted = self.inner.delete(id, version).await?;
+        // Nothing happened to a todo already gone.
+        if deleted > 0 {
+            self.events.publish(TodoEvent::Deleted(id)).await;
+        }
+
+        Ok(deleted)
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        self.inner.changes(since, limit).await
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        self.inner.revision(id, version).await
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.document(id).await
+    }
+
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error> {
+        let todo = self
+            .inner
+            .update_with_document(id, updated_todo, version, document)
+            .await?;
+        self.events.publish(TodoEvent::Updated(todo.clone())).await;
+
+        Ok(todo)
+    }
+}
+
+// Times a conflicting write is reapplied when yet another write lands before
+// it does.
+const RESOLVE_ATTEMPTS: u32 = 3;
+
+// Wraps a repository, resolving the conflicts of `update` and `delete` with
+// concurrent writes by the configured policy instead of reporting them. A
+// conflict that can't be resolved, and a write to a todo that is gone, are
+// still reported as `RowNotFound`.
+pub struct ResolvingTodoRepository {
+    inner: DynTodoRepository,
+    policy: ConflictPolicy,
+}
+
+impl ResolvingTodoRepository {
+    pub fn new(inner: DynTodoRepository, policy: ConflictPolicy) -> Self {
+        ResolvingTodoRepository { inner, policy }
+    }
+}
+
+#[async_trait]
+impl TodoRepository for ResolvingTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        self.inner.list().await
+    }
+
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        self.inner.page(offset, limit).await
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        self.inner.count().await
+    }
+
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        self.inner.stats().await
+    }
+
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        self.inner.time_series(metric, interval, from, to).await
+    }
+
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        self.inner.suggest(prefix, limit).await
+    }
+
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        self.inner.next(weights).await
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        self.inner.read(id).await
+    }
+
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        self.inner.create(new_todo).await
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        self.update_resolving(id, updated_todo, version)
+            .await
+            .map(|(todo, _)| todo)
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        self.delete_resolving(id, version)
+            .await
+            .map(|(deleted, _)| deleted)
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        self.inner.changes(since, limit).await
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        self.inner.revision(id, version).await
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.document(id).await
+    }
+
+    async fn update_with_document(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
+    ) -> Result<Todo, Error> {
+        self.inner
+            .update_with_document(id, updated_todo, version, document)
+            .await
+    }
+
+    async fn update_resolving(
+        &self,
+        id: i64,
+        updated_todo: UpdateTodo,
+        version: i64,
+    ) -> Result<(Todo, Option<Resolution>), Error> {
+        match self.inner.update(id, updated_todo.clone(), version).await {
+            Err(Error::RowNotFound) => {}
+            result => return result.map(|todo| (todo, None)),
+        }
+
+        // The base the client edited, for merging.
+        let base = match self.policy {
+            ConflictPolicy::Reject => return Err(Error::RowNotFound),
+            ConflictPolicy::LastWriteWins => None,
+            ConflictPolicy::Merge => Some(self.inner.revision(id, version).await?),
+        };
+
+        for _ in 0..RESOLVE_ATTEMPTS {
+            let current = self.inner.read(id).await?;
+
+            let (updated_todo, resolution) = match &base {
+                None => (updated_todo.clone(), Resolution::Overwritten),
+                Some(base) => (
+                    merge(base, &current, &updated_todo).ok_or(Error::RowNotFound)?,
+                    Resolution::Merged,
+                ),
+            };
+
+            match self.inner.update(id, updated_todo, current.version).await {
+                Err(Error::RowNotFound) => continue,
+                result => return result.map(|todo| (todo, Some(resolution))),
+            }
+        }
+
+        Err(Error::RowNotFound)
+    }
+
+    async fn delete_resolving(
+        &self,
+        id: i64,
+        version: Option<i64>,
+    ) -> Result<(u64, Option<Resolution>), Error> {
+        match self.inner.delete(id, version).await {
+            Err(Error::RowNotFound) => {}
+            result => return result.map(|deleted| (deleted, None)),
+        }
+
+        match self.policy {
+            // Deleting leaves nothing of a concurrent update to merge with.
+            ConflictPolicy::Reject | ConflictPolicy::Merge => Err(Error::RowNotFound),
+            ConflictPolicy::LastWriteWins => {
+                // Already gone: nothing to overwrite.
+                self.inner.read(id).await?;
+                let deleted = self.inner.delete(id, None).await?;
+
+                Ok((deleted, Some(Resolution::Overwritten)))
+            }
+        }
+    }
+}
+
+// Three-way merge of an update made to `base` with the todo as it is now:
+// each field takes the value of whichever side changed it. None when both
+// changed the same field to different values.
+fn merge(base: &Todo, current: &Todo, updated_todo: &UpdateTodo) -> Option<UpdateTodo> {
+    fn field<T: PartialEq + Clone>(base: &T, current: &T, updated: &T) -> Option<T> {
+        if updated == base || updated == current {
+            Some(current.clone())
+        } else if current == base {
+            Some(updated.clone())
+        } else {
+            None
+        }
+    }
+
+    Some(UpdateTodo::new(
+        field(&base.body, &current.body, &updated_todo.body().to_string())?,
+        field(
+            &base.completed,
+            &current.completed,
+            &updated_todo.completed(),
+        )?,
+    ))
+}
@@ -0,0 +1,70 @@
+use axum::{body::Body, extract::Request, http::header, middleware::Next, response::Response};
+use tower_http::request_id::RequestId;
+use tracing::Span;
+
+use crate::negotiate::Representation;
+
+// Returns the id assigned to the request by `SetRequestIdLayer`, either
+// propagated from the client's `X-Request-Id` header or freshly generated.
+pub fn request_id<B>(request: &axum::http::Request<B>) -> Option<&str> {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+}
+
+// Root span for every request, carrying the request id so that all events
+// and child spans (handlers, sqlx queries) can be correlated.
+pub fn make_span(request: &axum::http::Request<Body>) -> Span {
+    tracing::info_span!(
+        "request",
+        request_id = request_id(request).unwrap_or_default(),
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+    )
+}
+
+// Adds the request id to JSON error bodies so clients can quote it when
+// reporting a problem.
+pub async fn error_body(request: Request, next: Next) -> Response {
+    let request_id = request_id(&request).map(ToOwned::to_owned);
+
+    let response = next.run(request).await;
+
+    let Some(request_id) = request_id else {
+        return response;
+    };
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().ends_with(b"json"));
+
+    if !(response.status().is_client_error() || response.status().is_server_error()) || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut object)) => {
+            object.insert("request_id".to_string(), request_id.into());
+            let object = serde_json::Value::Object(object);
+            // Kept for `negotiate`, which encodes it instead of the body.
+            if parts.extensions.get::<Representation>().is_some() {
+                parts.extensions.insert(Representation(object.clone()));
+            }
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(object.to_string())
+        }
+        _ => Body::from(bytes),
+    };
+
+    Response::from_parts(parts, body)
+}
@@ -1,22 +1,290 @@
-pub async fn create_router(dbpool: sqlx::Pool<sqlx::Sqlite>) -> axum::Router {
-    use crate::api::{ping, todo_create, todo_delete, todo_list, todo_read, todo_update};
-    use axum::{routing::get, Router};
+use crate::{
+    config::{Config, StartupMigrations},
+    state::AppState,
+};
+
+pub async fn create_router(config: &Config, state: AppState) -> axum::Router {
+    use crate::admin::{self, AdminToken};
+    use crate::cache_control;
+    use crate::circuit_breaker::{self, CircuitBreaker};
+    use crate::{
+        access_log, backup, batch, build_info, caldav, deprecation, error_reporting, fallback,
+        graphql, grpc, health, idempotency, jsonrpc, maintenance, method_override, migrate,
+        negotiate, openapi, options, request_id, trailing_slash, usage, websocket,
+    };
+    use axum::{
+        http::{header, HeaderName},
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use tower_http::catch_panic::CatchPanicLayer;
     use tower_http::cors::{Any, CorsLayer};
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
     use tower_http::trace::TraceLayer;
 
-    Router::new()
-        .route("/alive", get(|| async { "ok" }))
-        .route("/ready", get(ping))
-        .nest(
-            "/v1",
+    // MIGRATE_ON_STARTUP=warn serves an outdated schema read-only.
+    let refuse_writes = config.refuse_writes_with_pending_migrations
+        || config.startup_migrations == StartupMigrations::Warn;
+    // Shared by the versions, which use the same database.
+    let breaker =
+        (config.db_breaker.failure_threshold > 0).then(|| CircuitBreaker::new(config.db_breaker));
+
+    // The versions of the REST API, served side by side so clients of one
+    // aren't stranded when the next changes in breaking ways.
+    let versions = [("/v1", v1(&state))];
+
+    let schema = graphql::schema(
+        state.todos.clone(),
+        state.events.clone(),
+        refuse_writes.then(|| state.lifecycle.clone()),
+    );
+    let grpc = grpc::routes(
+        state.todos.clone(),
+        refuse_writes.then(|| state.lifecycle.clone()),
+        health::grpc_health(&state),
+    );
+
+    let mut router = Router::new()
+        .route("/healthz/live", get(health::live))
+        .route("/healthz/ready", get(health::ready))
+        .route("/healthz", get(health::details))
+        .route("/version", get(build_info::version))
+        // Retries of a batch with an `Idempotency-Key` are replayed, as its
+        // requests are served without one.
+        .route(
+            "/batch",
+            post(batch::run).layer(middleware::from_fn_with_state(
+                state.idempotency.clone(),
+                idempotency::guard,
+            )),
+        )
+        .route("/openapi.json", get(openapi::spec))
+        .route("/docs", get(openapi::docs))
+        .route(
+            "/graphql",
+            get(graphql::graphiql).post_service(async_graphql_axum::GraphQL::new(schema.clone())),
+        )
+        .route_service(
+            "/graphql/ws",
+            async_graphql_axum::GraphQLSubscription::new(schema),
+        )
+        .route(
+            "/rpc",
+            post(jsonrpc::handle).with_state(jsonrpc::JsonRpc {
+                todos: state.todos.clone(),
+                refuse_writes: refuse_writes.then(|| state.lifecycle.clone()),
+            }),
+        )
+        .route(
+            "/ws",
+            get(websocket::stream).with_state(websocket::LiveUpdates {
+                events: state.events.clone(),
+                todos: state.todos.clone(),
+                refuse_writes: refuse_writes.then(|| state.lifecycle.clone()),
+            }),
+        );
+
+    // Every version is guarded alike.
+    for (prefix, routes) in versions {
+        let mut routes = routes.route_layer(middleware::from_fn_with_state(
+            state.idempotency.clone(),
+            idempotency::guard,
+        ));
+        if refuse_writes {
+            routes = routes.route_layer(middleware::from_fn_with_state(
+                state.lifecycle.clone(),
+                health::refuse_writes_if_schema_outdated,
+            ));
+        }
+        if let Some(breaker) = &breaker {
+            routes = routes.route_layer(middleware::from_fn_with_state(
+                breaker.clone(),
+                circuit_breaker::guard,
+            ));
+        }
+        router = router.nest(prefix, routes);
+    }
+
+    let caldav = caldav::CalDav {
+        todos: state.todos.clone(),
+        store: state.caldav.clone(),
+        refuse_writes: refuse_writes.then(|| state.lifecycle.clone()),
+    };
+    router = router.merge(
+        Router::new()
+            .route(
+                "/.well-known/caldav",
+                axum::routing::any(caldav::well_known),
+            )
+            .route("/caldav", axum::routing::any(caldav::home))
+            .route("/caldav/", axum::routing::any(caldav::home))
+            .route("/caldav/todos", axum::routing::any(caldav::calendar))
+            .route("/caldav/todos/", axum::routing::any(caldav::calendar))
+            .route("/caldav/todos/:name", axum::routing::any(caldav::object))
+            .with_state(caldav),
+    );
+
+    let deprecations = deprecation::Deprecations::new(config.deprecations.clone());
+    let batches = state.batch.clone();
+    let usage_counts = state.usage.clone();
+
+    if let Some(token) = &config.admin_token {
+        router = router.merge(
             Router::new()
-                .route("/todos", get(todo_list).post(todo_create))
                 .route(
-                    "/todos/:id",
-                    get(todo_read).put(todo_update).delete(todo_delete),
-                ),
+                    "/admin/deprecations",
+                    get(deprecation::usage).with_state(deprecations.clone()),
+                )
+                .route("/debug/pprof/profile", get(admin::pprof_profile))
+                .route("/debug/panic", get(admin::panic))
+                .route("/admin/backup", get(backup::download))
+                .route("/admin/integrity", get(maintenance::integrity_check))
+                .route("/admin/migrations", get(migrate::list))
+                .route("/admin/usage", get(usage::summary))
+                .route_layer(middleware::from_fn_with_state(
+                    AdminToken(token.clone()),
+                    admin::require_admin,
+                )),
+        );
+    }
+
+    let router = router
+        .layer(middleware::from_fn_with_state(
+            config.cache_control.clone(),
+            cache_control::apply,
+        ))
+        .layer(middleware::from_fn_with_state(
+            deprecations,
+            deprecation::announce,
+        ))
+        .with_state(state)
+        .merge(grpc)
+        .fallback(fallback::not_found)
+        // Below the fallback and gRPC, so their requests are counted too.
+        .layer(middleware::from_fn_with_state(usage_counts, usage::record))
+        .layer(CatchPanicLayer::custom(error_reporting::panic_response))
+        .layer(middleware::from_fn(fallback::method_not_allowed))
+        .layer(middleware::from_fn(request_id::error_body))
+        .layer(middleware::from_fn(negotiate::negotiate));
+
+    // Per-request Sentry hubs keep breadcrumbs and request data scoped to the
+    // request that produced them.
+    let router = if config.sentry_dsn.is_some() {
+        router
+            .layer(middleware::from_fn(error_reporting::report_server_errors))
+            .layer(sentry::integrations::tower::SentryHttpLayer::with_transaction())
+            .layer(sentry::integrations::tower::NewSentryLayer::new_from_top())
+    } else {
+        router
+    };
+
+    // Normalized before routing, as Router layers run once a route matched.
+    let mut router = Router::new()
+        .fallback_service(router)
+        .layer(middleware::from_fn_with_state(
+            config.trailing_slash,
+            trailing_slash::normalize,
+        ));
+    if config.method_override {
+        router = router.layer(middleware::from_fn(method_override::apply));
+    }
+    let routes = router.clone();
+    batches.serve(routes.clone());
+
+    router
+        .layer(
+            // Plus the headers gRPC-Web clients send and read.
+            CorsLayer::new()
+                .allow_methods(Any)
+                .allow_origin(Any)
+                .allow_headers([
+                    header::CONTENT_TYPE,
+                    HeaderName::from_static("x-http-method-override"),
+                    HeaderName::from_static("x-grpc-web"),
+                    HeaderName::from_static("x-user-agent"),
+                    HeaderName::from_static("grpc-timeout"),
+                ])
+                .expose_headers([
+                    HeaderName::from_static("x-request-id"),
+                    header::ETAG,
+                    HeaderName::from_static("x-total-count"),
+                    HeaderName::from_static("conflict-resolution"),
+                    HeaderName::from_static("deprecation"),
+                    HeaderName::from_static("sunset"),
+                    header::LINK,
+                    HeaderName::from_static("grpc-status"),
+                    HeaderName::from_static("grpc-message"),
+                    HeaderName::from_static("grpc-status-details-bin"),
+                ]),
+        )
+        .layer(middleware::from_fn_with_state(routes, options::allow))
+        .layer(middleware::from_fn_with_state(
+            config.access_log.clone(),
+            access_log::log,
+        ))
+        .layer(TraceLayer::new_for_http().make_span_with(request_id::make_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+// The routes of version 1, sharing the handlers with later versions but for
+// those whose representations differ.
+fn v1(state: &AppState) -> axum::Router<AppState> {
+    use crate::api::{
+        todo_count, todo_create, todo_delete, todo_list, todo_next, todo_read, todo_suggest,
+        todo_update,
+    };
+    use crate::{changes, long_poll, preferences, push, sse, stats, sync, webhooks};
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    let mut v1 = Router::new()
+        .route("/todos", get(todo_list).post(todo_create))
+        .route("/todos/count", get(todo_count))
+        .route("/todos/next", get(todo_next))
+        .route("/todos/suggest", get(todo_suggest))
+        .route("/todos/events", get(sse::todo_events))
+        .route("/todos/changes", get(long_poll::todo_changes))
+        .route(
+            "/todos/:id",
+            get(todo_read).put(todo_update).delete(todo_delete),
+        )
+        .route("/changes", get(changes::list))
+        .route("/stats", get(stats::stats))
+        .route("/stats/timeseries", get(stats::time_series))
+        .route("/todos/:id/document", get(sync::document))
+        .route("/sync", post(sync::sync))
+        .route("/webhooks", get(webhooks::list).post(webhooks::create))
+        .route(
+            "/webhooks/:id",
+            get(webhooks::read).delete(webhooks::delete),
         )
-        .with_state(dbpool)
-        .layer(CorsLayer::new().allow_methods(Any).allow_origin(Any))
-        .layer(TraceLayer::new_for_http())
+        .route("/webhooks/:id/deliveries", get(webhooks::deliveries))
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(webhooks::redeliver),
+        )
+        .route(
+            "/notifications/preferences",
+            get(preferences::get).put(preferences::update),
+        );
+
+    if let Some(web_push) = &state.push {
+        v1 = v1
+            .route(
+                "/push/public-key",
+                get(push::public_key).with_state(web_push.clone()),
+            )
+            .route(
+                "/push/subscriptions",
+                post(push::subscribe).with_state(web_push.clone()),
+            )
+            .route(
+                "/push/subscriptions/:id",
+                axum::routing::delete(push::unsubscribe).with_state(web_push.clone()),
+            );
+    }
+
+    v1
 }
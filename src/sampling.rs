@@ -0,0 +1,191 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tracing::{Event, Level, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+
+// A single `target@level=rate` directive. Both target and level are optional:
+// `access_log=0.1`, `@debug=0.05` and `access_log@info=0.01` are all valid.
+#[derive(Debug)]
+struct SamplingRule {
+    target: Option<String>,
+    level: Option<Level>,
+    // The share of events kept, in (0, 1].
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl SamplingRule {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        self.target
+            .as_deref()
+            .is_none_or(|target| metadata.target().starts_with(target))
+            && self.level.is_none_or(|level| *metadata.level() == level)
+    }
+
+    // Keeps the events that bring the kept ones, rounded up, to `rate` of
+    // those seen, the first one included: 2 of 5 for 0.4, where keeping one
+    // out of every `1 / rate` rounded would keep 1 of 2 or 1 of 3.
+    fn keep(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.rate).ceil() > (seen * self.rate).ceil()
+    }
+}
+
+// Sampling rules for high-volume events, parsed from LOG_SAMPLING. The first
+// matching rule decides; events that match no rule are always kept. Spans are
+// never sampled so that request context stays intact.
+#[derive(Clone, Debug, Default)]
+pub struct SamplingRules(Arc<Vec<SamplingRule>>);
+
+impl FromStr for SamplingRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (selector, rate) = directive
+                .rsplit_once('=')
+                .ok_or_else(|| format!("missing sampling rate in '{}'", directive))?;
+
+            let rate: f64 = rate
+                .parse()
+                .map_err(|_| format!("invalid sampling rate in '{}'", directive))?;
+
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(format!(
+                    "sampling rate must be in (0, 1] in '{}'",
+                    directive
+                ));
+            }
+
+            let (target, level) = match selector.split_once('@') {
+                Some((target, level)) => (
+                    target,
+                    Some(
+                        level
+                            .parse::<Level>()
+                            .map_err(|_| format!("invalid level in '{}'", directive))?,
+                    ),
+                ),
+                None => (selector, None),
+            };
+
+            rules.push(SamplingRule {
+                target: (!target.is_empty()).then(|| target.to_string()),
+                level,
+                rate,
+                seen: AtomicU64::new(0),
+            });
+        }
+
+        Ok(SamplingRules(Arc::new(rules)))
+    }
+}
+
+impl<S> Filter<S> for SamplingRules {
+    fn enabled(&self, _metadata: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        true
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, _cx: &Context<'_, S>) -> bool {
+        match self.0.iter().find(|rule| rule.matches(event.metadata())) {
+            Some(rule) => rule.keep(),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+    use super::*;
+
+    // Counts the events it's given, those of `access_log` and the others.
+    #[derive(Clone, Default)]
+    struct Count(Arc<(AtomicUsize, AtomicUsize)>);
+
+    impl<S: tracing::Subscriber> Layer<S> for Count {
+        fn on_event(&self, event: &Event<'_>, _cx: Context<'_, S>) {
+            let (access_log, other) = &*self.0;
+            match event.metadata().target() {
+                "access_log" => access_log.fetch_add(1, Ordering::Relaxed),
+                _ => other.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    // How many of `n` info events of `access_log`, and of `n` of another
+    // target, the rules keep.
+    fn kept(rules: &str, n: usize) -> (usize, usize) {
+        let rules: SamplingRules = rules.parse().unwrap();
+        let count = Count::default();
+        let subscriber = tracing_subscriber::registry().with(count.clone().with_filter(rules));
+
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..n {
+                tracing::info!(target: "access_log", "request");
+                tracing::info!(target: "other", "something");
+            }
+        });
+        let (access_log, other) = &*count.0;
+        (
+            access_log.load(Ordering::Relaxed),
+            other.load(Ordering::Relaxed),
+        )
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules: SamplingRules = "access_log=0.1, @debug=0.05,access_log@info=0.01,"
+            .parse()
+            .unwrap();
+        let targets: Vec<_> = rules.0.iter().map(|rule| rule.target.as_deref()).collect();
+        let levels: Vec<_> = rules.0.iter().map(|rule| rule.level).collect();
+        let rates: Vec<_> = rules.0.iter().map(|rule| rule.rate).collect();
+        assert_eq!(targets, [Some("access_log"), None, Some("access_log")]);
+        assert_eq!(levels, [None, Some(Level::DEBUG), Some(Level::INFO)]);
+        assert_eq!(rates, [0.1, 0.05, 0.01]);
+
+        assert!("".parse::<SamplingRules>().unwrap().0.is_empty());
+        for invalid in [
+            "access_log",
+            "access_log=often",
+            "access_log=0",
+            "access_log=1.5",
+            "access_log@loud=0.5",
+        ] {
+            assert!(invalid.parse::<SamplingRules>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn keeps_the_rate_of_events() {
+        assert_eq!(kept("access_log=1", 10), (10, 10));
+        assert_eq!(kept("access_log=0.5", 10), (5, 10));
+        // Not a fraction 1/n of events.
+        assert_eq!(kept("access_log=0.4", 10), (4, 10));
+        assert_eq!(kept("access_log=0.75", 100), (75, 100));
+        assert_eq!(kept("access_log=0.01", 1000), (10, 1000));
+        // The first of them, at least.
+        assert_eq!(kept("access_log=0.01", 1), (1, 1));
+    }
+
+    #[test]
+    fn the_first_matching_rule_decides() {
+        assert_eq!(kept("access_log@info=1,access_log=0.5", 10), (10, 10));
+        assert_eq!(kept("access_log@debug=1,access_log=0.5", 10), (5, 10));
+        assert_eq!(kept("access_log@warn=0.5", 10), (10, 10));
+        // Shared by the events of every target.
+        assert_eq!(kept("@info=0.5", 10), (10, 0));
+    }
+}
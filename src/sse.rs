@@ -0,0 +1,85 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    events::{parse_cursor, EventStream, NumberedEvent, Resume, TodoEvent},
+    state::Lifecycle,
+};
+
+// Server-Sent Events: every todo created, updated or deleted through any API
+// of this instance, as `created`, `updated` and `deleted` events whose data is
+// `{"id":1,"todo":{...}}` (no todo for deletions).
+//
+// Each event has an id, which browsers send back as Last-Event-ID when they
+// reconnect; the events published since are then replayed first. When they
+// can't be, because the id is too old or from before a restart, a `reset`
+// event tells the client to refetch what it shows. A client too slow to keep
+// up is disconnected, to catch up the same way, and so is every client once
+// the server starts draining, so they reconnect to another instance instead
+// of holding up shutdown.
+pub async fn todo_events(
+    State(events): State<EventStream>,
+    State(lifecycle): State<Lifecycle>,
+    headers: HeaderMap,
+) -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .map(|value| parse_cursor(value.to_str().unwrap_or_default()));
+
+    let (resume, mut receiver) = match last_event_id {
+        None => (Resume::Missed(Vec::new()), events.subscribe()),
+        Some(Some((run, seq))) => events.resume(run, seq),
+        // Not an id this server hands out.
+        Some(None) => events.resume(0, 0),
+    };
+
+    let stream = async_stream::stream! {
+        match resume {
+            Resume::Missed(missed) => {
+                for numbered in missed {
+                    yield Ok(event(&events, &numbered));
+                }
+            }
+            Resume::Lost { seq } => {
+                yield Ok(Event::default().event("reset").id(events.cursor(seq)).data("{}"));
+            }
+        }
+
+        loop {
+            let received = tokio::select! {
+                received = receiver.recv() => received,
+                _ = lifecycle.draining() => break,
+            };
+
+            match received {
+                Ok(numbered) => yield Ok(event(&events, &numbered)),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "event stream client fell behind, disconnecting");
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn event(events: &EventStream, numbered: &NumberedEvent) -> Event {
+    let (kind, data) = match &numbered.event {
+        TodoEvent::Created(todo) => ("created", serde_json::json!({"id": todo.id, "todo": todo})),
+        TodoEvent::Updated(todo) => ("updated", serde_json::json!({"id": todo.id, "todo": todo})),
+        TodoEvent::Deleted(id) => ("deleted", serde_json::json!({"id": id})),
+    };
+
+    Event::default()
+        .event(kind)
+        .id(events.cursor(numbered.seq))
+        .data(data.to_string())
+}
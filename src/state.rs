@@ -0,0 +1,146 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    batch::Batch, caldav::DynCalDavStore, config::Config, db::DbPool, events::EventStream,
+    health::DatabaseHealth, idempotency::Idempotency, preferences::Preferences, push::WebPush,
+    redis_store::RedisStore, repository::DynTodoRepository, usage::Usage, webhooks::Webhooks,
+};
+use axum::extract::FromRef;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    // None with the in-memory storage backend.
+    pub dbpool: Option<DbPool>,
+    pub todos: DynTodoRepository,
+    pub lifecycle: Lifecycle,
+    pub db_health: DatabaseHealth,
+    // None unless REDIS_URL is set.
+    pub redis: Option<RedisStore>,
+    pub idempotency: Idempotency,
+    pub events: EventStream,
+    pub webhooks: Webhooks,
+    // None unless VAPID_PRIVATE_KEY is set.
+    pub push: Option<WebPush>,
+    pub preferences: Preferences,
+    // Names and UIDs of the todos CalDAV clients created.
+    pub caldav: DynCalDavStore,
+    // The router serving the sub-requests of batches, set once built.
+    pub batch: Batch,
+    // Requests counted by principal and endpoint.
+    pub usage: Usage,
+}
+
+impl FromRef<AppState> for Option<DbPool> {
+    fn from_ref(state: &AppState) -> Option<DbPool> {
+        state.dbpool.clone()
+    }
+}
+
+impl FromRef<AppState> for DynTodoRepository {
+    fn from_ref(state: &AppState) -> DynTodoRepository {
+        state.todos.clone()
+    }
+}
+
+impl FromRef<AppState> for DatabaseHealth {
+    fn from_ref(state: &AppState) -> DatabaseHealth {
+        state.db_health.clone()
+    }
+}
+
+impl FromRef<AppState> for Idempotency {
+    fn from_ref(state: &AppState) -> Idempotency {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<AppState> for EventStream {
+    fn from_ref(state: &AppState) -> EventStream {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for Webhooks {
+    fn from_ref(state: &AppState) -> Webhooks {
+        state.webhooks.clone()
+    }
+}
+
+impl FromRef<AppState> for Preferences {
+    fn from_ref(state: &AppState) -> Preferences {
+        state.preferences.clone()
+    }
+}
+
+impl FromRef<AppState> for Usage {
+    fn from_ref(state: &AppState) -> Usage {
+        state.usage.clone()
+    }
+}
+
+impl FromRef<AppState> for Lifecycle {
+    fn from_ref(state: &AppState) -> Lifecycle {
+        state.lifecycle.clone()
+    }
+}
+
+// Process lifecycle shared between the shutdown handler and the probes.
+#[derive(Clone)]
+pub struct Lifecycle {
+    started_at: Instant,
+    draining: Arc<AtomicBool>,
+    draining_started: Arc<Notify>,
+    schema_outdated: Arc<AtomicBool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            started_at: Instant::now(),
+            draining: Arc::default(),
+            draining_started: Arc::default(),
+            schema_outdated: Arc::default(),
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.draining_started.notify_waiters();
+    }
+
+    // Completes once draining has started.
+    pub async fn draining(&self) {
+        let notified = self.draining_started.notified();
+        if !self.is_draining() {
+            notified.await;
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    // Updated at startup and by the database monitor.
+    pub fn set_schema_outdated(&self, outdated: bool) {
+        self.schema_outdated.store(outdated, Ordering::SeqCst);
+    }
+
+    pub fn is_schema_outdated(&self) -> bool {
+        self.schema_outdated.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,134 @@
+use axum::extract::State;
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    envelope::Success,
+    error::ApiError,
+    params::QueryParams,
+    repository::DynTodoRepository,
+    todo::{Interval, Metric},
+};
+
+// Buckets a time series has at most.
+const MAX_BUCKETS: usize = 1000;
+
+// Days a time series spans by default, up to `to`.
+const DEFAULT_DAYS: u64 = 30;
+
+// The days every backend stores, time series reaching past them refused.
+const FIRST_DAY: NaiveDate = NaiveDate::from_ymd_opt(1, 1, 1).unwrap();
+const LAST_DAY: NaiveDate = NaiveDate::from_ymd_opt(9999, 12, 31).unwrap();
+
+#[derive(Serialize)]
+pub struct StatsData {
+    total: i64,
+    open: i64,
+    completed: i64,
+    // Of the todos completed, 0 without todos.
+    completion_rate: f64,
+    // From creation to the first completion, over the todos completed now
+    // whose completion is in the change feed; null without any.
+    average_completion_seconds: Option<f64>,
+}
+
+// Statistics of the todos, aggregated by the database instead of read. Todos
+// have no tags or priorities yet, so they aren't counted by them.
+pub async fn stats(State(todos): State<DynTodoRepository>) -> Result<Success<StatsData>, ApiError> {
+    let stats = todos.stats().await?;
+
+    Ok(Success::new(StatsData {
+        total: stats.total,
+        open: stats.total - stats.completed,
+        completed: stats.completed,
+        completion_rate: if stats.total > 0 {
+            stats.completed as f64 / stats.total as f64
+        } else {
+            0.0
+        },
+        average_completion_seconds: stats.average_completion_seconds,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct TimeSeriesParams {
+    metric: Metric,
+    // Days when absent.
+    interval: Option<Interval>,
+    // The first and last days, in UTC; the last 30 days when absent.
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+pub struct TimeSeriesData {
+    metric: Metric,
+    interval: Interval,
+    from: NaiveDate,
+    to: NaiveDate,
+    points: Vec<Point>,
+}
+
+#[derive(Serialize)]
+pub struct Point {
+    // The first day of the bucket.
+    bucket: NaiveDate,
+    count: i64,
+}
+
+// The todos created, or completed for the first time, per day, week or month
+// of the range, for charts: bucketed by the database with its date functions,
+// then every bucket of the range is listed, the empty ones with 0. Deleted
+// todos still count, as far as the change feed recorded them.
+pub async fn time_series(
+    State(todos): State<DynTodoRepository>,
+    QueryParams(params): QueryParams<TimeSeriesParams>,
+) -> Result<Success<TimeSeriesData>, ApiError> {
+    let interval = params.interval.unwrap_or(Interval::Day);
+    let to = params.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = match params.from {
+        Some(from) => from,
+        None => to
+            .checked_sub_days(Days::new(DEFAULT_DAYS - 1))
+            .ok_or(ApiError::DateOutOfRange)?,
+    };
+    if ![from, to]
+        .iter()
+        .all(|day| (FIRST_DAY..=LAST_DAY).contains(day))
+    {
+        return Err(ApiError::DateOutOfRange);
+    }
+    if from > to {
+        return Err(ApiError::ReversedDateRange);
+    }
+
+    let mut buckets = Vec::new();
+    let mut bucket = interval.bucket(from);
+    while let Some(day) = bucket.filter(|day| *day <= to) {
+        if buckets.len() == MAX_BUCKETS {
+            return Err(ApiError::TooManyBuckets(MAX_BUCKETS));
+        }
+        buckets.push(day);
+        bucket = interval.next(day);
+    }
+
+    let counts = todos.time_series(params.metric, interval, from, to).await?;
+    let points = buckets
+        .into_iter()
+        .map(|bucket| Point {
+            bucket,
+            count: counts
+                .iter()
+                .find(|(counted, _)| *counted == bucket)
+                .map_or(0, |(_, count)| *count),
+        })
+        .collect();
+
+    Ok(Success::new(TimeSeriesData {
+        metric: params.metric,
+        interval,
+        from,
+        to,
+        points,
+    }))
+}
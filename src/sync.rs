@@ -0,0 +1,305 @@
+use axum::{extract::State, http::StatusCode};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    body::PlainJsonBody,
+    crdt,
+    envelope::Success,
+    params::PathParams,
+    problem::Problem,
+    repository::{DynTodoRepository, Resolution},
+    todo::{Change, CreateTodo, Todo, UpdateTodo},
+};
+
+// Local changes accepted at most per request.
+const MAX_CHANGES: usize = 100;
+
+// Server changes returned at most per response.
+const MAX_SERVER_CHANGES: i64 = 1000;
+
+// Times an edit is merged again when another write lands before it does.
+const EDIT_ATTEMPTS: u32 = 3;
+
+#[derive(Deserialize)]
+pub struct SyncRequest {
+    // The `next` of the previous sync; 0 for a client that has nothing yet.
+    #[serde(default)]
+    since: i64,
+    // Made while offline, applied in order.
+    #[serde(default)]
+    changes: Vec<LocalChange>,
+}
+
+#[derive(Deserialize)]
+pub struct LocalChange {
+    // Echoed back in the result; any JSON value the client picks, such as
+    // the local id of a todo it created.
+    #[serde(default)]
+    client_id: Value,
+    #[serde(flatten)]
+    op: Op,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Op {
+    Create {
+        body: String,
+    },
+    // `version` is the revision the client last saw.
+    Update {
+        id: i64,
+        version: i64,
+        body: String,
+        completed: bool,
+    },
+    Delete {
+        id: i64,
+        version: i64,
+    },
+    // A Yjs update of the body's document, base64 encoded. Edits merge with
+    // every other, so they never conflict.
+    Edit {
+        id: i64,
+        #[serde(deserialize_with = "base64_bytes")]
+        update: Vec<u8>,
+    },
+}
+
+fn base64_bytes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+        .decode(encoded)
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Serialize)]
+pub struct SyncData {
+    // Of the local changes, in order.
+    results: Vec<ChangeResult>,
+    // The `since` of the next sync.
+    next: i64,
+    // More changes are waiting: sync again from `next`.
+    more: bool,
+    changes: Vec<Change>,
+}
+
+#[derive(Serialize)]
+pub struct ChangeResult {
+    client_id: Value,
+    status: &'static str,
+    resolution: Option<&'static str>,
+    todo: Option<Todo>,
+    // Base64 encoded.
+    document: Option<String>,
+}
+
+// Delta sync for clients that work offline: applies the changes a client made
+// since it last synced, then answers with the outcome of each and with the
+// changes of the change feed since `since`, the client's own included, so
+// applying them in order brings it up to date.
+//
+// A change made to a revision of a todo that was modified or deleted on the
+// server in the meantime isn't applied, unless CONFLICT_POLICY resolves it:
+// its result is a `conflict` carrying the todo as it is on the server, or
+// null when it is gone.
+pub async fn sync(
+    State(todos): State<DynTodoRepository>,
+    PlainJsonBody(request): PlainJsonBody<SyncRequest>,
+) -> Result<Success<SyncData>, Problem> {
+    if request.since < 0 {
+        return Err(fail("since must not be negative".to_string()));
+    }
+    if request.changes.len() > MAX_CHANGES {
+        return Err(fail(format!(
+            "at most {} changes can be synced at once",
+            MAX_CHANGES
+        )));
+    }
+    // Checked up front, so no change is applied from a request that fails.
+    for change in &request.changes {
+        if let Op::Edit { update, .. } = &change.op {
+            crdt::decode(update).map_err(fail)?;
+        }
+    }
+
+    let mut results = Vec::with_capacity(request.changes.len());
+    for change in request.changes {
+        let result = apply(&todos, change.op).await?;
+        results.push(ChangeResult {
+            client_id: change.client_id,
+            status: result.status,
+            resolution: result.resolution.map(Resolution::as_str),
+            todo: result.todo,
+            document: result
+                .document
+                .map(|document| BASE64_STANDARD.encode(document)),
+        });
+    }
+
+    let changes = todos.changes(request.since, MAX_SERVER_CHANGES).await?;
+
+    Ok(Success::new(SyncData {
+        results,
+        next: changes.last().map_or(request.since, |change| change.seq),
+        more: changes.len() as i64 == MAX_SERVER_CHANGES,
+        changes,
+    }))
+}
+
+struct Outcome {
+    // `applied` or `conflict`.
+    status: &'static str,
+    // How a conflict was resolved, for a change applied despite one.
+    resolution: Option<Resolution>,
+    // The todo as it is now on the server, if it exists.
+    todo: Option<Todo>,
+    // The body's document after an edit, for the client to merge.
+    document: Option<Vec<u8>>,
+}
+
+impl Outcome {
+    fn applied(resolution: Option<Resolution>, todo: Option<Todo>) -> Self {
+        Outcome {
+            status: "applied",
+            resolution,
+            todo,
+            document: None,
+        }
+    }
+
+    fn conflict(todo: Option<Todo>) -> Self {
+        Outcome {
+            status: "conflict",
+            resolution: None,
+            todo,
+            document: None,
+        }
+    }
+}
+
+async fn apply(todos: &DynTodoRepository, op: Op) -> Result<Outcome, sqlx::Error> {
+    match op {
+        Op::Create { body } => {
+            let todo = todos.create(CreateTodo::new(body)).await?;
+            Ok(Outcome::applied(None, Some(todo)))
+        }
+        Op::Update {
+            id,
+            version,
+            body,
+            completed,
+        } => match todos
+            .update_resolving(id, UpdateTodo::new(body, completed), version)
+            .await
+        {
+            Ok((todo, resolution)) => Ok(Outcome::applied(resolution, Some(todo))),
+            Err(sqlx::Error::RowNotFound) => Ok(Outcome::conflict(current(todos, id).await?)),
+            Err(e) => Err(e),
+        },
+        Op::Delete { id, version } => match todos.delete_resolving(id, Some(version)).await {
+            Ok((_, resolution)) => Ok(Outcome::applied(resolution, None)),
+            Err(sqlx::Error::RowNotFound) => match current(todos, id).await? {
+                // Deleted on the server too: nothing to reconcile.
+                None => Ok(Outcome::applied(None, None)),
+                todo => Ok(Outcome::conflict(todo)),
+            },
+            Err(e) => Err(e),
+        },
+        Op::Edit { id, update } => {
+            for _ in 0..EDIT_ATTEMPTS {
+                let Some(todo) = current(todos, id).await? else {
+                    return Ok(Outcome::conflict(None));
+                };
+
+                let state = todos.document(id).await?;
+                let (document, body) = merge(state.as_deref(), &todo, Some(&update))?;
+
+                match todos
+                    .update_with_document(
+                        id,
+                        UpdateTodo::new(body, todo.completed),
+                        todo.version,
+                        document.clone(),
+                    )
+                    .await
+                {
+                    Ok(todo) => {
+                        return Ok(Outcome {
+                            document: Some(document),
+                            ..Outcome::applied(None, Some(todo))
+                        })
+                    }
+                    Err(sqlx::Error::RowNotFound) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(Outcome::conflict(current(todos, id).await?))
+        }
+    }
+}
+
+// The document of the todo's body with `update` merged in, and the body it
+// holds.
+fn merge(
+    state: Option<&[u8]>,
+    todo: &Todo,
+    update: Option<&[u8]>,
+) -> Result<(Vec<u8>, String), sqlx::Error> {
+    let updates = update
+        .map(crdt::decode)
+        .into_iter()
+        .collect::<Result<_, _>>();
+    updates
+        .and_then(|updates| crdt::apply(state, &todo.body, updates))
+        .map_err(|e| sqlx::Error::Decode(e.into()))
+}
+
+#[derive(Serialize)]
+pub struct DocumentData {
+    // Base64 encoded.
+    document: String,
+    // Of the todo the document was built from.
+    version: i64,
+}
+
+// The CRDT document of a todo's body, base64 encoded, for a client to start
+// editing it offline. Built from the body when it was never edited through
+// one; every document built from the same body is the same, so clients that
+// start from it merge cleanly.
+pub async fn document(
+    State(todos): State<DynTodoRepository>,
+    PathParams(id): PathParams<i64>,
+) -> Result<Success<DocumentData>, Problem> {
+    let todo = match todos.read(id).await {
+        Ok(todo) => todo,
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(Problem::new(StatusCode::NOT_FOUND)
+                .detail(format!("todo with ID: {} not found", id)));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let state = todos.document(id).await?;
+    let (document, _) = merge(state.as_deref(), &todo, None)?;
+
+    Ok(Success::new(DocumentData {
+        document: BASE64_STANDARD.encode(document),
+        version: todo.version,
+    }))
+}
+
+async fn current(todos: &DynTodoRepository, id: i64) -> Result<Option<Todo>, sqlx::Error> {
+    match todos.read(id).await {
+        Ok(todo) => Ok(Some(todo)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    config::TelegramConfig,
+    preferences::Preferences,
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, Todo, UpdateTodo},
+};
+
+// How long a request for updates is held by Telegram when there are none.
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Open todos listed at most per message.
+const MAX_LISTED: usize = 50;
+
+const HELP: &str =
+    "/add <text>: add a todo\n/list: list the open todos\n/done <id>: complete a todo";
+
+#[derive(Deserialize)]
+struct Response<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Clone)]
+struct Bot {
+    config: TelegramConfig,
+    todos: DynTodoRepository,
+    lifecycle: Lifecycle,
+    preferences: Preferences,
+    client: reqwest::Client,
+}
+
+// Runs the bot in the background: answers the commands sent in the allowed
+// chats, received by long polling, and sends them the open todos daily at
+// TELEGRAM_REMINDER_AT, unless that preference is disabled. Failures are
+// logged under the `telegram` target.
+pub fn spawn(
+    config: TelegramConfig,
+    todos: DynTodoRepository,
+    lifecycle: Lifecycle,
+    preferences: Preferences,
+) {
+    let bot = Bot {
+        config,
+        todos,
+        lifecycle,
+        preferences,
+        client: reqwest::Client::builder()
+            .timeout(POLL_TIMEOUT + Duration::from_secs(10))
+            .build()
+            .expect("couldn't build the Telegram HTTP client"),
+    };
+
+    if let Some(reminder_at) = bot.config.reminder_at {
+        tokio::spawn(bot.clone().remind(reminder_at));
+    }
+    tokio::spawn(bot.poll());
+}
+
+impl Bot {
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T, String> {
+        let response: Response<T> = self
+            .client
+            .post(format!(
+                "{}/bot{}/{}",
+                self.config.api_url, self.config.token, method
+            ))
+            .json(&params)
+            .send()
+            .await
+            .map_err(|e| e.without_url().to_string())?
+            .json()
+            .await
+            .map_err(|e| e.without_url().to_string())?;
+
+        match response.result {
+            Some(result) if response.ok => Ok(result),
+            _ => Err(response
+                .description
+                .unwrap_or_else(|| format!("{} failed", method))),
+        }
+    }
+
+    async fn send(&self, chat_id: i64, text: &str) {
+        let params = json!({"chat_id": chat_id, "text": text});
+        if let Err(e) = self.call::<serde_json::Value>("sendMessage", params).await {
+            tracing::warn!(target: "telegram", error = %e, chat_id, "couldn't send message");
+        }
+    }
+
+    async fn poll(self) {
+        let mut offset = 0;
+
+        loop {
+            let params = json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT.as_secs(),
+                "allowed_updates": ["message"],
+            });
+            let updates: Vec<Update> = match self.call("getUpdates", params).await {
+                Ok(updates) => updates,
+                Err(e) => {
+                    tracing::warn!(target: "telegram", error = %e, "couldn't get updates");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            for update in updates {
+                offset = update.update_id + 1;
+
+                let Some(Message {
+                    chat,
+                    text: Some(text),
+                }) = update.message
+                else {
+                    continue;
+                };
+                if !self.config.chat_ids.contains(&chat.id) {
+                    tracing::debug!(target: "telegram", chat_id = chat.id, "ignored a message from a chat not allowed");
+                    continue;
+                }
+
+                let reply = self.command(&text).await;
+                self.send(chat.id, &reply).await;
+            }
+        }
+    }
+
+    async fn command(&self, text: &str) -> String {
+        let (command, argument) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+        // `/add@some_bot` in groups.
+        let command = command.split('@').next().unwrap_or_default();
+        let argument = argument.trim();
+
+        let writes = matches!(command, "/add" | "/done");
+        if writes && self.lifecycle.is_schema_outdated() {
+            return "Database migrations are pending, writes are disabled.".to_string();
+        }
+
+        let result = match command {
+            "/add" if argument.is_empty() => Ok("Usage: /add <text>".to_string()),
+            "/add" => self.add(argument).await,
+            "/list" => self.list().await,
+            "/done" => match argument.parse() {
+                Ok(id) => self.done(id).await,
+                Err(_) => Ok("Usage: /done <id>".to_string()),
+            },
+            _ => Ok(HELP.to_string()),
+        };
+
+        result.unwrap_or_else(|e| {
+            tracing::warn!(target: "telegram", error = %e, command, "command failed");
+            "Something went wrong, try again later.".to_string()
+        })
+    }
+
+    async fn add(&self, body: &str) -> Result<String, sqlx::Error> {
+        let todo = self.todos.create(CreateTodo::new(body.to_string())).await?;
+
+        Ok(format!("Added #{}: {}", todo.id, todo.body))
+    }
+
+    async fn open(&self) -> Result<Vec<Todo>, sqlx::Error> {
+        Ok(self
+            .todos
+            .list()
+            .await?
+            .into_iter()
+            .filter(|todo| !todo.completed)
+            .collect())
+    }
+
+    async fn list(&self) -> Result<String, sqlx::Error> {
+        let open = self.open().await?;
+        if open.is_empty() {
+            return Ok("Nothing to do.".to_string());
+        }
+
+        Ok(listing(&open))
+    }
+
+    async fn done(&self, id: i64) -> Result<String, sqlx::Error> {
+        let todo = match self.todos.read(id).await {
+            Ok(todo) => todo,
+            Err(sqlx::Error::RowNotFound) => return Ok(format!("No todo #{}.", id)),
+            Err(e) => return Err(e),
+        };
+        if todo.completed {
+            return Ok(format!("#{} is already done.", id));
+        }
+
+        match self
+            .todos
+            .update(id, UpdateTodo::new(todo.body.clone(), true), todo.version)
+            .await
+        {
+            Ok(todo) => Ok(format!("Done #{}: {}", todo.id, todo.body)),
+            Err(sqlx::Error::RowNotFound) => {
+                Ok(format!("#{} was changed meanwhile, try again.", id))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remind(self, at: chrono::NaiveTime) {
+        loop {
+            tokio::time::sleep(until(at)).await;
+
+            if !self.preferences.allows("telegram", "reminder").await {
+                continue;
+            }
+
+            match self.open().await {
+                Ok(open) if open.is_empty() => {}
+                Ok(open) => {
+                    let text = format!("Open todos:\n{}", listing(&open));
+                    for &chat_id in &self.config.chat_ids {
+                        self.send(chat_id, &text).await;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(target: "telegram", error = %e, "couldn't list todos for the reminder")
+                }
+            }
+        }
+    }
+}
+
+fn listing(todos: &[Todo]) -> String {
+    let mut lines: Vec<_> = todos
+        .iter()
+        .take(MAX_LISTED)
+        .map(|todo| format!("#{} {}", todo.id, todo.body))
+        .collect();
+    if todos.len() > MAX_LISTED {
+        lines.push(format!("and {} more", todos.len() - MAX_LISTED));
+    }
+
+    lines.join("\n")
+}
+
+// Until `at` next comes, UTC.
+pub fn until(at: chrono::NaiveTime) -> Duration {
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut next = now.date().and_time(at);
+    if next <= now {
+        next += chrono::TimeDelta::try_days(1).unwrap();
+    }
+
+    (next - now).to_std().unwrap_or_default()
+}
@@ -1,83 +1,1196 @@
-use chrono::NaiveDateTime;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{LazyLock, OnceLock},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_as, Error, SqlitePool};
+use sqlx::{any::AnyRow, query, query_as, Error, FromRow, Row};
+use tracing::Instrument;
+
+use crate::{
+    config::NextTodoWeights,
+    db::{Backend, DbConnection, DbPools, DbQueryResult},
+    repository::TodoRepository,
+};
+
+static SLOW_QUERY_THRESHOLD: OnceLock<Duration> = OnceLock::new();
+
+// Statements taking longer than this are logged under the `slow_query` target.
+pub fn set_slow_query_threshold(threshold: Duration) {
+    let _ = SLOW_QUERY_THRESHOLD.set(threshold);
+}
+
+// Runs a query inside a `query` span carrying the table, operation and the
+// number of rows returned or affected, and logs it with its duration when it
+// exceeds the slow query threshold. Only the SQL text is logged, never the
+// bound values.
+async fn instrumented<T: RowCount>(
+    table: &str,
+    operation: &str,
+    sql: &str,
+    query: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let span = tracing::info_span!("query", table, operation, rows = tracing::field::Empty);
+
+    let start = Instant::now();
+    let result = query.instrument(span.clone()).await;
+    let elapsed = start.elapsed();
+
+    if let Ok(rows) = &result {
+        span.record("rows", rows.row_count());
+    }
+
+    if SLOW_QUERY_THRESHOLD
+        .get()
+        .is_some_and(|threshold| elapsed >= *threshold)
+    {
+        span.in_scope(|| {
+            tracing::warn!(
+                target: "slow_query",
+                duration_ms = elapsed.as_secs_f64() * 1000.0,
+                sql = %redact_sql(sql),
+                "slow query"
+            )
+        });
+    }
+
+    result
+}
 
-#[derive(Serialize, Clone, sqlx::FromRow)]
+trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for Todo {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl RowCount for AnyRow {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl<T: RowCount> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.as_ref().map_or(0, RowCount::row_count)
+    }
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl RowCount for DbQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+// Masks string literals in case a statement ever inlines user data.
+fn redact_sql(sql: &str) -> std::borrow::Cow<'_, str> {
+    static STRING_LITERAL: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"'(?:[^']|'')*'").unwrap());
+
+    STRING_LITERAL.replace_all(sql, "'?'")
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Todo {
     pub id: i64,
     pub body: String,
     pub completed: bool,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    // Incremented by every update, for optimistic concurrency.
+    pub version: i64,
 }
 
-impl Todo {
-    pub async fn list(dbpool: SqlitePool) -> Result<Vec<Todo>, Error> {
-        query_as("select * from todos")
-            .fetch_all(&dbpool)
-            .await
-            .map_err(Into::into)
+// The Any driver has no boolean or timestamp types, so `columns` selects them
+// as integers and text, parsed back here.
+impl FromRow<'_, AnyRow> for Todo {
+    fn from_row(row: &AnyRow) -> Result<Self, Error> {
+        Ok(Todo {
+            id: row.try_get("id")?,
+            body: row.try_get("body")?,
+            completed: row.try_get::<i64, _>("completed")? != 0,
+            created_at: timestamp(row, "created_at")?,
+            updated_at: timestamp(row, "updated_at")?,
+            version: row.try_get("version")?,
+        })
     }
+}
+
+fn timestamp(row: &AnyRow, column: &str) -> Result<NaiveDateTime, Error> {
+    let value: String = row.try_get(column)?;
+
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+        .ok_or_else(|| Error::ColumnDecode {
+            index: column.to_string(),
+            source: format!("invalid timestamp: {}", value).into(),
+        })
+}
 
-    pub async fn read(dbpool: SqlitePool, id: i64) -> Result<Todo, Error> {
-        query_as("select * from todos where id = ?")
-            .bind(id)
-            .fetch_one(&dbpool)
-            .await
-            .map_err(Into::into)
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ChangeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Deleted => "deleted",
+        }
     }
+}
+
+// An entry of the change feed: a write to a todo, numbered in the order
+// writes committed.
+#[derive(Serialize, Clone)]
+pub struct Change {
+    pub seq: i64,
+    pub kind: ChangeKind,
+    pub todo_id: i64,
+    // The todo as written; None for deletions.
+    pub todo: Option<Todo>,
+    pub changed_at: NaiveDateTime,
+}
+
+impl FromRow<'_, AnyRow> for Change {
+    fn from_row(row: &AnyRow) -> Result<Self, Error> {
+        let kind: String = row.try_get("kind")?;
+        // Selected as '' when NULL: the Any driver can't decode NULLs.
+        let todo: String = row.try_get("todo")?;
 
-    pub async fn create(dbpool: SqlitePool, new_todo: CreateTodo) -> Result<Todo, Error> {
-        query_as("insert into todos (body) values (?) returning *")
-            .bind(new_todo.body())
-            .fetch_one(&dbpool)
-            .await
-            .map_err(Into::into)
+        Ok(Change {
+            seq: row.try_get("seq")?,
+            kind: match kind.as_str() {
+                "created" => ChangeKind::Created,
+                "updated" => ChangeKind::Updated,
+                "deleted" => ChangeKind::Deleted,
+                _ => {
+                    return Err(Error::ColumnDecode {
+                        index: "kind".to_string(),
+                        source: format!("invalid change kind: {}", kind).into(),
+                    })
+                }
+            },
+            todo_id: row.try_get("todo_id")?,
+            todo: Some(todo)
+                .filter(|todo| !todo.is_empty())
+                .map(|todo| serde_json::from_str(&todo))
+                .transpose()
+                .map_err(|e| Error::ColumnDecode {
+                    index: "todo".to_string(),
+                    source: e.into(),
+                })?,
+            changed_at: timestamp(row, "changed_at")?,
+        })
     }
+}
+
+// Aggregates over every todo.
+#[derive(Clone, Copy, Default)]
+pub struct TodoStats {
+    pub total: i64,
+    pub completed: i64,
+    // Seconds from creation to the first completion the change feed records,
+    // on average over the todos completed now; None when there are none.
+    pub average_completion_seconds: Option<f64>,
+}
 
-    pub async fn update(
-        dbpool: SqlitePool,
+impl TodoStats {
+    // From every todo and every change, for backends without queries.
+    pub fn of(todos: &[Todo], changes: &[Change]) -> Self {
+        let mut completions = HashMap::new();
+        for change in changes {
+            if change.todo.as_ref().is_some_and(|todo| todo.completed) {
+                completions
+                    .entry(change.todo_id)
+                    .or_insert(change.changed_at);
+            }
+        }
+
+        let durations: Vec<f64> = todos
+            .iter()
+            .filter(|todo| todo.completed)
+            .filter_map(|todo| {
+                let completed_at = completions.get(&todo.id)?;
+                Some((*completed_at - todo.created_at).num_milliseconds() as f64 / 1000.0)
+            })
+            .collect();
+
+        TodoStats {
+            total: todos.len() as i64,
+            completed: todos.iter().filter(|todo| todo.completed).count() as i64,
+            average_completion_seconds: (!durations.is_empty())
+                .then(|| durations.iter().sum::<f64>() / durations.len() as f64),
+        }
+    }
+}
+
+// The first completion of each todo is the earliest change recording it
+// completed. The recorded todo is compact JSON, whose body can't hold the
+// pattern unescaped, so it's matched as text on every backend.
+const FIRST_COMPLETIONS: &str = "select todo_id, min(changed_at) as completed_at from changes \
+     where todo like '%\"completed\":true%' group by todo_id";
+
+// `TodoStats` in one query. The Any driver decodes neither NULLs nor
+// decimals, so the average is taken as 0 without completions and cast.
+fn stats_sql(backend: Backend) -> String {
+    let seconds = match backend {
+        Backend::Sqlite => "(julianday(c.completed_at) - julianday(t.created_at)) * 86400",
+        Backend::Postgres => "extract(epoch from c.completed_at - t.created_at)",
+        Backend::MySql => "timestampdiff(microsecond, t.created_at, c.completed_at) / 1000000",
+    };
+    let double = match backend {
+        Backend::Sqlite => "real",
+        Backend::Postgres => "double precision",
+        Backend::MySql => "double",
+    };
+    format!(
+        "select count(*) as total, count(case when t.completed then 1 end) as completed, \
+         count(case when t.completed then c.completed_at end) as timed, \
+         cast(coalesce(avg(case when t.completed then {} end), 0) as {}) as seconds \
+         from todos t left join ({}) c on c.todo_id = t.id",
+        seconds, double, FIRST_COMPLETIONS
+    )
+}
+
+// The open todo scoring highest, the oldest of equals, in one query. Its score
+// is the weight of age ($1) by the days since now ($2) it was created, plus
+// that of idleness ($3) by the days since now ($4) it was last updated. The
+// Any driver doesn't decode decimals, so the score is cast.
+fn next_sql(backend: Backend) -> String {
+    let days = |now: &str, since: &str| match backend {
+        Backend::Sqlite => format!("(julianday({}) - julianday({}))", now, since),
+        Backend::Postgres => format!(
+            "cast(extract(epoch from {} - {}) as double precision) / 86400",
+            timestamp_param(backend, now),
+            since
+        ),
+        Backend::MySql => format!(
+            "timestampdiff(microsecond, {}, {}) / 86400000000",
+            since, now
+        ),
+    };
+    let double = match backend {
+        Backend::Sqlite => "real",
+        Backend::Postgres => "double precision",
+        Backend::MySql => "double",
+    };
+    format!(
+        "select {}, cast($1 * {} + $3 * {} as {}) as score from todos \
+         where not completed order by score desc, id limit 1",
+        columns(backend),
+        days("$2", "created_at"),
+        days("$4", "updated_at"),
+        double
+    )
+}
+
+// What a time series counts: todos created, or completed for the first time.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Created,
+    Completed,
+}
+
+// The buckets of a time series, which start on Mondays for weeks.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    // The first day of the bucket the day falls in; None when it's before
+    // the first day there is.
+    pub fn bucket(self, day: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Interval::Day => Some(day),
+            Interval::Week => {
+                day.checked_sub_days(Days::new(day.weekday().num_days_from_monday().into()))
+            }
+            Interval::Month => day.with_day(1),
+        }
+    }
+
+    // None when it's after the last day there is.
+    pub fn next(self, bucket: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Interval::Day => bucket.checked_add_days(Days::new(1)),
+            Interval::Week => bucket.checked_add_days(Days::new(7)),
+            Interval::Month => bucket.checked_add_months(Months::new(1)),
+        }
+    }
+
+    // The SQL truncating the timestamp to its bucket, as a `YYYY-MM-DD` text.
+    fn sql(self, backend: Backend, timestamp: &str) -> String {
+        match (backend, self) {
+            (Backend::Sqlite, Interval::Day) => format!("date({})", timestamp),
+            (Backend::Sqlite, Interval::Week) => {
+                format!("date({}, 'weekday 0', '-6 days')", timestamp)
+            }
+            (Backend::Sqlite, Interval::Month) => format!("strftime('%Y-%m-01', {})", timestamp),
+            (Backend::Postgres, _) => format!(
+                "to_char(date_trunc('{}', {}), 'YYYY-MM-DD')",
+                self.as_str(),
+                timestamp
+            ),
+            (Backend::MySql, Interval::Day) => {
+                format!("cast(date_format({}, '%Y-%m-%d') as char)", timestamp)
+            }
+            (Backend::MySql, Interval::Week) => format!(
+                "cast(date_format(date_sub({0}, interval weekday({0}) day), '%Y-%m-%d') as char)",
+                timestamp
+            ),
+            (Backend::MySql, Interval::Month) => {
+                format!("cast(date_format({}, '%Y-%m-01') as char)", timestamp)
+            }
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+        }
+    }
+}
+
+// The non-empty buckets of the metric between the days, both included, in
+// order, from every todo and every change, for backends without queries.
+// Todos count as created when they were, which the change feed doesn't know
+// for those made before it; deleted todos, when their creation was recorded.
+pub fn time_series(
+    todos: &[Todo],
+    changes: &[Change],
+    metric: Metric,
+    interval: Interval,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<(NaiveDate, i64)> {
+    let mut times = HashMap::new();
+    if metric == Metric::Created {
+        for todo in todos {
+            times.insert(todo.id, todo.created_at);
+        }
+    }
+    for change in changes {
+        let counts = match metric {
+            Metric::Created => change.kind == ChangeKind::Created,
+            Metric::Completed => change.todo.as_ref().is_some_and(|todo| todo.completed),
+        };
+        if counts {
+            times.entry(change.todo_id).or_insert(change.changed_at);
+        }
+    }
+
+    let mut buckets = BTreeMap::new();
+    for time in times.into_values() {
+        let day = time.date();
+        if let Some(bucket) = interval.bucket(day).filter(|_| (from..=to).contains(&day)) {
+            *buckets.entry(bucket).or_insert(0) += 1;
+        }
+    }
+    buckets.into_iter().collect()
+}
+
+// A body todos start with, for typeahead: how many todos have it, and when
+// the latest of them was last updated.
+#[derive(Clone)]
+pub struct Suggestion {
+    pub body: String,
+    pub count: i64,
+    pub last_used_at: NaiveDateTime,
+}
+
+// The distinct bodies starting with the prefix, ignoring case, the most
+// frequent first, then the most recently used, the one last created among
+// those used in the same second; for backends without queries.
+pub fn suggestions(todos: &[Todo], prefix: &str, limit: usize) -> Vec<Suggestion> {
+    let prefix = prefix.to_lowercase();
+    let mut bodies: HashMap<&str, (Suggestion, i64)> = HashMap::new();
+    for todo in todos {
+        if !todo.body.to_lowercase().starts_with(&prefix) {
+            continue;
+        }
+        let (suggestion, last_id) = bodies.entry(&todo.body).or_insert_with(|| {
+            let suggestion = Suggestion {
+                body: todo.body.clone(),
+                count: 0,
+                last_used_at: todo.updated_at,
+            };
+            (suggestion, todo.id)
+        });
+        suggestion.count += 1;
+        suggestion.last_used_at = suggestion.last_used_at.max(todo.updated_at);
+        *last_id = (*last_id).max(todo.id);
+    }
+
+    let mut suggestions: Vec<(Suggestion, i64)> = bodies.into_values().collect();
+    suggestions.sort_by_key(|(suggestion, last_id)| {
+        std::cmp::Reverse((
+            suggestion.count,
+            suggestion.last_used_at.and_utc().timestamp(),
+            *last_id,
+        ))
+    });
+    suggestions
+        .into_iter()
+        .take(limit)
+        .map(|(suggestion, _)| suggestion)
+        .collect()
+}
+
+// A LIKE pattern matching what starts with the prefix, its wildcards escaped
+// with `!`, which needs no escaping itself in any backend's string literals.
+fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '!' | '%' | '_') {
+            pattern.push('!');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+// Todo columns in the shape `Todo::from_row` decodes. MySQL reports TEXT
+// columns as blobs, so the body is cast too.
+fn columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => {
+            "id, body, cast(completed as integer) as completed, \
+             cast(created_at as text) as created_at, cast(updated_at as text) as updated_at, \
+             version"
+        }
+        Backend::MySql => {
+            "id, cast(body as char) as body, cast(completed as signed) as completed, \
+             cast(created_at as char) as created_at, cast(updated_at as char) as updated_at, \
+             version"
+        }
+    }
+}
+
+// Change columns in the shape `Change::from_row` decodes. MySQL reports TEXT
+// columns as blobs.
+fn change_columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => {
+            "seq, kind, todo_id, coalesce(todo, '') as todo, \
+             cast(changed_at as text) as changed_at"
+        }
+        Backend::MySql => {
+            "seq, cast(kind as char) as kind, todo_id, \
+             cast(coalesce(todo, '') as char) as todo, \
+             cast(changed_at as char) as changed_at"
+        }
+    }
+}
+
+// Timestamps are bound as text; Postgres won't assign text to a timestamp
+// column without a cast.
+fn timestamp_param(backend: Backend, placeholder: &str) -> String {
+    match backend {
+        Backend::Postgres => format!("cast({} as timestamp)", placeholder),
+        Backend::Sqlite | Backend::MySql => placeholder.to_string(),
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now()
+        .naive_utc()
+        .format("%Y-%m-%d %H:%M:%S%.6f")
+        .to_string()
+}
+
+// The SQL implementation, for whichever backend the pools are connected to.
+// Selects go to the read pool, everything else to the write pool.
+pub struct SqlTodoRepository {
+    pools: DbPools,
+    // Whether writes also record their event in the outbox, for the relay to
+    // publish.
+    outbox: bool,
+}
+
+type TransactionFuture<'c, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'c>>;
+
+impl SqlTodoRepository {
+    pub fn new(pools: DbPools) -> Self {
+        SqlTodoRepository {
+            pools,
+            outbox: false,
+        }
+    }
+
+    pub fn with_outbox(mut self) -> Self {
+        self.outbox = true;
+        self
+    }
+
+    // Runs `f` in a transaction on the write pool, committed when it returns
+    // Ok and rolled back when it fails, so writes made of several statements
+    // never leave partial results behind.
+    async fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send,
+        F: for<'c> FnOnce(&'c mut DbConnection) -> TransactionFuture<'c, T> + Send,
+    {
+        let mut tx = self.pools.write.begin().await?;
+
+        match f(&mut tx).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn fetch_todo<'e, E>(executor: E, backend: Backend, id: i64) -> Result<Todo, Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Any>,
+{
+    let sql = format!("select {} from todos where id = $1", columns(backend));
+    let sql = backend.sql(&sql);
+    instrumented(
+        "todos",
+        "select",
+        &sql,
+        query_as(&sql).bind(id).fetch_one(executor),
+    )
+    .await
+}
+
+#[async_trait]
+impl TodoRepository for SqlTodoRepository {
+    async fn list(&self) -> Result<Vec<Todo>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+        // In id order, like the in-memory backend, so pages are stable.
+        let sql = format!("select {} from todos order by id", columns(backend));
+        let sql = backend.sql(&sql);
+        instrumented("todos", "select", &sql, query_as(&sql).fetch_all(dbpool)).await
+    }
+
+    async fn page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<(Vec<Todo>, Option<Duration>), Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+        let sql = format!(
+            "select {} from todos order by id limit $1 offset $2",
+            columns(backend)
+        );
+        let sql = backend.sql(&sql);
+        let limit = limit.map_or(i64::MAX, |limit| limit as i64);
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        let todos = instrumented(
+            "todos",
+            "select",
+            &sql,
+            query_as(&sql).bind(limit).bind(offset).fetch_all(dbpool),
+        )
+        .await?;
+        Ok((todos, None))
+    }
+
+    async fn count(&self) -> Result<i64, Error> {
+        let dbpool = &self.pools.read;
+        let sql = "select count(*) from todos";
+        let row = instrumented("todos", "select", sql, query(sql).fetch_one(dbpool)).await?;
+        row.try_get(0)
+    }
+
+    async fn time_series(
+        &self,
+        metric: Metric,
+        interval: Interval,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+
+        // Todos are created when they say, which the change feed doesn't know
+        // for those made before it; deleted todos, when their creation was
+        // recorded.
+        let (time, source) = match metric {
+            Metric::Created => (
+                "created_at",
+                "(select created_at from todos union all \
+                 select changed_at from changes where kind = 'created' \
+                 and not exists (select 1 from todos t where t.id = changes.todo_id)) c"
+                    .to_string(),
+            ),
+            Metric::Completed => ("completed_at", format!("({}) c", FIRST_COMPLETIONS)),
+        };
+        // Bounded by the day after `to`, unless it's the last day there is.
+        let until = to.succ_opt();
+        let sql = format!(
+            "select {} as bucket, count(*) as count from {} where c.{time} >= {}{} \
+             group by 1 order by 1",
+            interval.sql(backend, &format!("c.{}", time)),
+            source,
+            timestamp_param(backend, "$1"),
+            until.map_or(String::new(), |_| format!(
+                " and c.{time} < {}",
+                timestamp_param(backend, "$2")
+            )),
+        );
+        let sql = backend.sql(&sql);
+        let day = |day: NaiveDate| day.format("%Y-%m-%d 00:00:00").to_string();
+        let mut query = query(&sql).bind(day(from));
+        if let Some(until) = until {
+            query = query.bind(day(until));
+        }
+        let rows = instrumented("changes", "select", &sql, query.fetch_all(dbpool)).await?;
+
+        rows.iter()
+            .map(|row| {
+                let bucket: String = row.try_get("bucket")?;
+                let bucket = NaiveDate::parse_from_str(&bucket, "%Y-%m-%d").map_err(|e| {
+                    Error::ColumnDecode {
+                        index: "bucket".to_string(),
+                        source: e.into(),
+                    }
+                })?;
+                Ok((bucket, row.try_get("count")?))
+            })
+            .collect()
+    }
+
+    // Served by the todos_body_prefix index: on lower(body) in Postgres, whose
+    // LIKE is case-sensitive, on the body elsewhere.
+    async fn suggest(&self, prefix: &str, limit: i64) -> Result<Vec<Suggestion>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+        let (body, matched) = match backend {
+            Backend::Sqlite => ("body", "body like $1 escape '!'"),
+            Backend::Postgres => ("body", "lower(body) like lower($1) escape '!'"),
+            Backend::MySql => ("cast(body as char)", "body like $1 escape '!'"),
+        };
+        let last_used_at = match backend {
+            Backend::Sqlite | Backend::Postgres => "cast(max(updated_at) as text)",
+            Backend::MySql => "cast(max(updated_at) as char)",
+        };
+        let sql = format!(
+            "select {body} as body, count(*) as count, {last_used_at} as last_used_at \
+             from todos where {matched} group by body \
+             order by count(*) desc, max(updated_at) desc, max(id) desc limit $2"
+        );
+        let sql = backend.sql(&sql);
+        let rows = instrumented(
+            "todos",
+            "select",
+            &sql,
+            query(&sql)
+                .bind(like_prefix(prefix))
+                .bind(limit)
+                .fetch_all(dbpool),
+        )
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(Suggestion {
+                    body: row.try_get("body")?,
+                    count: row.try_get("count")?,
+                    last_used_at: timestamp(row, "last_used_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn next(&self, weights: NextTodoWeights) -> Result<Option<(Todo, f64)>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+        let sql = next_sql(backend);
+        let sql = backend.sql(&sql);
+        let now = now();
+        let row = instrumented(
+            "todos",
+            "select",
+            &sql,
+            query(&sql)
+                .bind(weights.age)
+                .bind(now.clone())
+                .bind(weights.idle)
+                .bind(now)
+                .fetch_optional(dbpool),
+        )
+        .await?;
+
+        row.map(|row| Ok((Todo::from_row(&row)?, row.try_get("score")?)))
+            .transpose()
+    }
+
+    async fn stats(&self) -> Result<TodoStats, Error> {
+        let dbpool = &self.pools.read;
+        let sql = stats_sql(Backend::of(dbpool));
+        let row = instrumented("todos", "select", &sql, query(&sql).fetch_one(dbpool)).await?;
+
+        let timed: i64 = row.try_get("timed")?;
+        Ok(TodoStats {
+            total: row.try_get("total")?,
+            completed: row.try_get("completed")?,
+            average_completion_seconds: (timed > 0).then(|| row.try_get("seconds")).transpose()?,
+        })
+    }
+
+    async fn read(&self, id: i64) -> Result<Todo, Error> {
+        let dbpool = &self.pools.read;
+        fetch_todo(dbpool, Backend::of(dbpool), id).await
+    }
+
+    // Writes are recorded in the change feed in the same transaction.
+    async fn create(&self, new_todo: CreateTodo) -> Result<Todo, Error> {
+        let backend = Backend::of(&self.pools.write);
+        let outbox = self.outbox;
+        let body = new_todo.body;
+
+        self.transaction(move |conn| {
+            Box::pin(async move {
+                // MySQL has no RETURNING, so the row is read back by its
+                // generated id.
+                let todo = if backend == Backend::MySql {
+                    let sql = backend.sql("insert into todos (body) values ($1)");
+                    let result = instrumented(
+                        "todos",
+                        "insert",
+                        &sql,
+                        query(&sql).bind(body).execute(&mut *conn),
+                    )
+                    .await?;
+
+                    let id = result.last_insert_id().ok_or_else(|| {
+                        Error::Protocol("no id returned for the inserted todo".into())
+                    })?;
+
+                    fetch_todo(&mut *conn, backend, id).await?
+                } else {
+                    let sql = format!(
+                        "insert into todos (body) values ($1) returning {}",
+                        columns(backend)
+                    );
+                    let sql = backend.sql(&sql);
+                    instrumented(
+                        "todos",
+                        "insert",
+                        &sql,
+                        query_as(&sql).bind(body).fetch_one(&mut *conn),
+                    )
+                    .await?
+                };
+
+                record_change(
+                    conn,
+                    backend,
+                    outbox,
+                    ChangeKind::Created,
+                    todo.id,
+                    Some(&todo),
+                )
+                .await?;
+
+                Ok(todo)
+            })
+        })
+        .await
+    }
+
+    async fn update(&self, id: i64, updated_todo: UpdateTodo, version: i64) -> Result<Todo, Error> {
+        let backend = Backend::of(&self.pools.write);
+        let outbox = self.outbox;
+
+        self.transaction(move |conn| {
+            Box::pin(
+                async move { update_todo(conn, backend, outbox, id, updated_todo, version).await },
+            )
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i64, version: Option<i64>) -> Result<u64, Error> {
+        let backend = Backend::of(&self.pools.write);
+        let outbox = self.outbox;
+
+        self.transaction(move |conn| {
+            Box::pin(async move {
+                let result = match version {
+                    None => {
+                        let sql = backend.sql("delete from todos where id = $1");
+                        instrumented(
+                            "todos",
+                            "delete",
+                            &sql,
+                            query(&sql).bind(id).execute(&mut *conn),
+                        )
+                        .await?
+                    }
+                    Some(version) => {
+                        let sql = backend.sql("delete from todos where id = $1 and version = $2");
+                        instrumented(
+                            "todos",
+                            "delete",
+                            &sql,
+                            query(&sql).bind(id).bind(version).execute(&mut *conn),
+                        )
+                        .await?
+                    }
+                };
+
+                let deleted = result.rows_affected();
+                match (deleted, version) {
+                    (0, Some(_)) => return Err(Error::RowNotFound),
+                    // Already gone: nothing changed.
+                    (0, None) => return Ok(0),
+                    _ => {}
+                }
+
+                let sql = backend.sql("delete from todo_documents where todo_id = $1");
+                instrumented(
+                    "todo_documents",
+                    "delete",
+                    &sql,
+                    query(&sql).bind(id).execute(&mut *conn),
+                )
+                .await?;
+
+                record_change(conn, backend, outbox, ChangeKind::Deleted, id, None).await?;
+
+                Ok(deleted)
+            })
+        })
+        .await
+    }
+
+    async fn changes(&self, since: i64, limit: i64) -> Result<Vec<Change>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+
+        let sql = format!(
+            "select {} from changes where seq > $1 order by seq limit $2",
+            change_columns(backend)
+        );
+        let sql = backend.sql(&sql);
+        instrumented(
+            "changes",
+            "select",
+            &sql,
+            query_as(&sql).bind(since).bind(limit).fetch_all(dbpool),
+        )
+        .await
+    }
+
+    async fn revision(&self, id: i64, version: i64) -> Result<Todo, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+
+        // Versions are only in the recorded todo, so the todo's writes are
+        // read latest first until the one that made it.
+        let sql = format!(
+            "select {} from changes where todo_id = $1 and kind <> 'deleted' order by seq desc",
+            change_columns(backend)
+        );
+        let sql = backend.sql(&sql);
+        let changes: Vec<Change> = instrumented(
+            "changes",
+            "select",
+            &sql,
+            query_as(&sql).bind(id).fetch_all(dbpool),
+        )
+        .await?;
+
+        changes
+            .into_iter()
+            .filter_map(|change| change.todo)
+            .find(|todo| todo.version == version)
+            .ok_or(Error::RowNotFound)
+    }
+
+    async fn document(&self, id: i64) -> Result<Option<Vec<u8>>, Error> {
+        let dbpool = &self.pools.read;
+        let backend = Backend::of(dbpool);
+
+        let sql = match backend {
+            Backend::Sqlite | Backend::Postgres => {
+                "select state from todo_documents where todo_id = $1"
+            }
+            Backend::MySql => {
+                "select cast(state as char) as state from todo_documents where todo_id = $1"
+            }
+        };
+        let sql = backend.sql(sql);
+        let row = instrumented(
+            "todo_documents",
+            "select",
+            &sql,
+            query(&sql).bind(id).fetch_optional(dbpool),
+        )
+        .await?;
+
+        row.map(|row| {
+            let state: String = row.try_get("state")?;
+            BASE64_STANDARD
+                .decode(state)
+                .map_err(|e| Error::ColumnDecode {
+                    index: "state".to_string(),
+                    source: e.into(),
+                })
+        })
+        .transpose()
+    }
+
+    async fn update_with_document(
+        &self,
         id: i64,
         updated_todo: UpdateTodo,
+        version: i64,
+        document: Vec<u8>,
     ) -> Result<Todo, Error> {
-        query_as(
-            "update todos set body = ?, completed = ?, updated_at = datetime('now') where id = ? returning *",
-        )
-        .bind(updated_todo.body())
-        .bind(updated_todo.completed())
-        .bind(id)
-        .fetch_one(&dbpool)
+        let backend = Backend::of(&self.pools.write);
+        let outbox = self.outbox;
+        let state = BASE64_STANDARD.encode(document);
+
+        self.transaction(move |conn| {
+            Box::pin(async move {
+                let todo = update_todo(conn, backend, outbox, id, updated_todo, version).await?;
+
+                // Replaced rather than upserted, which every backend spells
+                // differently.
+                let sql = backend.sql("delete from todo_documents where todo_id = $1");
+                instrumented(
+                    "todo_documents",
+                    "delete",
+                    &sql,
+                    query(&sql).bind(id).execute(&mut *conn),
+                )
+                .await?;
+
+                let sql =
+                    backend.sql("insert into todo_documents (todo_id, state) values ($1, $2)");
+                instrumented(
+                    "todo_documents",
+                    "insert",
+                    &sql,
+                    query(&sql).bind(id).bind(state).execute(&mut *conn),
+                )
+                .await?;
+
+                Ok(todo)
+            })
+        })
         .await
-        .map_err(Into::into)
     }
+}
+
+// Updates the todo if it is still at `version`, recording the change.
+async fn update_todo(
+    conn: &mut DbConnection,
+    backend: Backend,
+    outbox: bool,
+    id: i64,
+    updated_todo: UpdateTodo,
+    version: i64,
+) -> Result<Todo, Error> {
+    let mut sql = format!(
+        "update todos set body = $1, completed = $2, updated_at = {}, version = version + 1 \
+         where id = $4 and version = $5",
+        timestamp_param(backend, "$3")
+    );
+
+    let todo = if backend == Backend::MySql {
+        let sql = backend.sql(&sql);
+        let result = instrumented(
+            "todos",
+            "update",
+            &sql,
+            query(&sql)
+                .bind(updated_todo.body)
+                .bind(updated_todo.completed)
+                .bind(now())
+                .bind(id)
+                .bind(version)
+                .execute(&mut *conn),
+        )
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        fetch_todo(&mut *conn, backend, id).await?
+    } else {
+        sql.push_str(" returning ");
+        sql.push_str(columns(backend));
+        let sql = backend.sql(&sql);
+        instrumented(
+            "todos",
+            "update",
+            &sql,
+            query_as(&sql)
+                .bind(updated_todo.body)
+                .bind(updated_todo.completed)
+                .bind(now())
+                .bind(id)
+                .bind(version)
+                .fetch_one(&mut *conn),
+        )
+        .await?
+    };
+
+    record_change(conn, backend, outbox, ChangeKind::Updated, id, Some(&todo)).await?;
 
-    pub async fn delete(dbpool: SqlitePool, id: i64) -> Result<(), Error> {
-        query("delete from todos where id = ?")
-            .bind(id)
-            .execute(&dbpool)
-            .await?;
-        Ok(())
+    Ok(todo)
+}
+
+// Appends a change to the feed, numbered from `change_sequence`, and with
+// `outbox` its event to the outbox under the same number. The update keeps
+// its row locked until the transaction commits, so concurrent writes take
+// numbers in commit order: a consumer that has read up to a number will never
+// see a lower one appear later.
+async fn record_change(
+    conn: &mut DbConnection,
+    backend: Backend,
+    outbox: bool,
+    kind: ChangeKind,
+    todo_id: i64,
+    todo: Option<&Todo>,
+) -> Result<(), Error> {
+    let sql = "update change_sequence set last_seq = last_seq + 1";
+    instrumented(
+        "change_sequence",
+        "update",
+        sql,
+        query(sql).execute(&mut *conn),
+    )
+    .await?;
+
+    let sql = "select last_seq from change_sequence";
+    let seq: i64 = instrumented(
+        "change_sequence",
+        "select",
+        sql,
+        query(sql).fetch_one(&mut *conn),
+    )
+    .await?
+    .try_get("last_seq")?;
+
+    // The Any driver can't bind NULL to a text column on every backend, so
+    // a deletion's missing todo is bound as '' and stored as NULL.
+    let sql = format!(
+        "insert into changes (seq, kind, todo_id, todo, changed_at) \
+         values ($1, $2, $3, nullif($4, ''), {})",
+        timestamp_param(backend, "$5")
+    );
+    let sql = backend.sql(&sql);
+    instrumented(
+        "changes",
+        "insert",
+        &sql,
+        query(&sql)
+            .bind(seq)
+            .bind(kind.as_str())
+            .bind(todo_id)
+            .bind(todo.map_or_else(String::new, |todo| serde_json::to_string(todo).unwrap()))
+            .bind(now())
+            .execute(&mut *conn),
+    )
+    .await?;
+
+    if outbox {
+        let occurred_at = chrono::Utc::now();
+        // Shaped like the events of the live updates.
+        let mut payload = serde_json::json!({
+            "type": kind.as_str(),
+            "id": todo_id,
+            "seq": seq,
+            "occurred_at": occurred_at.to_rfc3339(),
+        });
+        if let Some(todo) = todo {
+            payload["todo"] = serde_json::json!(todo);
+        }
+
+        let sql = backend.sql(
+            "insert into outbox (seq, event, todo_id, payload, created_at) \
+             values ($1, $2, $3, $4, $5)",
+        );
+        instrumented(
+            "outbox",
+            "insert",
+            &sql,
+            query(&sql)
+                .bind(seq)
+                .bind(kind.as_str())
+                .bind(todo_id)
+                .bind(payload.to_string())
+                .bind(occurred_at.timestamp_millis())
+                .execute(&mut *conn),
+        )
+        .await?;
     }
+
+    Ok(())
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, utoipa::ToSchema)]
 pub struct CreateTodo {
     body: String,
 }
 
 impl CreateTodo {
+    pub fn new(body: String) -> Self {
+        CreateTodo { body }
+    }
+
     pub fn body(&self) -> &str {
         self.body.as_ref()
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, utoipa::ToSchema)]
 pub struct UpdateTodo {
     body: String,
     completed: bool,
 }
 
 impl UpdateTodo {
+    pub fn new(body: String, completed: bool) -> Self {
+        UpdateTodo { body, completed }
+    }
+
     pub fn body(&self) -> &str {
         self.body.as_ref()
     }
@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::config::TrailingSlash;
+
+// Serves paths with trailing slashes, like `/v1/todos/`, as the path without
+// them: routed as if they weren't there, or redirected there with 308, which
+// keeps the method and body. Runs before routing, routes being matched
+// exactly.
+pub async fn normalize(
+    State(mode): State<TrailingSlash>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let trimmed = path.trim_end_matches('/');
+    if mode == TrailingSlash::Keep || trimmed.len() == path.len() || trimmed.is_empty() {
+        return next.run(request).await;
+    }
+
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", trimmed, query),
+        None => trimmed.to_string(),
+    };
+    if mode == TrailingSlash::Redirect {
+        return (
+            StatusCode::PERMANENT_REDIRECT,
+            [(header::LOCATION, path_and_query)],
+        )
+            .into_response();
+    }
+
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
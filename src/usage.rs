@@ -0,0 +1,449 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use sqlx::{Error, Row};
+
+use crate::{
+    access_log::Principal,
+    db::{Backend, DbPool},
+    envelope::Success,
+    error::ApiError,
+    state::Lifecycle,
+};
+
+const HOUR: i64 = 3600;
+
+// Hours of usage kept, the longest window summarized.
+const RETENTION_HOURS: i64 = 7 * 24;
+
+// Principals and endpoints listed at most per window, busiest first.
+const MAX_ENTRIES: usize = 50;
+
+// The principal of requests no authentication layer accepted from a client
+// whose address is unknown, like the requests of batches.
+const ANONYMOUS: &str = "anonymous";
+
+// The route of requests no route matched.
+const UNMATCHED: &str = "-";
+
+// The hour, as the unix seconds it starts at, the principal, the method and
+// the route requests are counted by.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UsageKey {
+    pub hour: i64,
+    pub principal: String,
+    pub method: String,
+    pub route: String,
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct Counts {
+    pub requests: i64,
+    // Answered 4xx.
+    pub client_errors: i64,
+    // Answered 5xx.
+    pub server_errors: i64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.requests += other.requests;
+        self.client_errors += other.client_errors;
+        self.server_errors += other.server_errors;
+    }
+}
+
+// Where the hourly rollups are kept.
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    // Adds the counts to those of their keys.
+    async fn add(&self, counts: &[(UsageKey, Counts)]) -> Result<(), Error>;
+
+    // The rollups from the hour on.
+    async fn since(&self, hour: i64) -> Result<Vec<(UsageKey, Counts)>, Error>;
+
+    // Drops the rollups before the hour.
+    async fn prune(&self, hour: i64) -> Result<(), Error>;
+}
+
+pub type DynUsageStore = Arc<dyn UsageStore>;
+
+// Rollups in the `api_usage` table, shared by every instance.
+pub struct SqlUsageStore {
+    dbpool: DbPool,
+}
+
+impl SqlUsageStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlUsageStore { dbpool }
+    }
+}
+
+#[async_trait]
+impl UsageStore for SqlUsageStore {
+    async fn add(&self, counts: &[(UsageKey, Counts)]) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+        let upsert = match backend {
+            Backend::Sqlite | Backend::Postgres => {
+                "on conflict (hour, principal, method, route) do update set \
+                 requests = api_usage.requests + excluded.requests, \
+                 client_errors = api_usage.client_errors + excluded.client_errors, \
+                 server_errors = api_usage.server_errors + excluded.server_errors"
+            }
+            Backend::MySql => {
+                "on duplicate key update requests = requests + values(requests), \
+                 client_errors = client_errors + values(client_errors), \
+                 server_errors = server_errors + values(server_errors)"
+            }
+        };
+        let sql = format!(
+            "insert into api_usage \
+             (hour, principal, method, route, requests, client_errors, server_errors) \
+             values ($1, $2, $3, $4, $5, $6, $7) {}",
+            upsert
+        );
+        let sql = backend.sql(&sql);
+
+        let mut tx = self.dbpool.begin().await?;
+        for (key, counts) in counts {
+            sqlx::query(&sql)
+                .bind(key.hour)
+                .bind(&key.principal)
+                .bind(&key.method)
+                .bind(&key.route)
+                .bind(counts.requests)
+                .bind(counts.client_errors)
+                .bind(counts.server_errors)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await
+    }
+
+    async fn since(&self, hour: i64) -> Result<Vec<(UsageKey, Counts)>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        // MySQL reports VARCHAR columns as blobs.
+        let sql = match backend {
+            Backend::Sqlite | Backend::Postgres => {
+                "select hour, principal, method, route, requests, client_errors, server_errors \
+                 from api_usage where hour >= $1"
+            }
+            Backend::MySql => {
+                "select hour, cast(principal as char) as principal, \
+                 cast(method as char) as method, cast(route as char) as route, \
+                 requests, client_errors, server_errors from api_usage where hour >= $1"
+            }
+        };
+
+        sqlx::query(&backend.sql(sql))
+            .bind(hour)
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok((
+                    UsageKey {
+                        hour: row.try_get("hour")?,
+                        principal: row.try_get("principal")?,
+                        method: row.try_get("method")?,
+                        route: row.try_get("route")?,
+                    },
+                    Counts {
+                        requests: row.try_get("requests")?,
+                        client_errors: row.try_get("client_errors")?,
+                        server_errors: row.try_get("server_errors")?,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    async fn prune(&self, hour: i64) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+        sqlx::query(&backend.sql("delete from api_usage where hour < $1"))
+            .bind(hour)
+            .execute(&self.dbpool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Keeps rollups in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryUsageStore {
+    rollups: Mutex<BTreeMap<UsageKey, Counts>>,
+}
+
+#[async_trait]
+impl UsageStore for InMemoryUsageStore {
+    async fn add(&self, counts: &[(UsageKey, Counts)]) -> Result<(), Error> {
+        let mut rollups = self.rollups.lock().unwrap();
+
+        for (key, counts) in counts {
+            rollups.entry(key.clone()).or_default().add(*counts);
+        }
+
+        Ok(())
+    }
+
+    async fn since(&self, hour: i64) -> Result<Vec<(UsageKey, Counts)>, Error> {
+        let rollups = self.rollups.lock().unwrap();
+
+        Ok(rollups
+            .iter()
+            .filter(|(key, _)| key.hour >= hour)
+            .map(|(key, counts)| (key.clone(), *counts))
+            .collect())
+    }
+
+    async fn prune(&self, hour: i64) -> Result<(), Error> {
+        self.rollups
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.hour >= hour);
+
+        Ok(())
+    }
+}
+
+// Requests counted per hour, principal and endpoint: in memory as they are
+// answered, added to the store's rollups every USAGE_FLUSH_SECONDS, so
+// counting costs no query per request.
+#[derive(Clone)]
+pub struct Usage {
+    store: DynUsageStore,
+    pending: Arc<Mutex<HashMap<UsageKey, Counts>>>,
+}
+
+impl Usage {
+    pub fn new(store: DynUsageStore) -> Self {
+        Usage {
+            store,
+            pending: Arc::default(),
+        }
+    }
+
+    fn count(&self, key: UsageKey, status: u16) {
+        let counts = Counts {
+            requests: 1,
+            client_errors: (400..500).contains(&status).into(),
+            server_errors: (status >= 500).into(),
+        };
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.entry(key).or_default().add(counts);
+        }
+    }
+
+    // Adds the pending counts to the store, keeping them for the next flush
+    // when it fails, and drops the rollups past retention.
+    async fn flush(&self) -> Result<(), Error> {
+        let pending: Vec<_> = match self.pending.lock() {
+            Ok(mut pending) => pending.drain().collect(),
+            Err(_) => return Ok(()),
+        };
+
+        if !pending.is_empty() {
+            if let Err(e) = self.store.add(&pending).await {
+                if let Ok(mut kept) = self.pending.lock() {
+                    for (key, counts) in pending {
+                        kept.entry(key).or_default().add(counts);
+                    }
+                }
+                return Err(e);
+            }
+        }
+
+        self.store
+            .prune(current_hour() - RETENTION_HOURS * HOUR)
+            .await
+    }
+
+    // Flushes every `interval`, and once more when the server starts
+    // draining, so the counts of its last requests aren't lost.
+    pub fn spawn_flusher(&self, interval: Duration, lifecycle: Lifecycle) {
+        let usage = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let draining = tokio::select! {
+                    _ = lifecycle.draining() => true,
+                    _ = tokio::time::sleep(interval) => false,
+                };
+                if !lifecycle.is_schema_outdated() {
+                    if let Err(e) = usage.flush().await {
+                        tracing::warn!(target: "usage", error = %e, "couldn't store API usage");
+                    }
+                }
+                if draining {
+                    break;
+                }
+            }
+        });
+    }
+
+    // The rollups from the hour on, those not flushed yet included.
+    async fn since(&self, hour: i64) -> Result<Vec<(UsageKey, Counts)>, Error> {
+        let mut rollups = self.store.since(hour).await?;
+        if let Ok(pending) = self.pending.lock() {
+            rollups.extend(
+                pending
+                    .iter()
+                    .filter(|(key, _)| key.hour >= hour)
+                    .map(|(key, counts)| (key.clone(), *counts)),
+            );
+        }
+
+        Ok(rollups)
+    }
+}
+
+fn current_hour() -> i64 {
+    let now = chrono::Utc::now().timestamp();
+    now - now.rem_euclid(HOUR)
+}
+
+// Counts the request by the principal the authentication layer that accepted
+// it named, or else by the address it came from, `ip:<address>`, and by its
+// route.
+pub async fn record(State(usage): State<Usage>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()));
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, |path| path.as_str())
+        .to_string();
+
+    let response = next.run(request).await;
+
+    let principal = match response.extensions().get::<Principal>() {
+        Some(principal) => principal.0.clone(),
+        None => client.unwrap_or_else(|| ANONYMOUS.to_string()),
+    };
+    usage.count(
+        UsageKey {
+            hour: current_hour(),
+            principal,
+            method,
+            route,
+        },
+        response.status().as_u16(),
+    );
+    response
+}
+
+#[derive(Serialize)]
+pub struct UsageData {
+    last_24h: Window,
+    last_7d: Window,
+}
+
+#[derive(Serialize)]
+pub struct Window {
+    #[serde(flatten)]
+    counts: Counts,
+    // Of the requests answered 4xx or 5xx, 0 without requests.
+    error_rate: f64,
+    principals: Vec<PrincipalUsage>,
+    endpoints: Vec<EndpointUsage>,
+}
+
+#[derive(Serialize)]
+pub struct PrincipalUsage {
+    principal: String,
+    #[serde(flatten)]
+    counts: Counts,
+    error_rate: f64,
+}
+
+#[derive(Serialize)]
+pub struct EndpointUsage {
+    method: String,
+    route: String,
+    #[serde(flatten)]
+    counts: Counts,
+    error_rate: f64,
+}
+
+// Admin route: the requests of the last 24 hours and 7 days, in total, by
+// principal and by endpoint, busiest first, with how many failed. Hours are
+// whole, the current one included, so the windows span up to an hour more.
+pub async fn summary(State(usage): State<Usage>) -> Result<Success<UsageData>, ApiError> {
+    let hour = current_hour();
+    let rollups = usage.since(hour - (RETENTION_HOURS - 1) * HOUR).await?;
+
+    Ok(Success::new(UsageData {
+        last_24h: window(&rollups, hour - 23 * HOUR),
+        last_7d: window(&rollups, hour - (RETENTION_HOURS - 1) * HOUR),
+    }))
+}
+
+fn window(rollups: &[(UsageKey, Counts)], since: i64) -> Window {
+    let mut total = Counts::default();
+    let mut principals: HashMap<&str, Counts> = HashMap::new();
+    let mut endpoints: HashMap<(&str, &str), Counts> = HashMap::new();
+    for (key, counts) in rollups.iter().filter(|(key, _)| key.hour >= since) {
+        total.add(*counts);
+        principals.entry(&key.principal).or_default().add(*counts);
+        endpoints
+            .entry((&key.method, &key.route))
+            .or_default()
+            .add(*counts);
+    }
+
+    let mut principals: Vec<_> = principals
+        .into_iter()
+        .map(|(principal, counts)| PrincipalUsage {
+            principal: principal.to_string(),
+            counts,
+            error_rate: error_rate(counts),
+        })
+        .collect();
+    principals
+        .sort_by(|a, b| (b.counts.requests, &a.principal).cmp(&(a.counts.requests, &b.principal)));
+    principals.truncate(MAX_ENTRIES);
+
+    let mut endpoints: Vec<_> = endpoints
+        .into_iter()
+        .map(|((method, route), counts)| EndpointUsage {
+            method: method.to_string(),
+            route: route.to_string(),
+            counts,
+            error_rate: error_rate(counts),
+        })
+        .collect();
+    endpoints.sort_by(|a, b| {
+        (b.counts.requests, &a.route, &a.method).cmp(&(a.counts.requests, &b.route, &b.method))
+    });
+    endpoints.truncate(MAX_ENTRIES);
+
+    Window {
+        counts: total,
+        error_rate: error_rate(total),
+        principals,
+        endpoints,
+    }
+}
+
+fn error_rate(counts: Counts) -> f64 {
+    if counts.requests == 0 {
+        return 0.0;
+    }
+    (counts.client_errors + counts.server_errors) as f64 / counts.requests as f64
+}
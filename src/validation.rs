@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use serde_json::{json, Value};
+
+use crate::{
+    config::Constraints,
+    i18n::Message,
+    state::AppState,
+    todo::{CreateTodo, UpdateTodo},
+};
+
+impl FromRef<AppState> for Constraints {
+    fn from_ref(state: &AppState) -> Constraints {
+        state.config.constraints
+    }
+}
+
+// A field of a request body and what's wrong with it.
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub field: &'static str,
+    pub reason: Message,
+}
+
+// The violations of a refused body, as the response's `invalid-params`
+// (RFC 7807, section 3), kept on the response so `negotiate` can render
+// their reasons in the client's language.
+#[derive(Clone, Debug)]
+pub struct InvalidParams(pub Arc<Vec<Violation>>);
+
+impl InvalidParams {
+    pub fn to_value(&self, language: &str) -> Value {
+        self.0
+            .iter()
+            .map(|violation| {
+                json!({
+                    "name": violation.field,
+                    "reason": violation.reason.render(language),
+                })
+            })
+            .collect()
+    }
+}
+
+// A rule a field's value must follow, with why the value doesn't.
+type Rule<'a, T> = &'a dyn Fn(&T) -> Option<Message>;
+
+// The rules of request bodies, checked by `JsonBody` before handlers see
+// them, within the configured constraints.
+pub trait Validate {
+    fn validate(&self, constraints: &Constraints, violations: &mut Violations);
+}
+
+#[derive(Default)]
+pub struct Violations(Vec<Violation>);
+
+impl Violations {
+    // Records every rule the field's value breaks.
+    pub fn field<T: ?Sized>(&mut self, field: &'static str, value: &T, rules: &[Rule<'_, T>]) {
+        self.0.extend(
+            rules
+                .iter()
+                .filter_map(|rule| rule(value))
+                .map(|reason| Violation { field, reason }),
+        );
+    }
+
+    pub fn into_result(self) -> Result<(), Vec<Violation>> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self.0)
+        }
+    }
+}
+
+pub fn validate(body: &impl Validate, constraints: &Constraints) -> Result<(), Vec<Violation>> {
+    let mut violations = Violations::default();
+    body.validate(constraints, &mut violations);
+    violations.into_result()
+}
+
+pub fn not_blank(value: &str) -> Option<Message> {
+    value.trim().is_empty().then(|| Message::new("field-blank"))
+}
+
+pub fn min_length(min: usize) -> impl Fn(&str) -> Option<Message> {
+    move |value| {
+        (value.chars().count() < min).then(|| Message::new("field-too-short").arg("min", min))
+    }
+}
+
+pub fn max_length(max: usize) -> impl Fn(&str) -> Option<Message> {
+    move |value| {
+        (value.chars().count() > max).then(|| Message::new("field-too-long").arg("max", max))
+    }
+}
+
+impl Validate for CreateTodo {
+    fn validate(&self, constraints: &Constraints, violations: &mut Violations) {
+        body(self.body(), constraints, violations);
+    }
+}
+
+impl Validate for UpdateTodo {
+    fn validate(&self, constraints: &Constraints, violations: &mut Violations) {
+        body(self.body(), constraints, violations);
+    }
+}
+
+// A blank body is only blank, however short it may be.
+fn body(value: &str, constraints: &Constraints, violations: &mut Violations) {
+    if let Some(reason) = not_blank(value) {
+        violations.0.push(Violation {
+            field: "body",
+            reason,
+        });
+        return;
+    }
+    violations.field(
+        "body",
+        value,
+        &[
+            &min_length(constraints.body_min_length),
+            &max_length(constraints.body_max_length),
+        ],
+    );
+}
@@ -0,0 +1,1052 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{any::AnyRow, Error, Row};
+use tokio::sync::Notify;
+
+use crate::{
+    backoff,
+    body::PlainJsonBody,
+    config::WebhookConfig,
+    db::{Backend, DbPool},
+    envelope::Success,
+    events::{TodoEvent, TodoEventHandler},
+    params::PathParams,
+    problem::Problem,
+    state::Lifecycle,
+};
+
+// Event types a webhook can subscribe to.
+const EVENT_TYPES: [&str; 3] = ["created", "updated", "deleted"];
+
+// Deliveries the dispatcher attempts at once.
+const BATCH_SIZE: i64 = 32;
+
+// How often the dispatcher looks for retries that came due when nothing
+// wakes it up.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Redirects a delivery follows at most, as many as reqwest's default.
+const MAX_REDIRECTS: usize = 10;
+
+// Deliveries listed at most, latest first.
+const MAX_DELIVERIES: i64 = 100;
+
+// A URL notified of todo events. Times are unix seconds.
+#[derive(Clone, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    // Every type when empty.
+    pub events: Vec<String>,
+    // Only ever shown when the webhook is created.
+    #[serde(skip)]
+    pub secret: String,
+    pub created_at: i64,
+}
+
+impl Webhook {
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|wanted| wanted == event)
+    }
+}
+
+// An event to deliver to a webhook, and how delivering it went so far.
+#[derive(Clone, Serialize)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    #[serde(skip)]
+    pub payload: String,
+    // `pending`, `delivered` or `failed`, once out of attempts.
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    // 0 until a response is received.
+    pub last_status_code: i64,
+    pub last_error: String,
+    pub created_at: i64,
+    // 0 until delivered.
+    pub delivered_at: i64,
+}
+
+// Where webhooks and their deliveries are kept. A missing webhook or delivery
+// is reported as `sqlx::Error::RowNotFound`.
+#[async_trait]
+pub trait WebhookStore: Send + Sync {
+    async fn create(
+        &self,
+        url: &str,
+        events: &[String],
+        secret: &str,
+        now: i64,
+    ) -> Result<Webhook, Error>;
+
+    async fn list(&self) -> Result<Vec<Webhook>, Error>;
+
+    async fn get(&self, id: i64) -> Result<Webhook, Error>;
+
+    // Along with its deliveries.
+    async fn delete(&self, id: i64) -> Result<(), Error>;
+
+    // Queues a delivery of `payload` to every webhook subscribed to `event`,
+    // due `now`. Returns how many were queued.
+    async fn enqueue(&self, event: &str, payload: &str, now: i64) -> Result<usize, Error>;
+
+    // Claims up to `limit` pending deliveries due by `now`, postponing their
+    // next attempt to `lease_until` so that other instances leave them alone
+    // while they are attempted.
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Delivery>, Error>;
+
+    // Stores the outcome of an attempt at a claimed delivery.
+    async fn record(&self, delivery: &Delivery) -> Result<(), Error>;
+
+    // Latest first.
+    async fn deliveries(&self, webhook_id: i64, limit: i64) -> Result<Vec<Delivery>, Error>;
+
+    // Makes a delivery pending again with its attempts reset, due `now`.
+    async fn redeliver(&self, webhook_id: i64, id: i64, now: i64) -> Result<Delivery, Error>;
+}
+
+pub type DynWebhookStore = Arc<dyn WebhookStore>;
+
+// Webhooks in the `webhooks` and `webhook_deliveries` tables, so deliveries
+// survive restarts and any instance can make them.
+pub struct SqlWebhookStore {
+    dbpool: DbPool,
+}
+
+impl SqlWebhookStore {
+    pub fn new(dbpool: DbPool) -> Self {
+        SqlWebhookStore { dbpool }
+    }
+}
+
+// MySQL reports TEXT columns as blobs.
+fn webhook_columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => "id, url, events, secret, created_at",
+        Backend::MySql => "id, cast(url as char) as url, events, secret, created_at",
+    }
+}
+
+fn delivery_columns(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Sqlite | Backend::Postgres => {
+            "id, webhook_id, event, payload, status, attempts, next_attempt_at, \
+             last_status_code, last_error, created_at, delivered_at"
+        }
+        Backend::MySql => {
+            "id, webhook_id, event, cast(payload as char) as payload, status, attempts, \
+             next_attempt_at, last_status_code, cast(last_error as char) as last_error, \
+             created_at, delivered_at"
+        }
+    }
+}
+
+fn webhook_from_row(row: &AnyRow) -> Result<Webhook, Error> {
+    let events: String = row.try_get("events")?;
+
+    Ok(Webhook {
+        id: row.try_get("id")?,
+        url: row.try_get("url")?,
+        events: events
+            .split(',')
+            .filter(|event| !event.is_empty())
+            .map(str::to_string)
+            .collect(),
+        secret: row.try_get("secret")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn delivery_from_row(row: &AnyRow) -> Result<Delivery, Error> {
+    Ok(Delivery {
+        id: row.try_get("id")?,
+        webhook_id: row.try_get("webhook_id")?,
+        event: row.try_get("event")?,
+        payload: row.try_get("payload")?,
+        status: row.try_get("status")?,
+        attempts: row.try_get("attempts")?,
+        next_attempt_at: row.try_get("next_attempt_at")?,
+        last_status_code: row.try_get("last_status_code")?,
+        last_error: row.try_get("last_error")?,
+        created_at: row.try_get("created_at")?,
+        delivered_at: row.try_get("delivered_at")?,
+    })
+}
+
+#[async_trait]
+impl WebhookStore for SqlWebhookStore {
+    async fn create(
+        &self,
+        url: &str,
+        events: &[String],
+        secret: &str,
+        now: i64,
+    ) -> Result<Webhook, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = "insert into webhooks (url, events, secret, created_at) values ($1, $2, $3, $4)";
+
+        // MySQL has no RETURNING.
+        let id = if backend == Backend::MySql {
+            let result = sqlx::query(&backend.sql(sql))
+                .bind(url)
+                .bind(events.join(","))
+                .bind(secret)
+                .bind(now)
+                .execute(&self.dbpool)
+                .await?;
+            result
+                .last_insert_id()
+                .ok_or_else(|| Error::Protocol("no id returned for the inserted webhook".into()))?
+        } else {
+            sqlx::query(&format!("{} returning id", sql))
+                .bind(url)
+                .bind(events.join(","))
+                .bind(secret)
+                .bind(now)
+                .fetch_one(&self.dbpool)
+                .await?
+                .try_get("id")?
+        };
+
+        Ok(Webhook {
+            id,
+            url: url.to_string(),
+            events: events.to_vec(),
+            secret: secret.to_string(),
+            created_at: now,
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from webhooks order by id",
+            webhook_columns(backend)
+        );
+
+        sqlx::query(&sql)
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(webhook_from_row)
+            .collect()
+    }
+
+    async fn get(&self, id: i64) -> Result<Webhook, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from webhooks where id = $1",
+            webhook_columns(backend)
+        );
+
+        let row = sqlx::query(&backend.sql(&sql))
+            .bind(id)
+            .fetch_one(&self.dbpool)
+            .await?;
+        webhook_from_row(&row)
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+        let mut tx = self.dbpool.begin().await?;
+
+        sqlx::query(&backend.sql("delete from webhook_deliveries where webhook_id = $1"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query(&backend.sql("delete from webhooks where id = $1"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        tx.commit().await
+    }
+
+    async fn enqueue(&self, event: &str, payload: &str, now: i64) -> Result<usize, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let webhooks = self.list().await?;
+        let webhooks = webhooks.iter().filter(|webhook| webhook.wants(event));
+
+        let sql = backend.sql(
+            "insert into webhook_deliveries (webhook_id, event, payload, status, attempts, \
+             next_attempt_at, last_status_code, last_error, created_at, delivered_at) \
+             values ($1, $2, $3, 'pending', 0, $4, 0, '', $5, 0)",
+        );
+        let mut queued = 0;
+        for webhook in webhooks {
+            sqlx::query(&sql)
+                .bind(webhook.id)
+                .bind(event)
+                .bind(payload)
+                .bind(now)
+                .bind(now)
+                .execute(&self.dbpool)
+                .await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Delivery>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from webhook_deliveries where status = 'pending' and next_attempt_at <= $1 \
+             order by next_attempt_at limit $2",
+            delivery_columns(backend)
+        );
+        let due = sqlx::query(&backend.sql(&sql))
+            .bind(now)
+            .bind(limit)
+            .fetch_all(&self.dbpool)
+            .await?;
+
+        // Only the instance whose update still finds the attempt it read
+        // gets the delivery.
+        let sql = backend.sql(
+            "update webhook_deliveries set next_attempt_at = $1 \
+             where id = $2 and status = 'pending' and next_attempt_at = $3",
+        );
+        let mut claimed = Vec::new();
+        for row in &due {
+            let mut delivery = delivery_from_row(row)?;
+            let result = sqlx::query(&sql)
+                .bind(lease_until)
+                .bind(delivery.id)
+                .bind(delivery.next_attempt_at)
+                .execute(&self.dbpool)
+                .await?;
+
+            if result.rows_affected() == 1 {
+                delivery.next_attempt_at = lease_until;
+                claimed.push(delivery);
+            }
+        }
+
+        Ok(claimed)
+    }
+
+    async fn record(&self, delivery: &Delivery) -> Result<(), Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        sqlx::query(&backend.sql(
+            "update webhook_deliveries set status = $1, attempts = $2, next_attempt_at = $3, \
+             last_status_code = $4, last_error = $5, delivered_at = $6 where id = $7",
+        ))
+        .bind(&delivery.status)
+        .bind(delivery.attempts)
+        .bind(delivery.next_attempt_at)
+        .bind(delivery.last_status_code)
+        .bind(&delivery.last_error)
+        .bind(delivery.delivered_at)
+        .bind(delivery.id)
+        .execute(&self.dbpool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, webhook_id: i64, limit: i64) -> Result<Vec<Delivery>, Error> {
+        let backend = Backend::of(&self.dbpool);
+        let sql = format!(
+            "select {} from webhook_deliveries where webhook_id = $1 order by id desc limit $2",
+            delivery_columns(backend)
+        );
+
+        sqlx::query(&backend.sql(&sql))
+            .bind(webhook_id)
+            .bind(limit)
+            .fetch_all(&self.dbpool)
+            .await?
+            .iter()
+            .map(delivery_from_row)
+            .collect()
+    }
+
+    async fn redeliver(&self, webhook_id: i64, id: i64, now: i64) -> Result<Delivery, Error> {
+        let backend = Backend::of(&self.dbpool);
+
+        let result = sqlx::query(&backend.sql(
+            "update webhook_deliveries set status = 'pending', attempts = 0, next_attempt_at = $1 \
+             where id = $2 and webhook_id = $3",
+        ))
+        .bind(now)
+        .bind(id)
+        .bind(webhook_id)
+        .execute(&self.dbpool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        let sql = format!(
+            "select {} from webhook_deliveries where id = $1",
+            delivery_columns(backend)
+        );
+        let row = sqlx::query(&backend.sql(&sql))
+            .bind(id)
+            .fetch_one(&self.dbpool)
+            .await?;
+        delivery_from_row(&row)
+    }
+}
+
+// Keeps webhooks in process memory, for the in-memory storage backend.
+#[derive(Default)]
+pub struct InMemoryWebhookStore {
+    state: Mutex<InMemoryState>,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    webhooks: BTreeMap<i64, Webhook>,
+    deliveries: BTreeMap<i64, Delivery>,
+    last_webhook_id: i64,
+    last_delivery_id: i64,
+}
+
+#[async_trait]
+impl WebhookStore for InMemoryWebhookStore {
+    async fn create(
+        &self,
+        url: &str,
+        events: &[String],
+        secret: &str,
+        now: i64,
+    ) -> Result<Webhook, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.last_webhook_id += 1;
+        let webhook = Webhook {
+            id: state.last_webhook_id,
+            url: url.to_string(),
+            events: events.to_vec(),
+            secret: secret.to_string(),
+            created_at: now,
+        };
+        state.webhooks.insert(webhook.id, webhook.clone());
+
+        Ok(webhook)
+    }
+
+    async fn list(&self) -> Result<Vec<Webhook>, Error> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .webhooks
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get(&self, id: i64) -> Result<Webhook, Error> {
+        let state = self.state.lock().unwrap();
+
+        state.webhooks.get(&id).cloned().ok_or(Error::RowNotFound)
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.webhooks.remove(&id).ok_or(Error::RowNotFound)?;
+        state
+            .deliveries
+            .retain(|_, delivery| delivery.webhook_id != id);
+
+        Ok(())
+    }
+
+    async fn enqueue(&self, event: &str, payload: &str, now: i64) -> Result<usize, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let webhook_ids: Vec<i64> = state
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.wants(event))
+            .map(|webhook| webhook.id)
+            .collect();
+
+        for &webhook_id in &webhook_ids {
+            state.last_delivery_id += 1;
+            let delivery = Delivery {
+                id: state.last_delivery_id,
+                webhook_id,
+                event: event.to_string(),
+                payload: payload.to_string(),
+                status: "pending".to_string(),
+                attempts: 0,
+                next_attempt_at: now,
+                last_status_code: 0,
+                last_error: String::new(),
+                created_at: now,
+                delivered_at: 0,
+            };
+            state.deliveries.insert(delivery.id, delivery);
+        }
+
+        Ok(webhook_ids.len())
+    }
+
+    async fn claim(&self, now: i64, lease_until: i64, limit: i64) -> Result<Vec<Delivery>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        Ok(state
+            .deliveries
+            .values_mut()
+            .filter(|delivery| delivery.status == "pending" && delivery.next_attempt_at <= now)
+            .take(limit as usize)
+            .map(|delivery| {
+                delivery.next_attempt_at = lease_until;
+                delivery.clone()
+            })
+            .collect())
+    }
+
+    async fn record(&self, delivery: &Delivery) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(stored) = state.deliveries.get_mut(&delivery.id) {
+            *stored = delivery.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, webhook_id: i64, limit: i64) -> Result<Vec<Delivery>, Error> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .deliveries
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn redeliver(&self, webhook_id: i64, id: i64, now: i64) -> Result<Delivery, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let delivery = state
+            .deliveries
+            .get_mut(&id)
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .ok_or(Error::RowNotFound)?;
+        delivery.status = "pending".to_string();
+        delivery.attempts = 0;
+        delivery.next_attempt_at = now;
+
+        Ok(delivery.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct Webhooks {
+    pub store: DynWebhookStore,
+    // Wakes the dispatcher up when deliveries are queued.
+    queued: Arc<Notify>,
+    private_networks: bool,
+}
+
+impl Webhooks {
+    pub fn new(store: DynWebhookStore, private_networks: bool) -> Self {
+        Webhooks {
+            store,
+            queued: Arc::new(Notify::new()),
+            private_networks,
+        }
+    }
+
+    // Delivers queued deliveries in the background as they come due, unless
+    // the schema is outdated, as the webhook tables may be missing then.
+    // Attempts are logged under the `webhooks` target.
+    pub fn spawn_dispatcher(&self, config: WebhookConfig, lifecycle: Lifecycle) {
+        let webhooks = self.clone();
+        let mut client = reqwest::Client::builder().timeout(config.timeout);
+        if !config.private_networks {
+            // Checked as the receivers' hosts are resolved, and as redirects
+            // are followed, so neither leads to an address refused at create.
+            client = client.dns_resolver(Arc::new(PublicResolver)).redirect(
+                reqwest::redirect::Policy::custom(|attempt| {
+                    if attempt.previous().len() >= MAX_REDIRECTS {
+                        attempt.error("too many redirects")
+                    } else if private_ip(attempt.url()) {
+                        attempt.error("redirected to a private address")
+                    } else {
+                        attempt.follow()
+                    }
+                }),
+            );
+        }
+        let client = client
+            .build()
+            .expect("couldn't build the webhook HTTP client");
+
+        tokio::spawn(async move {
+            loop {
+                if !lifecycle.is_schema_outdated() {
+                    match webhooks.dispatch(&client, config).await {
+                        // More may be due already.
+                        Ok(attempted) if attempted > 0 => continue,
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::warn!(target: "webhooks", error = %e, "couldn't dispatch webhooks")
+                        }
+                    }
+                }
+
+                tokio::select! {
+                    _ = webhooks.queued.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+    }
+
+    // Attempts the deliveries due now, returning how many.
+    async fn dispatch(
+        &self,
+        client: &reqwest::Client,
+        config: WebhookConfig,
+    ) -> Result<usize, Error> {
+        let now = chrono::Utc::now().timestamp();
+        // Past the timeout of an attempt, a delivery claimed by an instance
+        // that went away is due again.
+        let lease_until = now + config.timeout.as_secs() as i64 + 1;
+
+        let due = self.store.claim(now, lease_until, BATCH_SIZE).await?;
+        let attempted = due.len();
+
+        let mut attempts = tokio::task::JoinSet::new();
+        for delivery in due {
+            let webhook = match self.store.get(delivery.webhook_id).await {
+                Ok(webhook) => webhook,
+                // Deleted meanwhile, along with the delivery.
+                Err(Error::RowNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            let client = client.clone();
+            attempts.spawn(async move { attempt(&client, &webhook, delivery, config).await });
+        }
+
+        while let Some(delivery) = attempts.join_next().await {
+            if let Ok(delivery) = delivery {
+                self.store.record(&delivery).await?;
+            }
+        }
+
+        Ok(attempted)
+    }
+}
+
+// Queues a delivery of every todo event to the webhooks subscribed to it.
+#[async_trait]
+impl TodoEventHandler for Webhooks {
+    async fn handle(&self, event: &TodoEvent) {
+        let now = chrono::Utc::now();
+        let mut payload = event.to_json();
+        payload["occurred_at"] = json!(now.to_rfc3339());
+
+        match self
+            .store
+            .enqueue(event.kind(), &payload.to_string(), now.timestamp())
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => self.queued.notify_one(),
+            Err(e) => {
+                tracing::warn!(target: "webhooks", error = %e, "couldn't queue webhook deliveries")
+            }
+        }
+    }
+}
+
+// POSTs the payload, signed with the webhook's secret, and returns the
+// delivery updated with the outcome.
+async fn attempt(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    mut delivery: Delivery,
+    config: WebhookConfig,
+) -> Delivery {
+    let timestamp = chrono::Utc::now().timestamp();
+    // Addresses in the URL itself are never resolved.
+    let refused = !config.private_networks
+        && reqwest::Url::parse(&webhook.url).is_ok_and(|url| private_ip(&url));
+    let result = if refused {
+        Err("refused to deliver to a private address".to_string())
+    } else {
+        client
+            .post(&webhook.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("webhook-id", delivery.id)
+            .header("webhook-event", &delivery.event)
+            .header("webhook-timestamp", timestamp)
+            .header(
+                "webhook-signature",
+                format!(
+                    "v1={}",
+                    sign(&webhook.secret, delivery.id, timestamp, &delivery.payload)
+                ),
+            )
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())
+    };
+
+    delivery.attempts += 1;
+    let now = chrono::Utc::now().timestamp();
+
+    let error = match result {
+        Ok(response) if response.status().is_success() => {
+            tracing::info!(
+                target: "webhooks",
+                webhook_id = webhook.id,
+                delivery_id = delivery.id,
+                status = response.status().as_u16(),
+                "webhook delivered"
+            );
+            delivery.status = "delivered".to_string();
+            delivery.last_status_code = response.status().as_u16() as i64;
+            delivery.last_error = String::new();
+            delivery.delivered_at = now;
+            return delivery;
+        }
+        Ok(response) => {
+            delivery.last_status_code = response.status().as_u16() as i64;
+            format!("responded {}", response.status())
+        }
+        Err(e) => {
+            delivery.last_status_code = 0;
+            e
+        }
+    };
+
+    if delivery.attempts >= config.max_attempts as i64 {
+        delivery.status = "failed".to_string();
+        tracing::warn!(
+            target: "webhooks",
+            webhook_id = webhook.id,
+            delivery_id = delivery.id,
+            attempts = delivery.attempts,
+            error = %error,
+            "webhook delivery failed, giving up"
+        );
+    } else {
+        // In whole seconds, rounded up so jitter never makes it immediate.
+        delivery.next_attempt_at = now
+            + backoff::delay(
+                config.retry_base,
+                config.retry_max,
+                delivery.attempts as u32,
+            )
+            .as_secs_f64()
+            .ceil() as i64;
+        tracing::info!(
+            target: "webhooks",
+            webhook_id = webhook.id,
+            delivery_id = delivery.id,
+            attempts = delivery.attempts,
+            error = %error,
+            "webhook delivery failed, will retry"
+        );
+    }
+    delivery.last_error = error;
+
+    delivery
+}
+
+// Whether the URL's host resolves, and only to public addresses.
+async fn public_host(url: &reqwest::Url) -> bool {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return false;
+    };
+    if private_ip(url) {
+        return false;
+    }
+    match tokio::net::lookup_host((host.trim_matches(['[', ']']), port)).await {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.collect();
+            !addrs.is_empty() && addrs.iter().all(|addr| public(addr.ip()))
+        }
+        Err(_) => false,
+    }
+}
+
+// Whether the URL's host is an address that isn't public.
+fn private_ip(url: &reqwest::Url) -> bool {
+    url.host_str()
+        .and_then(|host| host.trim_matches(['[', ']']).parse().ok())
+        .is_some_and(|ip| !public(ip))
+}
+
+// Whether an address is reachable on the internet: not loopback, link-local
+// (like the cloud metadata service at 169.254.169.254), private, shared,
+// unspecified, broadcast or multicast.
+fn public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_private()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+// Resolves receivers' hosts to their public addresses only, failing for
+// hosts with none, so a host that changes what it resolves to after its
+// webhook was registered can't lead deliveries to internal services.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+// Hex HMAC-SHA256 of `<delivery id>.<timestamp>.<payload>`, so receivers can
+// check a request came from this service and reject replays of old ones.
+fn sign(secret: &str, delivery_id: i64, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length works");
+    mac.update(format!("{}.{}.{}", delivery_id, timestamp, payload).as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhook {
+    url: String,
+    // Every type when absent or empty.
+    #[serde(default)]
+    events: Vec<String>,
+    // Generated when absent.
+    secret: Option<String>,
+}
+
+// Registers a webhook. The response is the only one showing its secret.
+#[derive(Serialize)]
+pub struct WebhookData<W> {
+    webhook: W,
+}
+
+// A webhook as created, the only time its secret is shown.
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+#[derive(Serialize)]
+pub struct WebhooksData {
+    webhooks: Vec<Webhook>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveriesData {
+    deliveries: Vec<Delivery>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryData {
+    delivery: Delivery,
+}
+
+pub async fn create(
+    State(webhooks): State<Webhooks>,
+    PlainJsonBody(request): PlainJsonBody<CreateWebhook>,
+) -> Result<Success<WebhookData<CreatedWebhook>>, Problem> {
+    let url = match reqwest::Url::parse(&request.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => {
+            return Err(fail(
+                "url must be an absolute http or https URL".to_string(),
+            ))
+        }
+    };
+    if !webhooks.private_networks && !public_host(&url).await {
+        return Err(fail(
+            "url must resolve to public addresses only, not loopback, link-local or private ones"
+                .to_string(),
+        ));
+    }
+    if let Some(event) = request
+        .events
+        .iter()
+        .find(|event| !EVENT_TYPES.contains(&event.as_str()))
+    {
+        return Err(fail(format!(
+            "unknown event type: {}, expected created, updated or deleted",
+            event
+        )));
+    }
+    let secret = match request.secret {
+        Some(secret) if secret.is_empty() => {
+            return Err(fail("secret must not be empty".to_string()))
+        }
+        Some(secret) => secret,
+        None => rand::random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    };
+
+    let webhook = webhooks
+        .store
+        .create(
+            &request.url,
+            &request.events,
+            &secret,
+            chrono::Utc::now().timestamp(),
+        )
+        .await?;
+
+    Ok(Success::new(WebhookData {
+        webhook: CreatedWebhook { webhook, secret },
+    }))
+}
+
+pub async fn list(State(webhooks): State<Webhooks>) -> Result<Success<WebhooksData>, Problem> {
+    let webhooks = webhooks.store.list().await?;
+
+    Ok(Success::new(WebhooksData { webhooks }))
+}
+
+pub async fn read(
+    State(webhooks): State<Webhooks>,
+    PathParams(id): PathParams<i64>,
+) -> Result<Success<WebhookData<Webhook>>, Problem> {
+    let webhook = webhooks
+        .store
+        .get(id)
+        .await
+        .map_err(|e| webhook_error(id, e))?;
+
+    Ok(Success::new(WebhookData { webhook }))
+}
+
+pub async fn delete(
+    State(webhooks): State<Webhooks>,
+    PathParams(id): PathParams<i64>,
+) -> Result<StatusCode, Problem> {
+    webhooks
+        .store
+        .delete(id)
+        .await
+        .map_err(|e| webhook_error(id, e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// The delivery log of a webhook: its latest deliveries and how they went.
+pub async fn deliveries(
+    State(webhooks): State<Webhooks>,
+    PathParams(id): PathParams<i64>,
+) -> Result<Success<DeliveriesData>, Problem> {
+    webhooks
+        .store
+        .get(id)
+        .await
+        .map_err(|e| webhook_error(id, e))?;
+    let deliveries = webhooks.store.deliveries(id, MAX_DELIVERIES).await?;
+
+    Ok(Success::new(DeliveriesData { deliveries }))
+}
+
+// Delivers again, right away and with a fresh set of attempts, whether the
+// delivery failed or already succeeded.
+pub async fn redeliver(
+    State(webhooks): State<Webhooks>,
+    PathParams((id, delivery_id)): PathParams<(i64, i64)>,
+) -> Result<(StatusCode, Success<DeliveryData>), Problem> {
+    let delivery = match webhooks
+        .store
+        .redeliver(id, delivery_id, chrono::Utc::now().timestamp())
+        .await
+    {
+        Ok(delivery) => delivery,
+        Err(Error::RowNotFound) => {
+            return Err(Problem::new(StatusCode::NOT_FOUND).detail(format!(
+                "delivery with ID: {} of webhook {} not found",
+                delivery_id, id
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    webhooks.queued.notify_one();
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Success::new(DeliveryData { delivery }),
+    ))
+}
+
+fn webhook_error(id: i64, e: Error) -> Problem {
+    match e {
+        Error::RowNotFound => {
+            Problem::new(StatusCode::NOT_FOUND).detail(format!("webhook with ID: {} not found", id))
+        }
+        e => e.into(),
+    }
+}
+
+fn fail(message: String) -> Problem {
+    Problem::new(StatusCode::BAD_REQUEST).detail(message)
+}
@@ -0,0 +1,308 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    events::{EventStream, NumberedEvent, TodoEvent},
+    params::QueryParams,
+    problem::Problem,
+    repository::DynTodoRepository,
+    state::Lifecycle,
+    todo::{CreateTodo, Todo, UpdateTodo},
+};
+
+#[derive(Clone)]
+pub struct LiveUpdates {
+    pub events: EventStream,
+    pub todos: DynTodoRepository,
+    // Present when writes are refused while migrations are pending, like the
+    // REST routes do.
+    pub refuse_writes: Option<Lifecycle>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamParams {
+    // Comma separated: `created`, `updated`, `deleted`. Every kind when absent.
+    kinds: Option<String>,
+    // Only the events of this todo.
+    id: Option<i64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Kind {
+    fn of(event: &TodoEvent) -> Self {
+        match event {
+            TodoEvent::Created(_) => Kind::Created,
+            TodoEvent::Updated(_) => Kind::Updated,
+            TodoEvent::Deleted(_) => Kind::Deleted,
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "created" => Some(Kind::Created),
+            "updated" => Some(Kind::Updated),
+            "deleted" => Some(Kind::Deleted),
+            _ => None,
+        }
+    }
+}
+
+struct Filter {
+    kinds: Option<Vec<Kind>>,
+    id: Option<i64>,
+}
+
+impl Filter {
+    fn matches(&self, event: &TodoEvent) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&Kind::of(event)))
+            && self.id.is_none_or(|id| id == event.id())
+    }
+}
+
+// Upgrades to a WebSocket pushing a JSON text message for every todo created,
+// updated or deleted through any API of this instance from then on, e.g.
+// `{"type":"updated","id":1,"todo":{...}}`; deletions carry no todo. A client
+// too slow to keep up is sent `{"type":"lagged","missed":n}` in place of the
+// events it missed, and should refetch what it shows.
+//
+// Clients can also write over the socket: each text message is a command
+// answered by a `result` or `error` message with the same `correlation_id`.
+// Commands run one at a time, in the order they are received.
+pub async fn stream(
+    State(live): State<LiveUpdates>,
+    QueryParams(params): QueryParams<StreamParams>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let kinds = match params.kinds {
+        None => None,
+        Some(kinds) => match kinds
+            .split(',')
+            .map(Kind::parse)
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(kinds) => Some(kinds),
+            None => {
+                return Problem::new(StatusCode::BAD_REQUEST)
+                    .detail("kinds must be a comma separated list of created, updated and deleted")
+                    .into_response();
+            }
+        },
+    };
+
+    let filter = Filter {
+        kinds,
+        id: params.id,
+    };
+
+    // Subscribed before the upgrade completes, so no event is missed once the
+    // client sees the connection open.
+    let receiver = live.events.subscribe();
+
+    upgrade.on_upgrade(move |socket| serve(socket, live, receiver, filter))
+}
+
+async fn serve(
+    mut socket: WebSocket,
+    live: LiveUpdates,
+    mut events: tokio::sync::broadcast::Receiver<NumberedEvent>,
+    filter: Filter,
+) {
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(NumberedEvent { event, .. }) if filter.matches(&event) => event.to_json(),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "websocket client fell behind, events skipped");
+                    json!({"type": "lagged", "missed": missed})
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => live.command(&text).await,
+                Some(Ok(Message::Binary(_))) => {
+                    command_error(Value::Null, "invalid", "commands must be text messages".to_string())
+                }
+                // Pings are answered by the socket itself.
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            },
+        };
+
+        if socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+// `{"correlation_id":"1","command":"create","body":"..."}`, answered by
+// `{"type":"result","correlation_id":"1","todo":{...}}`.
+#[derive(Deserialize)]
+struct CommandMessage {
+    // Echoed back; any JSON value the client picks.
+    #[serde(default)]
+    correlation_id: Value,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum Command {
+    Create {
+        body: String,
+    },
+    // Applies only while the todo is still at `version`.
+    Update {
+        id: i64,
+        version: i64,
+        body: String,
+        completed: bool,
+    },
+    // Marks the todo completed, or open again with `completed: false`. With
+    // `version`, only that revision of the todo.
+    Complete {
+        id: i64,
+        version: Option<i64>,
+        #[serde(default = "completed_default")]
+        completed: bool,
+    },
+}
+
+fn completed_default() -> bool {
+    true
+}
+
+struct CommandError {
+    code: &'static str,
+    message: String,
+}
+
+impl LiveUpdates {
+    async fn command(&self, text: &str) -> Value {
+        let message = match serde_json::from_str::<CommandMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                // Echo the correlation id of a malformed command if there is one.
+                let correlation_id = serde_json::from_str::<Value>(text)
+                    .ok()
+                    .and_then(|value| value.get("correlation_id").cloned())
+                    .unwrap_or(Value::Null);
+                return command_error(correlation_id, "invalid", e.to_string());
+            }
+        };
+
+        match self.run(message.command).await {
+            Ok(todo) => json!({
+                "type": "result",
+                "correlation_id": message.correlation_id,
+                "todo": todo,
+            }),
+            Err(e) => command_error(message.correlation_id, e.code, e.message),
+        }
+    }
+
+    async fn run(&self, command: Command) -> Result<Todo, CommandError> {
+        if let Some(lifecycle) = &self.refuse_writes {
+            if lifecycle.is_schema_outdated() {
+                return Err(CommandError {
+                    code: "unavailable",
+                    message: "database migrations are pending, writes are disabled".to_string(),
+                });
+            }
+        }
+
+        match command {
+            Command::Create { body } => self
+                .todos
+                .create(CreateTodo::new(body))
+                .await
+                .map_err(database_error),
+            Command::Update {
+                id,
+                version,
+                body,
+                completed,
+            } => self
+                .todos
+                .update(id, UpdateTodo::new(body, completed), version)
+                .await
+                .map_err(|e| write_error(id, e)),
+            Command::Complete {
+                id,
+                version,
+                completed,
+            } => {
+                let current = match self.todos.read(id).await {
+                    Ok(todo) => todo,
+                    Err(sqlx::Error::RowNotFound) => {
+                        return Err(CommandError {
+                            code: "not_found",
+                            message: format!("todo with ID: {} not found", id),
+                        })
+                    }
+                    Err(e) => return Err(database_error(e)),
+                };
+
+                self.todos
+                    .update(
+                        id,
+                        UpdateTodo::new(current.body, completed),
+                        version.unwrap_or(current.version),
+                    )
+                    .await
+                    .map_err(|e| write_error(id, e))
+            }
+        }
+    }
+}
+
+fn command_error(correlation_id: Value, code: &str, message: String) -> Value {
+    json!({
+        "type": "error",
+        "correlation_id": correlation_id,
+        "code": code,
+        "message": message,
+    })
+}
+
+fn write_error(id: i64, e: sqlx::Error) -> CommandError {
+    match e {
+        sqlx::Error::RowNotFound => CommandError {
+            code: "conflict",
+            message: format!(
+                "todo with ID: {} was modified or deleted, fetch it and retry",
+                id
+            ),
+        },
+        e => database_error(e),
+    }
+}
+
+fn database_error(e: sqlx::Error) -> CommandError {
+    CommandError {
+        code: "internal",
+        message: format!("Database error: {}", e),
+    }
+}